@@ -0,0 +1,51 @@
+contract Token {
+    mapping(address => uint256) private balances;
+    mapping(address => mapping(address => uint256)) private allowances;
+    uint256 private supply;
+
+    event Transfer(address indexed from, address indexed to, uint256 value);
+    event Approval(address indexed owner, address indexed spender, uint256 value);
+
+    /// Mints the whole supply to the deployer.
+    constructor(uint256 initialSupply) {
+        supply = initialSupply;
+        balances[msg.sender] = initialSupply;
+        emit Transfer(address(0), msg.sender, initialSupply);
+    }
+
+    function totalSupply() public view returns (uint256) {
+        return supply;
+    }
+
+    function balanceOf(address owner) public view returns (uint256) {
+        return balances[owner];
+    }
+
+    function allowance(address owner, address spender) public view returns (uint256) {
+        return allowances[owner][spender];
+    }
+
+    function transfer(address to, uint256 value) public returns (bool) {
+        require(balances[msg.sender] >= value, "insufficient balance");
+        balances[msg.sender] = balances[msg.sender] - value;
+        balances[to] = balances[to] + value;
+        emit Transfer(msg.sender, to, value);
+        return true;
+    }
+
+    function approve(address spender, uint256 value) public returns (bool) {
+        allowances[msg.sender][spender] = value;
+        emit Approval(msg.sender, spender, value);
+        return true;
+    }
+
+    function transferFrom(address from, address to, uint256 value) public returns (bool) {
+        require(allowances[from][msg.sender] >= value, "insufficient allowance");
+        require(balances[from] >= value, "insufficient balance");
+        allowances[from][msg.sender] = allowances[from][msg.sender] - value;
+        balances[from] = balances[from] - value;
+        balances[to] = balances[to] + value;
+        emit Transfer(from, to, value);
+        return true;
+    }
+}
@@ -0,0 +1,95 @@
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, LitStr};
+use tinysol::bindings::{binding_name, rust_bindings};
+use tinysol::diagnostics::Severity;
+use tinysol::selector::{event_topic as topic_of, selector as selector_of};
+use tinysol::settings::CompilerSettings;
+use tinysol::tinyvm::compile_source;
+
+/// Compiles Solidity source at build time and expands to typed bindings for each contract
+/// in it, as `tinysol bindings` generates them, plus `contract()` and `deploy()`:
+///
+/// ```ignore
+/// tinysol_macros::contract! { r#"
+///     contract Flipper {
+///         bool private value;
+///         function flip() public { value = !value; }
+///         function get() public view returns (bool) { return value; }
+///     }
+/// "# }
+///
+/// let mut world = World::new_dev();
+/// let mut flipper = Flipper::deploy(&mut world, alice());
+/// flipper.flip()?;
+/// ```
+///
+/// Parse errors and error diagnostics fail the Rust build.
+#[proc_macro]
+pub fn contract(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+    match expand(&source.value()) {
+        Ok(items) => items.parse().unwrap(),
+        Err(message) => syn::Error::new(source.span(), message).to_compile_error().into(),
+    }
+}
+
+fn expand(source: &str) -> Result<String, String> {
+    let contracts = compile_source(source, &CompilerSettings::default())
+        .map_err(|errors| errors.iter().map(|diagnostic| diagnostic.render("contract!", source)).collect::<Vec<_>>().join("\n"))?;
+    let errors = contracts.iter()
+        .flat_map(|contract| &contract.diagnostics)
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| diagnostic.render("contract!", source))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    Ok(contracts.iter().map(|contract| format!(
+        "{bindings}
+impl<'w> {name}<'w> {{
+    pub const SOURCE: &'static str = {source:?};
+
+    /// The contract, compiled again from `SOURCE` at run time.
+    pub fn contract() -> ::tinysol::tinyvm::Contract {{
+        ::tinysol::tinyvm::compile_source(Self::SOURCE, &::tinysol::settings::CompilerSettings::default())
+            .expect(\"checked when the macro expanded\")
+            .into_iter()
+            .find(|contract| contract.name == {contract_name:?})
+            .unwrap()
+    }}
+
+    /// Deploys the contract from `from`, who is also the sender of later calls.
+    pub fn deploy(world: &'w mut ::tinysol::world::World, from: ::tinysol::env::Address) -> Self {{
+        let address = world.deploy(from, Self::contract());
+        Self::new(world, address, from)
+    }}
+}}
+",
+        bindings = rust_bindings(contract), name = binding_name(contract), source = source, contract_name = contract.name,
+    )).collect())
+}
+
+/// The function selector of a canonical signature, as a `[u8; 4]` constant:
+///
+/// ```ignore
+/// const TRANSFER: [u8; 4] = tinysol_macros::selector!("transfer(address,uint256)");
+/// ```
+#[proc_macro]
+pub fn selector(input: TokenStream) -> TokenStream {
+    let signature = parse_macro_input!(input as LitStr);
+    format!("{:?}", selector_of(&signature.value())).parse().unwrap()
+}
+
+/// The first log topic of an event signature, as a `U256` constant:
+///
+/// ```ignore
+/// const TRANSFER: U256 = tinysol_macros::event_topic!("Transfer(address,address,uint256)");
+/// ```
+#[proc_macro]
+pub fn event_topic(input: TokenStream) -> TokenStream {
+    let signature = parse_macro_input!(input as LitStr);
+    // `from_words` is a `const fn`, unlike `from_be_bytes`.
+    let (hi, lo) = topic_of(&signature.value()).into_words();
+    format!("::tinysol::bindings::U256::from_words({:#x}, {:#x})", hi, lo).parse().unwrap()
+}
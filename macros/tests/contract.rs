@@ -0,0 +1,26 @@
+use tinysol::test_utils::*;
+use tinysol::world::World;
+
+tinysol_macros::contract! { r#"
+contract Flipper {
+    bool private value;
+
+    function flip() public {
+        value = !value;
+    }
+
+    function get() public view returns (bool) {
+        return value;
+    }
+}
+"# }
+
+#[test]
+fn test_contract_macro() {
+    let mut world = World::new_dev();
+    let mut flipper = Flipper::deploy(&mut world, alice());
+    assert_eq!(flipper.get(), Ok(false));
+    flipper.flip().unwrap();
+    assert_eq!(flipper.get(), Ok(true));
+    assert!(Flipper::contract().diagnostics.is_empty());
+}
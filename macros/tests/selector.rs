@@ -0,0 +1,11 @@
+use tinysol::bindings::U256;
+use tinysol::selector::{event_topic, selector};
+
+const TRANSFER: [u8; 4] = tinysol_macros::selector!("transfer(address,uint256)");
+const TRANSFER_EVENT: U256 = tinysol_macros::event_topic!("Transfer(address,address,uint256)");
+
+#[test]
+fn test_selector_macros() {
+    assert_eq!(TRANSFER, selector("transfer(address,uint256)"));
+    assert_eq!(TRANSFER_EVENT, event_topic("Transfer(address,address,uint256)"));
+}
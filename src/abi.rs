@@ -0,0 +1,654 @@
+use ethnum::{I256, U256};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use crate::bytecode::{from_hex, to_hex};
+use crate::env::Address;
+use crate::selector::{event_topic, selector};
+use crate::tinyvm::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiType {
+    Bool,
+    Uint(usize),
+    Int(usize),
+    Address,
+    String,
+    Bytes,
+}
+
+impl AbiType {
+    pub fn parse(ty: &str) -> Option<AbiType> {
+        match ty {
+            "bool" => Some(AbiType::Bool),
+            "address" => Some(AbiType::Address),
+            "string" => Some(AbiType::String),
+            "bytes" => Some(AbiType::Bytes),
+            "uint" => Some(AbiType::Uint(256)),
+            "int" => Some(AbiType::Int(256)),
+            _ => {
+                if let Some(bits) = ty.strip_prefix("uint") {
+                    bits.parse().ok().filter(valid_bits).map(AbiType::Uint)
+                } else if let Some(bits) = ty.strip_prefix("int") {
+                    bits.parse().ok().filter(valid_bits).map(AbiType::Int)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self, AbiType::String | AbiType::Bytes)
+    }
+}
+
+fn valid_bits(bits: &usize) -> bool {
+    *bits > 0 && *bits <= 256 && bits.is_multiple_of(8)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Uint(U256),
+    Int(I256),
+    Address(Address),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Whether the value is of type `ty`, and for `uintN` and `intN` fits in `N` bits.
+    pub fn matches(&self, ty: &AbiType) -> bool {
+        match (self, ty) {
+            (Value::Uint(v), AbiType::Uint(bits)) => *bits >= 256 || v.leading_zeros() as usize >= 256 - bits,
+            (Value::Int(v), AbiType::Int(bits)) => *bits >= 256 || {
+                let min = I256::MIN >> (256 - bits) as u32;
+                (min..=!min).contains(v)
+            },
+            _ => matches!(
+                (self, ty),
+                (Value::Bool(_), AbiType::Bool)
+                    | (Value::Address(_), AbiType::Address)
+                    | (Value::String(_), AbiType::String)
+                    | (Value::Bytes(_), AbiType::Bytes)
+            ),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        matches!(self, Value::String(_) | Value::Bytes(_))
+    }
+
+    fn head_word(&self) -> U256 {
+        match self {
+            Value::Bool(b) => U256::from(*b as u8),
+            Value::Uint(v) => *v,
+            Value::Int(v) => v.as_u256(),
+            Value::Address(a) => a.to_word(),
+            Value::String(_) | Value::Bytes(_) => U256::ZERO,
+        }
+    }
+}
+
+/// As Solidity would write the value: strings quoted, bytes and addresses in hex.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Uint(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Address(address) => write!(f, "{}", address),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Bytes(bytes) => write!(f, "{}", to_hex(bytes)),
+        }
+    }
+}
+
+/// ABI-encodes `values` as a tuple (head/tail layout, no selector).
+pub fn encode(values: &[Value]) -> Vec<u8> {
+    let head_len = values.len() * 32;
+    let mut head = vec![];
+    let mut tail = vec![];
+
+    values.iter().for_each(|value| {
+        if value.is_dynamic() {
+            head.extend_from_slice(&U256::from((head_len + tail.len()) as u64).to_be_bytes());
+            let bytes = match value {
+                Value::String(s) => s.as_bytes(),
+                Value::Bytes(b) => b.as_slice(),
+                _ => unreachable!(),
+            };
+            tail.extend_from_slice(&U256::from(bytes.len() as u64).to_be_bytes());
+            tail.extend_from_slice(bytes);
+            tail.resize(tail.len().div_ceil(32) * 32, 0);
+        } else {
+            head.extend_from_slice(&value.head_word().to_be_bytes());
+        }
+    });
+    [head, tail].concat()
+}
+
+/// Decodes an ABI-encoded tuple of `types`. Returns `None` on malformed input.
+pub fn decode(types: &[AbiType], data: &[u8]) -> Option<Vec<Value>> {
+    types.iter().enumerate().map(|(i, ty)| {
+        let word = read_word(data, i * 32)?;
+        match ty {
+            AbiType::Bool => Some(Value::Bool(word != U256::ZERO)),
+            AbiType::Uint(_) => Some(Value::Uint(word)),
+            AbiType::Int(_) => Some(Value::Int(word.as_i256())),
+            AbiType::Address => Some(Value::Address(Address::from_word(word))),
+            AbiType::String | AbiType::Bytes => {
+                let offset = usize::try_from(word).ok()?;
+                let len = usize::try_from(read_word(data, offset)?).ok()?;
+                let start = offset.checked_add(32)?;
+                let bytes = data.get(start..start.checked_add(len)?)?.to_vec();
+                if let AbiType::String = ty {
+                    String::from_utf8(bytes).ok().map(Value::String)
+                } else {
+                    Some(Value::Bytes(bytes))
+                }
+            }
+        }
+    }).collect()
+}
+
+fn read_word(data: &[u8], offset: usize) -> Option<U256> {
+    let bytes: [u8; 32] = data.get(offset..offset.checked_add(32)?)?.try_into().ok()?;
+    Some(U256::from_be_bytes(bytes))
+}
+
+/// `data` as the call it makes, e.g. `transfer(0x70997970c51812dc3a010c7d01b50e0d17dc79c8, 1000)`,
+/// for traces and error messages. See `format_calldata`.
+pub fn decode_calldata(abi: &Abi, data: &[u8]) -> String {
+    format_calldata(abi, data, &|value| value.to_string())
+}
+
+/// Like `decode_calldata`, writing each argument with `format`. Calldata that doesn't
+/// match a function of `abi` keeps its selector and shows the argument bytes by length,
+/// `0xa9059cbb(<64 bytes>)`; data too short for a selector stays as it is.
+pub fn format_calldata(abi: &Abi, data: &[u8], format: &dyn Fn(&Value) -> String) -> String {
+    let Some((called, args)) = data.split_first_chunk::<4>() else {
+        return to_hex(data);
+    };
+    let function = abi.functions.iter().find(|function| selector(&function.signature()) == *called);
+    let types = function.and_then(|function| function.inputs.iter().map(|param| AbiType::parse(&param.ty)).collect::<Option<Vec<_>>>());
+    let name = function.map_or_else(|| to_hex(called), |function| function.name.clone());
+    match types.and_then(|types| decode(&types, args)) {
+        Some(values) => format!("{}({})", name, values.iter().map(format).collect::<Vec<_>>().join(", ")),
+        None if args.is_empty() && function.is_none() => name,
+        None => format!("{}(<{} bytes>)", name, args.len()),
+    }
+}
+
+/// Hex calldata, without `0x`, for calling `signature` with `args`, as `World::call` takes it.
+pub fn calldata(signature: &str, args: &[Value]) -> String {
+    CalldataBuilder::new(signature).args(args.iter().cloned()).to_hex()
+}
+
+/// The calldata for calling `signature`, built up one argument at a time:
+///
+/// ```ignore
+/// let data = CalldataBuilder::new("transfer(address,uint256)").arg(Value::Address(to)).parse_arg("1000")?.build();
+/// ```
+///
+/// Arguments are encoded as given. `AbiRegistry::encode_call` checks them against an ABI first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalldataBuilder {
+    signature: String,
+    args: Vec<Value>,
+}
+
+impl CalldataBuilder {
+    pub fn new(signature: &str) -> Self {
+        Self { signature: signature.to_string(), args: vec![] }
+    }
+
+    pub fn arg(mut self, value: Value) -> Self {
+        self.args.push(value);
+        self
+    }
+
+    pub fn args(mut self, values: impl IntoIterator<Item = Value>) -> Self {
+        self.args.extend(values);
+        self
+    }
+
+    /// Adds `text` as the next argument, read as the signature's parameter type at its
+    /// position: `true`, `1000` or `0x3e8`, `-5`, `0x7099...79c8`, `hello`, `0xdead`.
+    pub fn parse_arg(self, text: &str) -> Result<Self, AbiCheckError> {
+        let index = self.args.len();
+        let types = param_types(&self.signature);
+        let ty = types.get(index).ok_or(AbiCheckError::ArgumentCount { expected: types.len(), got: index + 1 })?;
+        let value = AbiType::parse(ty).and_then(|parsed| parse_value(&parsed, text.trim()))
+            .ok_or_else(|| AbiCheckError::ArgumentType { index, expected: ty.to_string() })?;
+        Ok(self.arg(value))
+    }
+
+    /// Adds every argument of a comma-separated list, as `--args` takes them on the
+    /// command line. An empty list adds none.
+    pub fn parse_args(self, list: &str) -> Result<Self, AbiCheckError> {
+        if list.trim().is_empty() {
+            return Ok(self);
+        }
+        list.split(',').try_fold(self, |builder, text| builder.parse_arg(text))
+    }
+
+    /// The selector followed by the ABI-encoded arguments.
+    pub fn build(&self) -> Vec<u8> {
+        [selector(&self.signature).to_vec(), encode(&self.args)].concat()
+    }
+
+    /// `build` in hex, without `0x`, as `World::call` and `Contract::call` take it.
+    pub fn to_hex(&self) -> String {
+        self.build().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// The parameter types of a canonical signature such as `transfer(address,uint256)`.
+fn param_types(signature: &str) -> Vec<&str> {
+    let params = signature.split_once('(').and_then(|(_, rest)| rest.strip_suffix(')')).unwrap_or_default();
+    params.split(',').map(str::trim).filter(|ty| !ty.is_empty()).collect()
+}
+
+fn parse_value(ty: &AbiType, text: &str) -> Option<Value> {
+    let hex = text.strip_prefix("0x");
+    match ty {
+        AbiType::Bool => text.parse().ok().map(Value::Bool),
+        AbiType::Uint(_) => match hex {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_str_radix(text, 10).ok(),
+        }.map(Value::Uint),
+        AbiType::Int(_) => I256::from_str_radix(text, 10).ok().map(Value::Int),
+        AbiType::Address => from_hex(hex?)?.try_into().ok().map(|bytes| Value::Address(Address(bytes))),
+        AbiType::String => Some(Value::String(text.to_string())),
+        AbiType::Bytes => from_hex(hex?).map(Value::Bytes),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiParam {
+    pub name: String,
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiFunction {
+    pub name: String,
+    pub inputs: Vec<AbiParam>,
+    pub outputs: Vec<AbiParam>,
+    pub state_mutability: String,
+}
+
+impl AbiFunction {
+    pub fn signature(&self) -> String {
+        signature(&self.name, &self.inputs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiError {
+    pub name: String,
+    pub inputs: Vec<AbiParam>,
+}
+
+impl AbiError {
+    pub fn signature(&self) -> String {
+        signature(&self.name, &self.inputs)
+    }
+
+    pub fn selector(&self) -> [u8; 4] {
+        selector(&self.signature())
+    }
+}
+
+fn signature(name: &str, inputs: &[AbiParam]) -> String {
+    format!("{}({})", name, inputs.iter().map(|p| p.ty.as_str()).collect::<Vec<_>>().join(","))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiEventParam {
+    pub name: String,
+    pub ty: String,
+    /// Logged as a topic rather than in the data.
+    pub indexed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiEvent {
+    pub name: String,
+    pub inputs: Vec<AbiEventParam>,
+}
+
+impl AbiEvent {
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.inputs.iter().map(|p| p.ty.as_str()).collect::<Vec<_>>().join(","))
+    }
+
+    /// The first topic of the event's logs.
+    pub fn topic(&self) -> U256 {
+        event_topic(&self.signature())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Abi {
+    pub functions: Vec<AbiFunction>,
+    pub errors: Vec<AbiError>,
+    pub events: Vec<AbiEvent>,
+}
+
+impl AbiParam {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({ "name": self.name, "type": self.ty, "internalType": self.ty })
+    }
+}
+
+impl AbiParam {
+    fn from_json(json: &serde_json::Value) -> Option<AbiParam> {
+        Some(AbiParam {
+            name: json["name"].as_str().unwrap_or_default().to_string(),
+            ty: json["type"].as_str()?.to_string(),
+        })
+    }
+}
+
+fn params_from_json(json: &serde_json::Value) -> Option<Vec<AbiParam>> {
+    match json.as_array() {
+        Some(params) => params.iter().map(AbiParam::from_json).collect(),
+        None => Some(vec![]),
+    }
+}
+
+impl Abi {
+    /// Reads a standard JSON ABI. Entries other than functions, errors and events are ignored.
+    pub fn from_json(json: &serde_json::Value) -> Option<Abi> {
+        let mut abi = Abi::default();
+        for entry in json.as_array()? {
+            match entry["type"].as_str().unwrap_or("function") {
+                "function" => abi.functions.push(AbiFunction {
+                    name: entry["name"].as_str()?.to_string(),
+                    inputs: params_from_json(&entry["inputs"])?,
+                    outputs: params_from_json(&entry["outputs"])?,
+                    state_mutability: entry["stateMutability"].as_str().unwrap_or("nonpayable").to_string(),
+                }),
+                "error" => abi.errors.push(AbiError {
+                    name: entry["name"].as_str()?.to_string(),
+                    inputs: params_from_json(&entry["inputs"])?,
+                }),
+                "event" => abi.events.push(AbiEvent {
+                    name: entry["name"].as_str()?.to_string(),
+                    inputs: entry["inputs"].as_array().map_or(Some(vec![]), |inputs| inputs.iter().map(|input| Some(AbiEventParam {
+                        name: input["name"].as_str().unwrap_or_default().to_string(),
+                        ty: input["type"].as_str()?.to_string(),
+                        indexed: input["indexed"].as_bool().unwrap_or_default(),
+                    })).collect())?,
+                }),
+                _ => {},
+            }
+        }
+        Some(abi)
+    }
+
+    /// The standard JSON ABI, as emitted by solc.
+    pub fn to_json(&self) -> serde_json::Value {
+        let functions = self.functions.iter().map(|function| json!({
+            "type": "function",
+            "name": function.name,
+            "inputs": function.inputs.iter().map(AbiParam::to_json).collect::<Vec<_>>(),
+            "outputs": function.outputs.iter().map(AbiParam::to_json).collect::<Vec<_>>(),
+            "stateMutability": function.state_mutability,
+        }));
+        let errors = self.errors.iter().map(|error| json!({
+            "type": "error",
+            "name": error.name,
+            "inputs": error.inputs.iter().map(AbiParam::to_json).collect::<Vec<_>>(),
+        }));
+        let events = self.events.iter().map(|event| json!({
+            "type": "event",
+            "name": event.name,
+            "inputs": event.inputs.iter().map(|input| json!({
+                "name": input.name,
+                "type": input.ty,
+                "internalType": input.ty,
+                "indexed": input.indexed,
+            })).collect::<Vec<_>>(),
+            "anonymous": false,
+        }));
+        serde_json::Value::Array(functions.chain(errors).chain(events).collect())
+    }
+}
+
+impl Contract {
+    /// Builds the ABI description of the compiled contract, functions sorted by name and
+    /// overloads by signature.
+    pub fn abi(&self) -> Abi {
+        let mut functions = self.functions.values()
+            .filter(|function| matches!(function.visibility, FuncVisibility::Public | FuncVisibility::External))
+            .map(|function| AbiFunction {
+                name: function.name.clone(),
+                inputs: abi_params(&function.params),
+                outputs: abi_params(&function.returns),
+                state_mutability: match function.mutability {
+                    FuncMutability::Pure => "pure",
+                    FuncMutability::View | FuncMutability::Constant => "view",
+                    FuncMutability::Payable => "payable",
+                    FuncMutability::NonPayable => "nonpayable",
+                }.to_string(),
+            })
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|function| function.signature());
+
+        Abi {
+            functions,
+            errors: self.errors.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiCheckError {
+    InvalidAbi,
+    UnknownAddress(Address),
+    UnknownFunction(String),
+    ArgumentCount { expected: usize, got: usize },
+    ArgumentType { index: usize, expected: String },
+}
+
+/// ABIs of contracts living outside the compilation unit, keyed by address, so calldata
+/// built for them from Rust, e.g. by a test driving a `World`, is checked and encoded
+/// against the real interface. Compiled contracts don't consult it: code generation
+/// rejects external calls, and the grammar has no typed call targets to check them by.
+#[derive(Debug, Clone, Default)]
+pub struct AbiRegistry {
+    abis: HashMap<Address, Abi>,
+}
+
+impl AbiRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, address: Address, abi: Abi) {
+        self.abis.insert(address, abi);
+    }
+
+    pub fn register_json(&mut self, address: Address, json: &str) -> Result<(), AbiCheckError> {
+        let abi = serde_json::from_str(json).ok()
+            .and_then(|json| Abi::from_json(&json))
+            .ok_or(AbiCheckError::InvalidAbi)?;
+        self.register(address, abi);
+        Ok(())
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&Abi> {
+        self.abis.get(address)
+    }
+
+    /// Type-checks `args` against `function` (a name or full signature) and returns the calldata.
+    pub fn encode_call(&self, address: &Address, function: &str, args: &[Value]) -> Result<Vec<u8>, AbiCheckError> {
+        let abi = self.get(address).ok_or(AbiCheckError::UnknownAddress(*address))?;
+        let candidates = abi.functions.iter()
+            .filter(|f| f.name == function || f.signature() == function)
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return Err(AbiCheckError::UnknownFunction(function.to_string()));
+        }
+
+        // Overloads are resolved by the first candidate whose inputs accept the arguments.
+        let mut error = None;
+        for candidate in candidates {
+            match check_args(candidate, args) {
+                Ok(()) => return Ok(CalldataBuilder::new(&candidate.signature()).args(args.iter().cloned()).build()),
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+        Err(error.unwrap())
+    }
+}
+
+fn check_args(function: &AbiFunction, args: &[Value]) -> Result<(), AbiCheckError> {
+    if function.inputs.len() != args.len() {
+        return Err(AbiCheckError::ArgumentCount { expected: function.inputs.len(), got: args.len() });
+    }
+    function.inputs.iter().zip(args).enumerate().try_for_each(|(index, (input, arg))| {
+        match AbiType::parse(&input.ty) {
+            Some(ty) if arg.matches(&ty) => Ok(()),
+            _ => Err(AbiCheckError::ArgumentType { index, expected: input.ty.clone() }),
+        }
+    })
+}
+
+pub fn abi_params(params: &[crate::solidity::grammar::Parameter]) -> Vec<AbiParam> {
+    params.iter().map(|param| AbiParam {
+        name: param.name.as_ref().map(|n| n.name.clone()).unwrap_or_default(),
+        ty: param_type_name(param).unwrap_or_default().to_string(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let values = vec![
+            Value::Bool(true),
+            Value::Uint(U256::from(42u8)),
+            Value::String("hello".to_string()),
+            Value::Address(Address([7; 20])),
+        ];
+        let types = vec![AbiType::Bool, AbiType::Uint(256), AbiType::String, AbiType::Address];
+        let encoded = encode(&values);
+        assert_eq!(encoded.len(), 4 * 32 + 32 + 32);
+        assert_eq!(decode(&types, &encoded), Some(values));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        assert_eq!(decode(&[AbiType::Uint(256)], &[0u8; 31]), None);
+    }
+
+    #[test]
+    fn test_contract_abi_includes_errors() {
+        let code = "contract c { error Locked(bool); function get() public view returns (bool) { return true; } }";
+        let contract = create_contracts(crate::solidity::grammar::parse(code).unwrap()).remove(0);
+        let abi = contract.abi();
+        assert_eq!(abi.functions[0].signature(), "get()");
+        assert_eq!(abi.functions[0].state_mutability, "view");
+        assert_eq!(abi.errors[0].signature(), "Locked(bool)");
+    }
+
+    #[test]
+    fn test_registry_encodes_checked_calls() {
+        let token = Address([0x42; 20]);
+        let mut registry = AbiRegistry::new();
+        registry.register_json(token, r#"[
+            {"type": "function", "name": "transfer", "stateMutability": "nonpayable",
+             "inputs": [{"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}],
+             "outputs": [{"name": "", "type": "bool"}]},
+            {"type": "event", "name": "Transfer", "inputs": []}
+        ]"#).unwrap();
+
+        let args = [Value::Address(Address([1; 20])), Value::Uint(U256::from(100u8))];
+        let calldata = registry.encode_call(&token, "transfer", &args).unwrap();
+        assert_eq!(&calldata[..4], &[0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(calldata.len(), 4 + 64);
+
+        assert_eq!(
+            registry.encode_call(&token, "transfer", &args[..1]),
+            Err(AbiCheckError::ArgumentCount { expected: 2, got: 1 })
+        );
+        assert_eq!(
+            registry.encode_call(&token, "transfer", &[Value::Bool(true), Value::Uint(U256::ONE)]),
+            Err(AbiCheckError::ArgumentType { index: 0, expected: "address".to_string() })
+        );
+        assert_eq!(
+            registry.encode_call(&token, "approve", &[]),
+            Err(AbiCheckError::UnknownFunction("approve".to_string()))
+        );
+        assert_eq!(
+            registry.encode_call(&Address::ZERO, "transfer", &args),
+            Err(AbiCheckError::UnknownAddress(Address::ZERO))
+        );
+    }
+
+    #[test]
+    fn test_values_match_widths() {
+        assert!(Value::Uint(U256::from(255u8)).matches(&AbiType::Uint(8)));
+        assert!(!Value::Uint(U256::from(256u16)).matches(&AbiType::Uint(8)));
+        assert!(Value::Uint(U256::MAX).matches(&AbiType::Uint(256)));
+        assert!(Value::Int(I256::from(127)).matches(&AbiType::Int(8)));
+        assert!(Value::Int(I256::from(-128)).matches(&AbiType::Int(8)));
+        assert!(!Value::Int(I256::from(128)).matches(&AbiType::Int(8)));
+        assert!(!Value::Int(I256::from(-129)).matches(&AbiType::Int(8)));
+        assert!(Value::Int(I256::MIN).matches(&AbiType::Int(256)));
+        assert!(!Value::Uint(U256::ONE).matches(&AbiType::Int(256)));
+    }
+
+    #[test]
+    fn test_decode_calldata() {
+        let abi = Abi::from_json(&serde_json::json!([
+            {"type": "function", "name": "transfer", "stateMutability": "nonpayable",
+             "inputs": [{"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}, {"name": "memo", "type": "string"}],
+             "outputs": []}
+        ])).unwrap();
+        let data = from_hex(&calldata("transfer(address,uint256,string)", &[Value::Address(Address([0xab; 20])), Value::Uint(U256::from(1000u16)), Value::String("rent".to_string())])).unwrap();
+        assert_eq!(decode_calldata(&abi, &data), format!("transfer({}, 1000, \"rent\")", Address([0xab; 20])));
+
+        assert_eq!(decode_calldata(&abi, &data[..40]), "transfer(<36 bytes>)");
+        assert_eq!(decode_calldata(&abi, &[0xde, 0xad, 0xbe, 0xef, 0, 0]), "0xdeadbeef(<2 bytes>)");
+        assert_eq!(decode_calldata(&abi, &[0xde, 0xad, 0xbe, 0xef]), "0xdeadbeef");
+    }
+
+    #[test]
+    fn test_calldata_builder() {
+        let to = Address([0xab; 20]);
+        let built = CalldataBuilder::new("transfer(address,uint256)").arg(Value::Address(to)).arg(Value::Uint(U256::from(1000u16)));
+        let parsed = CalldataBuilder::new("transfer(address,uint256)").parse_args(&format!("{}, 0x3e8", to)).unwrap();
+        assert_eq!(parsed, built);
+        assert_eq!(built.to_hex(), calldata("transfer(address,uint256)", &[Value::Address(to), Value::Uint(U256::from(1000u16))]));
+        assert_eq!(built.build()[..4], selector("transfer(address,uint256)"));
+
+        let flags = CalldataBuilder::new("set(bool,int8,string)").parse_args("true,-5,hi").unwrap();
+        assert_eq!(flags, CalldataBuilder::new("set(bool,int8,string)").args([Value::Bool(true), Value::Int(I256::from(-5)), Value::String("hi".to_string())]));
+        assert_eq!(CalldataBuilder::new("get()").parse_args("").unwrap().build(), selector("get()"));
+
+        assert_eq!(
+            CalldataBuilder::new("transfer(address,uint256)").parse_args("0x12,1"),
+            Err(AbiCheckError::ArgumentType { index: 0, expected: "address".to_string() })
+        );
+        assert_eq!(
+            CalldataBuilder::new("get()").parse_arg("1"),
+            Err(AbiCheckError::ArgumentCount { expected: 0, got: 1 })
+        );
+    }
+
+    #[test]
+    fn test_parse_types() {
+        assert_eq!(AbiType::parse("uint8"), Some(AbiType::Uint(8)));
+        assert_eq!(AbiType::parse("int256"), Some(AbiType::Int(256)));
+        assert_eq!(AbiType::parse("uint7"), None);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+use crate::diagnostics::Diagnostic;
+use crate::solidity::grammar::*;
+use crate::visit::{walk_expression, walk_statement, Visitor};
+
+/// The parts of one contract a lint looks at.
+pub struct ContractSource<'a> {
+    pub name: &'a str,
+    pub parts: &'a [ContractPart],
+}
+
+impl ContractSource<'_> {
+    fn state_variables(&self) -> HashSet<&str> {
+        self.parts.iter().filter_map(|part| match part {
+            ContractPart::VariableDefinition(_, _, Identifier { name }, _) => Some(name.as_str()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Every function, the constructor, `receive` and `fallback`: how messages name it,
+    /// its parameters and its body.
+    fn functions(&self) -> Vec<(String, &ParameterList, &[Spanned<Statement>])> {
+        self.parts.iter().filter_map(|part| match part {
+            ContractPart::FunctionDefinition(_, Identifier { name }, params, _, _, _, body, _) => Some((format!("function `{}`", name), params, &body[..])),
+            ContractPart::ConstructorDefinition(_, params, _, _, body, _) => Some(("the constructor".to_string(), params, &body[..])),
+            ContractPart::ReceiveDefinition(_, params, _, _, body, _) => Some(("`receive`".to_string(), params, &body[..])),
+            ContractPart::FallbackDefinition(_, params, _, _, body, _) => Some(("`fallback`".to_string(), params, &body[..])),
+            _ => None,
+        }).collect()
+    }
+}
+
+/// A static check over a contract's AST.
+pub trait Lint {
+    /// The diagnostic code it reports under.
+    fn code(&self) -> &'static str;
+    fn check(&self, contract: &ContractSource, diagnostics: &mut Vec<Diagnostic>);
+}
+
+/// Function, constructor or return parameters named like a state variable, which hide
+/// the variable inside the function body.
+pub struct ShadowedStateVariable;
+
+fn named_params(list: &ParameterList) -> Vec<&Spanned<Identifier>> {
+    match list {
+        ParameterList::Params(_, Some(params), _) => params.params.iter().filter_map(|p| p.name.as_ref()).collect(),
+        _ => vec![],
+    }
+}
+
+impl Lint for ShadowedStateVariable {
+    fn code(&self) -> &'static str {
+        "shadowing"
+    }
+
+    fn check(&self, contract: &ContractSource, diagnostics: &mut Vec<Diagnostic>) {
+        let state = contract.state_variables();
+        contract.parts.iter().for_each(|part| {
+            let (function, params) = match part {
+                ContractPart::FunctionDefinition(_, Identifier { name }, params, _, returns, _, _, _) => {
+                    let mut list = named_params(params);
+                    if let Some(FunctionReturnParams::ParameterList(_, returns)) = returns {
+                        list.extend(named_params(returns));
+                    }
+                    (format!("function `{}`", name), list)
+                },
+                ContractPart::ConstructorDefinition(_, params, _, _, _, _) => ("the constructor".to_string(), named_params(params)),
+                _ => return,
+            };
+            params.into_iter().filter(|param| state.contains(param.name.as_str())).for_each(|param| {
+                diagnostics.push(Diagnostic::warning(self.code(), format!("parameter `{}` of {} shadows a state variable", param.name, function))
+                    .with_span(param.span)
+                    .with_note(format!("`{}` is declared as state variable `{}.{}`", param.name, contract.name, param.name)));
+            });
+        });
+    }
+}
+
+fn is_global(expr: &Expression, name: &str) -> bool {
+    matches!(expr, Expression::Global(global) if global == name)
+}
+
+/// Comparisons with `tx.origin`, which any contract the sender calls passes as well, so
+/// they don't show who is calling.
+pub struct TxOriginAuth;
+
+impl Lint for TxOriginAuth {
+    fn code(&self) -> &'static str {
+        "tx-origin"
+    }
+
+    fn check(&self, contract: &ContractSource, diagnostics: &mut Vec<Diagnostic>) {
+        /// The innermost statement around each comparison with `tx.origin`.
+        #[derive(Default)]
+        struct Comparisons {
+            statement: (usize, usize),
+            found: Vec<(usize, usize)>,
+        }
+        impl Visitor for Comparisons {
+            fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+                let outer = std::mem::replace(&mut self.statement, statement.span);
+                walk_statement(self, statement);
+                self.statement = outer;
+            }
+
+            fn visit_expression(&mut self, expr: &Expression) {
+                // Comparing it with `msg.sender` only checks that the caller isn't a contract.
+                if let Expression::Eq(lhs, _, rhs) | Expression::Ne(lhs, _, rhs) = expr {
+                    if (is_global(lhs, "tx.origin") || is_global(rhs, "tx.origin")) && !(is_global(lhs, "msg.sender") || is_global(rhs, "msg.sender")) {
+                        self.found.push(self.statement);
+                    }
+                }
+                walk_expression(self, expr);
+            }
+        }
+
+        contract.functions().into_iter().for_each(|(function, _, body)| {
+            let mut comparisons = Comparisons::default();
+            body.iter().for_each(|statement| comparisons.visit_statement(statement));
+            comparisons.found.into_iter().for_each(|span| {
+                diagnostics.push(Diagnostic::warning(self.code(), format!("{} authorizes with `tx.origin`", function))
+                    .with_span(span)
+                    .with_note("`tx.origin` is the account that started the transaction, even when a contract it called makes this call; compare `msg.sender` instead"));
+            });
+        });
+    }
+}
+
+/// Members that call another account without reverting when the call fails.
+const LOW_LEVEL_CALLS: &[&str] = &["call", "delegatecall", "staticcall", "send"];
+
+/// Low-level calls whose success flag is thrown away, so a failed call goes unnoticed.
+pub struct UncheckedLowLevelCall;
+
+impl Lint for UncheckedLowLevelCall {
+    fn code(&self) -> &'static str {
+        "unchecked-call"
+    }
+
+    fn check(&self, contract: &ContractSource, diagnostics: &mut Vec<Diagnostic>) {
+        #[derive(Default)]
+        struct Unchecked(Vec<(String, (usize, usize))>);
+        impl Visitor for Unchecked {
+            fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+                if let Statement::Expression(Expression::MemberCall(MemberAccess { member, .. }, _, _), _) = &statement.value {
+                    if LOW_LEVEL_CALLS.contains(&member.name.as_str()) {
+                        self.0.push((member.name.clone(), statement.span));
+                    }
+                }
+                walk_statement(self, statement);
+            }
+        }
+
+        contract.functions().into_iter().for_each(|(function, _, body)| {
+            let mut unchecked = Unchecked::default();
+            body.iter().for_each(|statement| unchecked.visit_statement(statement));
+            unchecked.0.into_iter().for_each(|(member, span)| {
+                diagnostics.push(Diagnostic::warning(self.code(), format!("the result of `{}` in {} is not checked", member, function))
+                    .with_span(span)
+                    .with_note(format!("`{}` returns false instead of reverting when the call fails; check it, e.g. with `require`", member)));
+            });
+        });
+    }
+}
+
+/// Writes to state variables after an external call in the same function, the pattern
+/// reentrancy exploits: the callee calls back in while the state is stale.
+pub struct StateWriteAfterCall;
+
+/// Walks a body in execution order, noting writes to state variables once an external
+/// call may have happened on the way there.
+struct Writes<'a> {
+    state: &'a HashSet<&'a str>,
+    params: HashSet<&'a str>,
+    called: bool,
+    statement: (usize, usize),
+    found: Vec<(String, (usize, usize))>,
+}
+
+impl Writes<'_> {
+    /// The state variable `place` is part of, unless a parameter hides it.
+    fn variable(&self, place: &Expression) -> Option<String> {
+        match place {
+            Expression::Variable(identifier) => Some(identifier.name.clone())
+                .filter(|name| self.state.contains(name.as_str()) && !self.params.contains(name.as_str())),
+            Expression::Index(base, _, _, _) | Expression::Member(MemberAccess { target: base, .. }) | Expression::Paren(_, base, _) => self.variable(base),
+            _ => None,
+        }
+    }
+}
+
+impl Visitor for Writes<'_> {
+    fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+        let outer = std::mem::replace(&mut self.statement, statement.span);
+        match &statement.value {
+            // Only one branch runs, so a call in one doesn't precede writes in the other.
+            Statement::If(_, _, cond, _, then, otherwise) => {
+                self.visit_expression(cond);
+                let before = self.called;
+                self.visit_statement(then);
+                let after_then = std::mem::replace(&mut self.called, before);
+                if let Some(Else::Else(_, otherwise)) = otherwise {
+                    self.visit_statement(otherwise);
+                }
+                self.called |= after_then;
+            },
+            _ => walk_statement(self, statement),
+        }
+        self.statement = outer;
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        // Operands run first, so the call in `sent = to.send(amount)` precedes the write.
+        walk_expression(self, expr);
+        match expr {
+            Expression::MemberCall(MemberAccess { target, .. }, _, _) if !matches!(&**target, Expression::Variable(identifier) if identifier.name == "abi") => self.called = true,
+            Expression::Assign(place, _, _) if self.called => if let Some(name) = self.variable(place) {
+                self.found.push((name, self.statement));
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Lint for StateWriteAfterCall {
+    fn code(&self) -> &'static str {
+        "reentrancy"
+    }
+
+    fn check(&self, contract: &ContractSource, diagnostics: &mut Vec<Diagnostic>) {
+        let state = contract.state_variables();
+        contract.functions().into_iter().for_each(|(function, params, body)| {
+            let params = named_params(params).into_iter().map(|param| param.name.as_str()).collect();
+            let mut writes = Writes { state: &state, params, called: false, statement: (0, 0), found: vec![] };
+            body.iter().for_each(|statement| writes.visit_statement(statement));
+            writes.found.into_iter().for_each(|(name, span)| {
+                diagnostics.push(Diagnostic::warning(self.code(), format!("state variable `{}` is written after an external call in {}", name, function))
+                    .with_span(span)
+                    .with_note(format!("the callee can call back into `{}` while `{}` still holds its old value; write state before making calls", contract.name, name)));
+            });
+        });
+    }
+}
+
+/// Every lint that `analyze` runs.
+pub fn default_lints() -> Vec<Box<dyn Lint>> {
+    vec![Box::new(ShadowedStateVariable), Box::new(TxOriginAuth), Box::new(UncheckedLowLevelCall), Box::new(StateWriteAfterCall)]
+}
+
+/// Runs `lints` over every contract in `source_unit`.
+pub fn analyze_with(source_unit: &SourceUnit, lints: &[Box<dyn Lint>]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    source_unit.parts.iter().for_each(|part| {
+        let SourceUnitPart::ContractDefinition(_, Identifier { name }, _, parts, _) = part;
+        let contract = ContractSource { name, parts };
+        lints.iter().for_each(|lint| lint.check(&contract, &mut diagnostics));
+    });
+    diagnostics
+}
+
+pub fn analyze(source_unit: &SourceUnit) -> Vec<Diagnostic> {
+    analyze_with(source_unit, &default_lints())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::flipper_source;
+
+    #[test]
+    fn test_shadowed_state_variable() {
+        let source = "contract Flag {
+    bool private value;
+    bool private other;
+    function set(bool value, bool fresh) public returns (bool other) { return !fresh; }
+}";
+        let diagnostics = analyze(&parse(source).unwrap());
+        assert_eq!(diagnostics.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(), vec![
+            "parameter `value` of function `set` shadows a state variable",
+            "parameter `other` of function `set` shadows a state variable",
+        ]);
+        let (start, end) = diagnostics[0].span.unwrap();
+        assert_eq!(&source[start..end], "value");
+        assert!(analyze(&parse(flipper_source()).unwrap()).is_empty());
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        analyze(&parse(source).unwrap()).into_iter().map(|d| format!("{}: {}", d.code, d.message)).collect()
+    }
+
+    #[test]
+    fn test_tx_origin_auth() {
+        let source = "contract Wallet {
+    address private owner;
+    function withdraw(address to) public { require(tx.origin == owner); to.transfer(1); }
+    function direct() public view returns (bool) { return tx.origin == msg.sender; }
+}";
+        assert_eq!(messages(source), ["tx-origin: function `withdraw` authorizes with `tx.origin`"]);
+        let diagnostics = analyze(&parse(source).unwrap());
+        let (start, end) = diagnostics[0].span.unwrap();
+        assert_eq!(&source[start..end], "require(tx.origin == owner);");
+    }
+
+    #[test]
+    fn test_unchecked_low_level_call() {
+        let source = r#"contract Payout {
+    function pay(address to) public { to.call{value: 1}(""); require(to.send(1)); to.transfer(1); }
+}"#;
+        assert_eq!(messages(source), ["unchecked-call: the result of `call` in function `pay` is not checked"]);
+    }
+
+    #[test]
+    fn test_state_write_after_call() {
+        let source = r#"contract Bank {
+    mapping(address => uint256) private balances;
+    bool private paid;
+    function withdraw() public {
+        require(msg.sender.send(balances[msg.sender]));
+        balances[msg.sender] = 0;
+    }
+    function settle(address to) public { balances[to] = 0; paid = to.send(1); }
+    function either(address to, bool early) public { if (early) { require(to.send(1)); } else { paid = true; } }
+}"#;
+        assert_eq!(messages(source), [
+            "reentrancy: state variable `balances` is written after an external call in function `withdraw`",
+            "reentrancy: state variable `paid` is written after an external call in function `settle`",
+        ]);
+    }
+}
@@ -0,0 +1,84 @@
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::bytecode::to_hex;
+use crate::tinyvm::Contract;
+
+/// Foundry-style artifact (`abi` plus `bytecode.object`), the shape alloy's `sol!`
+/// and ethers' `abigen!` both accept when pointed at a JSON file.
+pub fn alloy_artifact(contract: &Contract) -> serde_json::Value {
+    json!({
+        "abi": contract.abi().to_json(),
+        "bytecode": { "object": to_hex(&contract.bytecode()) },
+        "deployedBytecode": { "object": to_hex(&contract.deployed_bytecode()) },
+    })
+}
+
+/// Hardhat artifact (`hh-sol-artifact-1`) extended with the storage layout.
+pub fn hardhat_artifact(contract: &Contract, source_name: &str) -> serde_json::Value {
+    json!({
+        "_format": "hh-sol-artifact-1",
+        "contractName": contract.name,
+        "sourceName": source_name,
+        "abi": contract.abi().to_json(),
+        "bytecode": to_hex(&contract.bytecode()),
+        "deployedBytecode": to_hex(&contract.deployed_bytecode()),
+        "linkReferences": {},
+        "deployedLinkReferences": {},
+        "storageLayout": contract.storage_layout().to_json(&format!("{}:{}", source_name, contract.name)),
+    })
+}
+
+/// Writes `<out_dir>/<source file>/<Contract>.json` for every contract, like Hardhat does.
+pub fn write_hardhat_artifacts(contracts: &[Contract], source_name: &str, out_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let artifacts = contracts.iter().map(|contract| hardhat_artifact(contract, source_name)).collect::<Vec<_>>();
+    write_artifacts(&artifacts, source_name, out_dir)
+}
+
+/// Writes already built artifacts, named by their `contractName`.
+pub fn write_artifacts(artifacts: &[serde_json::Value], source_name: &str, out_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let file_name = Path::new(source_name).file_name().unwrap_or(source_name.as_ref());
+    let dir = out_dir.join(file_name);
+    fs::create_dir_all(&dir)?;
+
+    artifacts.iter().map(|artifact| {
+        let path = dir.join(format!("{}.json", artifact["contractName"].as_str().unwrap_or_default()));
+        fs::write(&path, serde_json::to_string_pretty(artifact)?)?;
+        Ok(path)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::parse;
+    use crate::test_utils::{flipper, flipper_source};
+    use crate::tinyvm::create_contracts;
+
+    #[test]
+    fn test_flipper_artifact() {
+        let contract = flipper();
+        let artifact = alloy_artifact(&contract);
+
+        let abi = artifact["abi"].as_array().unwrap();
+        assert_eq!(abi.len(), 2);
+        assert_eq!(abi[0]["name"], "flip");
+        assert_eq!(abi[1]["outputs"][0]["type"], "bool");
+        assert!(artifact["bytecode"]["object"].as_str().unwrap().starts_with("0x61"));
+    }
+
+    #[test]
+    fn test_write_hardhat_artifacts() {
+        let contracts = create_contracts(parse(flipper_source()).unwrap());
+        let out = std::env::temp_dir().join("tinysol_hardhat_artifacts");
+        let paths = write_hardhat_artifacts(&contracts, "contracts/flipper.sol", &out).unwrap();
+        assert_eq!(paths, vec![out.join("flipper.sol").join("flipper.json")]);
+
+        let artifact: serde_json::Value = serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+        assert_eq!(artifact["_format"], "hh-sol-artifact-1");
+        assert_eq!(artifact["contractName"], "flipper");
+        assert_eq!(artifact["storageLayout"]["storage"][0]["label"], "value");
+        fs::remove_dir_all(out).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+pub use ethnum::{I256, U256};
+use crate::abi::{AbiFunction, AbiParam, AbiType};
+use crate::solidity::grammar::Expression;
+use crate::tinyvm::Contract;
+use crate::world::CallResult;
+
+/// A return type generated bindings can convert a call's output into.
+pub trait FromOutput: Sized {
+    fn from_output(output: &[Expression]) -> Option<Self>;
+}
+
+impl FromOutput for () {
+    fn from_output(_output: &[Expression]) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl FromOutput for bool {
+    fn from_output(output: &[Expression]) -> Option<Self> {
+        match output {
+            [Expression::BoolLiteral(b)] => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromOutput for U256 {
+    fn from_output(output: &[Expression]) -> Option<Self> {
+        match output {
+            [Expression::NumberLiteral(n)] => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// Read back from the two's-complement word.
+impl FromOutput for I256 {
+    fn from_output(output: &[Expression]) -> Option<Self> {
+        match output {
+            [Expression::NumberLiteral(n)] => Some(n.as_i256()),
+            _ => None,
+        }
+    }
+}
+
+/// Returned as is for outputs the VM can't yet convert to native types.
+impl FromOutput for Vec<Expression> {
+    fn from_output(output: &[Expression]) -> Option<Self> {
+        Some(output.to_vec())
+    }
+}
+
+/// The output of a successful call as `T`. A failed call, or one whose output doesn't have
+/// the declared type, comes back as the error.
+pub fn decode_return<T: FromOutput>(result: CallResult) -> Result<T, Box<CallResult>> {
+    match result.success.then(|| T::from_output(&result.output)).flatten() {
+        Some(value) => Ok(value),
+        None => Err(Box::new(result)),
+    }
+}
+
+const KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+];
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    name.chars().enumerate().for_each(|(i, c)| {
+        if c.is_uppercase() && i > 0 && !snake.ends_with('_') {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    });
+    if KEYWORDS.contains(&snake.as_str()) {
+        format!("r#{}", snake)
+    } else {
+        snake
+    }
+}
+
+fn upper_camel_case(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// Name of the struct `rust_bindings` generates for `contract`.
+pub fn binding_name(contract: &Contract) -> String {
+    upper_camel_case(&contract.name)
+}
+
+/// Rust parameter type and the expression wrapping `arg` into an `abi::Value`.
+fn param_type(ty: &str, arg: &str) -> Option<(&'static str, String)> {
+    Some(match AbiType::parse(ty)? {
+        AbiType::Bool => ("bool", format!("::tinysol::abi::Value::Bool({})", arg)),
+        AbiType::Uint(_) => ("::tinysol::bindings::U256", format!("::tinysol::abi::Value::Uint({})", arg)),
+        AbiType::Int(_) => ("::tinysol::bindings::I256", format!("::tinysol::abi::Value::Int({})", arg)),
+        AbiType::Address => ("::tinysol::env::Address", format!("::tinysol::abi::Value::Address({})", arg)),
+        AbiType::String => ("&str", format!("::tinysol::abi::Value::String({}.to_string())", arg)),
+        AbiType::Bytes => ("&[u8]", format!("::tinysol::abi::Value::Bytes({}.to_vec())", arg)),
+    })
+}
+
+fn return_type(outputs: &[AbiParam]) -> &'static str {
+    match outputs {
+        [] => "()",
+        [output] if output.ty == "bool" => "bool",
+        [output] if output.ty == "uint256" => "::tinysol::bindings::U256",
+        [output] if output.ty == "int256" => "::tinysol::bindings::I256",
+        _ => "Vec<::tinysol::solidity::grammar::Expression>",
+    }
+}
+
+fn method(function: &AbiFunction, name: &str) -> String {
+    let payable = function.state_mutability == "payable";
+    let view = matches!(function.state_mutability.as_str(), "view" | "pure");
+    let args = function.inputs.iter().enumerate()
+        .map(|(i, input)| if input.name.is_empty() { format!("arg{}", i) } else { snake_case(&input.name) })
+        .collect::<Vec<_>>();
+
+    let mut params = vec![if view { "&self".to_string() } else { "&mut self".to_string() }];
+    if payable {
+        params.push("value: ::tinysol::bindings::U256".to_string());
+    }
+    let values = function.inputs.iter().zip(&args).map(|(input, arg)| {
+        let (ty, value) = param_type(&input.ty, arg)?;
+        params.push(format!("{}: {}", arg, ty));
+        Some(value)
+    }).collect::<Option<Vec<_>>>();
+    let Some(values) = values else {
+        return format!("    // `{}` skipped: unsupported parameter types\n", function.signature());
+    };
+
+    let transaction = format!(
+        "::tinysol::world::Transaction {{ {}, ..::tinysol::world::Transaction::new(self.from, self.address, &::tinysol::abi::calldata({:?}, &[{}])) }}",
+        if payable { "value" } else { "value: ::tinysol::bindings::U256::ZERO" }, function.signature(), values.join(", "),
+    );
+    let world = if view { "self.world.clone()" } else { "self.world" };
+    format!(
+        "    /// `{}`\n    pub fn {}({}) -> Result<{}, Box<::tinysol::world::CallResult>> {{\n        ::tinysol::bindings::decode_return({}.apply_transaction(&{}))\n    }}\n",
+        function.signature(), name, params.join(", "), return_type(&function.outputs), world, transaction,
+    )
+}
+
+/// Rust source for a typed wrapper around a deployed `contract`: a struct holding the world,
+/// the contract's address and the sender, with one method per public function that
+/// ABI-encodes its native arguments, calls the VM and converts the output back. View
+/// functions run on a copy of the world. Overloads after the first get a numeric suffix, and
+/// functions with parameter types the ABI can't name yet are left out.
+///
+/// Paths are absolute, so the items can be pasted into any module, as `contract!` does.
+pub fn rust_bindings(contract: &Contract) -> String {
+    let name = binding_name(contract);
+    let abi = contract.abi();
+    let mut names = Vec::<String>::new();
+    let methods = abi.functions.iter().map(|function| {
+        let base = snake_case(&function.name);
+        let overloads = names.iter().filter(|name| **name == base).count();
+        names.push(base.clone());
+        method(function, &if overloads == 0 { base } else { format!("{}_{}", base, overloads) })
+    }).collect::<Vec<_>>();
+
+    format!(
+        "// Generated by tinysol {} from contract `{}`. Do not edit.
+pub struct {name}<'w> {{
+    pub world: &'w mut ::tinysol::world::World,
+    pub address: ::tinysol::env::Address,
+    /// Sender of every call.
+    pub from: ::tinysol::env::Address,
+}}
+
+impl<'w> {name}<'w> {{
+    pub fn new(world: &'w mut ::tinysol::world::World, address: ::tinysol::env::Address, from: ::tinysol::env::Address) -> Self {{
+        Self {{ world, address, from }}
+    }}
+
+{}}}
+",
+        env!("CARGO_PKG_VERSION"), contract.name, methods.join("\n"), name = name,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::flipper;
+
+    #[test]
+    fn test_flipper_bindings() {
+        let bindings = rust_bindings(&flipper());
+
+        assert!(bindings.contains("pub struct Flipper<'w> {"));
+        assert!(bindings.contains("pub fn flip(&mut self) -> Result<(), Box<::tinysol::world::CallResult>> {"));
+        assert!(bindings.contains("pub fn get(&self) -> Result<bool, Box<::tinysol::world::CallResult>> {"));
+        assert!(bindings.contains("::tinysol::abi::calldata(\"get()\", &[])"));
+    }
+
+    #[test]
+    fn test_names_and_types() {
+        assert_eq!(snake_case("balanceOf"), "balance_of");
+        assert_eq!(snake_case("type"), "r#type");
+        assert_eq!(param_type("uint8", "amount"), Some(("::tinysol::bindings::U256", "::tinysol::abi::Value::Uint(amount)".to_string())));
+        assert_eq!(param_type("", "amount"), None);
+        assert_eq!(decode_return::<bool>(CallResult::success(vec![Expression::BoolLiteral(true)])), Ok(true));
+        assert!(decode_return::<bool>(CallResult::success(vec![])).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use ethnum::U256;
+use keccak_hash::keccak;
+use crate::log::Bloom;
+use crate::rlp::Rlp;
+use crate::trie::trie_root;
+
+/// A block of the simulated chain, sealed by `World::mine`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub number: u64,
+    pub timestamp: u64,
+    pub parent_hash: [u8; 32],
+    /// State root after the block's transactions.
+    pub state_root: [u8; 32],
+    /// Hashes of the transactions included, in order.
+    pub transactions: Vec<[u8; 32]>,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub base_fee: U256,
+    /// Randomness the block exposed to `PREVRANDAO`, the header's `mixHash`.
+    pub prevrandao: U256,
+    /// Union of the blooms of the block's receipts.
+    pub logs_bloom: Bloom,
+}
+
+impl Block {
+    /// Root of the trie mapping each transaction's RLP-encoded index to its hash.
+    pub fn transactions_root(&self) -> [u8; 32] {
+        let entries = self.transactions.iter().enumerate()
+            .map(|(i, hash)| (Rlp::uint(U256::from(i as u64)).encode(), Rlp::Bytes(hash.to_vec()).encode()))
+            .collect::<Vec<_>>();
+        trie_root(&entries)
+    }
+
+    /// keccak256 of the RLP-encoded header. Only the fields the simulation tracks are
+    /// hashed, so hashes identify simulated blocks but don't match any real chain's.
+    pub fn hash(&self) -> [u8; 32] {
+        let header = Rlp::List(vec![
+            Rlp::Bytes(self.parent_hash.to_vec()),
+            Rlp::Bytes(self.state_root.to_vec()),
+            Rlp::Bytes(self.transactions_root().to_vec()),
+            Rlp::Bytes(self.logs_bloom.0.to_vec()),
+            Rlp::uint(U256::from(self.number)),
+            Rlp::uint(U256::from(self.timestamp)),
+            Rlp::uint(U256::from(self.gas_used)),
+            Rlp::uint(U256::from(self.gas_limit)),
+            Rlp::uint(self.base_fee),
+            Rlp::uint(self.prevrandao),
+        ]);
+        keccak(header.encode()).into()
+    }
+
+    /// Base fee of the next block under EIP-1559: unchanged at half the gas limit, and
+    /// moving by up to 1/8 towards keeping blocks half full otherwise. A zero base fee
+    /// means the fee market is off, and stays zero.
+    pub fn next_base_fee(&self) -> U256 {
+        let target = U256::from(self.gas_limit / 2);
+        let used = U256::from(self.gas_used);
+        if self.base_fee == U256::ZERO || target == U256::ZERO || used == target {
+            self.base_fee
+        } else if used > target {
+            self.base_fee + (self.base_fee * (used - target) / target / 8).max(U256::ONE)
+        } else {
+            self.base_fee - self.base_fee * (target - used) / target / 8
+        }
+    }
+}
@@ -0,0 +1,587 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use keccak_hash::keccak;
+use crate::host::HOST_OPCODES;
+use crate::tinyvm::*;
+
+// Raw EVM opcodes used by the dispatcher and init code.
+const REVERT: u8 = 0xfd;
+
+const DUP_NAMES: [&str; 17] = [
+    "", "DUP1", "DUP2", "DUP3", "DUP4", "DUP5", "DUP6", "DUP7", "DUP8", "DUP9", "DUP10", "DUP11", "DUP12",
+    "DUP13", "DUP14", "DUP15", "DUP16",
+];
+
+const SWAP_NAMES: [&str; 17] = [
+    "", "SWAP1", "SWAP2", "SWAP3", "SWAP4", "SWAP5", "SWAP6", "SWAP7", "SWAP8", "SWAP9", "SWAP10", "SWAP11",
+    "SWAP12", "SWAP13", "SWAP14", "SWAP15", "SWAP16",
+];
+
+const HOST_NAMES: [&str; 16] = [
+    "HOST0", "HOST1", "HOST2", "HOST3", "HOST4", "HOST5", "HOST6", "HOST7", "HOST8", "HOST9", "HOST10",
+    "HOST11", "HOST12", "HOST13", "HOST14", "HOST15",
+];
+
+const PUSH_NAMES: [&str; 33] = [
+    "PUSH0", "PUSH1", "PUSH2", "PUSH3", "PUSH4", "PUSH5", "PUSH6", "PUSH7", "PUSH8", "PUSH9", "PUSH10",
+    "PUSH11", "PUSH12", "PUSH13", "PUSH14", "PUSH15", "PUSH16", "PUSH17", "PUSH18", "PUSH19", "PUSH20",
+    "PUSH21", "PUSH22", "PUSH23", "PUSH24", "PUSH25", "PUSH26", "PUSH27", "PUSH28", "PUSH29", "PUSH30",
+    "PUSH31", "PUSH32",
+];
+
+impl OP {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            OP::PUSH0 => 0x5f,
+            OP::PUSH1(_) => 0x60,
+            OP::PUSHN(n, _) => 0x5f + n,
+            OP::PUSH32(_) => 0x7f,
+            OP::POP => 0x50,
+            OP::DUP1 => 0x80,
+            OP::SWAP1 => 0x90,
+            OP::DUP(n) => 0x7f + n,
+            OP::SWAP(n) => 0x8f + n,
+            OP::CALLDATALOAD => 0x35,
+            OP::CALLDATASIZE => 0x36,
+            OP::HOST(n) => HOST_OPCODES.start() + n,
+            OP::ADDRESS => 0x30,
+            OP::BALANCE => 0x31,
+            OP::SELFBALANCE => 0x47,
+            OP::ORIGIN => 0x32,
+            OP::CALLER => 0x33,
+            OP::CALLVALUE => 0x34,
+            OP::GASPRICE => 0x3a,
+            OP::CALLDATACOPY => 0x37,
+            OP::CODESIZE => 0x38,
+            OP::CODECOPY => 0x39,
+            OP::EXTCODESIZE => 0x3b,
+            OP::EXTCODECOPY => 0x3c,
+            OP::RETURNDATASIZE => 0x3d,
+            OP::RETURNDATACOPY => 0x3e,
+            OP::EXTCODEHASH => 0x3f,
+            OP::SLOAD => 0x54,
+            OP::SSTORE => 0x55,
+            OP::TLOAD => 0x5c,
+            OP::TSTORE => 0x5d,
+            OP::MLOAD => 0x51,
+            OP::MSTORE => 0x52,
+            OP::MSTORE8 => 0x53,
+            OP::MSIZE => 0x59,
+            OP::MCOPY => 0x5e,
+            OP::ISZERO => 0x15,
+            OP::ADD => 0x01,
+            OP::MUL => 0x02,
+            OP::SUB => 0x03,
+            OP::DIV => 0x04,
+            OP::MOD => 0x06,
+            OP::ADDMOD => 0x08,
+            OP::MULMOD => 0x09,
+            OP::EXP => 0x0a,
+            OP::SDIV => 0x05,
+            OP::SMOD => 0x07,
+            OP::SIGNEXTEND => 0x0b,
+            OP::LT => 0x10,
+            OP::GT => 0x11,
+            OP::SLT => 0x12,
+            OP::SGT => 0x13,
+            OP::EQ => 0x14,
+            OP::AND => 0x16,
+            OP::OR => 0x17,
+            OP::XOR => 0x18,
+            OP::NOT => 0x19,
+            OP::BYTE => 0x1a,
+            OP::SHL => 0x1b,
+            OP::SHR => 0x1c,
+            OP::SAR => 0x1d,
+            OP::KECCAK256 => 0x20,
+            OP::BLOCKHASH => 0x40,
+            OP::COINBASE => 0x41,
+            OP::TIMESTAMP => 0x42,
+            OP::NUMBER => 0x43,
+            OP::PREVRANDAO => 0x44,
+            OP::GASLIMIT => 0x45,
+            OP::CHAINID => 0x46,
+            OP::BASEFEE => 0x48,
+            OP::JUMP => 0x56,
+            OP::JUMPI => 0x57,
+            OP::JUMPDEST => 0x5b,
+            OP::PC => 0x58,
+            OP::GAS => 0x5a,
+            OP::LOG0 => 0xa0,
+            OP::LOG1 => 0xa1,
+            OP::LOG2 => 0xa2,
+            OP::LOG3 => 0xa3,
+            OP::LOG4 => 0xa4,
+            OP::CREATE => 0xf0,
+            OP::CALL => 0xf1,
+            OP::CALLCODE => 0xf2,
+            OP::DELEGATECALL => 0xf4,
+            OP::STATICCALL => 0xfa,
+            OP::CREATE2 => 0xf5,
+            OP::RETURN => 0xf3,
+            OP::REVERT => 0xfd,
+            OP::SELFDESTRUCT => 0xff,
+            OP::STOP => 0x00,
+            OP::INVALID => 0xfe,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OP::PUSH0 => "PUSH0",
+            OP::PUSH1(_) => "PUSH1",
+            OP::PUSHN(n, _) => PUSH_NAMES[*n as usize],
+            OP::PUSH32(_) => "PUSH32",
+            OP::POP => "POP",
+            OP::DUP1 => "DUP1",
+            OP::SWAP1 => "SWAP1",
+            OP::DUP(n) => DUP_NAMES[*n as usize],
+            OP::SWAP(n) => SWAP_NAMES[*n as usize],
+            OP::CALLDATALOAD => "CALLDATALOAD",
+            OP::CALLDATASIZE => "CALLDATASIZE",
+            OP::HOST(n) => HOST_NAMES[*n as usize],
+            OP::ADDRESS => "ADDRESS",
+            OP::BALANCE => "BALANCE",
+            OP::SELFBALANCE => "SELFBALANCE",
+            OP::ORIGIN => "ORIGIN",
+            OP::CALLER => "CALLER",
+            OP::CALLVALUE => "CALLVALUE",
+            OP::GASPRICE => "GASPRICE",
+            OP::CALLDATACOPY => "CALLDATACOPY",
+            OP::CODESIZE => "CODESIZE",
+            OP::CODECOPY => "CODECOPY",
+            OP::EXTCODESIZE => "EXTCODESIZE",
+            OP::EXTCODECOPY => "EXTCODECOPY",
+            OP::RETURNDATASIZE => "RETURNDATASIZE",
+            OP::RETURNDATACOPY => "RETURNDATACOPY",
+            OP::EXTCODEHASH => "EXTCODEHASH",
+            OP::SLOAD => "SLOAD",
+            OP::SSTORE => "SSTORE",
+            OP::TLOAD => "TLOAD",
+            OP::TSTORE => "TSTORE",
+            OP::MLOAD => "MLOAD",
+            OP::MSTORE => "MSTORE",
+            OP::MSTORE8 => "MSTORE8",
+            OP::MSIZE => "MSIZE",
+            OP::MCOPY => "MCOPY",
+            OP::ISZERO => "ISZERO",
+            OP::ADD => "ADD",
+            OP::MUL => "MUL",
+            OP::SUB => "SUB",
+            OP::DIV => "DIV",
+            OP::MOD => "MOD",
+            OP::ADDMOD => "ADDMOD",
+            OP::MULMOD => "MULMOD",
+            OP::EXP => "EXP",
+            OP::SDIV => "SDIV",
+            OP::SMOD => "SMOD",
+            OP::SIGNEXTEND => "SIGNEXTEND",
+            OP::LT => "LT",
+            OP::GT => "GT",
+            OP::SLT => "SLT",
+            OP::SGT => "SGT",
+            OP::EQ => "EQ",
+            OP::AND => "AND",
+            OP::OR => "OR",
+            OP::XOR => "XOR",
+            OP::NOT => "NOT",
+            OP::BYTE => "BYTE",
+            OP::SHL => "SHL",
+            OP::SHR => "SHR",
+            OP::SAR => "SAR",
+            OP::KECCAK256 => "KECCAK256",
+            OP::BLOCKHASH => "BLOCKHASH",
+            OP::COINBASE => "COINBASE",
+            OP::TIMESTAMP => "TIMESTAMP",
+            OP::NUMBER => "NUMBER",
+            OP::PREVRANDAO => "PREVRANDAO",
+            OP::GASLIMIT => "GASLIMIT",
+            OP::CHAINID => "CHAINID",
+            OP::BASEFEE => "BASEFEE",
+            OP::JUMP => "JUMP",
+            OP::JUMPI => "JUMPI",
+            OP::JUMPDEST => "JUMPDEST",
+            OP::PC => "PC",
+            OP::GAS => "GAS",
+            OP::LOG0 => "LOG0",
+            OP::LOG1 => "LOG1",
+            OP::LOG2 => "LOG2",
+            OP::LOG3 => "LOG3",
+            OP::LOG4 => "LOG4",
+            OP::CREATE => "CREATE",
+            OP::CALL => "CALL",
+            OP::CALLCODE => "CALLCODE",
+            OP::DELEGATECALL => "DELEGATECALL",
+            OP::STATICCALL => "STATICCALL",
+            OP::CREATE2 => "CREATE2",
+            OP::RETURN => "RETURN",
+            OP::REVERT => "REVERT",
+            OP::SELFDESTRUCT => "SELFDESTRUCT",
+            OP::STOP => "STOP",
+            OP::INVALID => "INVALID",
+        }
+    }
+
+    /// Size of the encoded instruction, immediates included.
+    pub fn size(&self) -> usize {
+        match self {
+            OP::PUSH1(_) => 2,
+            OP::PUSHN(n, _) => 1 + *n as usize,
+            OP::PUSH32(_) => 33,
+            _ => 1,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            OP::PUSH1(value) => vec![self.opcode(), *value],
+            OP::PUSHN(n, value) => [vec![self.opcode()], value.to_be_bytes()[32 - *n as usize..].to_vec()].concat(),
+            OP::PUSH32(word) => [vec![self.opcode()], word.to_be_bytes().to_vec()].concat(),
+            _ => vec![self.opcode()],
+        }
+    }
+}
+
+/// Assembly text, e.g. `PUSH1 0x01`.
+impl std::fmt::Display for OP {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OP::PUSH1(value) => write!(f, "{} {:#04x}", self.name(), value),
+            OP::PUSHN(_, word) | OP::PUSH32(word) => write!(f, "{} {:#x}", self.name(), word),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+pub fn assemble(program: &[OP]) -> Vec<u8> {
+    program.iter().flat_map(|op| op.encode()).collect()
+}
+
+/// Inverse of `assemble`. Fails on opcodes the VM does not implement and on truncated immediates.
+pub fn disassemble(code: &[u8]) -> Option<Vec<OP>> {
+    let mut program = vec![];
+    let mut i = 0;
+    while i < code.len() {
+        let op = match code[i] {
+            0x5f => OP::PUSH0,
+            0x60 => OP::PUSH1(*code.get(i + 1)?),
+            0x61..=0x7e => {
+                let n = code[i] - 0x5f;
+                let mut word = [0; 32];
+                word[32 - n as usize..].copy_from_slice(code.get(i + 1..i + 1 + n as usize)?);
+                OP::PUSHN(n, U256::from_be_bytes(word))
+            },
+            0x7f => OP::PUSH32(U256::from_be_bytes(code.get(i + 1..i + 33)?.try_into().ok()?)),
+            0x50 => OP::POP,
+            0x80 => OP::DUP1,
+            0x90 => OP::SWAP1,
+            0x81..=0x8f => OP::DUP(code[i] - 0x7f),
+            0x91..=0x9f => OP::SWAP(code[i] - 0x8f),
+            0x35 => OP::CALLDATALOAD,
+            0x36 => OP::CALLDATASIZE,
+            0xc0..=0xcf => OP::HOST(code[i] - HOST_OPCODES.start()),
+            0x30 => OP::ADDRESS,
+            0x31 => OP::BALANCE,
+            0x47 => OP::SELFBALANCE,
+            0x32 => OP::ORIGIN,
+            0x33 => OP::CALLER,
+            0x34 => OP::CALLVALUE,
+            0x3a => OP::GASPRICE,
+            0x37 => OP::CALLDATACOPY,
+            0x38 => OP::CODESIZE,
+            0x39 => OP::CODECOPY,
+            0x3b => OP::EXTCODESIZE,
+            0x3c => OP::EXTCODECOPY,
+            0x3d => OP::RETURNDATASIZE,
+            0x3e => OP::RETURNDATACOPY,
+            0x3f => OP::EXTCODEHASH,
+            0x54 => OP::SLOAD,
+            0x55 => OP::SSTORE,
+            0x5c => OP::TLOAD,
+            0x5d => OP::TSTORE,
+            0x51 => OP::MLOAD,
+            0x52 => OP::MSTORE,
+            0x53 => OP::MSTORE8,
+            0x59 => OP::MSIZE,
+            0x5e => OP::MCOPY,
+            0x15 => OP::ISZERO,
+            0x01 => OP::ADD,
+            0x02 => OP::MUL,
+            0x03 => OP::SUB,
+            0x04 => OP::DIV,
+            0x06 => OP::MOD,
+            0x08 => OP::ADDMOD,
+            0x09 => OP::MULMOD,
+            0x0a => OP::EXP,
+            0x05 => OP::SDIV,
+            0x07 => OP::SMOD,
+            0x0b => OP::SIGNEXTEND,
+            0x10 => OP::LT,
+            0x11 => OP::GT,
+            0x12 => OP::SLT,
+            0x13 => OP::SGT,
+            0x14 => OP::EQ,
+            0x16 => OP::AND,
+            0x17 => OP::OR,
+            0x18 => OP::XOR,
+            0x19 => OP::NOT,
+            0x1a => OP::BYTE,
+            0x1b => OP::SHL,
+            0x1c => OP::SHR,
+            0x1d => OP::SAR,
+            0x20 => OP::KECCAK256,
+            0x40 => OP::BLOCKHASH,
+            0x41 => OP::COINBASE,
+            0x42 => OP::TIMESTAMP,
+            0x43 => OP::NUMBER,
+            0x44 => OP::PREVRANDAO,
+            0x45 => OP::GASLIMIT,
+            0x46 => OP::CHAINID,
+            0x48 => OP::BASEFEE,
+            0x56 => OP::JUMP,
+            0x57 => OP::JUMPI,
+            0x5b => OP::JUMPDEST,
+            0x58 => OP::PC,
+            0x5a => OP::GAS,
+            0xa0 => OP::LOG0,
+            0xa1 => OP::LOG1,
+            0xa2 => OP::LOG2,
+            0xa3 => OP::LOG3,
+            0xa4 => OP::LOG4,
+            0xf0 => OP::CREATE,
+            0xf1 => OP::CALL,
+            0xf2 => OP::CALLCODE,
+            0xf4 => OP::DELEGATECALL,
+            0xfa => OP::STATICCALL,
+            0xf5 => OP::CREATE2,
+            0xf3 => OP::RETURN,
+            0xfd => OP::REVERT,
+            0xff => OP::SELFDESTRUCT,
+            0x00 => OP::STOP,
+            0xfe => OP::INVALID,
+            _ => return None,
+        };
+        i += op.size();
+        program.push(op);
+    }
+    Some(program)
+}
+
+/// Byte offset of every instruction in the assembled program.
+pub fn code_offsets(program: &[OP]) -> Vec<usize> {
+    program.iter().scan(0, |offset, op| {
+        let at = *offset;
+        *offset += op.size();
+        Some(at)
+    }).collect()
+}
+
+/// Whether the instruction at `i` pushes the target of the jump right after it.
+fn is_jump_target(program: &[OP], i: usize) -> bool {
+    program[i].push_value().is_some() && matches!(program.get(i + 1), Some(OP::JUMP | OP::JUMPI))
+}
+
+/// Assembles a function placed at byte offset `start`. Like solc, functions that aren't
+/// `payable` first revert if the call carries value.
+///
+/// Jump targets in the program are offsets into the function's own code, so pushes that
+/// feed a jump are relocated to where the target lands in the contract, as `PUSH2`.
+fn assemble_function(function: &Function, start: usize) -> Vec<u8> {
+    let mut code = vec![OP::JUMPDEST.opcode(), OP::POP.opcode()];
+    if !matches!(function.mutability, FuncMutability::Payable) {
+        let dest = ((start + 12) as u16).to_be_bytes();
+        code.extend_from_slice(&[OP::CALLVALUE.opcode(), OP::ISZERO.opcode(), OP::PUSHN(2, U256::ZERO).opcode(), dest[0], dest[1], OP::JUMPI.opcode(), 0x60, 0x00, OP::DUP1.opcode(), REVERT, OP::JUMPDEST.opcode()]);
+    }
+    let program = function.program();
+    let sizes = program.iter().enumerate().map(|(i, op)| if is_jump_target(program, i) { 3 } else { op.size() });
+    let relocated = sizes.scan(start + code.len(), |offset, size| {
+        let at = *offset;
+        *offset += size;
+        Some(at)
+    }).collect::<Vec<_>>();
+    // A target that isn't an instruction stays invalid, past the end of the code.
+    let offsets = code_offsets(program);
+    let relocate = |target: U256| {
+        let dest = usize::try_from(target).ok()
+            .and_then(|target| offsets.binary_search(&target).ok())
+            .map_or(u16::MAX, |index| relocated[index] as u16);
+        OP::PUSHN(2, U256::from(dest)).encode()
+    };
+    program.iter().enumerate().for_each(|(i, op)| match op.push_value() {
+        Some(target) if is_jump_target(program, i) => code.extend(relocate(target)),
+        _ => code.extend(op.encode()),
+    });
+    code.push(OP::STOP.opcode());
+    code
+}
+
+impl Contract {
+    /// Runtime code: a selector dispatcher followed by one block per function. A contract
+    /// that is raw code, as `CREATE` or a `StateDB` deploys it, is its fallback's code.
+    pub fn deployed_bytecode(&self) -> Vec<u8> {
+        let mut code = match &self.fallback {
+            Some(fallback) if self.functions.is_empty() => assemble(fallback.program()),
+            _ => self.dispatcher(),
+        };
+        if let Some(metadata) = &self.metadata {
+            code.extend(metadata.encode());
+        }
+        code
+    }
+
+    /// A selector dispatcher followed by one block per function.
+    fn dispatcher(&self) -> Vec<u8> {
+        let functions = self.functions.iter().collect::<Vec<_>>();
+
+        let header_len = 6 + functions.len() * 11 + 4;
+        let bodies = functions.iter().scan(header_len, |start, (_, function)| {
+            let body = assemble_function(function, *start);
+            *start += body.len();
+            Some(body)
+        }).collect::<Vec<_>>();
+
+        let mut code = vec![0x60, 0x00, OP::CALLDATALOAD.opcode(), 0x60, 0xe0, OP::SHR.opcode()];
+        let mut dest = header_len;
+        functions.iter().zip(bodies.iter()).for_each(|((selector, _), body)| {
+            code.push(OP::DUP1.opcode());
+            code.extend(OP::PUSHN(4, U256::from(u32::from_be_bytes(**selector))).encode());
+            code.push(OP::EQ.opcode());
+            code.extend(OP::PUSHN(2, U256::from(dest as u16)).encode());
+            code.push(OP::JUMPI.opcode());
+            dest += body.len();
+        });
+        code.extend_from_slice(&[0x60, 0x00, OP::DUP1.opcode(), REVERT]);
+        bodies.into_iter().for_each(|body| code.extend(body));
+        code
+    }
+
+    /// keccak256 of the runtime code, as `EXTCODEHASH` reports it once deployed.
+    pub fn code_hash(&self) -> [u8; 32] {
+        keccak(self.deployed_bytecode()).into()
+    }
+
+    /// Init code that copies the runtime code into memory and returns it. The constructor
+    /// isn't part of it, so storage it would set starts out zero; see `Contract::construct`.
+    pub fn bytecode(&self) -> Vec<u8> {
+        let runtime = self.deployed_bytecode();
+        let len = U256::from(runtime.len() as u16);
+        [
+            assemble(&[OP::PUSHN(2, len), OP::DUP1, OP::PUSHN(2, U256::from(13u8)), OP::PUSH1(0)]),
+            assemble(&[OP::CODECOPY, OP::PUSH1(0), OP::RETURN]),
+            runtime,
+        ].concat()
+    }
+
+    /// The contract as a node would run it: a `fallback` running the runtime code, selector
+    /// dispatch included, on the same storage. Metadata isn't code, so it's left out.
+    /// Contracts without functions, such as those `CREATE` deploys, already are raw code,
+    /// and so is a contract whose code can't be read back; both are returned as they are.
+    pub fn as_deployed(&self) -> Contract {
+        let code = Contract { metadata: None, ..self.clone() }.deployed_bytecode();
+        match disassemble(&code).filter(|_| !self.functions.is_empty()) {
+            Some(program) => {
+                let mut fallback = Function::from_program("fallback".to_string(), program);
+                fallback.mutability = FuncMutability::Payable;
+                Contract { functions: BTreeMap::new(), receive: None, fallback: Some(fallback), ..self.clone() }
+            },
+            None => self.clone(),
+        }
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim().trim_start_matches("0x");
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::parse;
+    use crate::test_utils::flipper;
+
+    #[test]
+    fn test_assemble_ops() {
+        let program = vec![OP::PUSH1(0), OP::SLOAD, OP::ISZERO, OP::PUSH32(U256::ONE)];
+        let code = assemble(&program);
+        assert_eq!(&code[..4], &[0x60, 0x00, 0x54, 0x15]);
+        assert_eq!(code.len(), 4 + 33);
+        assert_eq!(disassemble(&code), Some(program));
+        assert!(disassemble(&[0x7f, 0x00]).is_none());
+    }
+
+    #[test]
+    fn test_push_widths() {
+        let values = [U256::ZERO, U256::from(0xffu8), U256::from(0x100u16), U256::from(u64::MAX), U256::MAX];
+        let program = values.iter().map(|value| OP::push(*value)).collect::<Vec<_>>();
+        assert_eq!(program[..3], [OP::PUSH0, OP::PUSH1(0xff), OP::PUSHN(2, U256::from(0x100u16))]);
+        assert_eq!(program[3..], [OP::PUSHN(8, U256::from(u64::MAX)), OP::PUSH32(U256::MAX)]);
+        assert_eq!(program.iter().map(|op| op.push_value()).collect::<Vec<_>>(), values.map(Some));
+
+        let code = assemble(&program);
+        assert_eq!(&code[..6], &[0x5f, 0x60, 0xff, 0x61, 0x01, 0x00]);
+        assert_eq!(code[6], 0x67);
+        assert_eq!(disassemble(&code), Some(program));
+        assert_eq!(OP::PUSHN(3, U256::from(7u8)).to_string(), "PUSH3 0x7");
+        assert!(disassemble(&[0x62, 0x00, 0x01]).is_none());
+    }
+
+    #[test]
+    fn test_flipper_bytecode() {
+        let contract = flipper();
+        let runtime = contract.deployed_bytecode();
+        let init = contract.bytecode();
+
+        assert_eq!(&init[13..], runtime.as_slice());
+        assert_eq!(u16::from_be_bytes([init[1], init[2]]) as usize, runtime.len());
+
+        // Every jump target of the dispatcher is a JUMPDEST, and so is the target of each
+        // function's call value check.
+        (0..contract.functions.len()).for_each(|i| {
+            let at = 6 + i * 11 + 8;
+            let dest = u16::from_be_bytes([runtime[at], runtime[at + 1]]) as usize;
+            assert_eq!(runtime[dest], OP::JUMPDEST.opcode());
+            assert_eq!(runtime[dest + 2], OP::CALLVALUE.opcode());
+            let check = u16::from_be_bytes([runtime[dest + 5], runtime[dest + 6]]) as usize;
+            assert_eq!((runtime[check], check), (OP::JUMPDEST.opcode(), dest + 12));
+        });
+    }
+
+    #[test]
+    fn test_jumps_are_relocated() {
+        let mut function = Function::from_program("f".to_string(), vec![OP::PUSH1(4), OP::JUMP, OP::JUMPDEST, OP::JUMPDEST]);
+        function.mutability = FuncMutability::Payable;
+        let code = assemble_function(&function, 100);
+        // The entry JUMPDEST and POP come first, then the relocated PUSH2 and the JUMP, so
+        // the second JUMPDEST moves from offset 4 to 100 + 7.
+        assert_eq!(&code[2..6], &[OP::PUSHN(2, U256::ZERO).opcode(), 0, 107, OP::JUMP.opcode()]);
+        assert_eq!(code[107 - 100], OP::JUMPDEST.opcode());
+    }
+
+    #[test]
+    fn test_compilation_is_reproducible() {
+        let source = "contract Many {
+    bool private a;
+    bool private b;
+    bool private c;
+    function d() public view returns (bool) { return a; }
+    function c2() public { c = !b; }
+    function b2() public { b = !a; }
+    function a2() public { a = !c; }
+}";
+        let build = || {
+            let contract = create_contracts(parse(source).unwrap()).remove(0);
+            let artifact = crate::artifact::hardhat_artifact(&contract, "Many.sol").to_string();
+            (contract.code_hash(), contract.bytecode(), artifact)
+        };
+        let first = build();
+        (0..10).for_each(|_| assert_eq!(build(), first));
+    }
+}
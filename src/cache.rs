@@ -0,0 +1,64 @@
+use keccak_hash::keccak;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::settings::CompilerSettings;
+
+/// On-disk cache of compiled artifacts, one JSON file per source file build. Entries are
+/// keyed by everything that affects the output, so a stale entry is never hit and old ones
+/// can simply be deleted.
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    /// Hash of the compiler version, settings, source name and source text.
+    pub fn key(source_name: &str, source: &str, settings: &CompilerSettings) -> String {
+        let preimage = format!("{}\n{:?}\n{}\n{}", env!("CARGO_PKG_VERSION"), settings, source_name, source);
+        keccak(preimage.as_bytes()).0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// The artifacts stored under `key`, if any. Unreadable entries count as misses.
+    pub fn get(&self, key: &str) -> Option<Vec<serde_json::Value>> {
+        let json = fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn insert(&self, key: &str, artifacts: &[serde_json::Value]) -> io::Result<()> {
+        fs::write(self.path(key), serde_json::to_string(artifacts)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_hits_only_identical_builds() {
+        let dir = std::env::temp_dir().join("tinysol_build_cache");
+        let cache = BuildCache::open(&dir).unwrap();
+        let settings = CompilerSettings::default();
+        let key = BuildCache::key("A.sol", "contract A {}", &settings);
+        let _ = fs::remove_file(cache.path(&key));
+
+        assert_eq!(cache.get(&key), None);
+        cache.insert(&key, &[json!({ "contractName": "A" })]).unwrap();
+        assert_eq!(cache.get(&key), Some(vec![json!({ "contractName": "A" })]));
+
+        assert_ne!(BuildCache::key("A.sol", "contract A { }", &settings), key);
+        assert_ne!(BuildCache::key("B.sol", "contract A {}", &settings), key);
+        assert_ne!(BuildCache::key("A.sol", "contract A {}", &CompilerSettings::unoptimized()), key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use ethnum::U256;
+use crate::env::Address;
+use crate::executor::Storage;
+
+/// Deepest a chain of calls may nest, as in the EVM.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+/// Gas per byte of runtime code a creation stores.
+pub const G_CODE_DEPOSIT: u64 = 200;
+
+/// Which opcode made a call, which decides whose storage and context the callee runs in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallKind {
+    /// `CALL`: the callee runs as itself, on its own storage.
+    #[default]
+    Call,
+    /// `CALLCODE`: the callee's code runs on the caller's storage, sent by the caller.
+    CallCode,
+    /// `DELEGATECALL`: the callee's code runs as the caller, keeping its sender and value.
+    DelegateCall,
+    /// `STATICCALL`: like `CALL` without value, and neither the callee nor the calls it
+    /// makes may change state.
+    StaticCall,
+}
+
+/// A message call made with `CALL` or one of its variants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCall {
+    pub kind: CallKind,
+    /// Account whose code made the call.
+    pub caller: Address,
+    /// Sender the callee sees: `caller`, except in a `DELEGATECALL`, which keeps the
+    /// caller's own sender.
+    pub sender: Address,
+    /// Account whose code runs.
+    pub to: Address,
+    /// Wei moved to the callee. In a `DELEGATECALL` this is the caller's own value, which
+    /// isn't moved again.
+    pub value: U256,
+    pub input: Vec<u8>,
+    /// Gas the callee may use.
+    pub gas: u64,
+    /// Whether the callee may not change state, because this call or one it is nested in
+    /// is a `STATICCALL`.
+    pub is_static: bool,
+}
+
+impl MessageCall {
+    /// Account the callee runs as, whose storage and balance it uses: `to`, or the caller
+    /// for `CALLCODE` and `DELEGATECALL`.
+    pub fn address(&self) -> Address {
+        match self.kind {
+            CallKind::CallCode | CallKind::DelegateCall => self.caller,
+            CallKind::Call | CallKind::StaticCall => self.to,
+        }
+    }
+}
+
+/// How a message call ended.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOutcome {
+    pub success: bool,
+    /// Bytes the callee returned, or its revert data.
+    pub return_data: Vec<u8>,
+    pub gas_used: u64,
+}
+
+impl CallOutcome {
+    /// A call that failed without running any code.
+    pub fn failed() -> Self {
+        Self::default()
+    }
+}
+
+/// A contract creation made with `CREATE`, or with `CREATE2` when there is a `salt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractCreation {
+    /// Account whose code creates the contract.
+    pub creator: Address,
+    pub value: U256,
+    /// Code run once to set up the contract, which returns its runtime code.
+    pub init_code: Vec<u8>,
+    pub salt: Option<U256>,
+    /// Gas the init code may use.
+    pub gas: u64,
+}
+
+/// How a contract creation ended.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreateOutcome {
+    /// Address of the new contract, `None` if the creation failed.
+    pub address: Option<Address>,
+    /// The init code's revert data, if it reverted.
+    pub return_data: Vec<u8>,
+    pub gas_used: u64,
+}
+
+impl CreateOutcome {
+    /// A creation that failed without running any code.
+    pub fn failed() -> Self {
+        Self::default()
+    }
+}
+
+/// Resolves the calls code makes: finds the contract at the target address, runs it with
+/// its own storage and moves the value. A VM without one fails every call. Handlers are
+/// `Send` so that VMs running them still can be.
+pub trait CallHandler: Send {
+    /// Runs `call`. `storage` is the caller's storage as it is now, which a call back into
+    /// the caller must see; the handler writes back what such a call changed.
+    fn call(&mut self, call: MessageCall, storage: &mut dyn Storage) -> CallOutcome;
+
+    /// Runs `creation`, with `storage` as in `call`. Handlers that can't create contracts
+    /// keep this default, under which every creation fails.
+    fn create(&mut self, creation: ContractCreation, storage: &mut dyn Storage) -> CreateOutcome {
+        let _ = (creation, storage);
+        CreateOutcome::failed()
+    }
+
+    /// Runs `SELFDESTRUCT` in `contract`, sending its balance to `beneficiary`. Handlers
+    /// that can't keep this default, under which the instruction only ends the call.
+    fn selfdestruct(&mut self, contract: Address, beneficiary: Address) {
+        let _ = (contract, beneficiary);
+    }
+
+    /// Runtime code of the account at `address`, for `EXTCODESIZE` and `EXTCODECOPY`.
+    /// Handlers without accounts keep this default, under which no account has code.
+    fn code(&self, address: Address) -> Vec<u8> {
+        let _ = address;
+        vec![]
+    }
+
+    /// Balance in wei of the account at `address`, for `BALANCE` and `SELFBALANCE`.
+    fn balance(&self, address: Address) -> U256 {
+        let _ = address;
+        U256::ZERO
+    }
+
+    /// What `EXTCODEHASH` reports for `address`: the hash of its code, or 0 if the account
+    /// is empty.
+    fn code_hash(&self, address: Address) -> U256 {
+        let _ = address;
+        U256::ZERO
+    }
+}
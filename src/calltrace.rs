@@ -0,0 +1,177 @@
+use ethnum::U256;
+use serde_json::json;
+use std::fmt::Write;
+use crate::bytecode::{from_hex, to_hex};
+use crate::env::Address;
+use crate::registry::ContractRegistry;
+use crate::revert::RevertReason;
+use crate::tinyvm::Halt;
+use crate::world::*;
+
+/// One call frame in the shape of geth's `callTracer` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// `CALL`, `STATICCALL`, `CREATE` and so on. Only `CALL` occurs until contracts can
+    /// call each other.
+    pub kind: String,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: u64,
+    pub gas_used: u64,
+    pub input: Vec<u8>,
+    /// Return data, or revert data if the call failed.
+    pub output: Vec<u8>,
+    pub error: Option<String>,
+    pub revert_reason: Option<String>,
+    pub calls: Vec<CallFrame>,
+}
+
+/// geth's error message for a halt.
+fn error_message(halt: &Halt) -> &'static str {
+    match halt {
+        Halt::OutOfGas => "out of gas",
+        Halt::FunctionNotFound(_) | Halt::NonPayableFunctionReceivedValue | Halt::Revert(_) => "execution reverted",
+        Halt::InsufficientFunds => "insufficient funds for gas * price + value",
+        Halt::FeeCapTooLow => "max fee per gas less than block base fee",
+        Halt::MemoryLimitExceeded => "memory limit exceeded",
+        Halt::InvalidJump => "invalid jump destination",
+        Halt::ReturnDataLimitExceeded => "return data limit exceeded",
+        Halt::StackUnderflow => "stack underflow",
+        Halt::StackOverflow => "stack limit reached 1024",
+        Halt::StateChangeInStaticCall => "write protection",
+        Halt::ReturnDataOutOfBounds => "return data out of bounds",
+        Halt::InvalidOpcode(_) => "invalid opcode",
+        Halt::InvalidInstruction => "invalid opcode: INVALID",
+    }
+}
+
+impl CallFrame {
+    /// The top-level frame of `tx`, which produced `result`.
+    pub fn from_result(tx: &Transaction, result: &CallResult) -> CallFrame {
+        let error = match &result.halt {
+            Some(halt) => Some(error_message(halt).to_string()),
+            None if !result.success => Some("execution reverted".to_string()),
+            None => None,
+        };
+        CallFrame {
+            kind: "CALL".to_string(),
+            from: tx.from,
+            to: tx.to,
+            value: tx.value,
+            gas: tx.gas_limit,
+            gas_used: result.gas_used,
+            input: from_hex(&tx.calldata).unwrap_or_default(),
+            output: if result.success { result.return_data.clone() } else { result.revert_data.clone() },
+            error,
+            revert_reason: match &result.revert_reason {
+                Some(RevertReason::Error(message)) => Some(message.clone()),
+                Some(RevertReason::Panic(code)) => Some(code.reason().to_string()),
+                _ => None,
+            },
+            calls: vec![],
+        }
+    }
+
+    /// The frame as `debug_traceTransaction` returns it with `{"tracer": "callTracer"}`.
+    /// Empty fields are left out, as geth does.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = json!({
+            "type": self.kind,
+            "from": self.from.to_string(),
+            "to": self.to.to_string(),
+            "value": format!("{:#x}", self.value),
+            "gas": format!("{:#x}", self.gas),
+            "gasUsed": format!("{:#x}", self.gas_used),
+            "input": to_hex(&self.input),
+        });
+        if !self.output.is_empty() {
+            json["output"] = json!(to_hex(&self.output));
+        }
+        if let Some(error) = &self.error {
+            json["error"] = json!(error);
+        }
+        if let Some(reason) = &self.revert_reason {
+            json["revertReason"] = json!(reason);
+        }
+        if !self.calls.is_empty() {
+            json["calls"] = self.calls.iter().map(CallFrame::to_json).collect();
+        }
+        json
+    }
+
+    /// The call tree as indented text, one frame per line with its gas, the decoded call
+    /// and what it returned or why it failed, e.g. `[3400] Token.transfer(alice, 100) -> true`.
+    pub fn pretty(&self, registry: &ContractRegistry) -> String {
+        let mut text = String::new();
+        self.write_pretty(registry, 0, &mut text);
+        text
+    }
+
+    fn write_pretty(&self, registry: &ContractRegistry, depth: usize, text: &mut String) {
+        let outcome = match self.error.as_deref() {
+            None => registry.describe_output(&self.to, &self.input, &self.output)
+                .filter(|output| !output.is_empty())
+                .map(|output| format!(" -> {}", output))
+                .unwrap_or_default(),
+            Some("execution reverted") => format!(" <- {}", registry.describe_revert(&registry.decode_revert(&self.to, &self.output))),
+            Some(error) => format!(" <- {}", error),
+        };
+        let _ = writeln!(text, "{}[{}] {}{}", "  ".repeat(depth), self.gas_used, registry.describe_call(&self.to, &self.input), outcome);
+        self.calls.iter().for_each(|call| call.write_pretty(registry, depth + 1, text));
+    }
+}
+
+impl World {
+    /// Applies `tx` and returns its call tree alongside the result.
+    pub fn trace_call(&mut self, tx: &Transaction) -> (CallResult, CallFrame) {
+        let result = self.apply_transaction(tx);
+        let frame = CallFrame::from_result(tx, &result);
+        (result, frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::abi::calldata;
+
+    #[test]
+    fn test_call_tracer_json() {
+        let contract = flipper();
+        let mut world = World::new();
+        let address = world.deploy(alice(), contract);
+
+        let get = calldata("get()", &[]);
+        let (_, frame) = world.trace_call(&Transaction::new(bob(), address, &get));
+        assert_eq!(frame.to_json(), json!({
+            "type": "CALL",
+            "from": bob().to_string(),
+            "to": address.to_string(),
+            "value": "0x0",
+            "gas": format!("{:#x}", crate::env::Env::default().gas_limit),
+            "gasUsed": format!("{:#x}", frame.gas_used),
+            "input": format!("0x{}", get),
+            "output": to_hex(&[0; 32]),
+        }));
+
+        let (_, frame) = world.trace_call(&Transaction::new(bob(), address, "deadbeef"));
+        assert_eq!(frame.to_json()["error"], "execution reverted");
+        assert_eq!(frame.to_json().get("output"), None);
+    }
+
+    #[test]
+    fn test_pretty_trace() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+
+        let (result, frame) = world.trace_call(&Transaction::new(bob(), address, &calldata("get()", &[])));
+        assert_eq!(frame.pretty(&world.registry), format!("[{}] flipper.get() -> false\n", result.gas_used));
+        let (result, frame) = world.trace_call(&Transaction::new(bob(), address, "deadbeef"));
+        assert_eq!(frame.pretty(&world.registry), format!("[{}] flipper.0xdeadbeef <- revert\n", result.gas_used));
+        let (_, frame) = world.trace_call(&Transaction::new(alice(), bob(), ""));
+        assert_eq!(frame.pretty(&world.registry), "[21000] bob\n");
+    }
+}
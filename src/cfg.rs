@@ -0,0 +1,168 @@
+use ethnum::U256;
+use std::collections::BTreeSet;
+use crate::bytecode::code_offsets;
+use crate::tinyvm::*;
+
+/// A maximal run of instructions with a single entry and a single exit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub id: usize,
+    /// Index of the first instruction in the program.
+    pub start: usize,
+    /// One past the last instruction.
+    pub end: usize,
+    /// Byte offset of the first instruction in the assembled code.
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution runs off the end of the block into the next one.
+    Fallthrough,
+    /// Control transfers to a jump target.
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// Control-flow graph of one compiled program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub program: Vec<OP>,
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+/// Whether execution never continues to the next instruction.
+fn is_terminator(op: &OP) -> bool {
+    matches!(op, OP::RETURN | OP::REVERT | OP::SELFDESTRUCT | OP::STOP | OP::INVALID | OP::JUMP)
+}
+
+/// Target of the jump at `i` when the instruction before it pushes a constant, the only
+/// form the compiler emits. `None` for other instructions and computed targets.
+pub fn static_jump_target(program: &[OP], i: usize) -> Option<U256> {
+    if !matches!(program.get(i), Some(OP::JUMP | OP::JUMPI)) {
+        return None;
+    }
+    program.get(i.checked_sub(1)?)?.push_value()
+}
+
+impl Cfg {
+    pub fn build(program: &[OP]) -> Cfg {
+        let offsets = code_offsets(program);
+        let mut leaders = BTreeSet::from([0]);
+        program.iter().enumerate().for_each(|(i, op)| match op {
+            OP::RETURN | OP::REVERT | OP::SELFDESTRUCT | OP::STOP | OP::INVALID | OP::JUMP | OP::JUMPI => { leaders.insert(i + 1); },
+            OP::JUMPDEST => { leaders.insert(i); },
+            _ => {},
+        });
+        leaders.retain(|leader| *leader < program.len());
+
+        let starts = leaders.into_iter().collect::<Vec<_>>();
+        let blocks = starts.iter().enumerate().map(|(id, start)| BasicBlock {
+            id,
+            start: *start,
+            end: starts.get(id + 1).copied().unwrap_or(program.len()),
+            offset: offsets[*start],
+        }).collect::<Vec<BasicBlock>>();
+
+        let fallthroughs = blocks.iter()
+            .filter(|block| block.id + 1 < blocks.len() && !is_terminator(&program[block.end - 1]))
+            .map(|block| Edge { from: block.id, to: block.id + 1, kind: EdgeKind::Fallthrough });
+        let jumps = blocks.iter().filter_map(|block| {
+            let target = usize::try_from(static_jump_target(program, block.end - 1)?).ok()?;
+            let to = blocks.iter().find(|b| b.offset == target && program[b.start] == OP::JUMPDEST)?;
+            Some(Edge { from: block.id, to: to.id, kind: EdgeKind::Jump })
+        }).collect::<Vec<_>>();
+        let edges = fallthroughs.chain(jumps).collect();
+
+        Cfg { program: program.to_vec(), blocks, edges }
+    }
+
+    pub fn ops(&self, block: &BasicBlock) -> &[OP] {
+        &self.program[block.start..block.end]
+    }
+
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter(move |edge| edge.from == block).map(|edge| edge.to)
+    }
+
+    pub fn predecessors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter(move |edge| edge.to == block).map(|edge| edge.from)
+    }
+
+    /// Blocks that can't be reached from the entry block.
+    pub fn unreachable_blocks(&self) -> Vec<usize> {
+        let mut seen = BTreeSet::new();
+        let mut work = if self.blocks.is_empty() { vec![] } else { vec![0] };
+        while let Some(block) = work.pop() {
+            if seen.insert(block) {
+                work.extend(self.successors(block));
+            }
+        }
+        (0..self.blocks.len()).filter(|block| !seen.contains(block)).collect()
+    }
+
+    /// Graphviz DOT, one record node per block listing its instructions.
+    pub fn to_dot(&self, name: &str) -> String {
+        let offsets = code_offsets(&self.program);
+        let mut dot = format!("digraph \"{}\" {{\n    node [shape=box, fontname=monospace];\n", name.replace('"', "\\\""));
+        self.blocks.iter().for_each(|block| {
+            let lines = (block.start..block.end)
+                .map(|i| format!("{}: {}\\l", offsets[i], self.program[i]))
+                .collect::<String>();
+            dot += &format!("    b{} [label=\"block {} @{}\\l{}\"];\n", block.id, block.id, block.offset, lines);
+        });
+        self.edges.iter().for_each(|edge| {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [style=bold]",
+            };
+            dot += &format!("    b{} -> b{}{};\n", edge.from, edge.to, style);
+        });
+        dot + "}\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_split_after_return() {
+        let cfg = Cfg::build(&[OP::PUSH1(1), OP::RETURN, OP::PUSH1(2), OP::POP]);
+        assert_eq!(cfg.blocks.iter().map(|b| (b.start, b.end, b.offset)).collect::<Vec<_>>(), vec![(0, 2, 0), (2, 4, 3)]);
+        assert!(cfg.edges.is_empty());
+        assert_eq!(cfg.unreachable_blocks(), vec![1]);
+        assert_eq!(cfg.ops(&cfg.blocks[1]), &[OP::PUSH1(2), OP::POP]);
+    }
+
+    #[test]
+    fn test_jump_edges() {
+        // A loop counting down from 3: the JUMPI at the end jumps back to the JUMPDEST at 2.
+        let program = [
+            OP::PUSH1(3), OP::JUMPDEST, OP::PUSH1(1), OP::SWAP1, OP::SUB,
+            OP::DUP1, OP::PUSH1(2), OP::JUMPI, OP::POP,
+        ];
+        let cfg = Cfg::build(&program);
+        assert_eq!(cfg.blocks.iter().map(|b| (b.start, b.offset)).collect::<Vec<_>>(), vec![(0, 0), (1, 2), (8, 11)]);
+        assert_eq!(cfg.successors(1).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(cfg.edges.iter().filter(|e| e.kind == EdgeKind::Jump).count(), 1);
+        assert!(cfg.unreachable_blocks().is_empty());
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let cfg = Cfg::build(&[OP::PUSH1(0), OP::SLOAD, OP::RETURN]);
+        assert_eq!(cfg.to_dot("get()"), "digraph \"get()\" {
+    node [shape=box, fontname=monospace];
+    b0 [label=\"block 0 @0\\l0: PUSH1 0x00\\l2: SLOAD\\l3: RETURN\\l\"];
+}
+");
+    }
+}
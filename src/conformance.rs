@@ -0,0 +1,115 @@
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::unsupported::{parse_source, unsupported_features};
+
+/// The bundled corpus: a subset of OpenZeppelin Contracts.
+pub const OPENZEPPELIN: &str = "./contracts/openzeppelin";
+
+/// How much of a corpus of Solidity files the grammar parses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub files: usize,
+    pub parsed: usize,
+    /// Number of unparsed files each unsupported construct appears in, by diagnostic code.
+    pub unsupported: BTreeMap<&'static str, usize>,
+}
+
+impl ConformanceReport {
+    /// Counts `source` in the report.
+    pub fn add(&mut self, source: &str) {
+        self.files += 1;
+        if parse_source(source).is_ok() {
+            self.parsed += 1;
+            return;
+        }
+        let codes = unsupported_features(source).iter().map(|diagnostic| diagnostic.code).collect::<BTreeSet<_>>();
+        codes.into_iter().for_each(|code| *self.unsupported.entry(code).or_default() += 1);
+    }
+
+    pub fn percent_parsed(&self) -> f64 {
+        if self.files == 0 {
+            return 100.0;
+        }
+        self.parsed as f64 * 100.0 / self.files as f64
+    }
+
+    /// The `n` constructs blocking the most files, most common first.
+    pub fn top_unsupported(&self, n: usize) -> Vec<(&'static str, usize)> {
+        let mut top = self.unsupported.iter().map(|(code, files)| (*code, *files)).collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// One metrics record, tagged with the tinysol version so runs can be compared across
+    /// releases.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "files": self.files,
+            "parsed": self.parsed,
+            "percentParsed": self.percent_parsed(),
+            "unsupported": self.unsupported,
+        })
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "parsed {} of {} files ({:.1}%)", self.parsed, self.files, self.percent_parsed())?;
+        self.top_unsupported(10).iter().try_for_each(|(code, files)| writeln!(f, "  {:>4} file(s): {}", files, code))
+    }
+}
+
+/// The `.sol` files under `dir`, in a stable order.
+fn solidity_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(solidity_files(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "sol") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Parses every `.sol` file under `dir`.
+pub fn run_corpus(dir: &Path) -> io::Result<ConformanceReport> {
+    let mut report = ConformanceReport::default();
+    for file in solidity_files(dir)? {
+        report.add(&fs::read_to_string(file)?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = ConformanceReport::default();
+        report.add("contract A { bool private a; }");
+        report.add("pragma solidity ^0.8.0;\ncontract B { struct S { bool a; } struct T { bool b; } }");
+        report.add("contract C { struct S { bool a; } }");
+        assert_eq!((report.files, report.parsed), (3, 1));
+        assert_eq!(report.top_unsupported(5), vec![("unsupported-struct", 2), ("unsupported-pragma", 1)]);
+        assert_eq!(report.to_string(), "parsed 1 of 3 files (33.3%)\n     2 file(s): unsupported-struct\n     1 file(s): unsupported-pragma\n");
+    }
+
+    /// Update the expected numbers as the grammar learns more of the corpus.
+    #[test]
+    fn test_openzeppelin_corpus() {
+        let report = run_corpus(Path::new(OPENZEPPELIN)).unwrap();
+        assert_eq!((report.files, report.parsed), (5, 0));
+        assert_eq!(report.top_unsupported(1), vec![("unsupported-pragma", 5)]);
+        assert_eq!(report.unsupported["unsupported-modifier"], 2);
+    }
+}
@@ -0,0 +1,324 @@
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use crate::debugger::*;
+use crate::env::{Address, Env};
+use crate::selector::selector;
+use crate::solidity::grammar::{parse, Type};
+use crate::tinyvm::*;
+
+const THREAD_ID: u64 = 1;
+const STORAGE_SCOPE: u64 = 1;
+const STACK_SCOPE: u64 = 2;
+
+/// Reads and writes Debug Adapter Protocol messages, framed by `Content-Length` headers.
+struct Connection<R, W> {
+    reader: R,
+    writer: W,
+    seq: u64,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    /// The next message, or `None` once the client has closed the stream.
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.reader.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body)?;
+        serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.writer.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+/// Everything the client configures before execution starts.
+#[derive(Default)]
+struct LaunchConfig {
+    program: String,
+    contract: Option<String>,
+    function: String,
+    stop_on_entry: bool,
+    lines: Vec<usize>,
+    functions: Vec<String>,
+}
+
+/// Serves a single debug session over `reader`/`writer`, typically stdin and stdout.
+///
+/// The client launches with `{ "program": "<file.sol>", "function": "flip()" }` and
+/// optionally `"contract"` and `"stopOnEntry"`. Execution starts on `configurationDone`.
+pub fn serve<R: BufRead, W: Write>(reader: R, writer: W) -> io::Result<()> {
+    let mut conn = Connection { reader, writer, seq: 0 };
+    let mut config = LaunchConfig::default();
+
+    while let Some(request) = conn.read()? {
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                conn.respond(&request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                }))?;
+                conn.event("initialized", json!({}))?;
+            },
+            "launch" => {
+                let args = &request["arguments"];
+                match (args["program"].as_str(), args["function"].as_str()) {
+                    (Some(program), Some(function)) => {
+                        config.program = program.to_string();
+                        config.function = function.to_string();
+                        config.contract = args["contract"].as_str().map(String::from);
+                        config.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                        conn.respond(&request, json!({}))?;
+                    },
+                    _ => conn.fail(&request, "launch requires `program` and `function`")?,
+                }
+            },
+            "setBreakpoints" => {
+                config.lines = requested_lines(&request);
+                let breakpoints = config.lines.iter().map(|line| json!({ "verified": true, "line": line })).collect::<Vec<_>>();
+                conn.respond(&request, json!({ "breakpoints": breakpoints }))?;
+            },
+            "setFunctionBreakpoints" => {
+                config.functions = requested_functions(&request);
+                let breakpoints = config.functions.iter().map(|_| json!({ "verified": true })).collect::<Vec<_>>();
+                conn.respond(&request, json!({ "breakpoints": breakpoints }))?;
+            },
+            "configurationDone" => {
+                conn.respond(&request, json!({}))?;
+                return session(&mut conn, config);
+            },
+            "disconnect" => return conn.respond(&request, json!({})),
+            _ => conn.fail(&request, "not supported before launch")?,
+        }
+    }
+    Ok(())
+}
+
+fn requested_lines(request: &Value) -> Vec<usize> {
+    request["arguments"]["breakpoints"].as_array().into_iter().flatten()
+        .filter_map(|bp| bp["line"].as_u64().map(|line| line as usize))
+        .collect()
+}
+
+fn requested_functions(request: &Value) -> Vec<String> {
+    request["arguments"]["breakpoints"].as_array().into_iter().flatten()
+        .filter_map(|bp| bp["name"].as_str().map(String::from))
+        .collect()
+}
+
+/// Runs the launched call under the debugger until the client disconnects.
+fn session<R: BufRead, W: Write>(conn: &mut Connection<R, W>, config: LaunchConfig) -> io::Result<()> {
+    let source = std::fs::read_to_string(&config.program);
+    let contracts = source.as_ref().ok()
+        .and_then(|source| parse(source).ok())
+        .map(create_contracts)
+        .unwrap_or_default();
+    let contract = contracts.iter().find(|c| config.contract.as_ref().is_none_or(|name| c.name == *name));
+    let calldata = selector(&config.function).to_vec();
+
+    let debugger = match (&source, contract) {
+        (Ok(source), Some(contract)) => Debugger::new(contract, &calldata, Env::default())
+            .map(|debugger| debugger.with_source(&config.program, source)),
+        _ => None,
+    };
+    let Some(mut debugger) = debugger else {
+        conn.event("output", json!({ "category": "stderr", "output": format!("Cannot debug {} in {}\n", config.function, config.program) }))?;
+        return conn.event("terminated", json!({}));
+    };
+    let contract = contract.unwrap();
+    let source = source.unwrap();
+
+    let mut line_breakpoints = set_line_breakpoints(&mut debugger, &config.program, &config.lines);
+    let mut function_breakpoints = config.functions.iter().map(|f| debugger.break_on_function(f)).collect::<Vec<_>>();
+
+    if config.stop_on_entry {
+        conn.event("stopped", json!({ "reason": "entry", "threadId": THREAD_ID }))?;
+    } else {
+        resume(conn, &mut debugger)?;
+    }
+
+    while let Some(request) = conn.read()? {
+        match request["command"].as_str().unwrap_or_default() {
+            "threads" => conn.respond(&request, json!({
+                "threads": [{ "id": THREAD_ID, "name": format!("{}.{}", contract.name, config.function) }],
+            }))?,
+            "stackTrace" => {
+                let (start, _) = debugger.span().unwrap_or_default();
+                let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+                conn.respond(&request, json!({
+                    "stackFrames": [{
+                        "id": 0,
+                        "name": config.function,
+                        "line": debugger.line().unwrap_or(0),
+                        "column": start - line_start + 1,
+                        "source": { "path": config.program },
+                    }],
+                    "totalFrames": 1,
+                }))?
+            },
+            "scopes" => conn.respond(&request, json!({
+                "scopes": [
+                    { "name": "Storage", "variablesReference": STORAGE_SCOPE, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK_SCOPE, "expensive": false },
+                ],
+            }))?,
+            "variables" => {
+                let variables = match request["arguments"]["variablesReference"].as_u64() {
+                    Some(STORAGE_SCOPE) => storage_variables(contract, debugger.storage()),
+                    Some(STACK_SCOPE) => debugger.vm().stack.as_slice().iter().enumerate().rev()
+                        .map(|(i, word)| json!({ "name": format!("[{}]", i), "value": format!("{:#x}", word), "variablesReference": 0 }))
+                        .collect(),
+                    _ => vec![],
+                };
+                conn.respond(&request, json!({ "variables": variables }))?
+            },
+            "setBreakpoints" => {
+                line_breakpoints.drain(..).flatten().for_each(|id| debugger.clear_breakpoint(id));
+                let lines = requested_lines(&request);
+                line_breakpoints = set_line_breakpoints(&mut debugger, &config.program, &lines);
+                let breakpoints = lines.iter().zip(&line_breakpoints)
+                    .map(|(line, id)| json!({ "verified": id.is_some(), "line": line }))
+                    .collect::<Vec<_>>();
+                conn.respond(&request, json!({ "breakpoints": breakpoints }))?
+            },
+            "setFunctionBreakpoints" => {
+                function_breakpoints.drain(..).for_each(|id| debugger.clear_breakpoint(id));
+                let functions = requested_functions(&request);
+                function_breakpoints = functions.iter().map(|f| debugger.break_on_function(f)).collect();
+                let breakpoints = functions.iter().map(|_| json!({ "verified": true })).collect::<Vec<_>>();
+                conn.respond(&request, json!({ "breakpoints": breakpoints }))?
+            },
+            "continue" => {
+                conn.respond(&request, json!({ "allThreadsContinued": true }))?;
+                resume(conn, &mut debugger)?;
+            },
+            "next" | "stepIn" | "stepOut" => {
+                conn.respond(&request, json!({}))?;
+                let span = debugger.span();
+                while debugger.step() && debugger.span() == span {}
+                if debugger.vm().is_finished() {
+                    conn.event("terminated", json!({}))?;
+                } else {
+                    conn.event("stopped", json!({ "reason": "step", "threadId": THREAD_ID }))?;
+                }
+            },
+            "disconnect" => return conn.respond(&request, json!({})),
+            _ => conn.fail(&request, "not supported")?,
+        }
+    }
+    Ok(())
+}
+
+fn set_line_breakpoints(debugger: &mut Debugger, program: &str, lines: &[usize]) -> Vec<Option<usize>> {
+    lines.iter().map(|line| debugger.set_breakpoint(&format!("{}:{}", program, line))).collect()
+}
+
+/// Runs until the debugger stops and reports why.
+fn resume<R: BufRead, W: Write>(conn: &mut Connection<R, W>, debugger: &mut Debugger) -> io::Result<()> {
+    match debugger.run() {
+        Stop::Breakpoint { .. } => conn.event("stopped", json!({ "reason": "breakpoint", "threadId": THREAD_ID })),
+        Stop::Watchpoint(_) => conn.event("stopped", json!({ "reason": "data breakpoint", "threadId": THREAD_ID })),
+        Stop::Finished => conn.event("terminated", json!({})),
+    }
+}
+
+/// State variables, in slot order, rendered by their declared type.
+fn storage_variables(contract: &Contract, storage: &ContractStorage) -> Vec<Value> {
+    let mut variables = contract.variable_map.iter().collect::<Vec<_>>();
+    variables.sort_by_key(|(_, slot)| **slot);
+    variables.into_iter().map(|(name, slot)| {
+        let word = storage.slots().get(*slot).copied().unwrap_or_default();
+        let ty = contract.variable_types.get(name);
+        let value = match ty {
+            Some(Type::Bool(_)) => (word != 0).to_string(),
+            Some(Type::Uint256(_)) => word.to_string(),
+            Some(Type::Int256(_)) => word.as_i256().to_string(),
+            Some(Type::Address(_)) => Address::from_word(word).to_string(),
+            // Entries live at hashed slots; the mapping's own slot stays zero.
+            Some(Type::Mapping(..)) | None => format!("{:#x}", word),
+        };
+        json!({ "name": name, "value": value, "type": ty.map(type_name), "variablesReference": 0 })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn messages(output: &[u8]) -> Vec<Value> {
+        let mut conn = Connection { reader: output, writer: io::sink(), seq: 0 };
+        std::iter::from_fn(|| conn.read().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_breakpoint_session() {
+        let requests = [
+            json!({ "seq": 1, "type": "request", "command": "initialize", "arguments": {} }),
+            json!({ "seq": 2, "type": "request", "command": "launch", "arguments": { "program": "./contracts/flipper.sol", "function": "flip()" } }),
+            json!({ "seq": 3, "type": "request", "command": "setBreakpoints", "arguments": { "source": { "path": "./contracts/flipper.sol" }, "breakpoints": [{ "line": 13 }] } }),
+            json!({ "seq": 4, "type": "request", "command": "configurationDone" }),
+            json!({ "seq": 5, "type": "request", "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 6, "type": "request", "command": "variables", "arguments": { "variablesReference": STORAGE_SCOPE } }),
+            json!({ "seq": 7, "type": "request", "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 8, "type": "request", "command": "disconnect" }),
+        ];
+        let input = requests.into_iter().map(frame).collect::<String>();
+        let mut output = vec![];
+        serve(input.as_bytes(), &mut output).unwrap();
+
+        let messages = messages(&output);
+        let events = messages.iter().filter(|m| m["type"] == "event").map(|m| m["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(events, vec!["initialized", "stopped", "terminated"]);
+        assert!(messages.iter().filter(|m| m["type"] == "response").all(|m| m["success"] == true));
+
+        let response = |command: &str| messages.iter().find(|m| m["command"] == command).unwrap()["body"].clone();
+        assert_eq!(response("stackTrace")["stackFrames"][0]["line"], 13);
+        assert_eq!(response("variables")["variables"][0], json!({ "name": "value", "value": "false", "type": "bool", "variablesReference": 0 }));
+    }
+}
@@ -0,0 +1,584 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use crate::bytecode::to_hex;
+use crate::env::Env;
+use crate::executor::Storage;
+use crate::selector::selector;
+use crate::tinyvm::*;
+
+/// What one instruction changed. The stack delta is relative to the longest common
+/// prefix of the stack before and after, which is all an instruction can touch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepDelta {
+    pub pc: usize,
+    pub op: OP,
+    pub popped: Vec<U256>,
+    pub pushed: Vec<U256>,
+    /// `(slot, old, new)` for every storage write.
+    pub storage_writes: Vec<(U256, U256, U256)>,
+    /// `(offset, old, new)` for the bytes of memory the instruction changed. Memory only
+    /// grows, so `old` is shorter than `new` when it grew past its old end.
+    pub memory_writes: Vec<(usize, Vec<u8>, Vec<u8>)>,
+}
+
+/// The span of `after` that differs from `before`, with bytes past the end of `before`
+/// counting as changed.
+fn memory_write(before: &[u8], after: &[u8]) -> Option<(usize, Vec<u8>, Vec<u8>)> {
+    let differs = |i: &usize| before.get(*i) != after.get(*i);
+    let start = (0..after.len()).find(differs)?;
+    let end = (start..after.len()).rev().find(differs).map_or(start, |i| i + 1);
+    Some((start, before[start.min(before.len())..end.min(before.len())].to_vec(), after[start..end].to_vec()))
+}
+
+/// Records a compact per-step history of an execution.
+#[derive(Debug, Default)]
+pub struct HistoryRecorder {
+    /// The value of each slot written, before its first write.
+    initial_storage: BTreeMap<U256, U256>,
+    steps: Vec<StepDelta>,
+    stack_before: Vec<U256>,
+    /// `(slot, old)` if the pending instruction is an `SSTORE`.
+    write_before: Option<(U256, U256)>,
+    memory_before: Vec<u8>,
+    pc_before: usize,
+}
+
+impl HistoryRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn finish(self) -> History {
+        History {
+            storage: self.initial_storage.clone(),
+            initial_storage: self.initial_storage,
+            steps: self.steps,
+            cursor: 0,
+            stack: vec![],
+            memory: vec![],
+        }
+    }
+}
+
+impl Inspector for HistoryRecorder {
+    fn step(&mut self, vm: &VM, storage: &dyn Storage) {
+        self.stack_before = vm.stack.as_slice().to_vec();
+        self.write_before = match vm.program()[vm.pc()] {
+            OP::SSTORE => vm.stack.peek(0).map(|slot| (slot, storage.load(slot))),
+            _ => None,
+        };
+        self.memory_before = vm.memory().to_vec();
+        self.pc_before = vm.pc();
+    }
+
+    fn step_end(&mut self, vm: &VM, storage: &dyn Storage) {
+        let after = vm.stack.as_slice();
+        let common = self.stack_before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let storage_writes = self.write_before.take()
+            .map(|(slot, old)| (slot, old, storage.load(slot)))
+            .filter(|(_, old, new)| old != new)
+            .into_iter()
+            .collect::<Vec<_>>();
+        for (slot, old, _) in &storage_writes {
+            self.initial_storage.entry(*slot).or_insert(*old);
+        }
+
+        self.steps.push(StepDelta {
+            pc: self.pc_before,
+            op: vm.program()[self.pc_before].clone(),
+            popped: self.stack_before[common..].to_vec(),
+            pushed: after[common..].to_vec(),
+            storage_writes,
+            memory_writes: memory_write(&self.memory_before, vm.memory()).into_iter().collect(),
+        });
+    }
+}
+
+/// A recorded execution that can be stepped through in both directions.
+///
+/// The cursor sits between steps: at position `n`, steps `0..n` have been applied.
+#[derive(Debug, Clone)]
+pub struct History {
+    initial_storage: BTreeMap<U256, U256>,
+    steps: Vec<StepDelta>,
+    cursor: usize,
+    stack: Vec<U256>,
+    storage: BTreeMap<U256, U256>,
+    memory: Vec<u8>,
+}
+
+impl History {
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn steps(&self) -> &[StepDelta] {
+        &self.steps
+    }
+
+    /// The step that would be applied next.
+    pub fn current(&self) -> Option<&StepDelta> {
+        self.steps.get(self.cursor)
+    }
+
+    pub fn stack(&self) -> &[U256] {
+        &self.stack
+    }
+
+    /// The slots the execution writes, with the values they hold at the cursor.
+    pub fn storage(&self) -> &BTreeMap<U256, U256> {
+        &self.storage
+    }
+
+    /// The slots the execution writes, with the values they held before it.
+    pub fn initial_storage(&self) -> &BTreeMap<U256, U256> {
+        &self.initial_storage
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn step_forward(&mut self) -> bool {
+        let Some(step) = self.steps.get(self.cursor) else { return false };
+        self.stack.truncate(self.stack.len() - step.popped.len());
+        self.stack.extend_from_slice(&step.pushed);
+        step.storage_writes.iter().for_each(|(slot, _, new)| { self.storage.insert(*slot, *new); });
+        for (offset, _, new) in &step.memory_writes {
+            let end = offset + new.len();
+            if self.memory.len() < end {
+                self.memory.resize(end, 0);
+            }
+            self.memory[*offset..end].copy_from_slice(new);
+        }
+        self.cursor += 1;
+        true
+    }
+
+    pub fn step_back(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        let step = &self.steps[self.cursor];
+        self.stack.truncate(self.stack.len() - step.pushed.len());
+        self.stack.extend_from_slice(&step.popped);
+        step.storage_writes.iter().for_each(|(slot, old, _)| { self.storage.insert(*slot, *old); });
+        for (offset, old, new) in &step.memory_writes {
+            self.memory[*offset..offset + old.len()].copy_from_slice(old);
+            // Memory the step grew into goes away again.
+            if old.len() < new.len() {
+                self.memory.truncate(offset + old.len());
+            }
+        }
+        true
+    }
+
+    /// Moves the cursor to `position`, clamped to the end of the history.
+    pub fn seek(&mut self, position: usize) {
+        let position = position.min(self.steps.len());
+        while self.cursor < position && self.step_forward() {}
+        while self.cursor > position && self.step_back() {}
+    }
+
+    /// Indices of all steps that wrote `slot`.
+    pub fn writes(&self, slot: U256) -> Vec<usize> {
+        self.steps.iter().enumerate()
+            .filter(|(_, step)| step.storage_writes.iter().any(|(s, _, _)| *s == slot))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The last step before the cursor that wrote `slot`.
+    pub fn last_write(&self, slot: U256) -> Option<usize> {
+        self.writes(slot).into_iter().rev().find(|i| *i < self.cursor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// A watched storage access, reported before the instruction executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: usize,
+    pub slot: U256,
+    /// The state variable stored in `slot`, if any.
+    pub variable: Option<String>,
+    pub access: Access,
+    /// The value currently in the slot.
+    pub value: U256,
+    /// The value about to be stored, for writes.
+    pub written: Option<U256>,
+    pub pc: usize,
+    pub span: Option<(usize, usize)>,
+}
+
+type WatchCallback<'a> = Box<dyn FnMut(&WatchHit) + 'a>;
+
+struct Watchpoint<'a> {
+    slot: U256,
+    kind: WatchKind,
+    callback: Option<WatchCallback<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Breakpoint {
+    Line(usize),
+    Function([u8; 4]),
+}
+
+/// Why `Debugger::run` returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint { breakpoint: usize, pc: usize, span: Option<(usize, usize)> },
+    Watchpoint(WatchHit),
+    Finished,
+}
+
+/// What the debugger last paused on; the instruction it paused before skips those checks on resume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Paused {
+    Breakpoint,
+    Watchpoint,
+}
+
+/// 1-based line number of the byte `offset` in `source`.
+pub fn line_at(source: &str, offset: usize) -> usize {
+    source.as_bytes()[..offset.min(source.len())].iter().filter(|b| **b == b'\n').count() + 1
+}
+
+/// Runs a single call step by step, pausing on watchpoints.
+pub struct Debugger<'a> {
+    contract: &'a Contract,
+    function: &'a Function,
+    selector: [u8; 4],
+    vm: VM<'a>,
+    storage: ContractStorage,
+    source: Option<(String, String)>,
+    breakpoints: Vec<Option<Breakpoint>>,
+    watchpoints: Vec<Option<Watchpoint<'a>>>,
+    paused: Option<Paused>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Prepares a call of the function selected by `calldata`, without running it.
+    pub fn new(contract: &'a Contract, calldata: &'a [u8], env: Env) -> Option<Self> {
+        let selector = <[u8; 4]>::try_from(calldata.get(..4)?).ok()?;
+        let function = contract.dispatch(to_hex(&selector).trim_start_matches("0x"))?;
+        Some(Self {
+            contract,
+            function,
+            selector,
+            vm: VM::builder(function.program().to_vec()).calldata(calldata).env(env).build(),
+            storage: contract.storage.clone(),
+            source: None,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            paused: None,
+        })
+    }
+
+    /// Attaches the source the contract was compiled from, needed for line breakpoints.
+    pub fn with_source(mut self, file_name: &str, source: &str) -> Self {
+        self.source = Some((file_name.to_string(), source.to_string()));
+        self
+    }
+
+    pub fn vm(&self) -> &VM<'a> {
+        &self.vm
+    }
+
+    pub fn storage(&self) -> &ContractStorage {
+        &self.storage
+    }
+
+    /// Source span of the instruction about to execute.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        self.function.span(self.vm.pc())
+    }
+
+    /// Source line of the instruction about to execute.
+    pub fn line(&self) -> Option<usize> {
+        let (_, source) = self.source.as_ref()?;
+        Some(line_at(source, self.span()?.0))
+    }
+
+    /// Pauses `run` before the first instruction of any statement starting on the given
+    /// line, written as `File.sol:42`. The file must match the attached source's file name.
+    /// Returns the breakpoint id.
+    pub fn set_breakpoint(&mut self, location: &str) -> Option<usize> {
+        let (file, line) = location.rsplit_once(':')?;
+        let line = line.parse().ok()?;
+        let (file_name, _) = self.source.as_ref()?;
+        let matches = std::path::Path::new(file_name).ends_with(file) || file_name == file;
+        matches.then(|| self.add_breakpoint(Breakpoint::Line(line)))
+    }
+
+    /// Pauses `run` on entry to the function with the given signature.
+    pub fn break_on_function(&mut self, signature: &str) -> usize {
+        self.add_breakpoint(Breakpoint::Function(selector(signature)))
+    }
+
+    pub fn clear_breakpoint(&mut self, id: usize) {
+        if let Some(breakpoint) = self.breakpoints.get_mut(id) {
+            *breakpoint = None;
+        }
+    }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(Some(breakpoint));
+        self.breakpoints.len() - 1
+    }
+
+    /// Pauses `run` whenever `slot` is accessed as `kind`. Returns the watchpoint id.
+    pub fn watch(&mut self, slot: U256, kind: WatchKind) -> usize {
+        self.add(Watchpoint { slot, kind, callback: None })
+    }
+
+    /// Like `watch`, addressing the slot by state variable name.
+    pub fn watch_variable(&mut self, name: &str, kind: WatchKind) -> Option<usize> {
+        let slot = *self.contract.variable_map.get(name)?;
+        Some(self.watch(U256::from(slot as u64), kind))
+    }
+
+    /// Calls `callback` whenever `slot` is accessed as `kind`, without pausing.
+    pub fn on_watch(&mut self, slot: U256, kind: WatchKind, callback: impl FnMut(&WatchHit) + 'a) -> usize {
+        self.add(Watchpoint { slot, kind, callback: Some(Box::new(callback)) })
+    }
+
+    pub fn unwatch(&mut self, id: usize) {
+        if let Some(watchpoint) = self.watchpoints.get_mut(id) {
+            *watchpoint = None;
+        }
+    }
+
+    fn add(&mut self, watchpoint: Watchpoint<'a>) -> usize {
+        self.watchpoints.push(Some(watchpoint));
+        self.watchpoints.len() - 1
+    }
+
+    /// Executes one instruction, ignoring watchpoints. Returns `false` once finished.
+    pub fn step(&mut self) -> bool {
+        self.paused = None;
+        self.vm.step(&mut self.storage, &mut NoopInspector)
+    }
+
+    /// Runs until a breakpoint or pausing watchpoint fires, or execution ends. Resuming
+    /// after a pause executes the instruction it paused before first.
+    pub fn run(&mut self) -> Stop {
+        while !self.vm.is_finished() {
+            if self.paused.is_none() {
+                if let Some(stop) = self.check_breakpoints() {
+                    self.paused = Some(Paused::Breakpoint);
+                    return stop;
+                }
+            }
+            if self.paused != Some(Paused::Watchpoint) {
+                if let Some(hit) = self.check_watchpoints() {
+                    self.paused = Some(Paused::Watchpoint);
+                    return Stop::Watchpoint(hit);
+                }
+            }
+            self.step();
+        }
+        Stop::Finished
+    }
+
+    fn check_breakpoints(&self) -> Option<Stop> {
+        let pc = self.vm.pc();
+        let span = self.span();
+        let statement_start = pc == 0 || self.function.span(pc - 1) != span;
+        self.breakpoints.iter().enumerate().find_map(|(id, breakpoint)| {
+            let hit = match breakpoint.as_ref()? {
+                Breakpoint::Line(line) => statement_start && self.line() == Some(*line),
+                Breakpoint::Function(selector) => pc == 0 && *selector == self.selector,
+            };
+            hit.then_some(Stop::Breakpoint { breakpoint: id, pc, span })
+        })
+    }
+
+    /// Fires callbacks for the pending instruction and returns the first pausing hit.
+    fn check_watchpoints(&mut self) -> Option<WatchHit> {
+        let stack = &self.vm.stack;
+        let (access, slot, written) = match self.vm.program()[self.vm.pc()] {
+            OP::SLOAD => (Access::Read, stack.peek(0)?, None),
+            OP::SSTORE => (Access::Write, stack.peek(0)?, Some(stack.peek(1)?)),
+            _ => return None,
+        };
+        let variable = self.contract.variable_map.iter()
+            .find(|(_, s)| U256::from(**s as u64) == slot)
+            .map(|(name, _)| name.clone());
+        let mut pause = None;
+        for (id, watchpoint) in self.watchpoints.iter_mut().enumerate() {
+            let Some(watchpoint) = watchpoint else { continue };
+            if watchpoint.slot != slot || !watchpoint.kind.matches(access) {
+                continue;
+            }
+            let hit = WatchHit {
+                watchpoint: id,
+                slot,
+                variable: variable.clone(),
+                access,
+                value: self.storage.load(slot),
+                written,
+                pc: self.vm.pc(),
+                span: self.function.span(self.vm.pc()),
+            };
+            match &mut watchpoint.callback {
+                Some(callback) => callback(&hit),
+                None => { pause.get_or_insert(hit); },
+            }
+        }
+        pause
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::test_utils::{flipper, flipper_source};
+
+    fn flip_history() -> History {
+        let contract = flipper();
+        let mut recorder = HistoryRecorder::new();
+        contract.call_with_inspector(&calldata("flip()", &[]), Env::default(), &mut recorder).unwrap();
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_step_deltas() {
+        let history = flip_history();
+        assert_eq!(history.len(), 5);
+        let iszero = &history.steps()[2];
+        assert_eq!((iszero.op.clone(), iszero.popped.clone(), iszero.pushed.clone()), (OP::ISZERO, vec![U256::ZERO], vec![U256::ONE]));
+        assert_eq!(history.steps()[4].storage_writes, vec![(U256::ZERO, U256::ZERO, U256::ONE)]);
+    }
+
+    #[test]
+    fn test_seek_both_ways() {
+        let mut history = flip_history();
+        history.seek(4);
+        assert_eq!(history.stack(), &[U256::ONE, U256::ZERO]);
+        assert_eq!(history.storage(), &BTreeMap::from([(U256::ZERO, U256::ZERO)]));
+        assert_eq!(history.last_write(U256::ZERO), None);
+
+        assert!(history.step_forward());
+        assert!(!history.step_forward());
+        assert_eq!(history.stack(), &[] as &[U256]);
+        assert_eq!(history.storage(), &BTreeMap::from([(U256::ZERO, U256::ONE)]));
+        assert_eq!(history.last_write(U256::ZERO), Some(4));
+
+        history.seek(2);
+        assert_eq!(history.stack(), &[U256::ZERO]);
+        assert_eq!(history.storage(), history.initial_storage());
+        assert_eq!(history.current().unwrap().op, OP::ISZERO);
+        history.seek(0);
+        assert!(!history.step_back());
+    }
+
+    #[test]
+    fn test_seek_restores_memory() {
+        let mut vm = VM::builder(vec![
+            OP::PUSH1(0xab), OP::PUSH1(1), OP::MSTORE,
+            OP::PUSH1(0xcd), OP::PUSH1(1), OP::MSTORE8,
+            OP::PUSH1(40), OP::MLOAD,
+        ]).build();
+        let mut recorder = HistoryRecorder::new();
+        vm.run_with_inspector(ContractStorage::new(vec![]), &mut recorder);
+        let mut history = recorder.finish();
+        let mut word = vec![0u8; 32];
+        word[31] = 0xab;
+        assert_eq!(history.steps()[2].memory_writes, vec![(0, vec![], [vec![0u8], word.clone(), vec![0; 31]].concat())]);
+        assert_eq!(history.steps()[5].memory_writes, vec![(1, vec![0], vec![0xcd])]);
+
+        history.seek(history.len());
+        assert_eq!(history.memory(), vm.memory());
+        history.seek(5);
+        assert_eq!(&history.memory()[1..33], word.as_slice());
+        assert_eq!(history.memory().len(), 64);
+        history.seek(2);
+        assert_eq!(history.memory(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_watchpoint_pauses_with_span() {
+        let (code, contract) = (flipper_source(), flipper());
+        let calldata = selector("flip()").to_vec();
+        let mut debugger = Debugger::new(&contract, &calldata, Env::default()).unwrap();
+        let id = debugger.watch_variable("value", WatchKind::Write).unwrap();
+
+        let Stop::Watchpoint(hit) = debugger.run() else { panic!("watchpoint did not fire") };
+        assert_eq!((hit.watchpoint, hit.access, hit.pc), (id, Access::Write, 4));
+        assert_eq!((hit.value, hit.written, hit.variable.as_deref()), (U256::ZERO, Some(U256::ONE), Some("value")));
+        let (start, end) = hit.span.unwrap();
+        assert_eq!(&code[start..end], "value = !value;");
+
+        assert_eq!(debugger.run(), Stop::Finished);
+        assert_eq!(debugger.storage().slots(), &[U256::ONE]);
+    }
+
+    #[test]
+    fn test_watchpoint_callback() {
+        let contract = flipper();
+        let calldata = selector("flip()").to_vec();
+        let mut reads = vec![];
+        {
+            let mut debugger = Debugger::new(&contract, &calldata, Env::default()).unwrap();
+            debugger.on_watch(U256::ZERO, WatchKind::Read, |hit| reads.push((hit.pc, hit.value)));
+            let write = debugger.watch(U256::ZERO, WatchKind::Write);
+            debugger.unwatch(write);
+            assert_eq!(debugger.run(), Stop::Finished);
+        }
+        assert_eq!(reads, vec![(1, U256::ZERO)]);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (code, contract) = (flipper_source(), flipper());
+        let calldata = selector("flip()").to_vec();
+        let mut debugger = Debugger::new(&contract, &calldata, Env::default()).unwrap()
+            .with_source("contracts/flipper.sol", code);
+        assert_eq!(debugger.set_breakpoint("other.sol:13"), None);
+        debugger.break_on_function("get()");
+        let function = debugger.break_on_function("flip()");
+        assert!(debugger.set_breakpoint("flipper.sol:13").is_some());
+        debugger.watch(U256::ZERO, WatchKind::Read);
+
+        assert!(matches!(debugger.run(), Stop::Breakpoint { breakpoint, pc: 0, .. } if breakpoint == function));
+        debugger.clear_breakpoint(function);
+        assert_eq!(debugger.line(), Some(13));
+        assert!(matches!(debugger.run(), Stop::Watchpoint(WatchHit { pc: 1, .. })));
+        assert_eq!(debugger.run(), Stop::Finished);
+    }
+}
@@ -0,0 +1,288 @@
+use ethnum::U256;
+use keccak_hash::keccak;
+use serde_json::json;
+use std::thread::sleep;
+use std::time::Duration;
+use crate::bytecode::{from_hex, to_hex};
+use crate::env::Address;
+use crate::rlp::{Rlp, RlpError};
+use crate::rpc::*;
+use crate::secp256k1;
+
+/// A pre-EIP-2718 transaction, signed with EIP-155 replay protection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyTransaction {
+    pub nonce: u64,
+    pub gas_price: U256,
+    pub gas_limit: u64,
+    /// `None` creates a contract.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+}
+
+impl LegacyTransaction {
+    fn fields(&self) -> Vec<Rlp> {
+        vec![
+            Rlp::uint(U256::from(self.nonce)),
+            Rlp::uint(self.gas_price),
+            Rlp::uint(U256::from(self.gas_limit)),
+            self.to.as_ref().map_or(Rlp::Bytes(vec![]), Rlp::address),
+            Rlp::uint(self.value),
+            Rlp::Bytes(self.data.clone()),
+        ]
+    }
+
+    /// Reads back a transaction produced by `sign`, ignoring the signature.
+    pub fn decode(raw: &[u8]) -> Result<Self, RlpError> {
+        let rlp = Rlp::decode(raw)?;
+        let [nonce, gas_price, gas_limit, to, value, data, ..] = rlp.as_list()? else {
+            return Err(RlpError::UnexpectedEnd);
+        };
+        Ok(Self {
+            nonce: nonce.as_u64()?,
+            gas_price: gas_price.as_uint()?,
+            gas_limit: gas_limit.as_u64()?,
+            to: if to.as_bytes()?.is_empty() { None } else { Some(to.as_address()?) },
+            value: value.as_uint()?,
+            data: data.as_bytes()?.to_vec(),
+        })
+    }
+
+    pub fn signing_hash(&self, chain_id: u64) -> [u8; 32] {
+        let fields = [self.fields(), vec![Rlp::uint(U256::from(chain_id)), Rlp::uint(U256::ZERO), Rlp::uint(U256::ZERO)]].concat();
+        keccak(Rlp::List(fields).encode()).into()
+    }
+
+    /// The raw signed transaction, ready for `eth_sendRawTransaction`.
+    pub fn sign(&self, private_key: U256, chain_id: u64) -> Option<Vec<u8>> {
+        let signature = secp256k1::sign(&self.signing_hash(chain_id), private_key)?;
+        let v = chain_id * 2 + 35 + signature.recovery_id as u64;
+        let fields = [self.fields(), vec![Rlp::uint(U256::from(v)), Rlp::uint(signature.r), Rlp::uint(signature.s)]].concat();
+        Some(Rlp::List(fields).encode())
+    }
+}
+
+/// An EIP-1559 (type 2) transaction, with an empty access list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eip1559Transaction {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub gas_limit: u64,
+    /// `None` creates a contract.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+}
+
+impl Eip1559Transaction {
+    fn fields(&self) -> Vec<Rlp> {
+        vec![
+            Rlp::uint(U256::from(self.chain_id)),
+            Rlp::uint(U256::from(self.nonce)),
+            Rlp::uint(self.max_priority_fee_per_gas),
+            Rlp::uint(self.max_fee_per_gas),
+            Rlp::uint(U256::from(self.gas_limit)),
+            self.to.as_ref().map_or(Rlp::Bytes(vec![]), Rlp::address),
+            Rlp::uint(self.value),
+            Rlp::Bytes(self.data.clone()),
+            Rlp::List(vec![]),
+        ]
+    }
+
+    pub fn signing_hash(&self) -> [u8; 32] {
+        keccak([vec![0x02], Rlp::List(self.fields()).encode()].concat()).into()
+    }
+
+    /// The raw signed transaction: the type byte followed by the RLP payload.
+    pub fn sign(&self, private_key: U256) -> Option<Vec<u8>> {
+        let signature = secp256k1::sign(&self.signing_hash(), private_key)?;
+        let fields = [self.fields(), vec![Rlp::uint(U256::from(signature.recovery_id)), Rlp::uint(signature.r), Rlp::uint(signature.s)]].concat();
+        Some([vec![0x02], Rlp::List(fields).encode()].concat())
+    }
+}
+
+/// Hash a node reports for the raw signed transaction `raw`, legacy or typed.
+pub fn transaction_hash(raw: &[u8]) -> [u8; 32] {
+    keccak(raw).into()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Signer {
+    /// Sign locally and send with `eth_sendRawTransaction`.
+    PrivateKey(U256),
+    /// Let the node sign for one of its unlocked accounts with `eth_sendTransaction`.
+    Unlocked(Address),
+}
+
+impl Signer {
+    pub fn address(&self) -> Option<Address> {
+        match self {
+            Signer::PrivateKey(key) => secp256k1::address_from_private_key(*key),
+            Signer::Unlocked(address) => Some(*address),
+        }
+    }
+}
+
+fn quantity(value: &serde_json::Value) -> Result<U256, RpcError> {
+    value.as_str()
+        .and_then(|hex| U256::from_str_radix(hex.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| RpcError::InvalidResponse(format!("expected a quantity, got {}", value)))
+}
+
+/// A quantity that must fit in 64 bits, such as a nonce, a gas amount or a chain id.
+fn small_quantity(value: &serde_json::Value) -> Result<u64, RpcError> {
+    u64::try_from(quantity(value)?).map_err(|_| RpcError::InvalidResponse(format!("{} does not fit in 64 bits", value)))
+}
+
+/// Sends `init_code` as a contract creation and waits for the receipt, returning the new address.
+pub fn deploy(client: &RpcClient, init_code: &[u8], signer: &Signer) -> Result<Address, RpcError> {
+    let from = signer.address().ok_or_else(|| RpcError::InvalidResponse("invalid private key".to_string()))?;
+    let from_hex_addr = format!("{}", from);
+    let gas_limit = small_quantity(&client.request("eth_estimateGas", json!([{ "from": from_hex_addr, "data": to_hex(init_code) }]))?)?;
+
+    let tx_hash = match signer {
+        Signer::PrivateKey(key) => {
+            let chain_id = small_quantity(&client.request("eth_chainId", json!([]))?)?;
+            let nonce = small_quantity(&client.request("eth_getTransactionCount", json!([from_hex_addr, "pending"]))?)?;
+            let gas_price = quantity(&client.request("eth_gasPrice", json!([]))?)?;
+            let tx = LegacyTransaction {
+                nonce,
+                gas_price,
+                gas_limit,
+                to: None,
+                value: U256::ZERO,
+                data: init_code.to_vec(),
+            };
+            let raw = tx.sign(*key, chain_id).ok_or_else(|| RpcError::InvalidResponse("signing failed".to_string()))?;
+            client.request("eth_sendRawTransaction", json!([to_hex(&raw)]))?
+        },
+        Signer::Unlocked(_) => client.request("eth_sendTransaction", json!([{
+            "from": from_hex_addr,
+            "data": to_hex(init_code),
+            "gas": format!("{:#x}", gas_limit),
+        }]))?,
+    };
+
+    for _ in 0..100 {
+        let receipt = client.request("eth_getTransactionReceipt", json!([tx_hash]))?;
+        if receipt.is_null() {
+            sleep(Duration::from_millis(100));
+            continue;
+        }
+        if receipt["status"] == "0x0" {
+            return Err(RpcError::InvalidResponse(format!("deployment {} reverted", tx_hash)));
+        }
+        return receipt["contractAddress"].as_str()
+            .and_then(from_hex)
+            .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+            .map(Address)
+            .ok_or_else(|| RpcError::InvalidResponse("receipt without contractAddress".to_string()));
+    }
+    Err(RpcError::InvalidResponse(format!("no receipt for {}", tx_hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_eip155_example() {
+        // The worked example from EIP-155.
+        let tx = LegacyTransaction {
+            nonce: 9,
+            gas_price: U256::from(20_000_000_000u64),
+            gas_limit: 21000,
+            to: Some(Address([0x35; 20])),
+            value: U256::from(1_000_000_000_000_000_000u64),
+            data: vec![],
+        };
+        assert_eq!(to_hex(&tx.signing_hash(1)), "0xdaf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53");
+
+        let key = U256::from_be_bytes([0x46; 32]);
+        assert_eq!(
+            to_hex(&tx.sign(key, 1).unwrap()),
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+        assert_eq!(LegacyTransaction::decode(&tx.sign(key, 1).unwrap()), Ok(tx.clone()));
+        assert_eq!(to_hex(&transaction_hash(&tx.sign(key, 1).unwrap())), "0x33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788");
+    }
+
+    #[test]
+    fn test_eip1559_signing() {
+        let tx = Eip1559Transaction {
+            chain_id: 1,
+            nonce: 0,
+            max_priority_fee_per_gas: U256::from(1_000_000_000u64),
+            max_fee_per_gas: U256::from(30_000_000_000u64),
+            gas_limit: 21000,
+            to: Some(Address([0x35; 20])),
+            value: U256::ONE,
+            data: vec![],
+        };
+        let key = U256::from_be_bytes([0x46; 32]);
+        let raw = tx.sign(key).unwrap();
+        assert_eq!(raw[0], 0x02);
+        let fields = Rlp::decode(&raw[1..]).unwrap();
+        let [.., parity, r, s] = fields.as_list().unwrap() else { panic!("too few fields") };
+        let signature = secp256k1::Signature { r: r.as_uint().unwrap(), s: s.as_uint().unwrap(), recovery_id: parity.as_u64().unwrap() as u8 };
+        assert_eq!(secp256k1::recover(&tx.signing_hash(), &signature), secp256k1::address_from_private_key(key));
+    }
+
+    /// Answers JSON-RPC requests the way anvil would for a single deployment.
+    fn mock_node() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 65536];
+                let mut request = String::new();
+                while !request.contains("\"method\"") || !request.ends_with('}') {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.push_str(&String::from_utf8_lossy(&buf[..n]));
+                }
+                // A client that hung up before sending a whole request gets no answer.
+                let Some(body) = request.split_once("\r\n\r\n").and_then(|(_, body)| serde_json::from_str::<serde_json::Value>(body).ok()) else {
+                    continue;
+                };
+                let result = match body["method"].as_str().unwrap() {
+                    "eth_chainId" => json!("0x7a69"),
+                    "eth_getTransactionCount" => json!("0x0"),
+                    "eth_gasPrice" => json!("0x3b9aca00"),
+                    "eth_estimateGas" => json!("0x186a0"),
+                    "eth_sendRawTransaction" => {
+                        assert!(body["params"][0].as_str().unwrap().starts_with("0xf8"));
+                        json!("0x1234")
+                    },
+                    "eth_getTransactionReceipt" => json!({ "status": "0x1", "contractAddress": "0x5fbdb2315678afecb367f032d93f642f64180aa3" }),
+                    _ => json!(null),
+                };
+                let response = json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string();
+                let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", response.len(), response);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_small_quantity() {
+        assert_eq!(small_quantity(&json!("0x7a69")), Ok(31337));
+        assert!(small_quantity(&json!("0x10000000000000000")).is_err());
+    }
+
+    #[test]
+    fn test_deploy_against_mock_node() {
+        let client = RpcClient::new(&mock_node()).unwrap();
+        let key = U256::from_str_radix("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80", 16).unwrap();
+        let address = deploy(&client, &[0x60, 0x00], &Signer::PrivateKey(key)).unwrap();
+        assert_eq!(format!("{}", address), "0x5fbdb2315678afecb367f032d93f642f64180aa3");
+    }
+}
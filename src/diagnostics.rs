@@ -0,0 +1,107 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A message about the source, optionally pointing at a byte span in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier of the check that produced it, e.g. `shadowing`.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<(usize, usize)>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self { severity, code, message: message.into(), span: None, notes: vec![] }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn with_span(mut self, span: (usize, usize)) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic with the offending source line underlined:
+    ///
+    /// ```text
+    /// warning[shadowing]: parameter `value` shadows a state variable
+    ///  --> Flipper.sol:5:23
+    ///   |
+    /// 5 |     function set(bool value) public {
+    ///   |                       ^^^^^
+    /// ```
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        if let Some((start, end)) = self.span {
+            let start = start.min(source.len());
+            let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+            let line = source[..start].matches('\n').count() + 1;
+            let column = source[line_start..start].chars().count();
+            let width = source[start..end.clamp(start, line_end)].chars().count().max(1);
+            let gutter = " ".repeat(line.to_string().len());
+
+            out += &format!("{} --> {}:{}:{}\n", gutter, file, line, column + 1);
+            out += &format!("{} |\n", gutter);
+            out += &format!("{} | {}\n", line, &source[line_start..line_end]);
+            out += &format!("{} | {}{}\n", gutter, " ".repeat(column), "^".repeat(width));
+        }
+        self.notes.iter().for_each(|note| out += &format!("  = note: {}\n", note));
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_underlines_span() {
+        let source = "contract A {\n    bool flag;\n}";
+        let diagnostic = Diagnostic::warning("unused", "`flag` is never read").with_span((22, 26)).with_note("remove it");
+        assert_eq!(diagnostic.render("A.sol", source), "\
+warning[unused]: `flag` is never read
+  --> A.sol:2:10
+  |
+2 |     bool flag;
+  |          ^^^^
+  = note: remove it
+");
+    }
+}
@@ -0,0 +1,325 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    pub const ZERO: Address = Address([0u8; 20]);
+
+    /// Addresses live on the stack as the low 20 bytes of a 256-bit word.
+    pub fn to_word(&self) -> U256 {
+        let mut bytes = [0u8; 32];
+        bytes[12..].copy_from_slice(&self.0);
+        U256::from_be_bytes(bytes)
+    }
+
+    pub fn from_word(word: U256) -> Self {
+        let mut bytes = [0u8; 20];
+        bytes.copy_from_slice(&word.to_be_bytes()[12..]);
+        Address(bytes)
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", self.0.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Hard caps on what a single call frame may allocate, enforced even when gas is not a
+/// meaningful bound, e.g. for untrusted code run with a huge gas limit. The defaults are
+/// far above what gas allows on mainnet, so they only stop runaway code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Bytes of working memory: linear memory plus the stack, at 32 bytes per item.
+    pub memory: usize,
+    /// Bytes of return data a single call frame may produce.
+    pub return_data: usize,
+}
+
+const MIB: usize = 1024 * 1024;
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory: 64 * MIB,
+            return_data: 16 * MIB,
+        }
+    }
+}
+
+/// The accounts and value of the running call frame, as `ADDRESS`, `CALLER`, `ORIGIN`,
+/// `CALLVALUE` and `GASPRICE` report them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallContext {
+    pub address: Address,
+    pub caller: Address,
+    pub origin: Address,
+    pub value: U256,
+    pub gas_price: U256,
+}
+
+/// The block code runs in, as `NUMBER`, `TIMESTAMP`, `COINBASE`, `CHAINID`, `BASEFEE`,
+/// `GASLIMIT` and `BLOCKHASH` report it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockEnv {
+    pub number: u64,
+    pub timestamp: u64,
+    pub coinbase: Address,
+    pub chain_id: u64,
+    pub base_fee: U256,
+    pub prevrandao: U256,
+    pub gas_limit: u64,
+    /// Hashes of earlier blocks by number.
+    pub hashes: BTreeMap<u64, [u8; 32]>,
+}
+
+/// Everything the VM needs to know about the transaction and block it runs in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Env {
+    pub caller: Address,
+    pub value: U256,
+    /// Account whose code runs.
+    pub address: Address,
+    /// Account that signed the transaction, when it isn't the caller.
+    pub origin: Option<Address>,
+    pub gas_limit: u64,
+    /// Gas limit of the block, for `GASLIMIT`, when it isn't the call's `gas_limit`.
+    pub block_gas_limit: Option<u64>,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub chain_id: u64,
+    /// Account charged for gas when it isn't the caller, as with a sponsored call.
+    pub payer: Option<Address>,
+    /// Price per gas of transactions without EIP-1559 fee caps.
+    pub gas_price: U256,
+    /// EIP-1559 base fee per gas, burned. `World::mine` adjusts it by how full each block
+    /// was.
+    pub base_fee: U256,
+    /// Receives the priority fee, the part of the gas price above the base fee.
+    pub coinbase: Address,
+    /// The beacon chain's randomness for the block, for `PREVRANDAO`. `World` draws it
+    /// from its seeded generator.
+    pub prevrandao: U256,
+    pub limits: ResourceLimits,
+    /// Hashes of earlier blocks by number, for `BLOCKHASH`. Only the 256 most recent are
+    /// visible to it.
+    pub block_hashes: BTreeMap<u64, [u8; 32]>,
+    /// Whether the code runs inside a `STATICCALL`, where anything that changes state halts.
+    pub is_static: bool,
+    /// Balances for `BALANCE` and `SELFBALANCE` when no `CallHandler` keeps accounts.
+    /// Missing accounts have none.
+    pub balances: BTreeMap<Address, U256>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self {
+            caller: Address::ZERO,
+            value: U256::ZERO,
+            address: Address::ZERO,
+            origin: None,
+            gas_limit: 30_000_000,
+            block_gas_limit: None,
+            block_number: 1,
+            timestamp: 1,
+            chain_id: 1,
+            payer: None,
+            gas_price: U256::ZERO,
+            base_fee: U256::ZERO,
+            coinbase: Address::ZERO,
+            prevrandao: U256::ZERO,
+            limits: ResourceLimits::default(),
+            block_hashes: BTreeMap::new(),
+            is_static: false,
+            balances: BTreeMap::new(),
+        }
+    }
+}
+
+impl Env {
+    pub fn builder() -> EnvBuilder {
+        EnvBuilder::new()
+    }
+
+    /// The account gas is charged to: the payer if there is one, otherwise the caller.
+    pub fn fee_payer(&self) -> Address {
+        self.payer.unwrap_or(self.caller)
+    }
+
+    pub fn block(&self) -> BlockEnv {
+        BlockEnv {
+            number: self.block_number,
+            timestamp: self.timestamp,
+            coinbase: self.coinbase,
+            chain_id: self.chain_id,
+            base_fee: self.base_fee,
+            prevrandao: self.prevrandao,
+            gas_limit: self.block_gas_limit.unwrap_or(self.gas_limit),
+            hashes: self.block_hashes.clone(),
+        }
+    }
+
+    pub fn context(&self) -> CallContext {
+        CallContext {
+            address: self.address,
+            caller: self.caller,
+            origin: self.origin.unwrap_or(self.caller),
+            value: self.value,
+            gas_price: self.gas_price,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EnvBuilder {
+    env: Env,
+}
+
+impl EnvBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn caller(mut self, caller: Address) -> Self {
+        self.env.caller = caller;
+        self
+    }
+
+    pub fn value(mut self, value: U256) -> Self {
+        self.env.value = value;
+        self
+    }
+
+    pub fn address(mut self, address: Address) -> Self {
+        self.env.address = address;
+        self
+    }
+
+    pub fn origin(mut self, origin: Address) -> Self {
+        self.env.origin = Some(origin);
+        self
+    }
+
+    pub fn gas_limit(mut self, gas_limit: u64) -> Self {
+        self.env.gas_limit = gas_limit;
+        self
+    }
+
+    pub fn block_gas_limit(mut self, block_gas_limit: u64) -> Self {
+        self.env.block_gas_limit = Some(block_gas_limit);
+        self
+    }
+
+    pub fn block_number(mut self, block_number: u64) -> Self {
+        self.env.block_number = block_number;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.env.timestamp = timestamp;
+        self
+    }
+
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.env.chain_id = chain_id;
+        self
+    }
+
+    pub fn payer(mut self, payer: Address) -> Self {
+        self.env.payer = Some(payer);
+        self
+    }
+
+    pub fn gas_price(mut self, gas_price: U256) -> Self {
+        self.env.gas_price = gas_price;
+        self
+    }
+
+    pub fn base_fee(mut self, base_fee: U256) -> Self {
+        self.env.base_fee = base_fee;
+        self
+    }
+
+    pub fn coinbase(mut self, coinbase: Address) -> Self {
+        self.env.coinbase = coinbase;
+        self
+    }
+
+    pub fn prevrandao(mut self, prevrandao: U256) -> Self {
+        self.env.prevrandao = prevrandao;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.env.limits = limits;
+        self
+    }
+
+    pub fn block_hash(mut self, number: u64, hash: [u8; 32]) -> Self {
+        self.env.block_hashes.insert(number, hash);
+        self
+    }
+
+    pub fn is_static(mut self, is_static: bool) -> Self {
+        self.env.is_static = is_static;
+        self
+    }
+
+    pub fn balance(mut self, address: Address, balance: U256) -> Self {
+        self.env.balances.insert(address, balance);
+        self
+    }
+
+    pub fn build(self) -> Env {
+        self.env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_builder_defaults() {
+        assert_eq!(EnvBuilder::new().build(), Env::default());
+    }
+
+    #[test]
+    fn test_env_builder_setters() {
+        let caller = Address([0x11; 20]);
+        let env = Env::builder()
+            .caller(caller)
+            .value(U256::from(5u8))
+            .gas_limit(100)
+            .block_number(42)
+            .timestamp(1000)
+            .chain_id(31337)
+            .build();
+
+        assert_eq!(env.caller, caller);
+        assert_eq!(env.value, U256::from(5u8));
+        assert_eq!(env.gas_limit, 100);
+        assert_eq!(env.block_number, 42);
+        assert_eq!(env.timestamp, 1000);
+        assert_eq!(env.chain_id, 31337);
+        assert_eq!(env.fee_payer(), caller);
+        assert_eq!(Env::builder().caller(caller).payer(Address::ZERO).build().fee_payer(), Address::ZERO);
+        assert_eq!(env.context().origin, caller);
+        assert_eq!(Env::builder().caller(caller).origin(Address::ZERO).build().context().origin, Address::ZERO);
+    }
+
+    #[test]
+    fn test_address_word_roundtrip() {
+        let addr = Address([0xab; 20]);
+        assert_eq!(Address::from_word(addr.to_word()), addr);
+    }
+}
@@ -0,0 +1,88 @@
+use ethnum::U256;
+use crate::abi::{calldata, Value};
+use crate::selector::selector;
+use crate::env::Address;
+use crate::solidity::grammar::Expression;
+use crate::tinyvm::Contract;
+use crate::world::{Transaction, World};
+
+/// `supportsInterface(bytes4)`, the one function of ERC-165 and therefore also its id.
+pub const SUPPORTS_INTERFACE: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+pub const ERC20_INTERFACE_ID: [u8; 4] = [0x36, 0x37, 0x2b, 0x07];
+pub const ERC721_INTERFACE_ID: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+/// Never a valid interface id; compliant contracts must answer `false` for it.
+pub const INVALID_INTERFACE_ID: [u8; 4] = [0xff; 4];
+
+/// XOR of the selectors of `signatures`, as Solidity's `type(I).interfaceId` computes it.
+pub fn interface_id(signatures: &[&str]) -> [u8; 4] {
+    signatures.iter().map(|signature| selector(signature)).fold([0; 4], |mut id, sel| {
+        id.iter_mut().zip(sel).for_each(|(a, b)| *a ^= b);
+        id
+    })
+}
+
+/// `id` as the left-aligned word a `bytes4` argument is encoded to.
+fn bytes4(id: [u8; 4]) -> Value {
+    let mut word = [0u8; 32];
+    word[..4].copy_from_slice(&id);
+    Value::Uint(U256::from_be_bytes(word))
+}
+
+impl Contract {
+    /// Interface id of every public and external function of the contract.
+    pub fn interface_id(&self) -> [u8; 4] {
+        let signatures = self.abi().functions.iter().map(|function| function.signature()).collect::<Vec<_>>();
+        interface_id(&signatures.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+}
+
+impl World {
+    /// Whether the contract at `address` implements interface `id`, following the ERC-165
+    /// detection procedure: it must claim ERC-165 itself, reject `0xffffffff`, and claim
+    /// `id`. Queries are simulated, so nothing is committed.
+    pub fn supports_interface(&self, address: Address, id: [u8; 4]) -> bool {
+        let claims = |id| {
+            let result = self.simulate(&Transaction::new(Address::default(), address, &calldata("supportsInterface(bytes4)", &[bytes4(id)])));
+            result.success && result.output == vec![Expression::BoolLiteral(true)]
+        };
+        claims(SUPPORTS_INTERFACE) && !claims(INVALID_INTERFACE_ID) && claims(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::{Parameter, Type};
+    use crate::test_utils::*;
+    use crate::selector::selector;
+    use crate::tinyvm::{Function, OP};
+
+    #[test]
+    fn test_interface_ids() {
+        assert_eq!(interface_id(&["supportsInterface(bytes4)"]), SUPPORTS_INTERFACE);
+        assert_eq!(interface_id(&crate::erc20::FUNCTIONS), ERC20_INTERFACE_ID);
+        assert_eq!(interface_id(&[
+            "balanceOf(address)", "ownerOf(uint256)", "approve(address,uint256)", "getApproved(uint256)",
+            "setApprovalForAll(address,bool)", "isApprovedForAll(address,address)", "transferFrom(address,address,uint256)",
+            "safeTransferFrom(address,address,uint256)", "safeTransferFrom(address,address,uint256,bytes)",
+        ]), ERC721_INTERFACE_ID);
+        assert_eq!(flipper().interface_id(), interface_id(&["flip()", "get()"]));
+    }
+
+    #[test]
+    fn test_supports_interface_rejects_non_compliant_contracts() {
+        // Answers `true` to everything, including the invalid id.
+        let mut yes = Function::from_program("supportsInterface".to_string(), vec![
+            OP::PUSH1(1), OP::PUSH1(0), OP::MSTORE, OP::PUSH1(32), OP::PUSH1(0), OP::RETURN,
+        ]);
+        yes.returns = vec![Parameter { ty: Expression::Type(Type::Bool(())), name: None }];
+        let mut contract = Contract::new("Yes".to_string());
+        contract.insert_function(selector("supportsInterface(bytes4)"), yes).unwrap();
+
+        let mut world = World::new_dev();
+        let yes = world.deploy(alice(), contract);
+        let flipper = world.deploy(alice(), flipper());
+        assert!(!world.supports_interface(yes, ERC20_INTERFACE_ID));
+        assert!(!world.supports_interface(flipper, SUPPORTS_INTERFACE));
+    }
+}
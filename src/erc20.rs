@@ -0,0 +1,88 @@
+use crate::tinyvm::Contract;
+
+/// The bundled reference token, `contracts/erc20.sol`.
+pub const SOURCE: &str = include_str!("../contracts/erc20.sol");
+
+/// Public functions every ERC-20 token exposes, by canonical signature.
+pub const FUNCTIONS: [&str; 6] = [
+    "totalSupply()",
+    "balanceOf(address)",
+    "allowance(address,address)",
+    "transfer(address,uint256)",
+    "approve(address,uint256)",
+    "transferFrom(address,address,uint256)",
+];
+
+pub const EVENTS: [&str; 2] = [
+    "Transfer(address,address,uint256)",
+    "Approval(address,address,uint256)",
+];
+
+/// ERC-20 functions `contract` does not implement.
+pub fn missing_functions(contract: &Contract) -> Vec<&'static str> {
+    let implemented = contract.abi().functions.iter().map(|function| function.signature()).collect::<Vec<_>>();
+    FUNCTIONS.iter().copied().filter(|signature| !implemented.iter().any(|s| s == signature)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethnum::U256;
+    use crate::abi::{calldata, encode, Value};
+    use crate::revert::RevertReason;
+    use crate::selector::event_topic;
+    use crate::solidity::grammar::{parse, Expression};
+    use crate::test_utils::*;
+    use crate::tinyvm::create_contracts;
+    use crate::world::World;
+
+    #[test]
+    fn test_missing_functions() {
+        assert_eq!(missing_functions(&flipper()), FUNCTIONS.to_vec());
+        assert_eq!(&calldata("transfer(address,uint256)", &[])[..8], "a9059cbb");
+    }
+
+    /// The flagship end-to-end test: compile the bundled token and exercise the standard
+    /// transfer and allowance flows against a `World`.
+    #[test]
+    fn test_erc20_conformance() {
+        let token = create_contracts(parse(SOURCE).expect("erc20.sol should parse")).remove(0);
+        assert!(token.diagnostics.is_empty(), "{:?}", token.diagnostics);
+        assert_eq!(missing_functions(&token), Vec::<&str>::new());
+        assert_eq!(token.abi().events.iter().map(|event| event.signature()).collect::<Vec<_>>(), EVENTS.to_vec());
+
+        let mut world = World::new_dev();
+        let amount = |n: u64| Value::Uint(U256::from(n));
+        let number = |n: u64| vec![Expression::NumberLiteral(U256::from(n))];
+        let ok = vec![Expression::BoolLiteral(true)];
+
+        // The constructor mints the supply to the deployer.
+        let (address, minted) = world.deploy_with_args(alice(), token, &[amount(100)]);
+        assert!(minted.success);
+        assert_eq!(minted.logs[0].topics, vec![event_topic(EVENTS[0]), U256::ZERO, alice().to_word()]);
+        let balance = |world: &mut World, account| world.call(alice(), address, &calldata("balanceOf(address)", &[Value::Address(account)])).output;
+        assert_eq!(world.call(alice(), address, &calldata("totalSupply()", &[])).output, number(100));
+        assert_eq!(balance(&mut world, alice()), number(100));
+
+        let transfer = world.call(alice(), address, &calldata("transfer(address,uint256)", &[Value::Address(bob()), amount(30)]));
+        assert_eq!(transfer.output, ok);
+        assert_eq!(transfer.logs[0].topics, vec![event_topic(EVENTS[0]), alice().to_word(), bob().to_word()]);
+        assert_eq!(transfer.logs[0].data, encode(&[amount(30)]));
+        assert_eq!((balance(&mut world, alice()), balance(&mut world, bob())), (number(70), number(30)));
+
+        // Transfers beyond the balance revert with a reason.
+        let over = world.call(bob(), address, &calldata("transfer(address,uint256)", &[Value::Address(carol()), amount(31)]));
+        assert_eq!(over.revert_reason, Some(RevertReason::Error("insufficient balance".to_string())));
+
+        // bob may move what alice approved, but no more than that.
+        assert_eq!(world.call(alice(), address, &calldata("approve(address,uint256)", &[Value::Address(bob()), amount(20)])).output, ok);
+        let allowance = calldata("allowance(address,address)", &[Value::Address(alice()), Value::Address(bob())]);
+        assert_eq!(world.call(carol(), address, &allowance).output, number(20));
+        let transfer_from = |n| calldata("transferFrom(address,address,uint256)", &[Value::Address(alice()), Value::Address(carol()), amount(n)]);
+        assert_eq!(world.call(bob(), address, &transfer_from(15)).output, ok);
+        assert_eq!((balance(&mut world, alice()), balance(&mut world, carol())), (number(55), number(15)));
+        assert_eq!(world.call(carol(), address, &allowance).output, number(5));
+        let over = world.call(bob(), address, &transfer_from(6));
+        assert_eq!(over.revert_reason, Some(RevertReason::Error("insufficient allowance".to_string())));
+    }
+}
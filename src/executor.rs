@@ -0,0 +1,156 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::call::CallHandler;
+use crate::env::Env;
+use crate::log::LogEntry;
+use crate::tinyvm::*;
+
+/// Slot-level access to a contract's storage, all an executor needs from the state.
+pub trait Storage {
+    fn load(&self, key: U256) -> U256;
+    fn store(&mut self, key: U256, value: U256);
+
+    /// Slots that may hold a nonzero value, so that a call frame can hand the storage on
+    /// to another. Backends that can't list their slots keep this default.
+    fn keys(&self) -> Vec<U256> {
+        vec![]
+    }
+}
+
+/// Writes buffered over `base`, which sees none of them until `commit`.
+pub struct Overlay<'a> {
+    base: &'a mut dyn Storage,
+    writes: BTreeMap<U256, U256>,
+}
+
+impl<'a> Overlay<'a> {
+    pub fn new(base: &'a mut dyn Storage) -> Self {
+        Self { base, writes: BTreeMap::new() }
+    }
+
+    pub fn commit(self) {
+        self.writes.into_iter().for_each(|(key, value)| self.base.store(key, value));
+    }
+}
+
+impl Storage for Overlay<'_> {
+    fn load(&self, key: U256) -> U256 {
+        self.writes.get(&key).copied().unwrap_or_else(|| self.base.load(key))
+    }
+
+    fn store(&mut self, key: U256, value: U256) {
+        self.writes.insert(key, value);
+    }
+
+    fn keys(&self) -> Vec<U256> {
+        let mut keys = self.base.keys();
+        keys.extend(self.writes.keys());
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// Result of running code on an `Executor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The stack when execution ended, bottom first.
+    pub stack: Vec<U256>,
+    /// Bytes passed to `RETURN`.
+    pub return_data: Vec<u8>,
+    /// Logs emitted, empty if execution halted.
+    pub logs: Vec<LogEntry>,
+    /// Inputs of the hashes computed, keyed by hash. Backends may leave this empty.
+    pub preimages: BTreeMap<U256, Vec<u8>>,
+    pub gas_used: u64,
+    pub halt: Option<Halt>,
+}
+
+/// A backend that runs compiled code. `Interpreter` is the built-in one; any other, such
+/// as one delegating to revm or a JIT, can be used for `Contract` and `World` calls
+/// instead by implementing this trait.
+pub trait Executor: fmt::Debug + Send + Sync {
+    /// Runs `program` with `calldata` under `env`. On success `storage` holds the new state
+    /// and `tx_state` the accesses made; on a halt both must be left as they were.
+    /// Backends that don't step through a `VM` may ignore `inspector`.
+    fn execute(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, inspector: &mut dyn Inspector) -> Outcome;
+
+    /// Like `execute`, with `calls` running the `CALL`s the code makes, and no inspector.
+    /// Backends that can't make calls keep this default, under which every call fails.
+    fn execute_with_calls(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Outcome {
+        let _ = calls;
+        self.execute(program, calldata, env, storage, tx_state, &mut NoopInspector)
+    }
+}
+
+/// The built-in interpreter, `VM`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interpreter;
+
+impl Executor for Interpreter {
+    fn execute(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, inspector: &mut dyn Inspector) -> Outcome {
+        interpret(VM::builder(program.to_vec()).calldata(calldata).env(env), storage, tx_state, inspector)
+    }
+
+    fn execute_with_calls(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Outcome {
+        interpret(VM::builder(program.to_vec()).calldata(calldata).env(env).calls(calls), storage, tx_state, &mut NoopInspector)
+    }
+}
+
+/// Runs the VM `builder` sets up, as `Executor::execute` does.
+pub(crate) fn interpret(builder: VmBuilder, storage: &mut dyn Storage, tx_state: &mut TxState, inspector: &mut dyn Inspector) -> Outcome {
+    let mut vm = builder.tx_state(tx_state.clone()).build();
+    vm.run_on(storage, inspector);
+    if vm.halt.is_none() {
+        *tx_state = vm.tx_state();
+    }
+    let logs = if vm.halt.is_none() { vm.logs().to_vec() } else { vec![] };
+    Outcome {
+        stack: vm.stack.as_slice().to_vec(),
+        return_data: vm.return_data().to_vec(),
+        logs,
+        preimages: vm.preimages().clone(),
+        gas_used: vm.gas_used,
+        halt: vm.halt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::solidity::grammar::Expression;
+    use crate::test_utils::*;
+    use crate::world::World;
+
+    /// Answers every call by returning 7, without touching storage.
+    #[derive(Debug)]
+    struct Seven;
+
+    impl Executor for Seven {
+        fn execute(&self, _program: &[OP], _calldata: &[u8], _env: Env, _storage: &mut dyn Storage, _tx_state: &mut TxState, _inspector: &mut dyn Inspector) -> Outcome {
+            Outcome { stack: vec![], return_data: U256::from(7u8).to_be_bytes().to_vec(), logs: vec![], preimages: BTreeMap::new(), gas_used: 1, halt: None }
+        }
+    }
+
+    #[test]
+    fn test_world_uses_executor() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        let flip = calldata("flip()", &[]);
+        let get = calldata("get()", &[]);
+
+        world.call(bob(), address, &flip);
+        assert_eq!(world.call(bob(), address, &get).output, vec![Expression::BoolLiteral(true)]);
+
+        world.executor = std::sync::Arc::new(Seven);
+        let result = world.call(bob(), address, &flip);
+        assert_eq!(result.gas_used, 21001);
+
+        // The flip didn't run, so `value` is still set.
+        world.executor = std::sync::Arc::new(Interpreter);
+        assert_eq!(world.call(bob(), address, &get).output, vec![Expression::BoolLiteral(true)]);
+    }
+}
@@ -0,0 +1,305 @@
+use ethnum::{I256, U256};
+use std::collections::HashSet;
+use std::fmt;
+use crate::abi::*;
+use crate::bytecode::to_hex;
+use crate::env::{Address, Env};
+use crate::solidity::grammar::Expression;
+use crate::test_utils::{address, DEV_ACCOUNTS};
+use crate::tinyvm::*;
+
+/// Deterministic splitmix64 generator, so a failing run can be reproduced from its seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, `n` must be non-zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A uniformly random word.
+    pub fn uniform_word(&mut self) -> U256 {
+        U256::from_words(
+            ((self.next_u64() as u128) << 64) | self.next_u64() as u128,
+            ((self.next_u64() as u128) << 64) | self.next_u64() as u128,
+        )
+    }
+
+    /// A random word, biased towards the edge values that tend to break arithmetic.
+    pub fn word(&mut self) -> U256 {
+        const EDGES: [U256; 4] = [U256::ZERO, U256::ONE, U256::new(2), U256::MAX];
+        if self.below(4) == 0 {
+            EDGES[self.below(EDGES.len())]
+        } else {
+            self.uniform_word()
+        }
+    }
+
+    fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        (0..self.below(max_len + 1)).map(|_| self.next_u64() as u8).collect()
+    }
+
+    /// A random value of type `ty`, within the type's range.
+    pub fn value(&mut self, ty: &AbiType) -> Value {
+        match ty {
+            AbiType::Bool => Value::Bool(self.next_u64() & 1 == 1),
+            AbiType::Uint(bits) => Value::Uint(self.word() & (U256::MAX >> (256 - bits) as u32)),
+            AbiType::Int(bits) => {
+                let shift = (256 - bits) as u32;
+                Value::Int((self.word().as_i256() << shift) >> shift)
+            },
+            AbiType::Address => match self.below(4) {
+                0 => Value::Address(Address(self.bytes(20).into_iter().chain([0; 20]).take(20).collect::<Vec<_>>().try_into().unwrap())),
+                _ => Value::Address(address(DEV_ACCOUNTS[self.below(DEV_ACCOUNTS.len())])),
+            },
+            AbiType::String => Value::String(self.bytes(32).into_iter().map(|b| (b' ' + b % 95) as char).collect()),
+            AbiType::Bytes => Value::Bytes(self.bytes(64)),
+        }
+    }
+}
+
+fn zero_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(_) => Value::Bool(false),
+        Value::Uint(_) => Value::Uint(U256::ZERO),
+        Value::Int(_) => Value::Int(I256::ZERO),
+        Value::Address(_) => Value::Address(Address::ZERO),
+        Value::String(_) => Value::String(String::new()),
+        Value::Bytes(_) => Value::Bytes(vec![]),
+    }
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Uint(v) => v.to_string(),
+        Value::Int(v) => v.to_string(),
+        Value::Address(a) => a.to_string(),
+        Value::String(s) => format!("{:?}", s),
+        Value::Bytes(b) => to_hex(b),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzConfig {
+    /// Number of call sequences to try.
+    pub runs: usize,
+    /// Maximum number of calls per sequence.
+    pub depth: usize,
+    pub seed: u64,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self { runs: 256, depth: 16, seed: 0 }
+    }
+}
+
+/// One generated call in a sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzCall {
+    pub signature: String,
+    pub args: Vec<Value>,
+    pub caller: Address,
+}
+
+impl FuzzCall {
+    pub fn calldata(&self) -> String {
+        CalldataBuilder::new(&self.signature).args(self.args.clone()).to_hex()
+    }
+}
+
+impl fmt::Display for FuzzCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.signature.split('(').next().unwrap_or_default();
+        let args = self.args.iter().map(display_value).collect::<Vec<_>>().join(", ");
+        write!(f, "{}({}) from {}", name, args, self.caller)
+    }
+}
+
+/// An invariant that returned something other than `true`, with the shortest call
+/// sequence found that breaks it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantFailure {
+    pub invariant: String,
+    pub sequence: Vec<FuzzCall>,
+}
+
+impl fmt::Display for InvariantFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} broken", self.invariant)?;
+        if self.sequence.is_empty() {
+            return write!(f, " on deployment");
+        }
+        write!(f, " after:")?;
+        self.sequence.iter().enumerate().try_for_each(|(i, call)| write!(f, "\n  {}. {}", i + 1, call))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzReport {
+    pub invariants: Vec<String>,
+    pub runs: usize,
+    pub calls: usize,
+    pub failures: Vec<InvariantFailure>,
+}
+
+impl FuzzReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for FuzzReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} invariants, {} runs, {} calls", self.invariants.len(), self.runs, self.calls)?;
+        self.invariants.iter().try_for_each(|invariant| {
+            match self.failures.iter().find(|failure| failure.invariant == *invariant) {
+                Some(failure) => writeln!(f, "[FAIL] {}", failure),
+                None => writeln!(f, "[PASS] {}", invariant),
+            }
+        })
+    }
+}
+
+fn run_sequence(contract: &Contract, sequence: &[FuzzCall]) -> Contract {
+    sequence.iter().fold(contract.clone(), |state, call| {
+        let env = Env::builder().caller(call.caller).build();
+        state.execute(&call.calldata(), env, &mut NoopInspector).contract
+    })
+}
+
+fn holds(contract: &Contract, invariant: &str) -> bool {
+    let execution = contract.execute(&calldata(invariant, &[]), Env::default(), &mut NoopInspector);
+    execution.halt.is_none() && execution.output == vec![Expression::BoolLiteral(true)]
+}
+
+/// Drops runs of calls, longest first, then zeroes arguments, as long as the invariant
+/// stays broken.
+fn shrink(contract: &Contract, invariant: &str, mut sequence: Vec<FuzzCall>) -> Vec<FuzzCall> {
+    let breaks = |sequence: &[FuzzCall]| !holds(&run_sequence(contract, sequence), invariant);
+    for chunk in (1..=sequence.len()).rev() {
+        let mut i = 0;
+        while i + chunk <= sequence.len() {
+            let mut candidate = sequence.clone();
+            candidate.drain(i..i + chunk);
+            if breaks(&candidate) {
+                sequence = candidate;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    for i in 0..sequence.len() {
+        for j in 0..sequence[i].args.len() {
+            let mut candidate = sequence.clone();
+            candidate[i].args[j] = zero_value(&candidate[i].args[j]);
+            if candidate != sequence && breaks(&candidate) {
+                sequence = candidate;
+            }
+        }
+    }
+    sequence
+}
+
+/// Runs random sequences of calls to the state-changing public functions of `contract`
+/// and checks every `invariant_*()` function, which must return `true`, after each one.
+pub fn fuzz(contract: &Contract, config: &FuzzConfig) -> FuzzReport {
+    let abi = contract.abi();
+    let invariants = abi.functions.iter()
+        .filter(|f| f.name.starts_with("invariant_") && f.inputs.is_empty())
+        .map(AbiFunction::signature)
+        .collect::<Vec<_>>();
+    let targets = abi.functions.iter()
+        .filter(|f| !f.name.starts_with("invariant_") && !matches!(f.state_mutability.as_str(), "view" | "pure"))
+        .filter_map(|f| Some((f.signature(), f.inputs.iter().map(|p| AbiType::parse(&p.ty)).collect::<Option<Vec<_>>>()?)))
+        .collect::<Vec<_>>();
+
+    let mut rng = Rng::new(config.seed);
+    let mut report = FuzzReport { invariants: invariants.clone(), runs: 0, calls: 0, failures: vec![] };
+    let mut broken = HashSet::new();
+    let runs = if targets.is_empty() { 1 } else { config.runs };
+
+    for run in 0..runs {
+        let sequence = if run == 0 || targets.is_empty() {
+            vec![]
+        } else {
+            (0..1 + rng.below(config.depth.max(1))).map(|_| {
+                let (signature, types) = &targets[rng.below(targets.len())];
+                FuzzCall {
+                    signature: signature.clone(),
+                    args: types.iter().map(|ty| rng.value(ty)).collect(),
+                    caller: address(DEV_ACCOUNTS[rng.below(DEV_ACCOUNTS.len())]),
+                }
+            }).collect()
+        };
+        report.runs += 1;
+        report.calls += sequence.len();
+
+        let state = run_sequence(contract, &sequence);
+        for invariant in &invariants {
+            if !broken.contains(invariant) && !holds(&state, invariant) {
+                broken.insert(invariant.clone());
+                report.failures.push(InvariantFailure {
+                    invariant: invariant.clone(),
+                    sequence: shrink(contract, invariant, sequence.clone()),
+                });
+            }
+        }
+        if broken.len() == invariants.len() {
+            break;
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::parse;
+
+    const TOGGLE: &str = "contract Toggle {
+        bool private on;
+        bool private broken;
+        function toggle() public { on = !on; }
+        function noop() public { on = on; }
+        function invariant_off() public view returns (bool) { return !on; }
+        function invariant_intact() public view returns (bool) { return !broken; }
+    }";
+
+    #[test]
+    fn test_finds_and_shrinks_failure() {
+        let contract = create_contracts(parse(TOGGLE).unwrap()).remove(0);
+        let report = fuzz(&contract, &FuzzConfig { runs: 64, depth: 8, seed: 7 });
+        assert!(!report.passed());
+        assert_eq!(report.invariants, vec!["invariant_intact()", "invariant_off()"]);
+        assert_eq!(report.failures.len(), 1);
+
+        let failure = &report.failures[0];
+        assert_eq!(failure.invariant, "invariant_off()");
+        assert_eq!(failure.sequence.iter().map(|c| c.signature.as_str()).collect::<Vec<_>>(), vec!["toggle()"]);
+        assert!(report.to_string().contains("[PASS] invariant_intact()"));
+    }
+
+    #[test]
+    fn test_values_in_range() {
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            assert!(matches!(rng.value(&AbiType::Uint(8)), Value::Uint(v) if v <= 255));
+            assert!(matches!(rng.value(&AbiType::Int(8)), Value::Int(v) if (I256::new(-128)..=I256::new(127)).contains(&v)));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::call::CallHandler;
+use crate::env::{CallContext, Env};
+use crate::executor::{interpret, Executor, Outcome, Storage};
+use crate::fuzz::Rng;
+use crate::tinyvm::*;
+
+/// Opcodes reserved for host functions, `0xc0` through `0xcf`, which `OP::HOST` numbers
+/// from 0.
+pub const HOST_OPCODES: std::ops::RangeInclusive<u8> = 0xc0..=0xcf;
+
+/// What a host function sees of the VM while it runs.
+pub struct HostCall<'a> {
+    /// The word popped from the stack.
+    pub input: U256,
+    pub context: &'a CallContext,
+    memory: &'a mut [u8],
+}
+
+impl<'a> HostCall<'a> {
+    pub(crate) fn new(input: U256, context: &'a CallContext, memory: &'a mut [u8]) -> Self {
+        Self { input, context, memory }
+    }
+
+    /// `len` bytes of memory from `offset`, `None` if they aren't all in memory yet.
+    pub fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.memory.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Overwrites memory from `offset` with `bytes`. Host functions can't grow memory, so
+    /// this is `None` if the code hasn't expanded it that far.
+    pub fn write(&mut self, offset: usize, bytes: &[u8]) -> Option<()> {
+        self.memory.get_mut(offset..offset.checked_add(bytes.len())?)?.copy_from_slice(bytes);
+        Some(())
+    }
+}
+
+/// Gas the randomness cheatcode charges, as much as `BLOCKHASH`.
+const G_RANDOMNESS: u64 = 20;
+
+/// A host function: the word it pushes, or the halt it ends the call with.
+pub type HostFn = Arc<dyn Fn(&mut HostCall) -> Result<U256, Halt> + Send + Sync>;
+
+#[derive(Clone)]
+struct Handler {
+    gas: u64,
+    function: HostFn,
+}
+
+/// Rust closures run by the `OP::HOST` opcodes, for extensions like oracles or randomness
+/// that the VM doesn't provide. Each pops one word, which the function gets as its input,
+/// and pushes the word it returns.
+#[derive(Clone, Default)]
+pub struct HostFunctions {
+    handlers: BTreeMap<u8, Handler>,
+}
+
+impl fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys().map(|id| OP::HOST(*id).name())).finish()
+    }
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `function` for `OP::HOST(id)`, charging `gas` each time. Replaces any function
+    /// registered for `id` before.
+    pub fn register(&mut self, id: u8, gas: u64, function: impl Fn(&mut HostCall) -> Result<U256, Halt> + Send + Sync + 'static) -> &mut Self {
+        assert!(id < HOST_OPCODES.len() as u8, "host function {} outside the reserved range", id);
+        self.handlers.insert(id, Handler { gas, function: Arc::new(function) });
+        self
+    }
+
+    /// Registers a randomness cheatcode for `OP::HOST(id)`: each call answers with the next
+    /// word from a generator seeded with `seed`, below the input unless that is zero. Clones
+    /// share the generator, so a run with the same seed and calls sees the same words.
+    pub fn randomness(&mut self, id: u8, seed: u64) -> &mut Self {
+        let rng = Mutex::new(Rng::new(seed));
+        self.register(id, G_RANDOMNESS, move |call| {
+            let word = rng.lock().unwrap().uniform_word();
+            Ok(if call.input == U256::ZERO { word } else { word % call.input })
+        })
+    }
+
+    /// Gas charged for `OP::HOST(id)`, `None` if nothing is registered for it.
+    pub fn gas(&self, id: u8) -> Option<u64> {
+        self.handlers.get(&id).map(|handler| handler.gas)
+    }
+
+    pub(crate) fn call(&self, id: u8, call: &mut HostCall) -> Result<U256, Halt> {
+        match self.handlers.get(&id) {
+            Some(handler) => (handler.function)(call),
+            None => Err(Halt::InvalidOpcode(HOST_OPCODES.start() + id)),
+        }
+    }
+}
+
+/// The built-in interpreter with host functions installed, for `World::executor`.
+#[derive(Debug, Clone, Default)]
+pub struct HostedInterpreter {
+    pub hosts: HostFunctions,
+}
+
+impl HostedInterpreter {
+    pub fn new(hosts: HostFunctions) -> Self {
+        Self { hosts }
+    }
+}
+
+impl Executor for HostedInterpreter {
+    fn execute(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, inspector: &mut dyn Inspector) -> Outcome {
+        interpret(VM::builder(program.to_vec()).calldata(calldata).env(env).hosts(self.hosts.clone()), storage, tx_state, inspector)
+    }
+
+    fn execute_with_calls(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Outcome {
+        let builder = VM::builder(program.to_vec()).calldata(calldata).env(env).hosts(self.hosts.clone()).calls(calls);
+        interpret(builder, storage, tx_state, &mut NoopInspector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::selector::selector;
+    use crate::solidity::grammar::{Expression, Parameter, Type};
+    use crate::test_utils::*;
+    use crate::world::World;
+
+    #[test]
+    fn test_host_functions() {
+        let mut hosts = HostFunctions::new();
+        // An oracle answering with twice the query, and one that copies its input into memory.
+        hosts.register(0, 50, |call| Ok(call.input * 2));
+        hosts.register(1, 10, |call| {
+            call.write(0, &call.input.to_be_bytes()).ok_or(Halt::Revert(vec![]))?;
+            Ok(call.context.caller.to_word())
+        });
+
+        let program = vec![OP::PUSH1(21), OP::HOST(0), OP::PUSH0, OP::MLOAD, OP::POP, OP::HOST(1)];
+        let env = Env::builder().caller(alice()).build();
+        let mut vm = VM::builder(program).env(env).hosts(hosts.clone()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(vm.stack.as_slice(), [alice().to_word()]);
+        assert_eq!(vm.memory()[31], 42);
+        assert_eq!(vm.gas_used, 3 + 50 + 2 + (3 + 3) + 2 + 10);
+
+        // Memory the code hasn't expanded can't be written, and unregistered ids are invalid.
+        let run = |program: Vec<OP>| {
+            let mut vm = VM::builder(program).hosts(hosts.clone()).build();
+            vm.run(ContractStorage::new(vec![]));
+            vm.halt
+        };
+        assert_eq!(run(vec![OP::PUSH1(1), OP::HOST(1)]), Some(Halt::Revert(vec![])));
+        assert_eq!(run(vec![OP::PUSH1(1), OP::HOST(2)]), Some(Halt::InvalidOpcode(0xc2)));
+
+        let mut contract = Contract::new("Oracle".to_string());
+        let program = vec![OP::PUSH1(21), OP::HOST(0), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN];
+        let mut function = Function::from_program("answer".to_string(), program);
+        function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+        contract.insert_function(selector("answer()"), function).unwrap();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        world.executor = Arc::new(HostedInterpreter::new(hosts));
+        let result = world.call(bob(), address, &calldata("answer()", &[]));
+        assert_eq!(result.output, vec![Expression::NumberLiteral(U256::from(42u8))]);
+    }
+
+    #[test]
+    fn test_randomness_cheatcode() {
+        let program = vec![OP::PUSH0, OP::HOST(3), OP::PUSH1(6), OP::HOST(3), OP::PUSH1(6), OP::HOST(3)];
+        let run = |seed: u64| {
+            let mut hosts = HostFunctions::new();
+            hosts.randomness(3, seed);
+            let mut vm = VM::builder(program.clone()).hosts(hosts).build();
+            vm.run(ContractStorage::new(vec![]));
+            assert_eq!(vm.gas_used, 3 * 20 + 2 + 3 + 3);
+            vm.stack.as_slice().to_vec()
+        };
+        let words = run(7);
+        assert_eq!(words, run(7));
+        assert_ne!(words, run(8));
+        assert_eq!(words[0], Rng::new(7).uniform_word());
+        assert!(words[1..].iter().all(|word| *word < 6));
+    }
+}
@@ -0,0 +1,1578 @@
+use ethnum::U256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use crate::solidity::grammar::*;
+use crate::tinyvm::OP;
+use crate::abi::{encode, AbiEvent, Value};
+use crate::bytecode::code_offsets;
+use crate::revert::ERROR_SELECTOR;
+
+/// An SSA value, the result of the instruction with the same index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ValueId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Const(U256),
+    /// The function's argument at this index.
+    Param(usize),
+    CalldataLoad(ValueId),
+    SLoad(ValueId),
+    /// Produces no meaningful value.
+    SStore { key: ValueId, value: ValueId },
+    IsZero(ValueId),
+    /// Bitwise complement.
+    BitNot(ValueId),
+    Binary(BinaryOp, ValueId, ValueId),
+    /// The keccak256 hash of the words, such as the slot of a mapping entry.
+    Keccak(Vec<ValueId>),
+    /// A word of the execution environment, read with an opcode that pops nothing, e.g.
+    /// `PREVRANDAO`.
+    Env(OP),
+    /// Logs `data`, one word per value, under `topics`. Produces no meaningful value.
+    Log { topics: Vec<ValueId>, data: Vec<ValueId> },
+    /// Calls an internal function by name with its arguments, producing its first return
+    /// value.
+    Call(String, Vec<ValueId>),
+    /// The value from whichever predecessor control came from.
+    Phi(Vec<(BlockId, ValueId)>),
+}
+
+impl Inst {
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Inst::Const(_) | Inst::Param(_) | Inst::Env(_) => vec![],
+            Inst::CalldataLoad(v) | Inst::SLoad(v) | Inst::IsZero(v) | Inst::BitNot(v) => vec![*v],
+            Inst::SStore { key, value } => vec![*key, *value],
+            Inst::Binary(_, lhs, rhs) => vec![*lhs, *rhs],
+            Inst::Keccak(words) | Inst::Call(_, words) => words.clone(),
+            Inst::Log { topics, data } => topics.iter().chain(data).copied().collect(),
+            Inst::Phi(incoming) => incoming.iter().map(|(_, v)| *v).collect(),
+        }
+    }
+
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, Inst::SStore { .. } | Inst::Log { .. } | Inst::Call(..))
+    }
+
+    /// Applies `f` to every operand in place.
+    pub fn map_operands(&mut self, mut f: impl FnMut(&mut ValueId)) {
+        match self {
+            Inst::Const(_) | Inst::Param(_) | Inst::Env(_) => {},
+            Inst::CalldataLoad(v) | Inst::SLoad(v) | Inst::IsZero(v) | Inst::BitNot(v) => f(v),
+            Inst::Keccak(words) | Inst::Call(_, words) => words.iter_mut().for_each(f),
+            Inst::Log { topics, data } => topics.iter_mut().chain(data).for_each(f),
+            Inst::SStore { key, value } => {
+                f(key);
+                f(value);
+            },
+            Inst::Binary(_, lhs, rhs) => {
+                f(lhs);
+                f(rhs);
+            },
+            Inst::Phi(incoming) => incoming.iter_mut().for_each(|(_, v)| f(v)),
+        }
+    }
+}
+
+/// Operations on two 256-bit words. Arithmetic wraps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Exp,
+    /// Signed division and remainder, for `int256` operands.
+    SDiv,
+    SMod,
+    And,
+    Or,
+    Xor,
+    /// Shifts, taking the shift amount as the left operand like the EVM: `Shl(n, x)` is
+    /// `x << n`. `Sar` is the arithmetic shift for `int256` operands.
+    Shl,
+    Shr,
+    Sar,
+    /// Comparisons, 1 if they hold and 0 otherwise.
+    Lt,
+    Gt,
+    SLt,
+    SGt,
+    Eq,
+}
+
+impl BinaryOp {
+    pub fn apply(self, lhs: U256, rhs: U256) -> U256 {
+        match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::Div => lhs.checked_div(rhs).unwrap_or_default(),
+            BinaryOp::Mod => lhs.checked_rem(rhs).unwrap_or_default(),
+            BinaryOp::Exp => crate::tinyvm::exp(lhs, rhs),
+            BinaryOp::SDiv => crate::tinyvm::sdiv(lhs, rhs),
+            BinaryOp::SMod => crate::tinyvm::smod(lhs, rhs),
+            BinaryOp::And => lhs & rhs,
+            BinaryOp::Or => lhs | rhs,
+            BinaryOp::Xor => lhs ^ rhs,
+            BinaryOp::Shl => crate::tinyvm::shl(lhs, rhs),
+            BinaryOp::Shr => crate::tinyvm::shr(lhs, rhs),
+            BinaryOp::Sar => crate::tinyvm::sar(lhs, rhs),
+            BinaryOp::Lt => U256::from(lhs < rhs),
+            BinaryOp::Gt => U256::from(lhs > rhs),
+            BinaryOp::SLt => U256::from(lhs.as_i256() < rhs.as_i256()),
+            BinaryOp::SGt => U256::from(lhs.as_i256() > rhs.as_i256()),
+            BinaryOp::Eq => U256::from(lhs == rhs),
+        }
+    }
+
+    fn op(self) -> OP {
+        match self {
+            BinaryOp::Add => OP::ADD,
+            BinaryOp::Sub => OP::SUB,
+            BinaryOp::Mul => OP::MUL,
+            BinaryOp::Div => OP::DIV,
+            BinaryOp::Mod => OP::MOD,
+            BinaryOp::Exp => OP::EXP,
+            BinaryOp::SDiv => OP::SDIV,
+            BinaryOp::SMod => OP::SMOD,
+            BinaryOp::And => OP::AND,
+            BinaryOp::Or => OP::OR,
+            BinaryOp::Xor => OP::XOR,
+            BinaryOp::Shl => OP::SHL,
+            BinaryOp::Shr => OP::SHR,
+            BinaryOp::Sar => OP::SAR,
+            BinaryOp::Lt => OP::LT,
+            BinaryOp::Gt => OP::GT,
+            BinaryOp::SLt => OP::SLT,
+            BinaryOp::SGt => OP::SGT,
+            BinaryOp::Eq => OP::EQ,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Mod => "mod",
+            BinaryOp::Exp => "exp",
+            BinaryOp::SDiv => "sdiv",
+            BinaryOp::SMod => "smod",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "shr",
+            BinaryOp::Sar => "sar",
+            BinaryOp::Lt => "lt",
+            BinaryOp::Gt => "gt",
+            BinaryOp::SLt => "slt",
+            BinaryOp::SGt => "sgt",
+            BinaryOp::Eq => "eq",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    /// Falls off the end of the program.
+    Stop,
+    Return(Vec<ValueId>),
+    /// Reverts, with the message ABI-encoded as `Error(string)` if there is one.
+    Revert(Option<String>),
+    Jump(BlockId),
+    Branch { cond: ValueId, then: BlockId, otherwise: BlockId },
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(to) => vec![*to],
+            Terminator::Branch { then, otherwise, .. } => vec![*then, *otherwise],
+            Terminator::Stop | Terminator::Return(_) | Terminator::Revert(_) => vec![],
+        }
+    }
+
+    /// The values the terminator reads.
+    pub fn uses(&self) -> Vec<ValueId> {
+        match self {
+            Terminator::Return(values) => values.clone(),
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Stop | Terminator::Revert(_) | Terminator::Jump(_) => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Instructions in program order.
+    pub insts: Vec<ValueId>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrError {
+    /// The IR uses something OP emission can't express yet.
+    Unsupported(&'static str),
+    /// A value can't be kept on the stack until its use.
+    Unschedulable(ValueId),
+    /// The body emits an event the contract doesn't declare, by name.
+    UndeclaredEvent(String),
+    /// The body calls a function, by name, that has no code to call.
+    UnknownCallee(String),
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrError::Unsupported(what) => write!(f, "{} is not supported by code generation yet", what),
+            IrError::Unschedulable(value) => write!(f, "v{} cannot be scheduled on the stack", value.0),
+            IrError::UndeclaredEvent(name) => write!(f, "event `{}` is not declared", name),
+            IrError::UnknownCallee(name) => write!(f, "calls `{}`, which is not declared or cannot be compiled", name),
+        }
+    }
+}
+
+/// One function in SSA form. Every value is defined by exactly one instruction, stored
+/// at the value's index; blocks list the instructions they contain in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrFunction {
+    pub values: Vec<Inst>,
+    pub blocks: Vec<Block>,
+}
+
+impl Default for IrFunction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrFunction {
+    /// A function with a single, empty entry block.
+    pub fn new() -> Self {
+        Self { values: vec![], blocks: vec![Block { insts: vec![], terminator: Terminator::Stop }] }
+    }
+
+    pub fn push(&mut self, block: BlockId, inst: Inst) -> ValueId {
+        let value = ValueId(self.values.len());
+        self.values.push(inst);
+        self.blocks[block.0].insts.push(value);
+        value
+    }
+
+    /// Adds an empty block that stops.
+    pub fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block { insts: vec![], terminator: Terminator::Stop });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    pub fn inst(&self, value: ValueId) -> &Inst {
+        &self.values[value.0]
+    }
+
+    /// Blocks control can reach from the entry block, in order.
+    pub fn reachable_blocks(&self) -> Vec<BlockId> {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut work = vec![BlockId(0)];
+        while let Some(block) = work.pop() {
+            if !std::mem::replace(&mut reached[block.0], true) {
+                work.extend(self.blocks[block.0].terminator.successors());
+            }
+        }
+        (0..self.blocks.len()).filter(|block| reached[*block]).map(BlockId).collect()
+    }
+
+    /// Replaces instructions whose operands are all constants with their result.
+    pub fn fold_constants(&mut self) {
+        for i in 0..self.values.len() {
+            let folded = match &self.values[i] {
+                Inst::IsZero(v) => match self.values[v.0] {
+                    Inst::Const(c) => Some(U256::from(c == U256::ZERO)),
+                    _ => None,
+                },
+                Inst::BitNot(v) => match self.values[v.0] {
+                    Inst::Const(c) => Some(!c),
+                    _ => None,
+                },
+                Inst::Binary(op, lhs, rhs) => match (&self.values[lhs.0], &self.values[rhs.0]) {
+                    (Inst::Const(a), Inst::Const(b)) => Some(op.apply(*a, *b)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(c) = folded {
+                self.values[i] = Inst::Const(c);
+            }
+        }
+    }
+
+    /// Rewrites every use of a value according to `replace`.
+    fn replace_uses(&mut self, replace: &HashMap<ValueId, ValueId>) {
+        let map = |v: &mut ValueId| if let Some(new) = replace.get(v) { *v = *new };
+        self.values.iter_mut().for_each(|inst| inst.map_operands(map));
+        self.blocks.iter_mut().for_each(|block| match &mut block.terminator {
+            Terminator::Return(values) => values.iter_mut().for_each(map),
+            Terminator::Branch { cond, .. } => map(cond),
+            Terminator::Stop | Terminator::Revert(_) | Terminator::Jump(_) => {},
+        });
+    }
+
+    /// Reuses the first read of a slot for later reads of the same slot in the block, as
+    /// long as no store that may write the slot happens in between. The cached value is
+    /// kept on the stack, so each repeated read costs a `DUP1` instead of an `SLOAD`.
+    pub fn eliminate_redundant_loads(&mut self) {
+        let mut replace = HashMap::new();
+        for block in &self.blocks {
+            let mut known = HashMap::new();
+            for value in &block.insts {
+                let constant = |v: &ValueId| match self.inst(*v) {
+                    Inst::Const(c) => Some(*c),
+                    _ => None,
+                };
+                match self.inst(*value) {
+                    Inst::SLoad(key) => if let Some(slot) = constant(key) {
+                        match known.get(&slot) {
+                            Some(first) => { replace.insert(*value, *first); },
+                            None => { known.insert(slot, *value); },
+                        }
+                    },
+                    Inst::SStore { key, .. } => match constant(key) {
+                        Some(slot) => { known.remove(&slot); },
+                        None => known.clear(),
+                    },
+                    Inst::Call(..) => known.clear(),
+                    _ => {},
+                }
+            }
+        }
+        self.replace_uses(&replace);
+    }
+
+    /// Whether the function can be inlined into a caller: a single reachable block without
+    /// calls of its own and no more than `threshold` live instructions.
+    pub fn is_inlinable(&self, threshold: usize) -> bool {
+        self.reachable_blocks().len() == 1
+            && !self.blocks[0].insts.iter().any(|v| matches!(self.inst(*v), Inst::Call(..)))
+            && self.live_values().len() <= threshold
+    }
+
+    /// Replaces calls to functions in `callees` that are inlinable under `threshold` with
+    /// a renamed copy of the callee's instructions, its parameters standing for the call's
+    /// arguments. Uses of the call's result become uses of the callee's first return value.
+    pub fn inline_calls(&mut self, callees: &HashMap<String, IrFunction>, threshold: usize) {
+        let mut replace = HashMap::new();
+        for block in 0..self.blocks.len() {
+            let mut insts = vec![];
+            for value in std::mem::take(&mut self.blocks[block].insts) {
+                let callee = match self.inst(value) {
+                    Inst::Call(name, args) => callees.get(name).filter(|callee| callee.is_inlinable(threshold)).map(|callee| (callee, args.clone())),
+                    _ => None,
+                };
+                let Some((callee, args)) = callee else {
+                    insts.push(value);
+                    continue;
+                };
+                let mut renamed = HashMap::new();
+                for old in &callee.blocks[0].insts {
+                    let arg = match callee.inst(*old) {
+                        Inst::Param(index) => args.get(*index),
+                        _ => None,
+                    };
+                    if let Some(arg) = arg {
+                        renamed.insert(*old, *arg);
+                        continue;
+                    }
+                    let mut inst = callee.inst(*old).clone();
+                    inst.map_operands(|v| *v = renamed[v]);
+                    let new = ValueId(self.values.len());
+                    self.values.push(inst);
+                    renamed.insert(*old, new);
+                    insts.push(new);
+                }
+                if let Terminator::Return(values) = &callee.blocks[0].terminator {
+                    if let Some(first) = values.first() {
+                        replace.insert(value, renamed[first]);
+                    }
+                }
+            }
+            self.blocks[block].insts = insts;
+        }
+        self.replace_uses(&replace);
+    }
+
+    fn terminator_uses(&self) -> impl Iterator<Item = ValueId> + '_ {
+        self.blocks.iter().flat_map(|block| block.terminator.uses())
+    }
+
+    /// Values whose result is needed, directly or indirectly, by a side effect or terminator.
+    pub fn live_values(&self) -> HashSet<ValueId> {
+        let mut live = HashSet::new();
+        let mut work = self.blocks.iter().flat_map(|block| block.insts.iter().copied())
+            .filter(|v| self.inst(*v).has_side_effects())
+            .chain(self.terminator_uses())
+            .collect::<Vec<_>>();
+        while let Some(value) = work.pop() {
+            if live.insert(value) {
+                work.extend(self.inst(value).operands());
+            }
+        }
+        live
+    }
+
+    /// Lowers to OPs. Values are emitted as expression trees at their use; a value with
+    /// several uses is computed once and duplicated, and pure values nothing uses are dropped.
+    /// Blocks are laid out in order, each starting with an empty stack, and jumps to a
+    /// block hold its offset in the program.
+    pub fn emit(&self) -> Result<Vec<OP>, IrError> {
+        self.emit_with(&HashMap::new())
+    }
+
+    /// Lowers to OPs like `emit`, followed by the code of every function in `callees` this
+    /// one calls, directly or through another callee. A call stores its arguments and a
+    /// return site in the callee's frame and jumps to it; the callee leaves its first return
+    /// value in the frame and jumps back through a table of its call sites. Nothing recurses,
+    /// so each frame has a fixed place in memory above the scratch space at `0x00..0x80`.
+    pub fn emit_with(&self, callees: &HashMap<String, IrFunction>) -> Result<Vec<OP>, IrError> {
+        let mut units = vec![self];
+        let mut program = Program { frames: vec![None], sites: vec![vec![]], heap: 0x80, ..Program::default() };
+        self.link(callees, &mut vec![], &mut units, &mut program)?;
+        for (unit, function) in units.iter().enumerate() {
+            function.emit_unit(unit, units.len() > 1, &mut program)?;
+        }
+        for unit in 1..units.len() {
+            program.assembler.place(Label::Return(unit));
+            let frame = program.frames[unit].expect("callees have frames");
+            let sites = std::mem::take(&mut program.sites[unit]);
+            let Some((last, rest)) = sites.split_last() else {
+                program.assembler.program.push(OP::INVALID);
+                continue;
+            };
+            for site in rest {
+                program.assembler.program.extend([constant(U256::from(frame.base)), OP::MLOAD, constant(U256::from(*site as u64)), OP::EQ]);
+                program.assembler.push_label(Label::Site(*site));
+                program.assembler.program.push(OP::JUMPI);
+            }
+            program.assembler.push_label(Label::Site(*last));
+            program.assembler.program.push(OP::JUMP);
+        }
+        Ok(program.assembler.finish())
+    }
+
+    /// The number of parameters the function reads.
+    fn arity(&self) -> usize {
+        self.values.iter().filter_map(|inst| match inst {
+            Inst::Param(index) => Some(index + 1),
+            _ => None,
+        }).max().unwrap_or(0)
+    }
+
+    /// Adds every function this one calls, directly or not, to `units`, giving each a frame.
+    fn link<'a>(&'a self, callees: &'a HashMap<String, IrFunction>, path: &mut Vec<&'a str>, units: &mut Vec<&'a IrFunction>, program: &mut Program) -> Result<(), IrError> {
+        for block in self.reachable_blocks() {
+            for value in &self.blocks[block.0].insts {
+                let Inst::Call(name, _) = self.inst(*value) else { continue };
+                let Some((name, callee)) = callees.get_key_value(name) else {
+                    return Err(IrError::UnknownCallee(name.clone()));
+                };
+                if path.contains(&name.as_str()) {
+                    return Err(IrError::Unsupported("recursive internal calls"));
+                }
+                if program.units.contains_key(name) {
+                    continue;
+                }
+                let params = callee.arity();
+                program.units.insert(name.clone(), units.len());
+                let base = program.alloc(params + 2);
+                program.frames.push(Some(Frame { base, params }));
+                program.sites.push(vec![]);
+                units.push(callee);
+                path.push(name);
+                callee.link(callees, path, units, program)?;
+                path.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits the function as `unit` of `program`. The first unit reads its arguments from
+    /// calldata and returns to the caller of the contract; callees use their frame.
+    fn emit_unit(&self, unit: usize, followed: bool, program: &mut Program) -> Result<(), IrError> {
+        let live = self.live_values();
+        let mut remaining = HashMap::<ValueId, usize>::new();
+        live.iter().flat_map(|v| self.inst(*v).operands()).chain(self.terminator_uses())
+            .for_each(|v| *remaining.entry(v).or_default() += 1);
+
+        let frame = program.frames[unit];
+        let order = self.reachable_blocks();
+        for (i, id) in order.iter().enumerate() {
+            let next = order.get(i + 1);
+            let block = &self.blocks[id.0];
+            if id.0 != 0 || frame.is_some() {
+                program.assembler.place(Label::Block(unit, *id));
+            }
+            let mut last_use = HashMap::new();
+            for (i, value) in block.insts.iter().enumerate().filter(|(_, v)| live.contains(v)) {
+                self.inst(*value).operands().into_iter().for_each(|v| { last_use.insert(v, i); });
+            }
+            block.terminator.uses().into_iter().for_each(|v| { last_use.insert(v, block.insts.len()); });
+            let mut emitter = Emitter {
+                function: self,
+                frame,
+                insts: &block.insts,
+                position: block.insts.iter().enumerate().map(|(i, v)| (*v, i)).collect(),
+                last_use,
+                remaining: &mut remaining,
+                last_store: None,
+                stack: vec![],
+                spilled: HashMap::new(),
+                program: &mut *program,
+            };
+            for (i, value) in block.insts.iter().enumerate() {
+                match self.inst(*value) {
+                    Inst::Call(name, args) => emitter.call(*value, name, args)?,
+                    inst if inst.has_side_effects() => emitter.emit(*value)?,
+                    _ => continue,
+                }
+                emitter.last_store = Some(i);
+            }
+            match (&block.terminator, frame) {
+                // Stopping is returning nothing, which only needs code before more code.
+                (Terminator::Stop, None) => if next.is_some() || followed {
+                    emitter.push(OP::PUSH0, 0, None);
+                    emitter.push(OP::PUSH0, 0, None);
+                    emitter.push(OP::RETURN, 0, None);
+                },
+                (Terminator::Return(values), None) => {
+                    // Each value goes in its own word of memory, which is returned ABI-encoded.
+                    values.iter().enumerate().try_for_each(|(i, v)| {
+                        emitter.emit(*v)?;
+                        emitter.push(constant(U256::from(i as u64 * 32)), 0, None);
+                        emitter.push(OP::MSTORE, 1, None);
+                        Ok(())
+                    })?;
+                    emitter.push(constant(U256::from(values.len() as u64 * 32)), 0, None);
+                    emitter.push(OP::PUSH0, 0, None);
+                    emitter.push(OP::RETURN, 0, None);
+                },
+                (Terminator::Stop, Some(_)) => emitter.jump(Label::Return(unit), OP::JUMP),
+                (Terminator::Return(values), Some(frame)) => {
+                    if let Some(first) = values.first() {
+                        emitter.emit(*first)?;
+                        emitter.push(constant(frame.result()), 0, None);
+                        emitter.push(OP::MSTORE, 1, None);
+                    }
+                    emitter.jump(Label::Return(unit), OP::JUMP);
+                },
+                (Terminator::Revert(message), _) => emitter.revert(message.as_deref()),
+                (Terminator::Jump(to), _) => if next != Some(to) {
+                    emitter.jump(Label::Block(unit, *to), OP::JUMP);
+                },
+                (Terminator::Branch { cond, then, otherwise }, _) => {
+                    emitter.emit(*cond)?;
+                    if next == Some(then) {
+                        // Falls through to `then`, so only the other way needs a jump.
+                        emitter.push(OP::ISZERO, 0, None);
+                        emitter.jump(Label::Block(unit, *otherwise), OP::JUMPI);
+                    } else {
+                        emitter.jump(Label::Block(unit, *then), OP::JUMPI);
+                        if next != Some(otherwise) {
+                            emitter.jump(Label::Block(unit, *otherwise), OP::JUMP);
+                        }
+                    }
+                },
+            }
+            if let Some(value) = emitter.stack.first() {
+                return Err(IrError::Unschedulable(*value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A place in the program that jumps go to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Label {
+    /// A block of a unit.
+    Block(usize, BlockId),
+    /// The table a callee returns through.
+    Return(usize),
+    /// Where a call continues after the callee returns.
+    Site(usize),
+}
+
+/// Emitted code, with jumps patched in once every label has an offset.
+#[derive(Default)]
+struct Assembler {
+    program: Vec<OP>,
+    /// The index of each label's `JUMPDEST`.
+    labels: HashMap<Label, usize>,
+    /// Pushes of label offsets, by index, still holding a placeholder.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    fn place(&mut self, label: Label) {
+        self.labels.insert(label, self.program.len());
+        self.program.push(OP::JUMPDEST);
+    }
+
+    /// Pushes the offset of `label`, always as `PUSH2` so offsets stay put while patching.
+    fn push_label(&mut self, label: Label) {
+        self.fixups.push((self.program.len(), label));
+        self.program.push(OP::PUSHN(2, U256::ZERO));
+    }
+
+    fn finish(mut self) -> Vec<OP> {
+        let offsets = code_offsets(&self.program);
+        for (at, label) in std::mem::take(&mut self.fixups) {
+            self.program[at] = OP::PUSHN(2, U256::from(offsets[self.labels[&label]] as u64));
+        }
+        self.program
+    }
+}
+
+/// Where a callee's arguments and result live: the id of the site to return to, then one
+/// word per parameter, then the result.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    base: u64,
+    params: usize,
+}
+
+impl Frame {
+    fn param(&self, index: usize) -> U256 {
+        U256::from(self.base + 32 + index as u64 * 32)
+    }
+
+    fn result(&self) -> U256 {
+        self.param(self.params)
+    }
+}
+
+/// The function being compiled, unit 0, and the functions it calls, in the order they're
+/// laid out.
+#[derive(Default)]
+struct Program {
+    assembler: Assembler,
+    /// Callees' units, by name.
+    units: HashMap<String, usize>,
+    /// Each unit's frame; the first unit has none.
+    frames: Vec<Option<Frame>>,
+    /// The sites each unit is called from.
+    sites: Vec<Vec<usize>>,
+    site_count: usize,
+    /// The first word of memory nothing uses yet. Words are never reused, so a value kept
+    /// in memory can't be overwritten by a callee.
+    heap: u64,
+}
+
+impl Program {
+    /// Reserves `words` of memory, returning the offset of the first.
+    fn alloc(&mut self, words: usize) -> u64 {
+        let at = self.heap;
+        self.heap += words as u64 * 32;
+        at
+    }
+}
+
+/// Emission state for one block, with a model of the values on the stack.
+struct Emitter<'a> {
+    function: &'a IrFunction,
+    frame: Option<Frame>,
+    insts: &'a [ValueId],
+    position: HashMap<ValueId, usize>,
+    /// The position of the last instruction in the block that uses each value, the
+    /// terminator counting as one past the end.
+    last_use: HashMap<ValueId, usize>,
+    /// Uses of each value not emitted yet.
+    remaining: &'a mut HashMap<ValueId, usize>,
+    last_store: Option<usize>,
+    stack: Vec<ValueId>,
+    /// Values kept in memory across a call, by offset.
+    spilled: HashMap<ValueId, u64>,
+    program: &'a mut Program,
+}
+
+impl Emitter<'_> {
+    fn push(&mut self, op: OP, operands: usize, result: Option<ValueId>) {
+        self.stack.truncate(self.stack.len() - operands);
+        self.stack.extend(result);
+        self.program.assembler.program.push(op);
+    }
+
+    /// Jumps to `label` with `JUMP`, or with `JUMPI` on the condition on top of the stack.
+    fn jump(&mut self, label: Label, op: OP) {
+        let operands = if op == OP::JUMPI { 1 } else { 0 };
+        self.program.assembler.push_label(label);
+        self.push(op, operands, None);
+    }
+
+    /// Stores `words` in memory from offset `at`, the first on top of the stack.
+    fn store_words(&mut self, at: u64, words: usize) {
+        (0..words).for_each(|i| {
+            self.push(constant(U256::from(at + i as u64 * 32)), 0, None);
+            self.push(OP::MSTORE, 1, None);
+        });
+    }
+
+    /// Moves the value on top of the stack to memory.
+    fn spill(&mut self) {
+        let value = *self.stack.last().expect("a value to spill");
+        let at = self.program.alloc(1);
+        self.push(constant(U256::from(at)), 0, None);
+        self.push(OP::MSTORE, 1, None);
+        self.spilled.insert(value, at);
+    }
+
+    /// Calls the function `name`, which starts from an empty stack, so the values the rest
+    /// of the block needs are moved to memory first: those on the stack, and those computed
+    /// before the call that would otherwise be computed after it.
+    fn call(&mut self, value: ValueId, name: &str, args: &[ValueId]) -> Result<(), IrError> {
+        while !self.stack.is_empty() {
+            self.spill();
+        }
+        let at = self.position[&value];
+        for kept in &self.insts[..at] {
+            let needed = self.last_use.get(kept).is_some_and(|last| *last > at)
+                && self.remaining.get(kept).is_some_and(|left| *left > 0)
+                && !self.spilled.contains_key(kept)
+                && !matches!(self.function.inst(*kept), Inst::Const(_) | Inst::Param(_) | Inst::Env(_));
+            if needed {
+                // Counted as the only use, so it isn't duplicated; later uses load it.
+                let left = self.remaining.insert(*kept, 1).unwrap_or_default();
+                self.emit(*kept)?;
+                self.remaining.insert(*kept, left);
+                self.spill();
+            }
+        }
+
+        let callee = self.program.units[name];
+        let frame = self.program.frames[callee].expect("callees have frames");
+        for (i, arg) in args.iter().enumerate().take(frame.params) {
+            self.emit(*arg)?;
+            self.push(constant(frame.param(i)), 0, None);
+            self.push(OP::MSTORE, 1, None);
+        }
+        let site = self.program.site_count;
+        self.program.site_count += 1;
+        self.program.sites[callee].push(site);
+        self.push(constant(U256::from(site as u64)), 0, None);
+        self.push(constant(U256::from(frame.base)), 0, None);
+        self.push(OP::MSTORE, 0, None);
+        self.jump(Label::Block(callee, BlockId(0)), OP::JUMP);
+        self.program.assembler.place(Label::Site(site));
+        if self.remaining.get(&value).is_some_and(|left| *left > 0) {
+            // Copied out of the frame, which the next call to the same function overwrites.
+            let at = self.program.alloc(1);
+            self.push(constant(frame.result()), 0, None);
+            self.push(OP::MLOAD, 0, None);
+            self.push(constant(U256::from(at)), 0, None);
+            self.push(OP::MSTORE, 0, None);
+            self.spilled.insert(value, at);
+        }
+        Ok(())
+    }
+
+    /// Reverts with `Error(message)`, or with no data.
+    fn revert(&mut self, message: Option<&str>) {
+        let data = message.map(|message| [&ERROR_SELECTOR[..], &encode(&[Value::String(message.to_string())])].concat()).unwrap_or_default();
+        data.chunks(32).enumerate().for_each(|(i, chunk)| {
+            let mut word = [0; 32];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.push(constant(U256::from_be_bytes(word)), 0, None);
+            self.push(constant(U256::from(i as u64 * 32)), 0, None);
+            self.push(OP::MSTORE, 0, None);
+        });
+        self.push(constant(U256::from(data.len() as u64)), 0, None);
+        self.push(OP::PUSH0, 0, None);
+        self.push(OP::REVERT, 0, None);
+    }
+
+    /// Leaves `value` on top of the stack for one use. Values already computed must be
+    /// on top of the stack or in memory; storage reads must not be moved past a store
+    /// that followed them in the block.
+    fn emit(&mut self, value: ValueId) -> Result<(), IrError> {
+        let inst = self.function.inst(value);
+        let left = self.remaining.get(&value).copied().unwrap_or(1).saturating_sub(1);
+        self.remaining.insert(value, left);
+
+        if let Some(at) = self.spilled.get(&value) {
+            self.push(constant(U256::from(*at)), 0, None);
+            self.push(OP::MLOAD, 0, Some(value));
+            return Ok(());
+        }
+        // Constants and arguments are cheaper to push again than to keep on the stack.
+        if !matches!(inst, Inst::Const(_) | Inst::Param(_)) && self.stack.contains(&value) {
+            if self.stack.last() != Some(&value) {
+                return Err(IrError::Unschedulable(value));
+            }
+            if left > 0 {
+                self.push(OP::DUP1, 0, Some(value));
+            }
+            return Ok(());
+        }
+        if let Inst::SLoad(_) = inst {
+            if matches!((self.position.get(&value), self.last_store), (Some(at), Some(store)) if *at < store) {
+                return Err(IrError::Unschedulable(value));
+            }
+        }
+        match inst {
+            Inst::Const(c) => {
+                self.push(constant(*c), 0, Some(value));
+                return Ok(());
+            },
+            Inst::Param(index) => {
+                match self.frame {
+                    Some(frame) => {
+                        self.push(constant(frame.param(*index)), 0, None);
+                        self.push(OP::MLOAD, 0, Some(value));
+                    },
+                    None => {
+                        self.push(constant(U256::from(4 + *index as u64 * 32)), 0, None);
+                        self.push(OP::CALLDATALOAD, 0, Some(value));
+                    },
+                }
+                return Ok(());
+            },
+            Inst::Env(op) => self.push(op.clone(), 0, Some(value)),
+            Inst::CalldataLoad(offset) => {
+                self.emit(*offset)?;
+                self.push(OP::CALLDATALOAD, 1, Some(value));
+            },
+            Inst::SLoad(key) => {
+                self.emit(*key)?;
+                self.push(OP::SLOAD, 1, Some(value));
+            },
+            Inst::IsZero(v) => {
+                self.emit(*v)?;
+                self.push(OP::ISZERO, 1, Some(value));
+            },
+            Inst::BitNot(v) => {
+                self.emit(*v)?;
+                self.push(OP::NOT, 1, Some(value));
+            },
+            Inst::Binary(op, lhs, rhs) => {
+                // The EVM computes `top op second`.
+                self.emit(*rhs)?;
+                self.emit(*lhs)?;
+                self.push(op.op(), 2, Some(value));
+            },
+            Inst::SStore { key, value: stored } => {
+                self.emit(*stored)?;
+                self.emit(*key)?;
+                self.push(OP::SSTORE, 2, None);
+            },
+            Inst::Keccak(words) => {
+                // Every word is computed before any is stored, since computing one may
+                // hash through the same scratch memory.
+                words.iter().rev().try_for_each(|word| self.emit(*word))?;
+                self.store_words(0, words.len());
+                self.push(constant(U256::from(words.len() as u64 * 32)), 0, None);
+                self.push(OP::PUSH0, 0, None);
+                self.push(OP::KECCAK256, 0, Some(value));
+            },
+            Inst::Log { topics, data } => {
+                let op = match topics.len() {
+                    0 => OP::LOG0,
+                    1 => OP::LOG1,
+                    2 => OP::LOG2,
+                    3 => OP::LOG3,
+                    4 => OP::LOG4,
+                    _ => return Err(IrError::Unsupported("more than four log topics")),
+                };
+                topics.iter().rev().chain(data.iter().rev()).try_for_each(|word| self.emit(*word))?;
+                // Past the frames, which may be in use further up the call chain.
+                let at = self.program.alloc(data.len());
+                self.store_words(at, data.len());
+                self.push(constant(U256::from(data.len() as u64 * 32)), 0, None);
+                self.push(constant(U256::from(at)), 0, None);
+                self.push(op, topics.len(), None);
+            },
+            // The call itself is emitted in order with the block's other side effects.
+            Inst::Call(..) => return Err(IrError::Unschedulable(value)),
+            Inst::Phi(_) => return Err(IrError::Unsupported("phi nodes")),
+        }
+        if left > 0 {
+            self.push(OP::DUP1, 0, Some(value));
+        }
+        Ok(())
+    }
+}
+
+/// The shortest push of `c`.
+fn constant(c: U256) -> OP {
+    OP::push(c)
+}
+
+fn value_list(values: &[ValueId]) -> String {
+    values.iter().map(|v| format!("v{}", v.0)).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.blocks.iter().enumerate().try_for_each(|(id, block)| {
+            writeln!(f, "b{}:", id)?;
+            block.insts.iter().try_for_each(|value| {
+                let v = |v: &ValueId| format!("v{}", v.0);
+                match self.inst(*value) {
+                    Inst::Const(c) => writeln!(f, "  v{} = const {:#x}", value.0, c),
+                    Inst::Param(index) => writeln!(f, "  v{} = param {}", value.0, index),
+                    Inst::CalldataLoad(o) => writeln!(f, "  v{} = calldataload {}", value.0, v(o)),
+                    Inst::SLoad(k) => writeln!(f, "  v{} = sload {}", value.0, v(k)),
+                    Inst::SStore { key, value } => writeln!(f, "  sstore {}, {}", v(key), v(value)),
+                    Inst::IsZero(x) => writeln!(f, "  v{} = iszero {}", value.0, v(x)),
+                    Inst::BitNot(x) => writeln!(f, "  v{} = not {}", value.0, v(x)),
+                    Inst::Binary(op, lhs, rhs) => writeln!(f, "  v{} = {} {}, {}", value.0, op.name(), v(lhs), v(rhs)),
+                    Inst::Keccak(words) => writeln!(f, "  v{} = keccak {}", value.0, value_list(words)),
+                    Inst::Env(op) => writeln!(f, "  v{} = {}", value.0, op.name().to_lowercase()),
+                    Inst::Log { topics, data } => writeln!(f, "  log [{}], [{}]", value_list(topics), value_list(data)),
+                    Inst::Call(name, args) => writeln!(f, "  v{} = call {}({})", value.0, name, value_list(args)),
+                    Inst::Phi(incoming) => writeln!(f, "  v{} = phi {}", value.0,
+                        incoming.iter().map(|(b, x)| format!("[b{}: {}]", b.0, v(x))).collect::<Vec<_>>().join(", ")),
+                }
+            })?;
+            match &block.terminator {
+                Terminator::Stop => writeln!(f, "  stop"),
+                Terminator::Return(values) => writeln!(f, "  return {}", value_list(values)),
+                Terminator::Revert(Some(message)) => writeln!(f, "  revert {:?}", message),
+                Terminator::Revert(None) => writeln!(f, "  revert"),
+                Terminator::Jump(to) => writeln!(f, "  jump b{}", to.0),
+                Terminator::Branch { cond, then, otherwise } => writeln!(f, "  branch v{}, b{}, b{}", cond.0, then.0, otherwise.0),
+            }
+        })
+    }
+}
+
+/// Lowers a function body. `variables` maps state variable names to their slots. Every
+/// variable is taken to be unsigned; see `lower_typed_statement`.
+pub fn lower_statement(statement: &Statement, variables: &BTreeMap<String, usize>) -> IrFunction {
+    lower_typed_statement(statement, variables, &BTreeMap::new())
+}
+
+/// Like `lower_statement`, with the declared `types` of state variables, so arithmetic on
+/// `int256` values lowers to the signed operations.
+pub fn lower_typed_statement(statement: &Statement, variables: &BTreeMap<String, usize>, types: &BTreeMap<String, Type>) -> IrFunction {
+    let scope = Scope { slots: variables, types, params: &[], events: &[] };
+    let mut lowering = Lowering::new(&scope);
+    lowering.statement(statement);
+    lowering.function
+}
+
+/// Lowers the statements of a function body in order.
+pub fn lower_body(body: &[Spanned<Statement>], scope: &Scope) -> Result<IrFunction, IrError> {
+    let mut lowering = Lowering::new(scope);
+    body.iter().for_each(|statement| lowering.statement(statement));
+    match lowering.error {
+        Some(error) => Err(error),
+        None => Ok(lowering.function),
+    }
+}
+
+/// What a function body can refer to.
+pub struct Scope<'a> {
+    /// State variables by name, with their slots and declared types.
+    pub slots: &'a BTreeMap<String, usize>,
+    pub types: &'a BTreeMap<String, Type>,
+    /// The function's parameters, which shadow state variables of the same name.
+    pub params: &'a [Parameter],
+    pub events: &'a [AbiEvent],
+}
+
+impl Scope<'_> {
+    fn param(&self, name: &str) -> Option<usize> {
+        self.params.iter().position(|param| param.name.as_ref().is_some_and(|n| n.name == name))
+    }
+
+    fn slot(&self, identifier: &Identifier) -> U256 {
+        U256::from(self.slots.get(&identifier.name).copied().unwrap_or_default() as u64)
+    }
+
+    /// The declared type of a variable, parameter or mapping entry, or the type converted to.
+    fn type_of(&self, expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Variable(identifier) => match self.param(&identifier.name) {
+                Some(index) => match &self.params[index].ty {
+                    Expression::Type(ty) => Some(ty.clone()),
+                    _ => None,
+                },
+                None => self.types.get(&identifier.name).cloned(),
+            },
+            Expression::Index(base, _, _, _) => match self.type_of(base)? {
+                Type::Mapping(_, _, _, _, value, _) => Some(*value),
+                _ => None,
+            },
+            Expression::Paren(_, expr, _) => self.type_of(expr),
+            Expression::Cast(ty, _, _, _) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether `expr` has a signed type: a signed variable, or arithmetic involving one.
+    fn is_signed(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Variable(_) | Expression::Index(..) | Expression::Paren(..) | Expression::Cast(..) => {
+                matches!(self.type_of(expr), Some(Type::Int256(_)))
+            },
+            Expression::Neg(_, expr) | Expression::BitNot(_, expr) => self.is_signed(expr),
+            Expression::Add(lhs, _, rhs) | Expression::Sub(lhs, _, rhs) | Expression::Mul(lhs, _, rhs)
+            | Expression::Div(lhs, _, rhs) | Expression::Mod(lhs, _, rhs) | Expression::Exp(lhs, _, rhs)
+            | Expression::BitAnd(lhs, _, rhs) | Expression::BitOr(lhs, _, rhs) | Expression::BitXor(lhs, _, rhs) => {
+                self.is_signed(lhs) || self.is_signed(rhs)
+            },
+            // A shift has the type of the value shifted.
+            Expression::Shl(value, _, _) | Expression::Shr(value, _, _) => self.is_signed(value),
+            _ => false,
+        }
+    }
+}
+
+/// Lowering state: the function so far and the block code is added to.
+struct Lowering<'a> {
+    scope: &'a Scope<'a>,
+    function: IrFunction,
+    block: BlockId,
+    /// The first construct found that can't be lowered.
+    error: Option<IrError>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(scope: &'a Scope<'a>) -> Self {
+        Self { scope, function: IrFunction::new(), block: BlockId(0), error: None }
+    }
+
+    fn push(&mut self, inst: Inst) -> ValueId {
+        self.function.push(self.block, inst)
+    }
+
+    fn set_terminator(&mut self, block: BlockId, terminator: Terminator) {
+        self.function.blocks[block.0].terminator = terminator;
+    }
+
+    /// Ends the current block with `terminator`. Code after it goes in a new block that
+    /// nothing jumps to, which emission leaves out.
+    fn terminate(&mut self, terminator: Terminator) {
+        self.set_terminator(self.block, terminator);
+        self.block = self.function.new_block();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression(expr, _) => {
+                self.expression(expr);
+            },
+            Statement::Return(_, expr, _) => {
+                let values = expr.iter().filter_map(|expr| self.expression(expr)).collect();
+                self.terminate(Terminator::Return(values));
+            },
+            Statement::If(_, _, cond, _, then, otherwise) => {
+                let cond = match self.expression(cond) {
+                    Some(cond) => cond,
+                    None => self.push(Inst::Const(U256::ZERO)),
+                };
+                let then_block = self.function.new_block();
+                let else_block = otherwise.as_ref().map(|_| self.function.new_block());
+                let join = self.function.new_block();
+                self.set_terminator(self.block, Terminator::Branch { cond, then: then_block, otherwise: else_block.unwrap_or(join) });
+                self.block = then_block;
+                self.statement(&then.value);
+                self.set_terminator(self.block, Terminator::Jump(join));
+                if let (Some(Else::Else(_, otherwise)), Some(else_block)) = (otherwise, else_block) {
+                    self.block = else_block;
+                    self.statement(&otherwise.value);
+                    self.set_terminator(self.block, Terminator::Jump(join));
+                }
+                self.block = join;
+            },
+            Statement::Block(_, statements, _) => statements.iter().for_each(|statement| self.statement(statement)),
+            Statement::Emit(_, event, args, _) => self.emit_event(&event.name, args.args()),
+        }
+    }
+
+    /// Logs the event's signature hash and indexed arguments as topics, the other
+    /// arguments as data.
+    fn emit_event(&mut self, name: &str, args: &[Expression]) {
+        let Some(event) = self.scope.events.iter().find(|event| event.name == name && event.inputs.len() == args.len()) else {
+            self.error.get_or_insert(IrError::UndeclaredEvent(name.to_string()));
+            return;
+        };
+        let mut topics = vec![self.push(Inst::Const(event.topic()))];
+        let mut data = vec![];
+        for (input, arg) in event.inputs.iter().zip(args) {
+            if let Some(value) = self.expression(arg) {
+                if input.indexed { topics.push(value) } else { data.push(value) }
+            }
+        }
+        self.push(Inst::Log { topics, data });
+    }
+
+    /// `require(cond)` or `require(cond, "message")`: carries on in a new block if `cond`
+    /// holds and reverts otherwise.
+    fn require(&mut self, args: &[Expression]) {
+        let Some(cond) = args.first().and_then(|cond| self.expression(cond)) else { return };
+        let fails = self.function.new_block();
+        let holds = self.function.new_block();
+        self.set_terminator(self.block, Terminator::Branch { cond, then: holds, otherwise: fails });
+        self.set_terminator(fails, Terminator::Revert(message(args.get(1))));
+        self.block = holds;
+    }
+
+    /// The storage slot `expr` names: a state variable's own slot, or for an entry of a
+    /// mapping, the keccak256 hash of the key followed by the mapping's slot, as in Solidity.
+    fn place(&mut self, expr: &Expression) -> Option<ValueId> {
+        match expr {
+            Expression::Variable(identifier) if self.scope.param(&identifier.name).is_none() => {
+                Some(self.push(Inst::Const(self.scope.slot(identifier))))
+            },
+            Expression::Index(base, _, key, _) => {
+                let key = self.expression(key)?;
+                let base = self.place(base)?;
+                Some(self.push(Inst::Keccak(vec![key, base])))
+            },
+            Expression::Paren(_, expr, _) => self.place(expr),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of `expr`, or `None` for expressions without one. `&&` and `||`
+    /// evaluate both operands.
+    fn expression(&mut self, expr: &Expression) -> Option<ValueId> {
+        match expr {
+            Expression::BoolLiteral(val) => Some(self.push(Inst::Const(U256::from(*val)))),
+            Expression::NumberLiteral(val) => Some(self.push(Inst::Const(*val))),
+            Expression::StringLiteral(_) => None,
+            Expression::Variable(identifier) => match self.scope.param(&identifier.name) {
+                Some(index) => Some(self.push(Inst::Param(index))),
+                None => {
+                    let key = self.push(Inst::Const(self.scope.slot(identifier)));
+                    Some(self.push(Inst::SLoad(key)))
+                },
+            },
+            Expression::Global(name) => match name.as_str() {
+                "block.prevrandao" | "block.difficulty" => Some(self.push(Inst::Env(OP::PREVRANDAO))),
+                "msg.sender" => Some(self.push(Inst::Env(OP::CALLER))),
+                "msg.value" => Some(self.push(Inst::Env(OP::CALLVALUE))),
+                "tx.origin" => Some(self.push(Inst::Env(OP::ORIGIN))),
+                _ => None,
+            },
+            Expression::Paren(_, expr, _) => self.expression(expr),
+            Expression::Index(..) => {
+                let key = self.place(expr)?;
+                Some(self.push(Inst::SLoad(key)))
+            },
+            Expression::Cast(Type::Address(_), _, operand, _) => {
+                let value = self.expression(operand)?;
+                let mask = self.push(Inst::Const(U256::MAX >> 96));
+                Some(self.push(Inst::Binary(BinaryOp::And, value, mask)))
+            },
+            Expression::Cast(_, _, operand, _) => self.expression(operand),
+            Expression::Assign(left, _, right) => {
+                let value = self.expression(right)?;
+                let key = self.place(left)?;
+                self.push(Inst::SStore { key, value });
+                Some(value)
+            },
+            Expression::Not(_, expr) => {
+                let value = self.expression(expr)?;
+                Some(self.push(Inst::IsZero(value)))
+            },
+            Expression::Add(lhs, _, rhs) => self.binary(BinaryOp::Add, lhs, rhs),
+            Expression::Sub(lhs, _, rhs) => self.binary(BinaryOp::Sub, lhs, rhs),
+            Expression::Mul(lhs, _, rhs) => self.binary(BinaryOp::Mul, lhs, rhs),
+            Expression::Neg(_, expr) => {
+                let zero = self.push(Inst::Const(U256::ZERO));
+                let value = self.expression(expr)?;
+                Some(self.push(Inst::Binary(BinaryOp::Sub, zero, value)))
+            },
+            Expression::BitNot(_, expr) => {
+                let value = self.expression(expr)?;
+                Some(self.push(Inst::BitNot(value)))
+            },
+            Expression::BitAnd(lhs, _, rhs) | Expression::And(lhs, _, rhs) => self.binary(BinaryOp::And, lhs, rhs),
+            Expression::BitOr(lhs, _, rhs) | Expression::Or(lhs, _, rhs) => self.binary(BinaryOp::Or, lhs, rhs),
+            Expression::BitXor(lhs, _, rhs) => self.binary(BinaryOp::Xor, lhs, rhs),
+            Expression::Shl(value, _, shift) => self.binary(BinaryOp::Shl, shift, value),
+            Expression::Shr(value, _, shift) if self.scope.is_signed(value) => self.binary(BinaryOp::Sar, shift, value),
+            Expression::Shr(value, _, shift) => self.binary(BinaryOp::Shr, shift, value),
+            Expression::Lt(lhs, _, rhs) => self.comparison(BinaryOp::Lt, false, lhs, rhs),
+            Expression::Gt(lhs, _, rhs) => self.comparison(BinaryOp::Gt, false, lhs, rhs),
+            Expression::Le(lhs, _, rhs) => self.comparison(BinaryOp::Gt, true, lhs, rhs),
+            Expression::Ge(lhs, _, rhs) => self.comparison(BinaryOp::Lt, true, lhs, rhs),
+            Expression::Eq(lhs, _, rhs) => self.binary(BinaryOp::Eq, lhs, rhs),
+            Expression::Ne(lhs, _, rhs) => {
+                let value = self.binary(BinaryOp::Eq, lhs, rhs)?;
+                Some(self.push(Inst::IsZero(value)))
+            },
+            Expression::Div(lhs, _, rhs) if self.scope.is_signed(expr) => self.binary(BinaryOp::SDiv, lhs, rhs),
+            Expression::Mod(lhs, _, rhs) if self.scope.is_signed(expr) => self.binary(BinaryOp::SMod, lhs, rhs),
+            Expression::Div(lhs, _, rhs) => self.binary(BinaryOp::Div, lhs, rhs),
+            Expression::Mod(lhs, _, rhs) => self.binary(BinaryOp::Mod, lhs, rhs),
+            Expression::Exp(lhs, _, rhs) => self.binary(BinaryOp::Exp, lhs, rhs),
+            Expression::Call(identifier, args) => match identifier.name.as_str() {
+                "require" => {
+                    self.require(args.args());
+                    None
+                },
+                "revert" => {
+                    self.terminate(Terminator::Revert(message(args.args().first())));
+                    None
+                },
+                name => {
+                    let args = args.args().iter().filter_map(|arg| self.expression(arg)).collect();
+                    Some(self.push(Inst::Call(name.to_string(), args)))
+                },
+            },
+            Expression::Member(..) => {
+                self.error.get_or_insert(IrError::Unsupported("member access"));
+                None
+            },
+            Expression::MemberCall(..) => {
+                self.error.get_or_insert(IrError::Unsupported("external calls"));
+                None
+            },
+            Expression::Type(_) => None,
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: &Expression, rhs: &Expression) -> Option<ValueId> {
+        let lhs = self.expression(lhs)?;
+        let rhs = self.expression(rhs)?;
+        Some(self.push(Inst::Binary(op, lhs, rhs)))
+    }
+
+    /// `lhs op rhs` for `Lt` or `Gt`, comparing as signed if either side is, and negated for
+    /// `<=` and `>=`.
+    fn comparison(&mut self, op: BinaryOp, negate: bool, lhs: &Expression, rhs: &Expression) -> Option<ValueId> {
+        let op = match (op, self.scope.is_signed(lhs) || self.scope.is_signed(rhs)) {
+            (BinaryOp::Lt, true) => BinaryOp::SLt,
+            (BinaryOp::Gt, true) => BinaryOp::SGt,
+            (op, _) => op,
+        };
+        let value = self.binary(op, lhs, rhs)?;
+        Some(if negate { self.push(Inst::IsZero(value)) } else { value })
+    }
+}
+
+/// The message of a `require` or `revert`, if given as a string literal.
+fn message(arg: Option<&Expression>) -> Option<String> {
+    match arg {
+        Some(Expression::StringLiteral(message)) => Some(message.clone()),
+        _ => None,
+    }
+}
+
+/// The value of `expr` if it is a constant, computed as the compiled code computes it: in
+/// unsigned 256-bit words that wrap, since only variables can be signed. `None` if it reads
+/// state or the environment, calls a function, or mixes booleans with numbers.
+pub fn eval_const(expr: &Expression) -> Option<Value> {
+    let number = |expr: &Expression| match eval_const(expr)? {
+        Value::Uint(x) => Some(x),
+        _ => None,
+    };
+    match expr {
+        Expression::BoolLiteral(b) => Some(Value::Bool(*b)),
+        Expression::NumberLiteral(x) => Some(Value::Uint(*x)),
+        Expression::Paren(_, operand, _) => eval_const(operand),
+        Expression::Not(_, operand) => match eval_const(operand)? {
+            Value::Bool(b) => Some(Value::Bool(!b)),
+            _ => None,
+        },
+        Expression::Neg(_, operand) => Some(Value::Uint(number(operand)?.wrapping_neg())),
+        Expression::BitNot(_, operand) => Some(Value::Uint(!number(operand)?)),
+        Expression::Eq(lhs, _, rhs) | Expression::Ne(lhs, _, rhs) => {
+            let equal = match (eval_const(lhs)?, eval_const(rhs)?) {
+                (Value::Bool(a), Value::Bool(b)) => a == b,
+                (Value::Uint(a), Value::Uint(b)) => a == b,
+                _ => return None,
+            };
+            Some(Value::Bool(equal == matches!(expr, Expression::Eq(..))))
+        },
+        Expression::And(lhs, _, rhs) | Expression::Or(lhs, _, rhs) => match (eval_const(lhs)?, eval_const(rhs)?) {
+            (Value::Bool(a), Value::Bool(b)) => Some(Value::Bool(if matches!(expr, Expression::And(..)) { a && b } else { a || b })),
+            _ => None,
+        },
+        Expression::Lt(lhs, _, rhs) => Some(Value::Bool(number(lhs)? < number(rhs)?)),
+        Expression::Gt(lhs, _, rhs) => Some(Value::Bool(number(lhs)? > number(rhs)?)),
+        Expression::Le(lhs, _, rhs) => Some(Value::Bool(number(lhs)? <= number(rhs)?)),
+        Expression::Ge(lhs, _, rhs) => Some(Value::Bool(number(lhs)? >= number(rhs)?)),
+        Expression::Add(lhs, _, rhs) | Expression::Sub(lhs, _, rhs) | Expression::Mul(lhs, _, rhs)
+        | Expression::Div(lhs, _, rhs) | Expression::Mod(lhs, _, rhs) | Expression::Exp(lhs, _, rhs)
+        | Expression::BitAnd(lhs, _, rhs) | Expression::BitOr(lhs, _, rhs) | Expression::BitXor(lhs, _, rhs) => {
+            let op = match expr {
+                Expression::Add(..) => BinaryOp::Add,
+                Expression::Sub(..) => BinaryOp::Sub,
+                Expression::Mul(..) => BinaryOp::Mul,
+                Expression::Div(..) => BinaryOp::Div,
+                Expression::Mod(..) => BinaryOp::Mod,
+                Expression::Exp(..) => BinaryOp::Exp,
+                Expression::BitAnd(..) => BinaryOp::And,
+                Expression::BitOr(..) => BinaryOp::Or,
+                _ => BinaryOp::Xor,
+            };
+            Some(Value::Uint(op.apply(number(lhs)?, number(rhs)?)))
+        },
+        Expression::Shl(value, _, shift) => Some(Value::Uint(BinaryOp::Shl.apply(number(shift)?, number(value)?))),
+        Expression::Shr(value, _, shift) => Some(Value::Uint(BinaryOp::Shr.apply(number(shift)?, number(value)?))),
+        Expression::Variable(_) | Expression::Global(_) | Expression::Call(..) | Expression::Assign(..) | Expression::Type(_)
+        | Expression::StringLiteral(_) | Expression::Index(..) | Expression::Cast(..) | Expression::Member(..)
+        | Expression::MemberCall(..) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selector::selector;
+    use crate::abi::calldata;
+    use crate::env::Env;
+    use crate::executor::Storage;
+    use crate::revert::RevertReason;
+    use crate::settings::{CompilerSettings, Passes};
+    use crate::test_utils::alice;
+    use crate::tinyvm::Halt;
+
+    fn body(source: &str) -> Statement {
+        let contract = format!("contract C {{ bool private a; bool private b; function f() public {{ {} }} }}", source);
+        let SourceUnitPart::ContractDefinition(_, _, _, parts, _) = parse(&contract).unwrap().parts.remove(0);
+        let Some(ContractPart::FunctionDefinition(_, _, _, _, _, _, mut statements, _)) = parts.into_iter().last() else { panic!("no body") };
+        statements.remove(0).value
+    }
+
+    #[test]
+    fn test_lower_and_emit() {
+        let variables = BTreeMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let function = lower_statement(&body("b = !a;"), &variables);
+        assert_eq!(function.to_string(), "b0:\n  v0 = const 0x0\n  v1 = sload v0\n  v2 = iszero v1\n  v3 = const 0x1\n  sstore v3, v2\n  stop\n");
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH0, OP::SLOAD, OP::ISZERO, OP::PUSH1(1), OP::SSTORE]));
+    }
+
+    #[test]
+    fn test_lower_globals() {
+        let variables = BTreeMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let function = lower_statement(&body("b = block.prevrandao;"), &variables);
+        assert_eq!(function.to_string(), "b0:\n  v0 = prevrandao\n  v1 = const 0x1\n  sstore v1, v0\n  stop\n");
+        assert_eq!(function.emit(), Ok(vec![OP::PREVRANDAO, OP::PUSH1(1), OP::SSTORE]));
+    }
+
+    #[test]
+    fn test_lower_arithmetic() {
+        let variables = BTreeMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let function = lower_statement(&body("b = a - 2;"), &variables);
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(2), OP::PUSH0, OP::SLOAD, OP::SUB, OP::PUSH1(1), OP::SSTORE]));
+
+        // `*` binds tighter than `+`, and operators of equal precedence group to the left.
+        let mut function = lower_statement(&body("b = 1 + 2 * 3 - 8 / 2 / 2;"), &variables);
+        function.fold_constants();
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(5), OP::PUSH1(1), OP::SSTORE]));
+    }
+
+    #[test]
+    fn test_lower_comparisons() {
+        let variables = BTreeMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let function = lower_statement(&body("b = a <= 2;"), &variables);
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(2), OP::PUSH0, OP::SLOAD, OP::GT, OP::ISZERO, OP::PUSH1(1), OP::SSTORE]));
+
+        let types = BTreeMap::from([("a".to_string(), Type::Int256(()))]);
+        let function = lower_typed_statement(&body("b = a < -1;"), &variables, &types);
+        assert!(function.emit().unwrap().contains(&OP::SLT));
+
+        // Bitwise operators bind looser than arithmetic, `&` before `^` before `|`.
+        let mut function = lower_statement(&body("b = 1 | 6 ^ 3 & ~0 + 1;"), &variables);
+        function.fold_constants();
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(7), OP::PUSH1(1), OP::SSTORE]));
+
+        // Shifts bind looser than arithmetic and tighter than `&`.
+        let mut function = lower_statement(&body("b = 1 << 2 + 1 & 255 >> 4;"), &variables);
+        function.fold_constants();
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(8), OP::PUSH1(1), OP::SSTORE]));
+        let function = lower_typed_statement(&body("b = a >> 1;"), &variables, &types);
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH0, OP::SLOAD, OP::PUSH1(1), OP::SAR, OP::PUSH1(1), OP::SSTORE]));
+
+        // Comparisons bind looser than arithmetic.
+        let mut function = lower_statement(&body("b = 1 + 1 == 2;"), &variables);
+        function.fold_constants();
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH1(1), OP::PUSH1(1), OP::SSTORE]));
+    }
+
+    #[test]
+    fn test_lower_control_flow() {
+        let variables = BTreeMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let function = lower_statement(&body("if (a) { b = true; } else { b = false; }"), &variables);
+        assert_eq!(function.blocks[0].terminator, Terminator::Branch { cond: ValueId(1), then: BlockId(1), otherwise: BlockId(2) });
+        let program = function.emit().unwrap();
+        // `then` comes next, so only `else` needs a jump; `else` falls through to the join.
+        assert_eq!(program, vec![
+            OP::PUSH0, OP::SLOAD, OP::ISZERO, OP::PUSHN(2, U256::from(17u8)), OP::JUMPI,
+            OP::JUMPDEST, OP::PUSH1(1), OP::PUSH1(1), OP::SSTORE, OP::PUSHN(2, U256::from(22u8)), OP::JUMP,
+            OP::JUMPDEST, OP::PUSH0, OP::PUSH1(1), OP::SSTORE,
+            OP::JUMPDEST,
+        ]);
+        assert_eq!(crate::verify::verify_stack(&program).map(|_| ()), Ok(()));
+
+        // Code after a `return` is left out.
+        let function = lower_statement(&body("{ return; b = a; }"), &variables);
+        assert_eq!(function.reachable_blocks(), vec![BlockId(0)]);
+        assert_eq!(function.emit(), Ok(vec![OP::PUSH0, OP::PUSH0, OP::RETURN]));
+    }
+
+    #[test]
+    fn test_require_and_mappings() {
+        let source = r#"contract Vault {
+    mapping(address => uint256) private balances;
+    function deposit(uint256 amount) public { require(amount != 0, "nothing to deposit"); balances[msg.sender] = balances[msg.sender] + amount; }
+    function balanceOf(address owner) public view returns (uint256) { return balances[owner]; }
+}"#;
+        let vault = crate::tinyvm::compile(parse(source).unwrap(), &CompilerSettings::default()).remove(0);
+        assert!(vault.diagnostics.is_empty(), "{:?}", vault.diagnostics);
+        let env = Env { caller: alice(), ..Env::default() };
+        let deposit = |amount: u8| calldata("deposit(uint256)", &[Value::Uint(U256::from(amount))]);
+        let (vault, _, _) = vault.call_with_env(&deposit(5), env.clone()).unwrap();
+        let (_, output, _) = vault.call(&calldata("balanceOf(address)", &[Value::Address(alice())])).unwrap();
+        assert_eq!(output, vec![Expression::NumberLiteral(U256::from(5u8))]);
+
+        // Solidity's layout: the entry for a key lives at keccak256(key . slot).
+        let slot = U256::from_be_bytes(keccak_hash::keccak([alice().to_word().to_be_bytes(), [0; 32]].concat()).0);
+        assert_eq!(vault.storage.load(slot), U256::from(5u8));
+
+        let Err(Halt::Revert(data)) = vault.call_with_env(&deposit(0), env) else { panic!("an empty deposit should revert") };
+        assert_eq!(RevertReason::decode(&data), RevertReason::Error("nothing to deposit".to_string()));
+    }
+
+    #[test]
+    fn test_eval_const_agrees_with_folding() {
+        let variables = BTreeMap::from([("a".to_string(), 0)]);
+        for source in ["1 + 2 * 3 - 8 / 2 / 2", "1 | 6 ^ 3 & ~0 + 1", "1 << 2 + 1 & 255 >> 4", "-1", "2 ** 256 + 7 % 4", "1 + 1 == 2", "3 <= 2"] {
+            let statement = body(&format!("return {};", source));
+            let Statement::Return(_, Some(expr), _) = &statement else { panic!("no return value") };
+            let mut function = lower_statement(&statement, &variables);
+            function.fold_constants();
+            let Terminator::Return(values) = &function.blocks[0].terminator else { panic!("no return") };
+            let word = match eval_const(expr) {
+                Some(Value::Uint(x)) => x,
+                Some(Value::Bool(b)) => U256::from(b),
+                other => panic!("{} evaluated to {:?}", source, other),
+            };
+            assert_eq!(function.inst(values[0]), &Inst::Const(word), "{}", source);
+        }
+
+        let eval = |source: &str| {
+            let Statement::Return(_, Some(expr), _) = body(&format!("return {};", source)) else { panic!("no return value") };
+            eval_const(&expr)
+        };
+        assert_eq!(eval("!(1 > 2)"), Some(Value::Bool(true)));
+        assert_eq!(eval("a + 1"), None);
+        assert_eq!(eval("true + 1"), None);
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let mut function = IrFunction::new();
+        let one = function.push(BlockId(0), Inst::Const(U256::ONE));
+        let not = function.push(BlockId(0), Inst::IsZero(one));
+        function.blocks[0].terminator = Terminator::Return(vec![not]);
+        let ret = [OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN];
+        assert_eq!(function.emit(), Ok([&[OP::PUSH1(1), OP::ISZERO][..], &ret].concat()));
+        function.fold_constants();
+        assert_eq!(function.emit(), Ok([&[OP::PUSH0][..], &ret].concat()));
+    }
+
+    #[test]
+    fn test_rejects_reads_moved_past_stores() {
+        let mut function = IrFunction::new();
+        let key = function.push(BlockId(0), Inst::Const(U256::ZERO));
+        let old = function.push(BlockId(0), Inst::SLoad(key));
+        let one = function.push(BlockId(0), Inst::Const(U256::ONE));
+        function.push(BlockId(0), Inst::SStore { key, value: one });
+        function.blocks[0].terminator = Terminator::Return(vec![old]);
+        assert_eq!(function.emit(), Err(IrError::Unschedulable(old)));
+    }
+
+    #[test]
+    fn test_inline_internal_calls() {
+        let source = "contract C {
+    bool private value;
+    function get() public view returns (bool) { return value; }
+    function set(bool v) public { value = v; }
+    function negated() public view returns (bool) { return !get(); }
+}";
+        let compile = |settings| crate::tinyvm::compile(parse(source).unwrap(), &settings).remove(0);
+        let contract = compile(CompilerSettings::default());
+        assert!(contract.diagnostics.is_empty());
+        let negated = &contract.functions[&selector("negated()")];
+        assert_eq!(negated.program(), &[OP::PUSH0, OP::SLOAD, OP::ISZERO, OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN]);
+
+        // Calls that aren't inlined jump to a copy of the callee appended to the function.
+        for settings in [
+            CompilerSettings { passes: Passes { inline: false, ..Passes::default() }, ..CompilerSettings::default() },
+            CompilerSettings { optimizer: false, ..CompilerSettings::default() },
+            CompilerSettings { runs: 1, ..CompilerSettings::default() },
+            CompilerSettings { inline_size: 0, ..CompilerSettings::default() },
+        ] {
+            let contract = compile(settings);
+            assert!(contract.diagnostics.is_empty(), "{:?}", contract.diagnostics);
+            let negated = contract.functions[&selector("negated()")].program();
+            assert!(negated.contains(&OP::JUMP));
+            assert_eq!(crate::verify::verify_stack(negated).map(|_| ()), Ok(()));
+            let (contract, _, _) = contract.call(&calldata("set(bool)", &[Value::Bool(true)])).unwrap();
+            let (_, output, _) = contract.call(&calldata("negated()", &[])).unwrap();
+            assert_eq!(output, vec![Expression::BoolLiteral(false)]);
+        }
+    }
+
+    #[test]
+    fn test_internal_calls() {
+        let source = "contract C {
+    uint256 private total;
+    function add(uint256 a, uint256 b) internal pure returns (uint256) { return a + b; }
+    function twice(uint256 x) internal pure returns (uint256) { return add(x, x); }
+    function run(uint256 x) public returns (uint256) { total = x; return total + twice(add(x, 1)) + add(x, 2); }
+    function again(uint256 x) public returns (uint256) { return again(x); }
+}";
+        let settings = CompilerSettings { passes: Passes { inline: false, ..Passes::default() }, ..CompilerSettings::default() };
+        let contract = crate::tinyvm::compile(parse(source).unwrap(), &settings).remove(0);
+        assert_eq!(contract.diagnostics.iter().map(|d| d.message.as_str()).collect::<Vec<_>>(), vec![
+            "cannot compile function `again`: recursive internal calls is not supported by code generation yet",
+        ]);
+        let run = contract.functions[&selector("run(uint256)")].program();
+        assert_eq!(crate::verify::verify_stack(run).map(|_| ()), Ok(()));
+        // `total` is read before the calls, and kept in memory across them: 3 + 2 * 4 + 5.
+        let (_, output, _) = contract.call(&calldata("run(uint256)", &[Value::Uint(U256::from(3u8))])).unwrap();
+        assert_eq!(output, vec![Expression::NumberLiteral(U256::from(16u8))]);
+
+        let mut caller = IrFunction::new();
+        caller.push(BlockId(0), Inst::Call("missing".to_string(), vec![]));
+        assert_eq!(caller.emit(), Err(IrError::UnknownCallee("missing".to_string())));
+    }
+
+    #[test]
+    fn test_eliminate_redundant_loads() {
+        // slot 1 = !slot 0; return slot 0
+        let mut function = IrFunction::new();
+        let b = BlockId(0);
+        let k0 = function.push(b, Inst::Const(U256::ZERO));
+        let first = function.push(b, Inst::SLoad(k0));
+        let k0 = function.push(b, Inst::Const(U256::ZERO));
+        let second = function.push(b, Inst::SLoad(k0));
+        let not = function.push(b, Inst::IsZero(second));
+        let k1 = function.push(b, Inst::Const(U256::ONE));
+        function.push(b, Inst::SStore { key: k1, value: not });
+        let k0 = function.push(b, Inst::Const(U256::ZERO));
+        let third = function.push(b, Inst::SLoad(k0));
+        function.blocks[0].terminator = Terminator::Return(vec![first, third]);
+        assert_eq!(function.emit(), Err(IrError::Unschedulable(first)));
+
+        function.eliminate_redundant_loads();
+        let program = function.emit().unwrap();
+        assert_eq!(program, vec![
+            OP::PUSH0, OP::SLOAD, OP::DUP1, OP::ISZERO, OP::PUSH1(1), OP::SSTORE, OP::DUP1,
+            OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::MSTORE, OP::PUSH1(64), OP::PUSH0, OP::RETURN,
+        ]);
+        assert_eq!(crate::verify::verify_stack(&program).map(|_| ()), Ok(()));
+    }
+}
@@ -0,0 +1,238 @@
+use ethnum::U256;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::tinyvm::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+    pub label: String,
+    pub slot: usize,
+    pub offset: usize,
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageLayout {
+    pub entries: Vec<StorageEntry>,
+}
+
+fn type_size(ty: &str) -> usize {
+    match ty {
+        "bool" => 1,
+        _ => 32,
+    }
+}
+
+impl StorageLayout {
+    /// Reads solc's `storageLayout` object, or any object containing one.
+    pub fn from_solc_json(json: &serde_json::Value) -> Option<StorageLayout> {
+        let layout = if json.get("storageLayout").is_some() { &json["storageLayout"] } else { json };
+        let entries = layout["storage"].as_array()?.iter().map(|entry| {
+            let type_id = entry["type"].as_str()?;
+            let ty = layout["types"][type_id]["label"].as_str()
+                .unwrap_or_else(|| type_id.trim_start_matches("t_"));
+            Some(StorageEntry {
+                label: entry["label"].as_str()?.to_string(),
+                slot: entry["slot"].as_str()?.parse().ok()?,
+                offset: entry["offset"].as_u64()? as usize,
+                ty: ty.to_string(),
+            })
+        }).collect::<Option<Vec<_>>>()?;
+        Some(StorageLayout { entries })
+    }
+
+    /// The `storageLayout` object in solc's output format.
+    pub fn to_json(&self, contract_name: &str) -> serde_json::Value {
+        let storage = self.entries.iter().map(|entry| json!({
+            "label": entry.label,
+            "offset": entry.offset,
+            "slot": entry.slot.to_string(),
+            "type": format!("t_{}", entry.ty),
+            "contract": contract_name,
+        })).collect::<Vec<_>>();
+
+        let types = self.entries.iter().map(|entry| (format!("t_{}", entry.ty), json!({
+            "encoding": "inplace",
+            "label": entry.ty,
+            "numberOfBytes": type_size(&entry.ty).to_string(),
+        }))).collect::<BTreeMap<_, _>>();
+
+        json!({ "storage": storage, "types": types })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutMismatch {
+    /// Declared in the reference layout but absent from ours.
+    Missing(String),
+    /// Present in our layout only.
+    Unexpected(String),
+    Slot { label: String, ours: usize, expected: usize },
+    Offset { label: String, ours: usize, expected: usize },
+    Type { label: String, ours: String, expected: String },
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutMismatch::Missing(label) => write!(f, "{}: missing from tinysol layout", label),
+            LayoutMismatch::Unexpected(label) => write!(f, "{}: not present in reference layout", label),
+            LayoutMismatch::Slot { label, ours, expected } => write!(f, "{}: slot {} (expected {})", label, ours, expected),
+            LayoutMismatch::Offset { label, ours, expected } => write!(f, "{}: offset {} (expected {})", label, ours, expected),
+            LayoutMismatch::Type { label, ours, expected } => write!(f, "{}: type {} (expected {})", label, ours, expected),
+        }
+    }
+}
+
+/// Diffs two layouts variable by variable, in the order of the reference layout.
+pub fn compare_layouts(ours: &StorageLayout, expected: &StorageLayout) -> Vec<LayoutMismatch> {
+    let mut mismatches = vec![];
+    expected.entries.iter().for_each(|exp| {
+        match ours.entries.iter().find(|entry| entry.label == exp.label) {
+            None => mismatches.push(LayoutMismatch::Missing(exp.label.clone())),
+            Some(entry) => {
+                if entry.slot != exp.slot {
+                    mismatches.push(LayoutMismatch::Slot { label: exp.label.clone(), ours: entry.slot, expected: exp.slot });
+                }
+                if entry.offset != exp.offset {
+                    mismatches.push(LayoutMismatch::Offset { label: exp.label.clone(), ours: entry.offset, expected: exp.offset });
+                }
+                if entry.ty != exp.ty {
+                    mismatches.push(LayoutMismatch::Type { label: exp.label.clone(), ours: entry.ty.clone(), expected: exp.ty.clone() });
+                }
+            }
+        }
+    });
+    ours.entries.iter()
+        .filter(|entry| !expected.entries.iter().any(|exp| exp.label == entry.label))
+        .for_each(|entry| mismatches.push(LayoutMismatch::Unexpected(entry.label.clone())));
+    mismatches
+}
+
+impl Contract {
+    /// Every state variable gets a full slot of its own, in declaration order.
+    pub fn storage_layout(&self) -> StorageLayout {
+        let mut entries = self.variable_map.iter().map(|(name, slot)| StorageEntry {
+            label: name.clone(),
+            slot: *slot,
+            offset: 0,
+            ty: self.variable_types.get(name).map(type_name).unwrap_or_default().to_string(),
+        }).collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.slot);
+        StorageLayout { entries }
+    }
+}
+
+/// How far past `keccak256(base)` a slot is still taken for array data.
+const ARRAY_SPAN: U256 = U256::new(1 << 64);
+
+/// Where a storage slot comes from, as Solidity lays out storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotOrigin {
+    /// A state variable's own slot.
+    Variable { label: String },
+    /// `keccak256(key . base)`, the entry for `key` of the mapping at slot `base`.
+    /// `mapping` names the state variable at `base`, unless the mapping is nested.
+    MappingEntry { mapping: Option<String>, base: U256, key: U256 },
+    /// `keccak256(base) + index`, element data of the dynamic array at slot `base`.
+    ArrayData { array: Option<String>, base: U256, index: U256 },
+    /// No variable or recorded hash accounts for the slot.
+    Unknown,
+}
+
+impl Contract {
+    /// Explains a slot key seen at runtime, for example in a storage write. Mapping and
+    /// array slots are hashes, so they are only recognised when a call made through this
+    /// contract computed them with `KECCAK256`.
+    pub fn explain_slot(&self, slot: U256) -> SlotOrigin {
+        let label = |base: U256| self.variable_map.iter()
+            .find(|(_, at)| U256::from(**at as u64) == base)
+            .map(|(name, _)| name.clone());
+        if let Some(label) = label(slot) {
+            return SlotOrigin::Variable { label };
+        }
+        if let Some(preimage) = self.preimages.get(&slot).filter(|preimage| preimage.len() == 64) {
+            let key = U256::from_be_bytes(preimage[..32].try_into().unwrap());
+            let base = U256::from_be_bytes(preimage[32..].try_into().unwrap());
+            return SlotOrigin::MappingEntry { mapping: label(base), base, key };
+        }
+        self.preimages.range(..=slot).rev()
+            .find(|(_, preimage)| preimage.len() == 32)
+            .filter(|(hash, _)| slot - **hash < ARRAY_SPAN)
+            .map_or(SlotOrigin::Unknown, |(hash, preimage)| {
+                let base = U256::from_be_bytes(preimage[..].try_into().unwrap());
+                SlotOrigin::ArrayData { array: label(base), base, index: slot - hash }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::selector::selector;
+    use crate::solidity::grammar::parse;
+
+    #[test]
+    fn test_storage_layout() {
+        let code = "contract c { bool private a; bool public b; }";
+        let contract = create_contracts(parse(code).unwrap()).remove(0);
+        let layout = contract.storage_layout();
+        assert_eq!(layout.entries.iter().map(|e| (e.label.as_str(), e.slot)).collect::<Vec<_>>(), vec![("a", 0), ("b", 1)]);
+
+        let json = layout.to_json("c");
+        assert_eq!(json["storage"][1]["slot"], "1");
+        assert_eq!(json["types"]["t_bool"]["numberOfBytes"], "1");
+        assert_eq!(StorageLayout::from_solc_json(&json), Some(layout));
+    }
+
+    #[test]
+    fn test_compare_layouts() {
+        let code = "contract c { bool private a; bool public b; }";
+        let ours = create_contracts(parse(code).unwrap()).remove(0).storage_layout();
+        // solc packs both bools into slot 0.
+        let solc = StorageLayout::from_solc_json(&json!({
+            "storageLayout": {
+                "storage": [
+                    { "label": "a", "offset": 0, "slot": "0", "type": "t_bool" },
+                    { "label": "b", "offset": 1, "slot": "0", "type": "t_bool" },
+                    { "label": "c", "offset": 0, "slot": "1", "type": "t_uint256" },
+                ],
+                "types": { "t_bool": { "label": "bool" }, "t_uint256": { "label": "uint256" } },
+            }
+        })).unwrap();
+
+        assert_eq!(compare_layouts(&ours, &ours), vec![]);
+        assert_eq!(compare_layouts(&ours, &solc), vec![
+            LayoutMismatch::Slot { label: "b".to_string(), ours: 1, expected: 0 },
+            LayoutMismatch::Offset { label: "b".to_string(), ours: 0, expected: 1 },
+            LayoutMismatch::Missing("c".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_explain_slot() {
+        let code = "contract c { bool private a; uint256 private b; }";
+        let mut contract = create_contracts(parse(code).unwrap()).remove(0);
+        // Hashes `7 . 1` and then `1`: the slot of `b[7]` if `b` were a mapping, and of
+        // `b[0]` if it were an array.
+        let program = vec![
+            OP::PUSH1(7), OP::PUSH1(0), OP::MSTORE, OP::PUSH1(1), OP::PUSH1(32), OP::MSTORE,
+            OP::PUSH1(64), OP::PUSH1(0), OP::KECCAK256, OP::POP,
+            OP::PUSH1(32), OP::PUSH1(32), OP::KECCAK256, OP::POP,
+        ];
+        contract.insert_function(selector("hash()"), Function::from_program("hash".to_string(), program)).unwrap();
+        let (contract, ..) = contract.call(&calldata("hash()", &[])).unwrap();
+
+        let mut words = [0; 64];
+        words[31] = 7;
+        words[63] = 1;
+        let entry = U256::from_be_bytes(keccak_hash::keccak(words).0);
+        let data = U256::from_be_bytes(keccak_hash::keccak(&words[32..]).0);
+        assert_eq!(contract.explain_slot(U256::ONE), SlotOrigin::Variable { label: "b".to_string() });
+        assert_eq!(contract.explain_slot(entry), SlotOrigin::MappingEntry { mapping: Some("b".to_string()), base: U256::ONE, key: U256::from(7u8) });
+        assert_eq!(contract.explain_slot(data + 3), SlotOrigin::ArrayData { array: Some("b".to_string()), base: U256::ONE, index: U256::from(3u8) });
+        assert_eq!(contract.explain_slot(U256::from(5u8)), SlotOrigin::Unknown);
+    }
+}
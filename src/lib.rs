@@ -0,0 +1,50 @@
+pub mod solidity;
+pub mod diagnostics;
+pub mod unsupported;
+pub mod conformance;
+pub mod visit;
+pub mod analysis;
+pub mod env;
+pub mod tinyvm;
+pub mod executor;
+pub mod host;
+pub mod call;
+pub mod state;
+pub mod abi;
+pub mod selector;
+pub mod revert;
+pub mod bytecode;
+pub mod metadata;
+pub mod ir;
+pub mod settings;
+pub mod cfg;
+pub mod verify;
+pub mod layout;
+pub mod artifact;
+pub mod bindings;
+pub mod cache;
+pub mod trace;
+pub mod debugger;
+pub mod taint;
+pub mod symbolic;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod rlp;
+pub mod trie;
+pub mod block;
+pub mod log;
+pub mod secp256k1;
+pub mod rpc;
+pub mod deploy;
+pub mod world;
+pub mod overrides;
+pub mod scenario;
+pub mod replay;
+pub mod prestate;
+pub mod calltrace;
+pub mod registry;
+pub mod fuzz;
+pub mod mutation;
+pub mod erc20;
+pub mod erc165;
+pub mod test_utils;
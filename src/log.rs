@@ -0,0 +1,191 @@
+use ethnum::U256;
+use std::fmt;
+use keccak_hash::keccak;
+use crate::bytecode::to_hex;
+use crate::env::Address;
+
+/// A log emitted by a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
+
+/// A log as the VM records it, before it's attributed to the account that emitted it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogEntry {
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
+
+impl LogEntry {
+    pub fn emitted_by(self, address: Address) -> Log {
+        Log { address, topics: self.topics, data: self.data }
+    }
+}
+
+/// The 2048-bit logs bloom of a receipt or block header: for the address and every topic
+/// of each log, three bits picked from its keccak256 hash are set. A bloom can rule a log
+/// out, but may match logs that aren't there.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bloom(pub [u8; 256]);
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom([0; 256])
+    }
+}
+
+impl fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bloom({})", to_hex(&self.0))
+    }
+}
+
+impl Bloom {
+    pub fn from_logs<'a>(logs: impl IntoIterator<Item = &'a Log>) -> Self {
+        let mut bloom = Bloom::default();
+        logs.into_iter().for_each(|log| bloom.accrue_log(log));
+        bloom
+    }
+
+    /// The bits `input` sets: the low 11 bits of each of the first three byte pairs of its
+    /// hash, counted from the end of the bloom.
+    fn bits(input: &[u8]) -> [(usize, u8); 3] {
+        let hash = keccak(input);
+        [0, 2, 4].map(|i| {
+            let bit = (usize::from(hash[i]) << 8 | usize::from(hash[i + 1])) & 2047;
+            (255 - bit / 8, 1 << (bit % 8))
+        })
+    }
+
+    pub fn accrue(&mut self, input: &[u8]) {
+        Self::bits(input).iter().for_each(|(byte, mask)| self.0[*byte] |= mask);
+    }
+
+    pub fn accrue_log(&mut self, log: &Log) {
+        self.accrue(&log.address.0);
+        log.topics.iter().for_each(|topic| self.accrue(&topic.to_be_bytes()));
+    }
+
+    /// Adds every bit of `other`, as a block's bloom covers its receipts'.
+    pub fn accrue_bloom(&mut self, other: &Bloom) {
+        self.0.iter_mut().zip(other.0).for_each(|(byte, other)| *byte |= other);
+    }
+
+    /// Whether `input` may have been accrued. `false` means it certainly wasn't.
+    pub fn contains(&self, input: &[u8]) -> bool {
+        Self::bits(input).iter().all(|(byte, mask)| self.0[*byte] & mask != 0)
+    }
+}
+
+/// A log as `eth_getLogs` returns it, with where it was included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub log: Log,
+    pub block_number: u64,
+    pub tx_hash: [u8; 32],
+    /// Position of the log in its block.
+    pub log_index: usize,
+}
+
+/// Selects logs like an `eth_getLogs` filter. Empty address and topic lists match
+/// anything; a log matches a list of alternatives if it matches any of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    pub addresses: Vec<Address>,
+    /// Alternatives for each topic position, in order.
+    pub topics: Vec<Vec<U256>>,
+}
+
+impl LogFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_block(mut self, from_block: u64) -> Self {
+        self.from_block = Some(from_block);
+        self
+    }
+
+    pub fn to_block(mut self, to_block: u64) -> Self {
+        self.to_block = Some(to_block);
+        self
+    }
+
+    pub fn address(mut self, address: Address) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    /// Requires topic `index` to be one of `values`.
+    pub fn topic(mut self, index: usize, values: Vec<U256>) -> Self {
+        if self.topics.len() <= index {
+            self.topics.resize(index + 1, vec![]);
+        }
+        self.topics[index] = values;
+        self
+    }
+
+    /// Whether a receipt or block with `bloom` may have logs the filter matches, ignoring
+    /// the block range.
+    pub fn may_match(&self, bloom: &Bloom) -> bool {
+        (self.addresses.is_empty() || self.addresses.iter().any(|address| bloom.contains(&address.0)))
+            && self.topics.iter().all(|values| values.is_empty() || values.iter().any(|topic| bloom.contains(&topic.to_be_bytes())))
+    }
+
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        let log = &record.log;
+        self.from_block.is_none_or(|from| record.block_number >= from)
+            && self.to_block.is_none_or(|to| record.block_number <= to)
+            && (self.addresses.is_empty() || self.addresses.contains(&log.address))
+            && self.topics.iter().enumerate().all(|(i, values)| {
+                values.is_empty() || log.topics.get(i).is_some_and(|topic| values.contains(topic))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::from_hex;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_filter_matches() {
+        let record = LogRecord {
+            log: Log { address: alice(), topics: vec![U256::ONE, U256::from(2u8)], data: vec![] },
+            block_number: 5,
+            tx_hash: [0; 32],
+            log_index: 0,
+        };
+        assert!(LogFilter::new().matches(&record));
+        assert!(LogFilter::new().address(bob()).address(alice()).from_block(5).to_block(5).matches(&record));
+        assert!(!LogFilter::new().address(bob()).matches(&record));
+        assert!(!LogFilter::new().from_block(6).matches(&record));
+        assert!(LogFilter::new().topic(1, vec![U256::ZERO, U256::from(2u8)]).matches(&record));
+        assert!(!LogFilter::new().topic(0, vec![U256::from(2u8)]).matches(&record));
+        assert!(!LogFilter::new().topic(2, vec![U256::ONE]).matches(&record));
+    }
+
+    #[test]
+    fn test_bloom() {
+        // Each input sets three bits, here all in different bytes.
+        let address = Address(from_hex("0x22341ae42d6dd7384bc8584e50419ea3ac75b83f").unwrap().try_into().unwrap());
+        let mut bloom = Bloom::default();
+        bloom.accrue(&address.0);
+        let set = bloom.0.iter().enumerate().filter(|(_, byte)| **byte != 0).map(|(i, byte)| (i, *byte)).collect::<Vec<_>>();
+        assert_eq!(set.len(), 3);
+        assert!(bloom.contains(&address.0));
+
+        let log = Log { address: alice(), topics: vec![U256::from(7u8)], data: vec![1, 2] };
+        let bloom = Bloom::from_logs([&log]);
+        assert!(LogFilter::new().address(alice()).topic(0, vec![U256::ONE, U256::from(7u8)]).may_match(&bloom));
+        assert!(!LogFilter::new().address(bob()).may_match(&bloom));
+        assert!(!LogFilter::new().topic(0, vec![U256::ONE]).may_match(&bloom));
+        assert!(LogFilter::new().may_match(&Bloom::default()));
+    }
+}
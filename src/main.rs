@@ -1,10 +1,531 @@
-pub mod solidity;
-use crate::solidity::grammar::*;
-
-pub mod tinyvm;
-use crate::tinyvm::*;
+use std::path::Path;
+use std::io::Write;
+use std::process::exit;
+use tinysol::solidity::grammar::*;
+use tinysol::tinyvm::*;
+use tinysol::artifact::{hardhat_artifact, write_artifacts};
+use tinysol::cache::BuildCache;
+use tinysol::layout::{StorageLayout, compare_layouts};
+use tinysol::trace::*;
+use tinysol::env::{Address, Env};
+use tinysol::bytecode::from_hex;
+use tinysol::abi::{calldata, AbiCheckError, CalldataBuilder};
+use tinysol::rpc::RpcClient;
+use tinysol::deploy::Signer;
+use tinysol::replay::Fixture;
+use tinysol::fuzz::FuzzConfig;
+use tinysol::diagnostics::Severity;
+use tinysol::cfg::Cfg;
+use tinysol::selector::selector;
+use tinysol::world::{Transaction, World};
+use tinysol::settings::{CompilerSettings, DebugInfo};
+use ethnum::U256;
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(String::as_str) {
+        Some("compile") => compile(&args[1..]),
+        Some("check-layout") => check_layout(&args[1..]),
+        Some("trace") => trace(&args[1..]),
+        Some("deploy") => deploy(&args[1..]),
+        Some("record") => record(&args[1..]),
+        Some("replay") => replay(&args[1..]),
+        Some("fuzz") => fuzz(&args[1..]),
+        Some("mutate") => mutate(&args[1..]),
+        Some("lint") => lint(&args[1..]),
+        Some("cfg") => cfg(&args[1..]),
+        Some("bindings") => bindings(&args[1..]),
+        Some("decode") => decode(&args[1..]),
+        Some("conformance") => conformance(&args[1..]),
+        Some("ast") => ast(&args[1..]),
+        #[cfg(feature = "dap")]
+        Some("dap") => {
+            if let Err(err) = tinysol::dap::serve(std::io::stdin().lock(), std::io::stdout()) {
+                eprintln!("Debug adapter failed: {}", err);
+                exit(1);
+            }
+        },
+        Some(other) => {
+            eprintln!("Unknown command: {}", other);
+            exit(1);
+        },
+        None => demo(),
+    }
+}
+
+/// Value following `flag` in the argument list, e.g. `--out artifacts/`.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+/// Flags that take no value.
+const SWITCHES: [&str; 6] = ["--no-cache", "--metadata", "--no-optimize", "--no-inline", "--no-fold-constants", "--no-load-elimination"];
+
+fn has_switch(args: &[String], switch: &str) -> bool {
+    args.iter().any(|arg| arg == switch)
+}
+
+/// Positional arguments, skipping flags and their values.
+fn positional(args: &[String]) -> Vec<&str> {
+    let mut skip = false;
+    args.iter().filter_map(|arg| {
+        if skip {
+            skip = false;
+            None
+        } else if arg.starts_with("--") {
+            skip = !SWITCHES.contains(&arg.as_str());
+            None
+        } else {
+            Some(arg.as_str())
+        }
+    }).collect()
+}
+
+fn read_file(path: &str) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path, e);
+        exit(1);
+    })
+}
+
+/// Compiler settings from `--no-optimize`, `--optimizer-runs N`, `--debug-info LEVEL`,
+/// `--metadata` and the `--no-<pass>` switches.
+fn compiler_settings(args: &[String]) -> CompilerSettings {
+    let mut settings = CompilerSettings { optimizer: !has_switch(args, "--no-optimize"), ..CompilerSettings::default() };
+    settings.passes.inline = !has_switch(args, "--no-inline");
+    settings.passes.fold_constants = !has_switch(args, "--no-fold-constants");
+    settings.passes.eliminate_redundant_loads = !has_switch(args, "--no-load-elimination");
+    settings.metadata = has_switch(args, "--metadata");
+    if let Some(runs) = flag_value(args, "--optimizer-runs") {
+        settings.runs = runs.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid --optimizer-runs: {}", runs);
+            exit(1);
+        });
+    }
+    if let Some(level) = flag_value(args, "--debug-info") {
+        settings.debug_info = level.parse::<DebugInfo>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        });
+    }
+    settings
+}
+
+fn parse_file(args: &[String], path: &str) -> Vec<Contract> {
+    parse_source(path, &read_file(path), &compiler_settings(args))
+}
+
+fn parse_source(file: &str, code: &str, settings: &CompilerSettings) -> Vec<Contract> {
+    match compile_source(code, settings) {
+        Ok(contracts) => contracts,
+        Err(diagnostics) => {
+            diagnostics.iter().for_each(|diagnostic| eprintln!("{}", diagnostic.render(file, code)));
+            exit(1);
+        }
+    }
+}
+
+/// The contract named by `--contract`, or the first one in the file.
+fn select_contract<'a>(args: &[String], contracts: &'a [Contract], file: &str) -> &'a Contract {
+    let contract = match flag_value(args, "--contract") {
+        Some(name) => contracts.iter().find(|contract| contract.name == name),
+        None => contracts.first(),
+    };
+    contract.unwrap_or_else(|| {
+        eprintln!("No matching contract in {}", file);
+        exit(1);
+    })
+}
+
+/// `tinysol compile File.sol... [--out artifacts/] [--cache cache/] [--no-cache] [--no-optimize] [--optimizer-runs N] [--debug-info none|statements]`
+fn compile(args: &[String]) {
+    let out = flag_value(args, "--out").unwrap_or("artifacts");
+    let settings = compiler_settings(args);
+    let cache = match has_switch(args, "--no-cache") {
+        true => None,
+        false => BuildCache::open(Path::new(flag_value(args, "--cache").unwrap_or("cache")))
+            .map_err(|e| eprintln!("Build cache disabled: {}", e))
+            .ok(),
+    };
+    positional(args).iter().for_each(|file| {
+        let source = read_file(file);
+        let key = BuildCache::key(file, &source, &settings);
+        let artifacts = match cache.as_ref().and_then(|cache| cache.get(&key)) {
+            Some(artifacts) => artifacts,
+            None => build_artifacts(file, &source, &settings, cache.as_ref().map(|cache| (cache, key.as_str()))),
+        };
+        match write_artifacts(&artifacts, file, Path::new(out)) {
+            Ok(paths) => paths.iter().for_each(|path| println!("{}", path.display())),
+            Err(e) => {
+                eprintln!("Unable to write artifacts: {}", e);
+                exit(1);
+            }
+        }
+    });
+}
+
+/// Compiles `source` into Hardhat artifacts, storing them in the cache when it compiled
+/// without diagnostics.
+fn build_artifacts(file: &str, source: &str, settings: &CompilerSettings, cache: Option<(&BuildCache, &str)>) -> Vec<serde_json::Value> {
+    let contracts = parse_source(file, source, settings);
+    let diagnostics = contracts.iter().flat_map(|contract| &contract.diagnostics).collect::<Vec<_>>();
+    diagnostics.iter().for_each(|diagnostic| eprintln!("{}", diagnostic.render(file, source)));
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        exit(1);
+    }
+    let errors = contracts.iter()
+        .flat_map(|contract| contract.verify_stack().into_iter().map(move |(function, e)| (&contract.name, function, e)))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        errors.iter().for_each(|(contract, function, e)| eprintln!("{}.{}: {}", contract, function, e));
+        exit(1);
+    }
+    let artifacts = contracts.iter().map(|contract| hardhat_artifact(contract, file)).collect::<Vec<_>>();
+    if let Some((cache, key)) = cache.filter(|_| diagnostics.is_empty()) {
+        if let Err(e) = cache.insert(key, &artifacts) {
+            eprintln!("Unable to update build cache: {}", e);
+        }
+    }
+    artifacts
+}
+
+/// `tinysol check-layout File.sol --solc-output layout.json [--contract Name]`
+fn check_layout(args: &[String]) {
+    let (Some(file), Some(solc_output)) = (positional(args).first().copied(), flag_value(args, "--solc-output")) else {
+        eprintln!("Usage: tinysol check-layout File.sol --solc-output layout.json [--contract Name]");
+        exit(1);
+    };
+
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+
+    let expected = std::fs::read_to_string(solc_output).ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .and_then(|json| StorageLayout::from_solc_json(&json));
+    let Some(expected) = expected else {
+        eprintln!("Unable to read a storage layout from {}", solc_output);
+        exit(1);
+    };
+
+    let mismatches = compare_layouts(&contract.storage_layout(), &expected);
+    if mismatches.is_empty() {
+        println!("{}: storage layout matches", contract.name);
+    } else {
+        mismatches.iter().for_each(|mismatch| println!("{}: {}", contract.name, mismatch));
+        exit(1);
+    }
+}
+
+/// `tinysol trace File.sol --function "transfer(address,uint256)" --args "0x7099...79c8,1000" [--reference trace.jsonl]`
+///
+/// Without a reference, prints the EIP-3155 trace of the call. With one, reports
+/// the first step where execution diverges from it.
+fn trace(args: &[String]) {
+    let (Some(file), Some(function)) = (positional(args).first().copied(), flag_value(args, "--function")) else {
+        eprintln!("Usage: tinysol trace File.sol --function \"name(types)\" [--args a,b] [--contract Name] [--reference trace.jsonl]");
+        exit(1);
+    };
+    let calldata = match CalldataBuilder::new(function).parse_args(flag_value(args, "--args").unwrap_or_default()) {
+        Ok(builder) => builder.to_hex(),
+        Err(e) => {
+            match e {
+                AbiCheckError::ArgumentCount { expected, .. } => eprintln!("{} takes {} arguments", function, expected),
+                AbiCheckError::ArgumentType { index, expected } => eprintln!("Argument {} of {} should be a {}", index + 1, function, expected),
+                e => eprintln!("Invalid --args: {:?}", e),
+            }
+            exit(1);
+        }
+    };
+
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+
+    if !contract.functions.contains_key(&selector(function)) {
+        eprintln!("No function {} in {}", function, contract.name);
+        exit(1);
+    }
+    // Sent as a transaction, so the trace starts at the dispatcher, as a node's does.
+    let sender = Env::default().caller;
+    let mut world = World::new();
+    let address = world.deploy(sender, contract.clone());
+    let (_, steps) = trace_transaction(&world, &Transaction::new(sender, address, &calldata));
+
+    match flag_value(args, "--reference") {
+        None => print!("{}", to_eip3155(&steps)),
+        Some(reference) => {
+            let Some(expected) = std::fs::read_to_string(reference).ok().and_then(|trace| parse_eip3155(&trace)) else {
+                eprintln!("Unable to read an EIP-3155 trace from {}", reference);
+                exit(1);
+            };
+            match compare_traces(&steps, &expected) {
+                None => println!("traces match ({} steps)", expected.len()),
+                Some(divergence) => {
+                    println!("{}", divergence);
+                    exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Environment variable `tinysol deploy` reads the signing key from. Unlike a flag, it
+/// doesn't end up in shell history or in other users' `ps` output.
+const PRIVATE_KEY_VAR: &str = "TINYSOL_PRIVATE_KEY";
+
+/// `TINYSOL_PRIVATE_KEY=KEY tinysol deploy File.sol --rpc http://localhost:8545`, or
+/// `tinysol deploy File.sol --rpc http://localhost:8545 --from ADDRESS`
+fn deploy(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("Usage: tinysol deploy File.sol --rpc URL [--from ADDRESS] [--contract Name]");
+        eprintln!("Without --from, the key to sign with is read from {}.", PRIVATE_KEY_VAR);
+        exit(1);
+    };
+    let Some(file) = positional(args).first().copied() else { usage() };
+    if flag_value(args, "--private-key").is_some() {
+        eprintln!("--private-key is not supported, as it would leak the key to other processes; set {} instead", PRIVATE_KEY_VAR);
+        exit(1);
+    }
+    let signer = match (flag_value(args, "--from"), std::env::var(PRIVATE_KEY_VAR)) {
+        (Some(from), _) => from_hex(from)
+            .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+            .map(|bytes| Signer::Unlocked(Address(bytes))),
+        (None, Ok(key)) => U256::from_str_radix(key.trim().trim_start_matches("0x"), 16).ok().map(Signer::PrivateKey),
+        (None, Err(_)) => usage(),
+    };
+    let Some(signer) = signer else { usage() };
+    let client = match RpcClient::new(flag_value(args, "--rpc").unwrap_or("http://localhost:8545")) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    match tinysol::deploy::deploy(&client, &contract.bytecode(), &signer) {
+        Ok(address) => println!("{} deployed at {}", contract.name, address),
+        Err(e) => {
+            eprintln!("Deployment failed: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// `tinysol record File.sol --function "flip()" [--contract Name] [--out fixture.json]`
+fn record(args: &[String]) {
+    let (Some(file), Some(function)) = (positional(args).first().copied(), flag_value(args, "--function")) else {
+        eprintln!("Usage: tinysol record File.sol --function \"name(types)\" [--contract Name] [--out fixture.json]");
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    let Some(fixture) = Fixture::record(contract, function, Env::default()) else {
+        eprintln!("{} has no function {}", contract.name, function);
+        exit(1);
+    };
+
+    let json = serde_json::to_string_pretty(&fixture.to_json()).unwrap_or_default();
+    match flag_value(args, "--out") {
+        Some(out) => {
+            if let Err(e) = std::fs::write(out, json) {
+                eprintln!("Unable to write {}: {}", out, e);
+                exit(1);
+            }
+        },
+        None => println!("{}", json),
+    }
+}
+
+/// `tinysol replay fixture.json`
+fn replay(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol replay fixture.json");
+        exit(1);
+    };
+    let fixture = std::fs::read_to_string(file).ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .and_then(|json| Fixture::from_json(&json));
+    let Some(fixture) = fixture else {
+        eprintln!("Unable to read a fixture from {}", file);
+        exit(1);
+    };
+
+    match fixture.replay() {
+        Some(outcome) if outcome == fixture.expected => println!("{}.{}: replay matches recording", fixture.contract, fixture.function),
+        Some(outcome) => {
+            println!("{}.{}: replay diverged", fixture.contract, fixture.function);
+            println!("  expected: {:?}", fixture.expected);
+            println!("  actual:   {:?}", outcome);
+            exit(1);
+        },
+        None => {
+            eprintln!("Fixture code could not be decoded");
+            exit(1);
+        }
+    }
+}
+
+/// `tinysol decode File.sol --calldata 0x...`
+fn decode(args: &[String]) {
+    let (Some(file), Some(calldata)) = (positional(args).first().copied(), flag_value(args, "--calldata")) else {
+        eprintln!("Usage: tinysol decode File.sol --calldata HEX [--contract Name]");
+        exit(1);
+    };
+    let Some(data) = from_hex(calldata) else {
+        eprintln!("--calldata expects hex, got {}", calldata);
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    println!("{}", tinysol::abi::decode_calldata(&contract.abi(), &data));
+}
+
+/// `tinysol conformance [DIR] [--metrics FILE]`: how much of a corpus of Solidity files
+/// parses, by default the bundled OpenZeppelin subset. `--metrics` appends the numbers to
+/// FILE as a JSON line, to follow them across releases.
+fn conformance(args: &[String]) {
+    let dir = positional(args).first().copied().unwrap_or(tinysol::conformance::OPENZEPPELIN);
+    let report = tinysol::conformance::run_corpus(Path::new(dir)).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", dir, e);
+        exit(1);
+    });
+    print!("{}", report);
+    if let Some(metrics) = flag_value(args, "--metrics") {
+        let appended = std::fs::OpenOptions::new().create(true).append(true).open(metrics)
+            .and_then(|mut file| writeln!(file, "{}", report.to_json()));
+        if let Err(e) = appended {
+            eprintln!("Unable to write {}: {}", metrics, e);
+            exit(1);
+        }
+    }
+}
+
+/// `tinysol ast File.sol`: the parsed AST as JSON.
+fn ast(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol ast File.sol");
+        exit(1);
+    };
+    let source = read_file(file);
+    let source_unit = tinysol::unsupported::parse_source(&source).unwrap_or_else(|diagnostics| {
+        diagnostics.iter().for_each(|diagnostic| eprintln!("{}", diagnostic.render(file, &source)));
+        exit(1);
+    });
+    println!("{}", serde_json::to_string_pretty(&source_unit).unwrap());
+}
+
+/// `tinysol fuzz File.sol [--contract Name] [--runs N] [--depth N] [--seed N]`
+fn fuzz(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol fuzz File.sol [--contract Name] [--runs N] [--depth N] [--seed N]");
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    let report = tinysol::fuzz::fuzz(contract, &fuzz_config(args));
+    print!("{}", report);
+    if !report.passed() {
+        exit(1);
+    }
+}
+
+/// The `--runs`, `--depth` and `--seed` flags.
+fn fuzz_config(args: &[String]) -> FuzzConfig {
+    let defaults = FuzzConfig::default();
+    let number = |flag: &str, default: u64| match flag_value(args, flag) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("{} expects a number, got {}", flag, value);
+            exit(1);
+        }),
+        None => default,
+    };
+    FuzzConfig {
+        runs: number("--runs", defaults.runs as u64) as usize,
+        depth: number("--depth", defaults.depth as u64) as usize,
+        seed: number("--seed", defaults.seed),
+    }
+}
+
+/// `tinysol mutate File.sol [--contract Name] [--runs N] [--depth N] [--seed N]`
+fn mutate(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol mutate File.sol [--contract Name] [--runs N] [--depth N] [--seed N]");
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    let config = fuzz_config(args);
+
+    match tinysol::mutation::mutation_test(contract, &config) {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.passed() {
+                exit(1);
+            }
+        },
+        Err(test) => {
+            eprintln!("{} fails on the unmutated contract", test);
+            exit(1);
+        },
+    }
+}
+
+/// `tinysol lint File.sol`
+fn lint(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol lint File.sol");
+        exit(1);
+    };
+    let source = std::fs::read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", file, e);
+        exit(1);
+    });
+    let source_unit = tinysol::unsupported::parse_source(&source).unwrap_or_else(|diagnostics| {
+        diagnostics.iter().for_each(|diagnostic| println!("{}", diagnostic.render(file, &source)));
+        exit(1);
+    });
+
+    let diagnostics = tinysol::analysis::analyze(&source_unit);
+    diagnostics.iter().for_each(|diagnostic| println!("{}", diagnostic.render(file, &source)));
+    println!("{}: {} issue(s)", file, diagnostics.len());
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        exit(1);
+    }
+}
+
+/// `tinysol cfg File.sol --function "name(types)" [--contract Name]`, printed as Graphviz DOT.
+fn cfg(args: &[String]) {
+    let (Some(file), Some(function)) = (positional(args).first().copied(), flag_value(args, "--function")) else {
+        eprintln!("Usage: tinysol cfg File.sol --function \"name(types)\" [--contract Name]");
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let contract = select_contract(args, &contracts, file);
+    let Some(compiled) = contract.functions.get(&selector(function)) else {
+        eprintln!("{} has no function {}", contract.name, function);
+        exit(1);
+    };
+    print!("{}", Cfg::build(compiled.program()).to_dot(&format!("{}.{}", contract.name, function)));
+}
+
+fn bindings(args: &[String]) {
+    let Some(file) = positional(args).first().copied() else {
+        eprintln!("Usage: tinysol bindings File.sol [--contract Name] [--out bindings.rs]");
+        exit(1);
+    };
+    let contracts = parse_file(args, file);
+    let source = tinysol::bindings::rust_bindings(select_contract(args, &contracts, file));
+    match flag_value(args, "--out") {
+        Some(out) => if let Err(err) = std::fs::write(out, source) {
+            eprintln!("Failed to write {}: {}", out, err);
+            exit(1);
+        },
+        None => print!("{}", source),
+    }
+}
+
+fn demo() {
     let code = std::fs::read_to_string("./contracts/flipper.sol").expect("Unable to read source file");
     let parsed = parse(code.as_str());
     println!("{:#?}", parsed);
@@ -12,16 +533,17 @@ fn main() {
     match parsed {
         Ok(source_unit) => {
             create_contracts(source_unit).iter().map(|contract| {
-                let func_sig = get_func_sig("constructor(bool)".to_string());
-                contract.call(func_sig.as_str()).0
+                // Constructors aren't dispatchable yet, so this leaves the contract as is.
+                let func_sig = calldata("constructor(bool)", &[]);
+                contract.call(func_sig.as_str()).map_or_else(|_| contract.clone(), |(contract, ..)| contract)
             }).collect::<Vec<Contract>>().iter().for_each(|contract| {
                 println!("{:#?}", contract);
-                let func_sig = get_func_sig("flip()".to_string());
-                let new_contract = contract.call(func_sig.as_str()).0;
+                let func_sig = calldata("flip()", &[]);
+                let new_contract = contract.call(func_sig.as_str()).unwrap().0;
                 println!("modified contract: {:#?}", new_contract);
 
-                let func_sig = get_func_sig("get()".to_string());
-                let ret = new_contract.call(func_sig.as_str()).1;
+                let func_sig = calldata("get()", &[]);
+                let ret = new_contract.call(func_sig.as_str()).unwrap().1;
                 println!("Return value: {:#?}", ret);
             });
         },
@@ -32,8 +554,14 @@ fn main() {
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use tinysol::log::LogEntry;
+    use tinysol::env::{BlockEnv, CallContext};
+    use std::collections::BTreeMap;
+    use tinysol::executor::Storage;
+    use tinysol::test_utils::flipper;
 
     #[test]
     fn test_parse_empty() {
@@ -80,13 +608,479 @@ mod tests {
                 let contract = contracts.first();
                 assert!(contract.is_some());
 
-                let func_sig = get_func_sig("flip()".to_string());
-                let _ = contract.unwrap().call(func_sig.as_str()).0;
+                let func_sig = calldata("flip()", &[]);
+                let _ = contract.unwrap().call(func_sig.as_str()).unwrap().0;
             },
             Err(e) => assert!(false, "Error: {:?}", e)
         };
     }
 
+    #[test]
+    fn test_uint256_arithmetic() {
+        let source = "contract Counter {
+    uint256 private count;
+    function add() public { count = count + 10 - 4 * 2 / 2; }
+    function underflow() public { count = count - 7; }
+    function get() public view returns (uint256) { return count; }
+}";
+        let contract = create_contracts(parse(source).unwrap()).remove(0);
+        assert!(contract.diagnostics.is_empty(), "{:?}", contract.diagnostics);
+        let get = calldata("get()", &[]);
+
+        let (contract, ..) = contract.call(&calldata("add()", &[])).unwrap();
+        assert_eq!(contract.call(&get).unwrap().1, vec![Expression::NumberLiteral(U256::from(6u8))]);
+        let (contract, ..) = contract.call(&calldata("underflow()", &[])).unwrap();
+        assert_eq!(contract.call(&get).unwrap().1, vec![Expression::NumberLiteral(U256::MAX)]);
+    }
+
+    #[test]
+    fn test_modular_arithmetic() {
+        let run = |program: Vec<OP>| {
+            let mut vm = VM::builder(program).build();
+            vm.run(ContractStorage::new(vec![]));
+            (vm.stack.peek(0), vm.gas_used)
+        };
+        let max = U256::MAX;
+        assert_eq!(run(vec![OP::PUSH1(0), OP::PUSH1(7), OP::MOD]).0, Some(U256::ZERO));
+        assert_eq!(run(vec![OP::PUSH1(3), OP::PUSH1(7), OP::MOD]).0, Some(U256::ONE));
+        assert_eq!(run(vec![OP::PUSH1(10), OP::PUSH32(max), OP::PUSH32(max), OP::ADDMOD]).0, Some(U256::from(0u8)));
+        assert_eq!(run(vec![OP::PUSH1(12), OP::PUSH32(max), OP::PUSH32(max), OP::MULMOD]).0, Some(U256::from(9u8)));
+        assert_eq!(run(vec![OP::PUSH1(0), OP::PUSH1(2), OP::PUSH1(3), OP::MULMOD]).0, Some(U256::ZERO));
+        // 2 ** 256 wraps to zero; the two-byte exponent costs 10 + 2 * 50.
+        assert_eq!(run(vec![OP::PUSH32(U256::from(256u16)), OP::PUSH1(2), OP::EXP]), (Some(U256::ZERO), 3 + 3 + 110));
+        assert_eq!(run(vec![OP::PUSH1(0), OP::PUSH1(0), OP::EXP]).0, Some(U256::ONE));
+
+        let source = "contract Power {
+    uint256 private value;
+    function set() public { value = 2 ** 3 ** 2 % 100 + value % 7; }
+    function get() public view returns (uint256) { return value; }
+}";
+        let contract = create_contracts(parse(source).unwrap()).remove(0);
+        assert!(contract.diagnostics.is_empty(), "{:?}", contract.diagnostics);
+        let (contract, ..) = contract.call(&calldata("set()", &[])).unwrap();
+        assert_eq!(contract.call(&calldata("get()", &[])).unwrap().1, vec![Expression::NumberLiteral(U256::from(12u8))]);
+    }
+
+    #[test]
+    fn test_shifts_and_byte() {
+        let run = |program: Vec<OP>| {
+            let mut vm = VM::builder(program).build();
+            vm.run(ContractStorage::new(vec![]));
+            vm.stack.peek(0).unwrap()
+        };
+        let min = U256::ONE << 255;
+        assert_eq!(run(vec![OP::PUSH1(1), OP::PUSH1(255), OP::SHL]), min);
+        assert_eq!(run(vec![OP::PUSH1(1), OP::PUSH32(U256::from(256u16)), OP::SHL]), U256::ZERO);
+        assert_eq!(run(vec![OP::PUSH32(min), OP::PUSH1(255), OP::SHR]), U256::ONE);
+        assert_eq!(run(vec![OP::PUSH32(min), OP::PUSH1(254), OP::SAR]).as_i256(), -2);
+        assert_eq!(run(vec![OP::PUSH32(min), OP::PUSH32(U256::MAX), OP::SAR]), U256::MAX);
+        assert_eq!(run(vec![OP::PUSH1(0x7f), OP::PUSH32(U256::MAX), OP::SAR]), U256::ZERO);
+        assert_eq!(run(vec![OP::PUSH32(U256::from(0xabcdu16)), OP::PUSH1(30), OP::BYTE]), U256::from(0xabu8));
+        assert_eq!(run(vec![OP::PUSH32(U256::MAX), OP::PUSH1(32), OP::BYTE]), U256::ZERO);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut vm = VM::builder(vec![
+            OP::PUSH1(0xab), OP::PUSH1(1), OP::MSTORE,
+            OP::PUSH1(0xcd), OP::PUSH1(40), OP::MSTORE8,
+            OP::PUSH1(1), OP::MLOAD, OP::MSIZE,
+        ]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.peek(0), Some(U256::from(64u8)));
+        assert_eq!(vm.stack.peek(1), Some(U256::from(0xabu8)));
+        assert_eq!((vm.memory().len(), vm.memory()[32], vm.memory()[40]), (64, 0xab, 0xcd));
+        // Five pushes, three memory ops and MSIZE, plus 3 gas for each of the two words.
+        assert_eq!(vm.gas_used, 5 * 3 + 3 * 3 + 2 + 2 * 3);
+
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::PUSH32(U256::MAX), OP::MSTORE]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::MemoryLimitExceeded));
+
+        // A megabyte of memory is well inside the default limits.
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::PUSHN(3, U256::from(1u32 << 20)), OP::MSTORE]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(vm.memory().len(), (1 << 20) + 32);
+    }
+
+    #[test]
+    fn test_keccak256() {
+        let hash = |hex: &str| U256::from_str_hex(hex).unwrap();
+        let mut vm = VM::builder(vec![OP::PUSH1(32), OP::PUSH1(0), OP::KECCAK256, OP::PUSH1(0), OP::PUSH1(0), OP::KECCAK256]).build();
+        vm.run(ContractStorage::new(vec![]));
+        // The hash of a zero word, then of no bytes at all.
+        assert_eq!(vm.stack.peek(1), Some(hash("0x290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563")));
+        assert_eq!(vm.stack.peek(0), Some(hash("0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")));
+        // Four pushes, two hashes at 30 gas, one hashed word and one word of memory.
+        assert_eq!(vm.gas_used, 4 * 3 + 2 * 30 + 6 + 3);
+    }
+
+    #[test]
+    fn test_calldata_ops() {
+        let calldata = [0xaa, 0xbb, 0xcc];
+        let mut vm = VM::builder(vec![
+            OP::PUSH1(4), OP::PUSH1(1), OP::PUSH1(30), OP::CALLDATACOPY,
+            OP::CALLDATASIZE, OP::PUSH1(0), OP::MLOAD,
+        ]).calldata(&calldata).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.peek(0), Some(U256::from(0xbbccu16)));
+        assert_eq!(vm.stack.peek(1), Some(U256::from(3u8)));
+        assert_eq!(vm.memory()[30..34], [0xbb, 0xcc, 0, 0]);
+        // Four pushes, CALLDATACOPY with one copied word and two words of memory,
+        // CALLDATASIZE, and an MLOAD that doesn't expand memory.
+        assert_eq!(vm.gas_used, 4 * 3 + (3 + 3 + 2 * 3) + 2 + 3);
+    }
+
+    #[test]
+    fn test_return_data() {
+        let mut vm = VM::builder(vec![
+            OP::PUSH1(0xab), OP::PUSH1(0), OP::MSTORE8, OP::PUSH1(3), OP::PUSH1(0), OP::RETURN, OP::PUSH1(1),
+        ]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.return_data(), &[0xab, 0, 0]);
+        assert!(vm.stack.as_slice().is_empty());
+
+        let contract = flipper();
+        let (contract, ..) = contract.call(&calldata("flip()", &[])).unwrap();
+        let execution = contract.execute(&calldata("get()", &[]), Env::default(), &mut NoopInspector);
+        assert_eq!(execution.return_data, U256::ONE.to_be_bytes());
+        assert_eq!(execution.output, vec![Expression::BoolLiteral(true)]);
+    }
+
+    #[test]
+    fn test_revert() {
+        let program = vec![
+            OP::PUSH1(1), OP::PUSH1(0), OP::SSTORE,
+            OP::PUSH1(0xde), OP::PUSH1(0), OP::MSTORE8, OP::PUSH1(2), OP::PUSH1(0), OP::REVERT, OP::PUSH1(1),
+        ];
+        let mut vm = VM::builder(program.clone()).build();
+        let storage = vm.run(ContractStorage::new(vec![U256::from(7u8)]));
+        assert_eq!(vm.halt, Some(Halt::Revert(vec![0xde, 0])));
+        assert_eq!(storage, ContractStorage::new(vec![U256::from(7u8)]));
+        assert!(vm.return_data().is_empty());
+
+        let mut contract = Contract::new("Reverter".to_string());
+        contract.storage = ContractStorage::new(vec![U256::ZERO]);
+        contract.insert_function(selector("fail()"), Function::from_program("fail".to_string(), program)).unwrap();
+        assert_eq!(contract.call(&calldata("fail()", &[])).unwrap_err(), Halt::Revert(vec![0xde, 0]));
+    }
+
+    #[test]
+    fn test_logs() {
+        let program = vec![
+            OP::PUSH1(0xab), OP::PUSH1(2), OP::MSTORE8,
+            OP::PUSH1(7), OP::PUSH1(8), OP::PUSH1(3), OP::PUSH1(0), OP::LOG2,
+            OP::PUSH1(0), OP::PUSH1(0), OP::LOG0,
+        ];
+        let mut vm = VM::builder(program.clone()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.logs(), &[
+            LogEntry { topics: vec![U256::from(8u8), U256::from(7u8)], data: vec![0, 0, 0xab] },
+            LogEntry { topics: vec![], data: vec![] },
+        ]);
+        // Eight pushes, MSTORE8 with a word of memory, LOG2 with 3 bytes of data, and LOG0.
+        assert_eq!(vm.gas_used, 8 * 3 + 3 + 3 + (375 + 2 * 375 + 3 * 8) + 375);
+
+        let mut contract = Contract::new("Logger".to_string());
+        contract.insert_function(selector("emit()"), Function::from_program("emit".to_string(), program)).unwrap();
+        let (_, _, logs) = contract.call(&calldata("emit()", &[])).unwrap();
+        assert_eq!(logs.len(), 2);
+    }
+
+    #[test]
+    fn test_jumps() {
+        // Counts 3 down to 0, adding 1 to the running total each time round.
+        let mut vm = VM::builder(vec![
+            OP::PUSH1(0), OP::PUSH1(3), OP::JUMPDEST,
+            OP::SWAP1, OP::PUSH1(1), OP::ADD, OP::SWAP1,
+            OP::PUSH1(1), OP::SWAP1, OP::SUB,
+            OP::DUP1, OP::PUSH1(4), OP::JUMPI,
+            OP::POP, OP::PC,
+        ]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(vm.stack.peek(0), Some(U256::from(19u8)));
+        assert_eq!(vm.stack.peek(1), Some(U256::from(3u8)));
+
+        let mut vm = VM::builder(vec![OP::PUSH1(3), OP::JUMP, OP::PUSH1(0)]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::InvalidJump));
+    }
+
+    #[test]
+    fn test_call_context() {
+        let program = vec![OP::ADDRESS, OP::ORIGIN, OP::CALLER, OP::CALLVALUE];
+        let (alice, bob) = (Address([0xa1; 20]), Address([0xb0; 20]));
+        let env = Env::builder().caller(alice).value(U256::from(7u8)).address(bob).build();
+        let mut vm = VM::builder(program.clone()).env(env).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [bob.to_word(), alice.to_word(), alice.to_word(), U256::from(7u8)]);
+        assert_eq!(vm.gas_used, 4 * 2);
+
+        let context = CallContext { address: alice, caller: bob, origin: Address::ZERO, value: U256::ONE, gas_price: U256::ZERO };
+        let mut vm = VM::builder(program).context(context).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [alice.to_word(), U256::ZERO, bob.to_word(), U256::ONE]);
+    }
+
+    #[test]
+    fn test_block_env() {
+        let program = vec![OP::NUMBER, OP::TIMESTAMP, OP::CHAINID, OP::COINBASE, OP::BASEFEE, OP::PREVRANDAO];
+        let coinbase = Address([0xc0; 20]);
+        let env = Env::builder().block_number(9).timestamp(1_700_000_000).chain_id(31337).coinbase(coinbase)
+            .base_fee(U256::from(7u8)).prevrandao(U256::MAX).build();
+        let mut vm = VM::builder(program).env(env).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [U256::from(9u8), U256::from(1_700_000_000u32), U256::from(31337u16), coinbase.to_word(), U256::from(7u8), U256::MAX]);
+        assert_eq!(vm.gas_used, 6 * 2);
+
+        let block = BlockEnv { number: 300, hashes: BTreeMap::from([(43, [1; 32]), (299, [2; 32])]), ..BlockEnv::default() };
+        let mut vm = VM::builder(vec![OP::PUSH1(43), OP::BLOCKHASH, OP::push(U256::from(299u16)), OP::BLOCKHASH]).block(block).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [U256::ZERO, U256::from_be_bytes([2; 32])]);
+    }
+
+    #[test]
+    fn test_balance_opcodes() {
+        let (alice, bob) = (Address([0xa1; 20]), Address([0xb0; 20]));
+        let env = Env::builder().address(alice).balance(alice, U256::from(7u8)).balance(bob, U256::MAX).build();
+        let program = vec![OP::SELFBALANCE, OP::PUSH32(bob.to_word()), OP::BALANCE, OP::PUSH0, OP::BALANCE];
+        let mut vm = VM::builder(program).env(env).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [U256::from(7u8), U256::MAX, U256::ZERO]);
+        assert_eq!(vm.gas_used, 5 + 3 + 2 + 2 * 2600);
+    }
+
+    #[test]
+    fn test_code_opcodes() {
+        // Init code that returns the runtime code following it, as `Contract::bytecode` lays
+        // it out.
+        let runtime = vec![OP::PUSH1(1), OP::PUSH0, OP::SSTORE];
+        let init = vec![OP::PUSH1(4), OP::DUP1, OP::PUSH1(9), OP::PUSH0, OP::CODECOPY, OP::PUSH0, OP::RETURN];
+        let mut vm = VM::builder([init, runtime.clone()].concat()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.return_data(), tinysol::bytecode::assemble(&runtime));
+        // Three pushes and DUP1, then CODECOPY with one copied word and one word of memory.
+        assert_eq!(vm.gas_used, 3 + 3 + 3 + 2 + (3 + 3 + 3) + 2);
+
+        let mut vm = VM::builder(vec![OP::CODESIZE, OP::PUSH32(U256::ONE)]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [U256::from(1u8 + 33), U256::ONE]);
+    }
+
+    #[test]
+    fn test_mcopy_overlapping() {
+        let bytes: [u8; 32] = std::array::from_fn(|i| i as u8 + 1);
+        let store = vec![OP::PUSH32(U256::from_be_bytes(bytes)), OP::PUSH0, OP::MSTORE];
+        let copy = |dest: u8, src: u8| [store.clone(), vec![OP::PUSH1(4), OP::PUSH1(src), OP::PUSH1(dest), OP::MCOPY]].concat();
+
+        // Forwards and backwards over itself, each reading the bytes as they were.
+        let mut vm = VM::builder(copy(1, 0)).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.memory()[..6], [1, 1, 2, 3, 4, 6]);
+        // The store, three pushes, then MCOPY with one copied word and no expansion.
+        assert_eq!(vm.gas_used, 3 + 2 + (3 + 3) + 3 + 3 + 3 + (3 + 3));
+
+        let mut vm = VM::builder(copy(0, 1)).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.memory()[..6], [2, 3, 4, 5, 5, 6]);
+
+        // Copying past the end grows memory, reading zeros.
+        let mut vm = VM::builder(copy(30, 0)).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.memory().len(), 64);
+        assert_eq!(vm.memory()[28..36], [29, 30, 1, 2, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn test_invalid_and_panic_codes() {
+        use tinysol::revert::{PanicCode, PANIC_SELECTOR};
+
+        // How an `assert` failed before Solidity 0.8: INVALID, using up all the gas.
+        let program = vec![OP::PUSH1(1), OP::INVALID, OP::PUSH1(2)];
+        let mut vm = VM::builder(program.clone()).env(Env::builder().gas_limit(1000).build()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::InvalidInstruction));
+        assert_eq!((vm.gas_used, vm.stack.as_slice()), (1000, &[U256::ONE][..]));
+        assert_eq!(tinysol::bytecode::disassemble(&tinysol::bytecode::assemble(&program)), Some(program));
+
+        // And since: a revert with Panic(0x11) for overflow, Panic(0x01) for asserts.
+        let selector = U256::from(u32::from_be_bytes(PANIC_SELECTOR)) << 224;
+        let program = vec![OP::PUSH32(selector), OP::PUSH0, OP::MSTORE, OP::PUSH1(0x11), OP::PUSH1(4), OP::MSTORE, OP::PUSH1(36), OP::PUSH0, OP::REVERT];
+        let mut vm = VM::builder(program).build();
+        vm.run(ContractStorage::new(vec![]));
+        let halt = vm.halt.unwrap();
+        assert_eq!(halt.panic_code(), Some(PanicCode::ArithmeticOverflow));
+        assert_eq!(halt.panic_code().unwrap().reason(), "arithmetic underflow or overflow");
+        assert_eq!(Halt::Revert(vec![]).panic_code(), None);
+        assert_eq!(Halt::InvalidInstruction.panic_code(), None);
+    }
+
+    #[test]
+    fn test_block_prevrandao() {
+        let code = "contract Lottery { function draw() public view returns (uint256) { return block.prevrandao; } function old() public view returns (uint256) { return block.difficulty; } }";
+        let contract = create_contracts(parse(code).unwrap()).remove(0);
+        let env = Env::builder().prevrandao(U256::from(1234u16)).build();
+        for function in ["draw()", "old()"] {
+            let execution = contract.execute(&calldata(function, &[]), env.clone(), &mut NoopInspector);
+            assert_eq!(execution.output, vec![Expression::NumberLiteral(U256::from(1234u16))]);
+        }
+    }
+
+    #[test]
+    fn test_gas_opcodes() {
+        let program = vec![OP::GAS, OP::GASPRICE, OP::GASLIMIT];
+        let env = Env::builder().gas_limit(1000).gas_price(U256::from(5u8)).block_gas_limit(30_000_000).build();
+        let mut vm = VM::builder(program).env(env).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.stack.as_slice(), [U256::from(998u16), U256::from(5u8), U256::from(30_000_000u32)]);
+        assert_eq!((vm.gas_used, vm.gas_left()), (6, 994));
+
+        // A runaway loop stops once its gas runs out.
+        let program = vec![OP::JUMPDEST, OP::PUSH0, OP::JUMP];
+        let mut vm = VM::builder(program).env(Env::builder().gas_limit(100).build()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::OutOfGas));
+        assert_eq!(vm.gas_left(), 0);
+    }
+
+    #[test]
+    fn test_dup_and_swap() {
+        let program = vec![OP::PUSH1(1), OP::PUSH1(2), OP::PUSH1(3), OP::DUP(3), OP::SWAP(2)];
+        let mut vm = VM::builder(program.clone()).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(vm.stack.as_slice(), [1u8, 1, 3, 2].map(U256::from));
+        assert_eq!(vm.gas_used, 5 * 3);
+        assert_eq!(tinysol::bytecode::disassemble(&tinysol::bytecode::assemble(&program)), Some(program));
+        assert_eq!(OP::DUP(16).opcode(), 0x8f);
+        assert_eq!(OP::SWAP(16).name(), "SWAP16");
+
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::SWAP(2)]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::StackUnderflow));
+        assert_eq!(vm.stack.as_slice(), [U256::ONE]);
+    }
+
+    #[test]
+    fn test_stack_bounds() {
+        // Every instruction checks its operands, not just DUP and SWAP.
+        for program in [vec![OP::PUSH1(1), OP::ADD], vec![OP::SWAP1], vec![OP::PUSH1(1), OP::PUSH0, OP::SSTORE, OP::JUMP]] {
+            let mut vm = VM::builder(program).build();
+            vm.run(ContractStorage::new(vec![U256::ZERO]));
+            assert_eq!(vm.halt, Some(Halt::StackUnderflow));
+        }
+
+        let mut vm = VM::builder(vec![OP::PUSH0; STACK_LIMIT + 1]).build();
+        vm.run(ContractStorage::new(vec![]));
+        assert_eq!(vm.halt, Some(Halt::StackOverflow));
+        assert_eq!(vm.stack.as_slice().len(), STACK_LIMIT);
+        assert_eq!(vm.stack.push32(U256::ONE), None);
+
+        let mut stack = Stack::new();
+        stack.push1(1);
+        assert_eq!(stack.swap(), None);
+        assert_eq!(stack.as_slice(), [U256::ONE]);
+    }
+
+    #[test]
+    fn test_sparse_storage() {
+        // Slots past the declared variables, like mapping entries, are stored sparsely.
+        let slot = U256::MAX - 1;
+        let mut vm = VM::builder(vec![OP::PUSH1(5), OP::PUSH32(slot), OP::SSTORE, OP::PUSH32(slot), OP::SLOAD]).build();
+        let storage = vm.run(ContractStorage::new(vec![U256::ZERO]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(vm.stack.peek(0), Some(U256::from(5u8)));
+        assert_eq!(storage.iter().collect::<Vec<_>>(), vec![(U256::ZERO, U256::ZERO), (slot, U256::from(5u8))]);
+
+        // A halt undoes every write of the run.
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::PUSH0, OP::SSTORE, OP::PUSH1(5), OP::PUSH32(slot), OP::SSTORE, OP::INVALID]).build();
+        let storage = vm.run(ContractStorage::new(vec![U256::ZERO]));
+        assert_eq!(vm.halt, Some(Halt::InvalidInstruction));
+        assert_eq!(storage.load(slot), U256::ZERO);
+        assert_eq!(storage.slots(), [U256::ZERO]);
+    }
+
+    #[test]
+    fn test_signed_arithmetic() {
+        let run = |program: Vec<OP>| {
+            let mut vm = VM::builder(program).build();
+            vm.run(ContractStorage::new(vec![]));
+            vm.stack.peek(0).unwrap()
+        };
+        let (minus_one, min) = (U256::MAX, U256::ONE << 255);
+        assert_eq!(run(vec![OP::PUSH32(minus_one), OP::PUSH32(min), OP::SDIV]), min);
+        assert_eq!(run(vec![OP::PUSH32(minus_one), OP::PUSH32(min), OP::SMOD]), U256::ZERO);
+        assert_eq!(run(vec![OP::PUSH1(2), OP::PUSH32(U256::MAX - 6), OP::SDIV]).as_i256(), -3);
+        assert_eq!(run(vec![OP::PUSH1(3), OP::PUSH32(U256::MAX - 6), OP::SMOD]).as_i256(), -1);
+        assert_eq!(run(vec![OP::PUSH1(0), OP::PUSH1(7), OP::SDIV]), U256::ZERO);
+        assert_eq!(run(vec![OP::PUSH1(0xff), OP::PUSH1(0), OP::SIGNEXTEND]), minus_one);
+        assert_eq!(run(vec![OP::PUSH1(0x7f), OP::PUSH1(0), OP::SIGNEXTEND]), U256::from(0x7fu8));
+        assert_eq!(run(vec![OP::PUSH32(min | U256::ONE), OP::PUSH1(31), OP::SIGNEXTEND]), min | U256::ONE);
+
+        let source = "contract Signed {
+    int256 private value;
+    function min() public { value = -2 ** 255; }
+    function seven() public { value = -7; }
+    function flip() public { value = value / -1; }
+    function half() public { value = value / 2; }
+    function rem() public { value = value % 4; }
+    function get() public view returns (int256) { return value; }
+}";
+        let contract = create_contracts(parse(source).unwrap()).remove(0);
+        assert!(contract.diagnostics.is_empty(), "{:?}", contract.diagnostics);
+        let call = |contract: &Contract, name: &str| contract.call(&calldata(&format!("{}()", name), &[])).unwrap().0;
+        let get = |contract: &Contract| match contract.call(&calldata("get()", &[])).unwrap().1[..] {
+            [Expression::NumberLiteral(n)] => n.as_i256(),
+            ref output => panic!("unexpected output {:?}", output),
+        };
+
+        let min_int = call(&contract, "min");
+        assert_eq!(get(&call(&min_int, "flip")), tinysol::bindings::I256::MIN);
+        let seven = call(&contract, "seven");
+        assert_eq!(get(&call(&seven, "flip")), 7);
+        assert_eq!(get(&call(&seven, "half")), -3);
+        assert_eq!(get(&call(&seven, "rem")), -3);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let limits = |memory, return_data| Env::builder().limits(tinysol::env::ResourceLimits { memory, return_data }).build();
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::PUSH1(2), OP::PUSH1(3)]).env(limits(64, 0)).build();
+        vm.run(Default::default());
+        assert_eq!(vm.halt, Some(Halt::MemoryLimitExceeded));
+        assert_eq!(vm.stack.as_slice().len(), 2);
+
+        let contract = flipper();
+        let get = calldata("get()", &[]);
+        assert_eq!(contract.call_with_env(&get, limits(1024, 0)).unwrap_err(), Halt::ReturnDataLimitExceeded);
+        assert!(contract.call_with_env(&get, limits(1024, 32)).is_ok());
+    }
+
+    #[test]
+    fn test_call_async_yields() {
+        use std::future::Future;
+        let contract = flipper();
+        let flip = calldata("flip()", &[]);
+
+        let mut call = Box::pin(contract.call_async(&flip, Env::default(), 2));
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        let mut yields = 0;
+        let (flipped, ..) = loop {
+            match call.as_mut().poll(&mut cx) {
+                std::task::Poll::Ready(result) => break result.unwrap(),
+                std::task::Poll::Pending => yields += 1,
+            }
+        };
+        // flip() is five instructions.
+        assert_eq!(yields, 2);
+        assert_eq!(flipped.call(&calldata("get()", &[])).unwrap().1, vec![Expression::BoolLiteral(true)]);
+
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&call);
+    }
+
     #[test]
     fn test_call_flipper_contract_flip() {
         let code = std::fs::read_to_string("./contracts/flipper.sol").expect("Unable to read source file");
@@ -100,13 +1094,13 @@ mod tests {
                 let contract = contracts.first();
                 assert!(contract.is_some());
 
-                let flip_func_sig = get_func_sig("flip()".to_string());
-                let mutated_contract = contract.unwrap().call(flip_func_sig.as_str()).0;
+                let flip_func_sig = calldata("flip()", &[]);
+                let mutated_contract = contract.unwrap().call(flip_func_sig.as_str()).unwrap().0;
 
-                let get_func_sig = get_func_sig("get()".to_string());
-                let ret = mutated_contract.call(get_func_sig.as_str()).1;
+                let get_func_sig = calldata("get()", &[]);
+                let ret = mutated_contract.call(get_func_sig.as_str()).unwrap().1;
                 match ret.as_slice() {
-                    [solidity::grammar::Expression::BoolLiteral(val)] => {
+                    [Expression::BoolLiteral(val)] => {
                         assert_eq!(*val, true);
                     }
                     _ => assert!(false, "Unexpected return value: {:?}", ret)
@@ -118,4 +1112,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_duplicate_functions_and_selector_collisions() {
+        let source = "contract C {
+    bool private a;
+    function get() public view returns (bool) { return a; }
+    function get() public view returns (bool) { return !a; }
+}";
+        let contract = create_contracts(parse(source).unwrap()).remove(0);
+        let messages = contract.diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages, vec!["error[duplicate-function]: function `get()` is defined more than once"]);
+        let (start, _) = contract.diagnostics[0].span.unwrap();
+        assert_eq!(&source[start..start + 10], "return !a;");
+        assert_eq!(contract.call(&calldata("get()", &[])).unwrap().1, vec![Expression::BoolLiteral(false)]);
+
+        // Distinct signatures forced onto one selector.
+        let mut contract = Contract::new("C".to_string());
+        let selector = selector("get()");
+        assert!(contract.insert_function(selector, Function::from_program("get".to_string(), vec![])).is_ok());
+        let collision = contract.insert_function(selector, Function::from_program("other".to_string(), vec![])).unwrap_err();
+        assert_eq!(collision.code, "selector-collision");
+        assert_eq!(collision.message, format!("functions `get()` and `other()` have the same selector 0x{:08x}", u32::from_be_bytes(selector)));
+        assert_eq!(contract.functions[&selector].name, "get");
+    }
+
 }
@@ -0,0 +1,132 @@
+use keccak_hash::keccak;
+
+/// Describes how a contract was built. Appended to runtime code as a CBOR map followed by
+/// its two-byte big-endian length, the same trailer layout solc uses, so tools that know
+/// to look for one can find it:
+///
+/// ```text
+/// { "tinysol": "0.1.0", "source": <keccak256 of the source, 32 bytes> }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub version: String,
+    pub source_hash: [u8; 32],
+}
+
+impl Metadata {
+    /// Metadata for code compiled from `source` by this version of tinysol.
+    pub fn for_source(source: &str) -> Self {
+        Self { version: env!("CARGO_PKG_VERSION").to_string(), source_hash: keccak(source.as_bytes()).0 }
+    }
+
+    /// The CBOR map plus its length, ready to be appended to code.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut cbor = vec![0xa2];
+        cbor_header(&mut cbor, 3, "tinysol".len());
+        cbor.extend(b"tinysol");
+        cbor_header(&mut cbor, 3, self.version.len());
+        cbor.extend(self.version.as_bytes());
+        cbor_header(&mut cbor, 3, "source".len());
+        cbor.extend(b"source");
+        cbor_header(&mut cbor, 2, self.source_hash.len());
+        cbor.extend(self.source_hash);
+        let len = (cbor.len() as u16).to_be_bytes();
+        cbor.extend(len);
+        cbor
+    }
+
+    fn decode(mut cbor: &[u8]) -> Option<Self> {
+        let (major, entries) = read_header(&mut cbor)?;
+        if major != 5 {
+            return None;
+        }
+        let (mut version, mut source_hash) = (None, None);
+        for _ in 0..entries {
+            let key = read_item(&mut cbor, 3)?;
+            match key {
+                b"tinysol" => version = Some(String::from_utf8(read_item(&mut cbor, 3)?.to_vec()).ok()?),
+                b"source" => source_hash = Some(read_item(&mut cbor, 2)?.try_into().ok()?),
+                _ => return None,
+            }
+        }
+        if !cbor.is_empty() {
+            return None;
+        }
+        Some(Self { version: version?, source_hash: source_hash? })
+    }
+}
+
+/// Writes a CBOR header for `major` type with a length below 256.
+fn cbor_header(out: &mut Vec<u8>, major: u8, len: usize) {
+    match len {
+        0..=23 => out.push(major << 5 | len as u8),
+        _ => out.extend([major << 5 | 24, len as u8]),
+    }
+}
+
+fn read_header(cbor: &mut &[u8]) -> Option<(u8, usize)> {
+    let (&first, rest) = cbor.split_first()?;
+    *cbor = rest;
+    let len = match first & 0x1f {
+        len @ 0..=23 => len as usize,
+        24 => {
+            let (&len, rest) = cbor.split_first()?;
+            *cbor = rest;
+            len as usize
+        },
+        _ => return None,
+    };
+    Some((first >> 5, len))
+}
+
+/// Reads a byte or text string of the `major` type.
+fn read_item<'a>(cbor: &mut &'a [u8], major: u8) -> Option<&'a [u8]> {
+    let (found, len) = read_header(cbor)?;
+    if found != major || cbor.len() < len {
+        return None;
+    }
+    let (item, rest) = cbor.split_at(len);
+    *cbor = rest;
+    Some(item)
+}
+
+/// Splits runtime code into the code itself and its metadata trailer, if it ends with one.
+pub fn strip_metadata(code: &[u8]) -> (&[u8], Option<Metadata>) {
+    let Some(len) = code.len().checked_sub(2).map(|at| u16::from_be_bytes([code[at], code[at + 1]]) as usize) else {
+        return (code, None);
+    };
+    let Some(start) = code.len().checked_sub(2 + len) else {
+        return (code, None);
+    };
+    match Metadata::decode(&code[start..code.len() - 2]) {
+        Some(metadata) => (&code[..start], Some(metadata)),
+        None => (code, None),
+    }
+}
+
+pub fn parse_metadata(code: &[u8]) -> Option<Metadata> {
+    strip_metadata(code).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::CompilerSettings;
+    use crate::test_utils::flipper_source;
+    use crate::tinyvm::compile_source;
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let source = flipper_source();
+        let plain = compile_source(source, &CompilerSettings::default()).unwrap().remove(0);
+        let settings = CompilerSettings { metadata: true, ..CompilerSettings::default() };
+        let contract = compile_source(source, &settings).unwrap().remove(0);
+
+        let code = contract.deployed_bytecode();
+        let (stripped, metadata) = strip_metadata(&code);
+        assert_eq!(stripped, plain.deployed_bytecode().as_slice());
+        assert_eq!(metadata, Some(Metadata::for_source(source)));
+        assert_eq!(parse_metadata(stripped), None);
+        assert_eq!(contract.bytecode().len(), plain.bytecode().len() + Metadata::for_source(source).encode().len());
+    }
+}
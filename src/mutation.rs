@@ -0,0 +1,247 @@
+use ethnum::U256;
+use std::fmt;
+use crate::abi::calldata;
+use crate::bytecode::code_offsets;
+use crate::cfg::static_jump_target;
+use crate::env::Env;
+use crate::fuzz::{fuzz, FuzzConfig};
+use crate::solidity::grammar::Expression;
+use crate::tinyvm::*;
+
+/// A single change to a compiled function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    /// An operator swapped for its counterpart: `LT` and `GT`, `SLT` and `SGT`, `ADD`
+    /// and `SUB`, `MUL` and `DIV`, `AND` and `OR`.
+    Replace(OP, OP),
+    /// An `ISZERO` removed, dropping a negation.
+    RemoveNegation,
+    /// A `JUMPI` made unconditional, so the check guarding it always passes.
+    DropCheck,
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Replace(from, to) => write!(f, "{} replaced by {}", from.name(), to.name()),
+            Mutation::RemoveNegation => write!(f, "ISZERO removed"),
+            Mutation::DropCheck => write!(f, "JUMPI always taken"),
+        }
+    }
+}
+
+fn counterpart(op: &OP) -> Option<OP> {
+    Some(match op {
+        OP::LT => OP::GT,
+        OP::GT => OP::LT,
+        OP::SLT => OP::SGT,
+        OP::SGT => OP::SLT,
+        OP::ADD => OP::SUB,
+        OP::SUB => OP::ADD,
+        OP::MUL => OP::DIV,
+        OP::DIV => OP::MUL,
+        OP::AND => OP::OR,
+        OP::OR => OP::AND,
+        _ => return None,
+    })
+}
+
+/// A mutation of the instruction at `pc` of the function with signature `function`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutant {
+    pub function: String,
+    pub pc: usize,
+    pub mutation: Mutation,
+}
+
+impl fmt::Display for Mutant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}: {}", self.function, self.pc, self.mutation)
+    }
+}
+
+/// Every mutant of `function`, in program order.
+pub fn mutants(function: &Function) -> Vec<Mutant> {
+    let program = function.program();
+    program.iter().enumerate().filter_map(|(pc, op)| {
+        let mutation = match op {
+            OP::ISZERO => Mutation::RemoveNegation,
+            OP::JUMPI if static_jump_target(program, pc).is_some() => Mutation::DropCheck,
+            op => Mutation::Replace(op.clone(), counterpart(op)?),
+        };
+        Some(Mutant { function: function.signature(), pc, mutation })
+    }).collect()
+}
+
+/// `program` with `mutant` applied, each instruction paired with the index of the one
+/// in `program` it came from. Jump targets are relocated to where their `JUMPDEST` moved; `None`
+/// if one no longer fits its push.
+fn apply(program: &[OP], mutant: &Mutant) -> Option<Vec<(usize, OP)>> {
+    let pc = mutant.pc;
+    let mut mutated = program.iter().cloned().enumerate().collect::<Vec<_>>();
+    match &mutant.mutation {
+        Mutation::Replace(_, to) => mutated[pc].1 = to.clone(),
+        Mutation::RemoveNegation => {
+            mutated.remove(pc);
+        },
+        Mutation::DropCheck => {
+            // Pop the condition from under the target, then jump.
+            mutated[pc].1 = OP::JUMP;
+            mutated.insert(pc - 1, (pc, OP::POP));
+        },
+    }
+
+    let old_offsets = code_offsets(program);
+    let new_offsets = code_offsets(&mutated.iter().map(|(_, op)| op.clone()).collect::<Vec<_>>());
+    let moved = (0..program.len())
+        .map(|old| mutated.iter().position(|(origin, op)| *origin == old && *op == program[old]).map(|new| new_offsets[new]))
+        .collect::<Vec<_>>();
+    let relocated = |target: U256| {
+        let old = old_offsets.iter().position(|offset| U256::from(*offset as u64) == target)?;
+        moved[old]
+    };
+    for i in 0..mutated.len() {
+        if !matches!(mutated[i].1, OP::JUMP | OP::JUMPI) || i == 0 {
+            continue;
+        }
+        let Some(target) = mutated[i - 1].1.push_value() else {
+            continue;
+        };
+        let Some(offset) = relocated(target) else {
+            continue;
+        };
+        // Keep the width of the push so code offsets don't shift again.
+        let offset = U256::from(offset as u64);
+        mutated[i - 1].1 = match mutated[i - 1].1 {
+            OP::PUSH32(_) => OP::PUSH32(offset),
+            _ if OP::push(offset).size() > mutated[i - 1].1.size() => return None,
+            OP::PUSH1(_) => OP::PUSH1(offset.as_u8()),
+            OP::PUSHN(n, _) => OP::PUSHN(n, offset),
+            _ => return None,
+        };
+    }
+    Some(mutated)
+}
+
+/// `contract` with `mutant` applied, or `None` if it can't be.
+pub fn mutate_contract(contract: &Contract, mutant: &Mutant) -> Option<Contract> {
+    let mut contract = contract.clone();
+    let function = contract.functions.values_mut().find(|f| f.signature() == mutant.function)?;
+    let mutated = apply(function.program(), mutant)?;
+    if !function.source_map.is_empty() {
+        function.source_map = mutated.iter().map(|(origin, _)| function.source_map[*origin]).collect();
+    }
+    function.set_program(mutated.into_iter().map(|(_, op)| op).collect());
+    Some(contract)
+}
+
+fn is_test(name: &str) -> bool {
+    name.starts_with("test") || name.starts_with("invariant_")
+}
+
+/// Runs the `test*()` functions of `contract` on its deployed state, each of which must
+/// return `true` or nothing, then fuzzes its invariants. Returns the first that fails.
+fn failing_test(contract: &Contract, config: &FuzzConfig) -> Option<String> {
+    let tests = contract.functions.values()
+        .filter(|f| f.name.starts_with("test") && f.params.is_empty())
+        .map(Function::signature);
+    for test in tests {
+        let execution = contract.execute(&calldata(&test, &[]), Env::default(), &mut NoopInspector);
+        if execution.halt.is_some() || !matches!(execution.output.as_slice(), [] | [Expression::BoolLiteral(true)]) {
+            return Some(test);
+        }
+    }
+    fuzz(contract, config).failures.first().map(|failure| failure.invariant.clone())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutantResult {
+    pub mutant: Mutant,
+    /// The test or invariant that failed, `None` if the mutant survived.
+    pub killed_by: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutationReport {
+    pub results: Vec<MutantResult>,
+}
+
+impl MutationReport {
+    pub fn survivors(&self) -> impl Iterator<Item = &Mutant> {
+        self.results.iter().filter(|result| result.killed_by.is_none()).map(|result| &result.mutant)
+    }
+
+    pub fn passed(&self) -> bool {
+        self.survivors().next().is_none()
+    }
+}
+
+impl fmt::Display for MutationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let survived = self.survivors().count();
+        writeln!(f, "{} mutants, {} killed, {} survived", self.results.len(), self.results.len() - survived, survived)?;
+        self.results.iter().try_for_each(|result| match &result.killed_by {
+            Some(test) => writeln!(f, "[KILLED] {} by {}", result.mutant, test),
+            None => writeln!(f, "[SURVIVED] {}", result.mutant),
+        })
+    }
+}
+
+/// Mutates every function of `contract` other than its tests and invariants, one change
+/// at a time, and reruns the tests against each mutant. A mutant is killed when a
+/// `test*()` function fails or the fuzzer breaks an `invariant_*()` function.
+///
+/// Tests only see mutants through the calls the fuzzer makes, since calls inlined into a
+/// test keep the original code. Fails with the name of the test if one already fails on
+/// the unmutated contract.
+pub fn mutation_test(contract: &Contract, config: &FuzzConfig) -> Result<MutationReport, String> {
+    if let Some(test) = failing_test(contract, config) {
+        return Err(test);
+    }
+    let results = contract.functions.values()
+        .filter(|function| !is_test(&function.name))
+        .flat_map(mutants)
+        .filter_map(|mutant| {
+            let mutated = mutate_contract(contract, &mutant)?;
+            Some(MutantResult { killed_by: failing_test(&mutated, config), mutant })
+        })
+        .collect();
+    Ok(MutationReport { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::parse;
+
+    const COUNTER: &str = "contract Counter {
+        uint256 private count;
+        bool private on;
+        function increment() public { count = count + 1; }
+        function toggle() public { on = !on; }
+        function invariant_bounded() public view returns (bool) { return count < 100; }
+    }";
+
+    #[test]
+    fn test_mutation_report() {
+        let contract = create_contracts(parse(COUNTER).unwrap()).remove(0);
+        let report = mutation_test(&contract, &FuzzConfig { runs: 16, depth: 4, seed: 1 }).unwrap();
+        assert!(!report.passed());
+        let add = report.results.iter().find(|r| r.mutant.function == "increment()" && r.mutant.mutation == Mutation::Replace(OP::ADD, OP::SUB)).unwrap();
+        assert_eq!(add.killed_by.as_deref(), Some("invariant_bounded()"));
+        assert!(report.survivors().any(|m| m.function == "toggle()" && m.mutation == Mutation::RemoveNegation));
+        assert!(report.to_string().contains("[SURVIVED] toggle() at"));
+    }
+
+    #[test]
+    fn test_drop_check_relocates_jumps() {
+        let program = vec![OP::PUSH1(1), OP::PUSH1(6), OP::JUMPI, OP::ISZERO, OP::JUMPDEST, OP::RETURN];
+        let mutant = Mutant { function: String::new(), pc: 2, mutation: Mutation::DropCheck };
+        let mutated = apply(&program, &mutant).unwrap().into_iter().map(|(_, op)| op).collect::<Vec<_>>();
+        assert_eq!(mutated, vec![OP::PUSH1(1), OP::POP, OP::PUSH1(7), OP::JUMP, OP::ISZERO, OP::JUMPDEST, OP::RETURN]);
+
+        let mutant = Mutant { function: String::new(), pc: 3, mutation: Mutation::RemoveNegation };
+        let mutated = apply(&program, &mutant).unwrap().into_iter().map(|(_, op)| op).collect::<Vec<_>>();
+        assert_eq!(mutated, vec![OP::PUSH1(1), OP::PUSH1(5), OP::JUMPI, OP::JUMPDEST, OP::RETURN]);
+    }
+}
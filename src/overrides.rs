@@ -0,0 +1,155 @@
+use ethnum::U256;
+use std::collections::{BTreeMap, HashMap};
+use crate::bytecode::from_hex;
+use crate::env::Address;
+use crate::executor::Storage;
+use crate::tinyvm::{Contract, ContractStorage};
+use crate::world::Account;
+
+/// Changes to one account for a single simulation, like an entry of `eth_call`'s
+/// `stateOverride`. Fields left `None` keep the account's own values.
+#[derive(Debug, Clone, Default)]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<u64>,
+    /// Replaces the account's code, and with it its storage unless `state` or `state_diff`
+    /// set slots of the new contract.
+    pub code: Option<Contract>,
+    /// Replaces all of storage: slots not listed read as zero.
+    pub state: Option<BTreeMap<U256, U256>>,
+    /// Sets the listed slots and keeps the rest.
+    pub state_diff: BTreeMap<U256, U256>,
+}
+
+/// Account overrides by address, applied to a copy of the world for one call. See
+/// `World::simulate_with_overrides`.
+#[derive(Debug, Clone, Default)]
+pub struct StateOverride {
+    pub accounts: BTreeMap<Address, AccountOverride>,
+}
+
+fn word(json: &serde_json::Value) -> Option<U256> {
+    U256::from_str_radix(json.as_str()?.trim_start_matches("0x"), 16).ok()
+}
+
+fn slots(json: &serde_json::Value) -> Option<BTreeMap<U256, U256>> {
+    json.as_object()?.iter().map(|(slot, value)| Some((word(&serde_json::json!(slot))?, word(value)?))).collect()
+}
+
+impl StateOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn account(&mut self, address: Address) -> &mut AccountOverride {
+        self.accounts.entry(address).or_default()
+    }
+
+    pub fn balance(mut self, address: Address, balance: U256) -> Self {
+        self.account(address).balance = Some(balance);
+        self
+    }
+
+    pub fn nonce(mut self, address: Address, nonce: u64) -> Self {
+        self.account(address).nonce = Some(nonce);
+        self
+    }
+
+    pub fn code(mut self, address: Address, contract: Contract) -> Self {
+        self.account(address).code = Some(contract);
+        self
+    }
+
+    pub fn state(mut self, address: Address, state: BTreeMap<U256, U256>) -> Self {
+        self.account(address).state = Some(state);
+        self
+    }
+
+    pub fn state_diff(mut self, address: Address, slot: U256, value: U256) -> Self {
+        self.account(address).state_diff.insert(slot, value);
+        self
+    }
+
+    /// Parses an `eth_call` `stateOverride` object. The world runs compiled contracts
+    /// rather than bytecode, so `code` can't be given here; use `code` instead. Like geth,
+    /// rejects accounts with both `state` and `stateDiff`.
+    pub fn from_json(json: &serde_json::Value) -> Option<Self> {
+        let mut overrides = StateOverride::new();
+        for (address, fields) in json.as_object()? {
+            let address = Address(from_hex(address)?.try_into().ok()?);
+            if fields.get("code").is_some() || (fields.get("state").is_some() && fields.get("stateDiff").is_some()) {
+                return None;
+            }
+            let account = overrides.account(address);
+            if let Some(balance) = fields.get("balance") {
+                account.balance = Some(word(balance)?);
+            }
+            if let Some(nonce) = fields.get("nonce") {
+                account.nonce = Some(u64::try_from(word(nonce)?).ok()?);
+            }
+            if let Some(state) = fields.get("state") {
+                account.state = Some(slots(state)?);
+            }
+            if let Some(state_diff) = fields.get("stateDiff") {
+                account.state_diff = slots(state_diff)?;
+            }
+        }
+        Some(overrides)
+    }
+
+    /// Applies the overrides to `accounts`. Slots past a contract's state variables are
+    /// ignored, since its code can't read them.
+    pub fn apply(&self, accounts: &mut HashMap<Address, Account>) {
+        for (address, overrides) in &self.accounts {
+            let account = accounts.entry(*address).or_default();
+            if let Some(balance) = overrides.balance {
+                account.balance = balance;
+            }
+            if let Some(nonce) = overrides.nonce {
+                account.nonce = nonce;
+            }
+            if let Some(code) = &overrides.code {
+                account.contract = Some(code.clone());
+            }
+            let Some(contract) = &mut account.contract else { continue };
+            if overrides.state.is_some() {
+                contract.storage = ContractStorage::new(vec![U256::ZERO; contract.storage.slots().len()]);
+            }
+            overrides.state.iter().flatten().chain(&overrides.state_diff).for_each(|(slot, value)| contract.storage.store(*slot, *value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::solidity::grammar::Expression;
+    use crate::test_utils::*;
+    use crate::abi::calldata;
+    use crate::world::{Transaction, World};
+
+    #[test]
+    fn test_simulate_with_overrides() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        let get = Transaction::new(bob(), address, &calldata("get()", &[]));
+
+        let overrides = StateOverride::from_json(&json!({ address.to_string(): { "stateDiff": { "0x0": "0x1" }, "balance": "0x64" } })).unwrap();
+        let result = world.simulate_with_overrides(&get, &overrides);
+        assert_eq!(result.output, vec![Expression::BoolLiteral(true)]);
+        // The world itself is untouched.
+        assert_eq!(world.simulate(&get).output, vec![Expression::BoolLiteral(false)]);
+        assert_eq!(world.balance(&address), U256::ZERO);
+
+        // A sender that can't afford the call can be given the balance for one call.
+        let broke = Address([9; 20]);
+        let flip = Transaction { value: U256::from(5u8), ..Transaction::new(broke, address, &calldata("flip()", &[])) };
+        let overrides = StateOverride::new().balance(broke, U256::from(u128::MAX)).state(address, BTreeMap::from([(U256::from(9u8), U256::ONE)]));
+        assert_eq!(world.simulate(&flip).halt, Some(crate::tinyvm::Halt::InsufficientFunds));
+        assert_eq!(world.simulate_with_overrides(&flip, &overrides).halt, Some(crate::tinyvm::Halt::NonPayableFunctionReceivedValue));
+
+        assert!(StateOverride::from_json(&json!({ address.to_string(): { "state": {}, "stateDiff": {} } })).is_none());
+    }
+}
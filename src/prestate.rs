@@ -0,0 +1,154 @@
+use ethnum::U256;
+use serde_json::json;
+use std::collections::BTreeMap;
+use crate::bytecode::to_hex;
+use crate::env::Address;
+use crate::world::*;
+
+/// The parts of an account a prestate trace reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountState {
+    pub balance: U256,
+    pub nonce: u64,
+    /// Runtime code, empty for accounts without a contract.
+    pub code: Vec<u8>,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl AccountState {
+    fn of(world: &World, address: &Address) -> AccountState {
+        let Some(account) = world.account(address) else {
+            return AccountState::default();
+        };
+        let contract = account.contract.as_ref();
+        AccountState {
+            balance: account.balance,
+            nonce: account.nonce,
+            code: contract.map(|contract| contract.deployed_bytecode()).unwrap_or_default(),
+            storage: contract.map(|contract| contract.storage.iter()
+                .filter(|(_, value)| *value != U256::ZERO)
+                .collect()).unwrap_or_default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == AccountState::default()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = json!({});
+        if self.balance != U256::ZERO {
+            json["balance"] = json!(format!("{:#x}", self.balance));
+        }
+        if self.nonce != 0 {
+            json["nonce"] = json!(self.nonce);
+        }
+        if !self.code.is_empty() {
+            json["code"] = json!(to_hex(&self.code));
+        }
+        if !self.storage.is_empty() {
+            json["storage"] = self.storage.iter()
+                .map(|(slot, value)| (to_hex(&slot.to_be_bytes()), json!(to_hex(&value.to_be_bytes()))))
+                .collect();
+        }
+        json
+    }
+}
+
+/// Accounts before and, in diff mode, after a transaction, like geth's `prestateTracer`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrestateTrace {
+    pub pre: BTreeMap<Address, AccountState>,
+    /// Only the fields that changed, set in diff mode.
+    pub post: Option<BTreeMap<Address, AccountState>>,
+}
+
+impl PrestateTrace {
+    /// The tracer's result as `debug_traceTransaction` returns it: the `pre` map alone, or
+    /// `pre` and `post` in diff mode.
+    pub fn to_json(&self) -> serde_json::Value {
+        let accounts = |accounts: &BTreeMap<Address, AccountState>| accounts.iter()
+            .map(|(address, state)| (address.to_string(), state.to_json()))
+            .collect::<serde_json::Map<_, _>>();
+        match &self.post {
+            Some(post) => json!({ "pre": accounts(&self.pre), "post": accounts(post) }),
+            None => json!(accounts(&self.pre)),
+        }
+    }
+}
+
+impl World {
+    /// Applies `tx` and reports the accounts it touched: the sender, fee payer, callee and
+    /// coinbase.
+    ///
+    /// Without `diff_mode`, the trace holds their state before the transaction. Storage
+    /// lists every nonzero slot rather than only those read, which the world doesn't
+    /// record. With `diff_mode`, only accounts that changed are reported: `pre` holds them
+    /// with just the changed slots, and `post` just the fields and slots that changed,
+    /// leaving out slots that were cleared.
+    pub fn trace_prestate(&mut self, tx: &Transaction, diff_mode: bool) -> (CallResult, PrestateTrace) {
+        let touched = [tx.from, tx.payer.unwrap_or(tx.from), tx.to, self.env.coinbase];
+        let pre = touched.iter().map(|address| (*address, AccountState::of(self, address))).collect::<BTreeMap<_, _>>();
+        let result = self.apply_transaction(tx);
+        if !diff_mode {
+            return (result, PrestateTrace { pre, post: None });
+        }
+
+        let (mut changed_pre, mut changed_post) = (BTreeMap::new(), BTreeMap::new());
+        pre.into_iter().for_each(|(address, before)| {
+            let after = AccountState::of(self, &address);
+            if before == after {
+                return;
+            }
+            let slots = before.storage.keys().chain(after.storage.keys())
+                .filter(|slot| before.storage.get(slot) != after.storage.get(slot))
+                .copied()
+                .collect::<Vec<_>>();
+            let post = AccountState {
+                balance: if after.balance != before.balance { after.balance } else { U256::ZERO },
+                nonce: if after.nonce != before.nonce { after.nonce } else { 0 },
+                code: if after.code != before.code { after.code.clone() } else { vec![] },
+                storage: slots.iter().filter_map(|slot| Some((*slot, *after.storage.get(slot)?))).collect(),
+            };
+            let pre = AccountState {
+                storage: slots.iter().filter_map(|slot| Some((*slot, *before.storage.get(slot)?))).collect(),
+                ..before
+            };
+            if !pre.is_empty() {
+                changed_pre.insert(address, pre);
+            }
+            changed_post.insert(address, post);
+        });
+        (result, PrestateTrace { pre: changed_pre, post: Some(changed_post) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::abi::calldata;
+
+    #[test]
+    fn test_prestate_trace() {
+        let contract = flipper();
+        let mut world = World::new();
+        let address = world.deploy(alice(), contract);
+        world.set_balance(bob(), U256::from(1000u16));
+        let flip = Transaction::new(bob(), address, &calldata("flip()", &[]));
+
+        let (result, trace) = world.clone().trace_prestate(&flip, false);
+        assert!(result.success);
+        assert_eq!(trace.pre.len(), 3);
+        assert_eq!(trace.to_json()[bob().to_string()], json!({ "balance": "0x3e8" }));
+        assert!(trace.pre[&address].storage.is_empty() && !trace.pre[&address].code.is_empty());
+
+        let (_, trace) = world.trace_prestate(&flip, true);
+        let slot = to_hex(&[0; 32]);
+        let json = trace.to_json();
+        assert_eq!(json["pre"][address.to_string()]["storage"], json!(null));
+        assert_eq!(json["post"][address.to_string()]["storage"][&slot], json!(to_hex(&U256::ONE.to_be_bytes())));
+        assert_eq!(json["post"][address.to_string()].get("code"), None);
+        assert!(json["pre"].get(alice().to_string()).is_none());
+    }
+}
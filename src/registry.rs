@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use crate::abi::{decode, format_calldata, Abi, AbiFunction, AbiParam, AbiType, Value};
+use crate::selector::selector;
+use crate::bytecode::to_hex;
+use crate::env::Address;
+use crate::revert::RevertReason;
+
+/// What traces know about a deployed contract.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContractInfo {
+    pub name: String,
+    pub abi: Abi,
+    /// Source the contract was compiled from, if known.
+    pub source: Option<String>,
+}
+
+/// Names, ABIs and sources of deployed contracts, and names of plain accounts, keyed by
+/// address, so traces can show `Token.transfer(alice, 100)` instead of raw calldata.
+#[derive(Debug, Clone, Default)]
+pub struct ContractRegistry {
+    contracts: HashMap<Address, ContractInfo>,
+    labels: HashMap<Address, String>,
+}
+
+fn types(params: &[AbiParam]) -> Option<Vec<AbiType>> {
+    params.iter().map(|param| AbiType::parse(&param.ty)).collect()
+}
+
+impl ContractRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, address: Address, info: ContractInfo) {
+        self.contracts.insert(address, info);
+    }
+
+    /// Names an account that has no contract, e.g. `alice`.
+    pub fn label(&mut self, address: Address, name: &str) {
+        self.labels.insert(address, name.to_string());
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&ContractInfo> {
+        self.contracts.get(address)
+    }
+
+    /// The contract name or label of `address`, or the address itself.
+    pub fn name(&self, address: &Address) -> String {
+        self.contracts.get(address).map(|info| &info.name)
+            .or(self.labels.get(address))
+            .cloned()
+            .unwrap_or_else(|| address.to_string())
+    }
+
+    /// The function of the contract at `address` that `input` calls.
+    pub fn function(&self, address: &Address, input: &[u8]) -> Option<&AbiFunction> {
+        let called = input.get(..4)?;
+        self.get(address)?.abi.functions.iter().find(|function| selector(&function.signature()) == called)
+    }
+
+    /// `value` as Solidity would write it, with addresses replaced by their names.
+    pub fn format_value(&self, value: &Value) -> String {
+        match value {
+            Value::Address(address) => self.name(address),
+            value => value.to_string(),
+        }
+    }
+
+    fn format_values(&self, values: &[Value]) -> String {
+        values.iter().map(|value| self.format_value(value)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// A call of `address` with `input`, e.g. `Token.transfer(alice, 100)`. Calls the
+    /// registry can't decode show their selector instead, as `format_calldata` does.
+    pub fn describe_call(&self, address: &Address, input: &[u8]) -> String {
+        let name = self.name(address);
+        if input.is_empty() {
+            return name;
+        }
+        let unknown = Abi::default();
+        let abi = self.get(address).map_or(&unknown, |info| &info.abi);
+        format!("{}.{}", name, format_calldata(abi, input, &|value| self.format_value(value)))
+    }
+
+    /// What a successful call of `address` with `input` returned, decoded as its outputs.
+    pub fn describe_output(&self, address: &Address, input: &[u8], output: &[u8]) -> Option<String> {
+        let function = self.function(address, input)?;
+        decode(&types(&function.outputs)?, output).map(|values| self.format_values(&values))
+    }
+
+    /// Decodes revert data from the contract at `address` against its custom errors.
+    pub fn decode_revert(&self, address: &Address, data: &[u8]) -> RevertReason {
+        let errors = self.get(address).map(|info| info.abi.errors.as_slice()).unwrap_or_default();
+        RevertReason::decode_with_errors(data, errors)
+    }
+
+    /// A decoded revert reason, e.g. `InsufficientBalance(alice, 5)`.
+    pub fn describe_revert(&self, reason: &RevertReason) -> String {
+        match reason {
+            RevertReason::Empty => "revert".to_string(),
+            RevertReason::Error(message) => format!("Error({:?})", message),
+            RevertReason::Panic(code) => format!("Panic({:?})", code),
+            RevertReason::Custom { name, args } => format!("{}({})", name, self.format_values(args)),
+            RevertReason::Raw(data) => to_hex(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethnum::U256;
+    use crate::abi::{calldata, encode, AbiError};
+    use crate::bytecode::from_hex;
+    use crate::test_utils::*;
+
+    fn token() -> ContractInfo {
+        let param = |name: &str, ty: &str| AbiParam { name: name.to_string(), ty: ty.to_string() };
+        let abi = Abi {
+            functions: vec![AbiFunction {
+                name: "transfer".to_string(),
+                inputs: vec![param("to", "address"), param("amount", "uint256")],
+                outputs: vec![param("", "bool")],
+                state_mutability: "nonpayable".to_string(),
+            }],
+            errors: vec![AbiError { name: "InsufficientBalance".to_string(), inputs: vec![param("needed", "uint256")] }],
+            events: vec![],
+        };
+        ContractInfo { name: "Token".to_string(), abi, source: None }
+    }
+
+    #[test]
+    fn test_describe_call() {
+        let mut registry = ContractRegistry::new();
+        registry.register(carol(), token());
+        registry.label(alice(), "alice");
+
+        let input = from_hex(&calldata("transfer(address,uint256)", &[Value::Address(alice()), Value::Uint(U256::from(100u8))])).unwrap();
+        assert_eq!(registry.describe_call(&carol(), &input), "Token.transfer(alice, 100)");
+        assert_eq!(registry.describe_output(&carol(), &input, &encode(&[Value::Bool(true)])), Some("true".to_string()));
+        assert_eq!(registry.describe_call(&carol(), &[0xde, 0xad]), "Token.0xdead");
+        assert_eq!(registry.describe_call(&carol(), &input[..20]), "Token.transfer(<16 bytes>)");
+        assert_eq!(registry.name(&bob()), bob().to_string());
+
+        let error = &registry.get(&carol()).unwrap().abi.errors[0];
+        let data = [error.selector().to_vec(), encode(&[Value::Uint(U256::from(5u8))])].concat();
+        assert_eq!(registry.describe_revert(&registry.decode_revert(&carol(), &data)), "InsufficientBalance(5)");
+        assert_eq!(registry.decode_revert(&bob(), &data), RevertReason::Raw(data));
+    }
+}
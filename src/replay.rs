@@ -0,0 +1,271 @@
+use ethnum::U256;
+use serde_json::json;
+use crate::abi::calldata;
+use crate::bytecode::*;
+use crate::env::*;
+use crate::solidity::grammar::*;
+use crate::selector::selector;
+use crate::tinyvm::*;
+
+/// What a call produced, kept in the fixture so a replay can detect regressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: Vec<Expression>,
+    pub storage: Vec<U256>,
+    pub gas_used: u64,
+    pub halt: Option<Halt>,
+}
+
+/// Every input of a single call, self-contained so it can be re-run without the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixture {
+    pub contract: String,
+    pub function: String,
+    pub calldata: String,
+    pub code: Vec<u8>,
+    pub returns: Vec<String>,
+    pub mutability: String,
+    pub storage: Vec<U256>,
+    pub env: Env,
+    pub expected: Outcome,
+}
+
+fn mutability_name(mutability: &FuncMutability) -> &'static str {
+    match mutability {
+        FuncMutability::Constant => "constant",
+        FuncMutability::NonPayable => "nonpayable",
+        FuncMutability::Payable => "payable",
+        FuncMutability::View => "view",
+        FuncMutability::Pure => "pure",
+    }
+}
+
+fn mutability_from_name(name: &str) -> FuncMutability {
+    match name {
+        "constant" => FuncMutability::Constant,
+        "payable" => FuncMutability::Payable,
+        "view" => FuncMutability::View,
+        "pure" => FuncMutability::Pure,
+        _ => FuncMutability::NonPayable,
+    }
+}
+
+fn parameter_from_type_name(name: &str) -> Option<Parameter> {
+    let ty = match name {
+        "bool" => Type::Bool(()),
+        "uint256" => Type::Uint256(()),
+        "int256" => Type::Int256(()),
+        "address" => Type::Address(()),
+        _ => return None,
+    };
+    Some(Parameter { ty: Expression::Type(ty), name: None })
+}
+
+fn word_to_json(word: &U256) -> serde_json::Value {
+    json!(format!("{:#x}", word))
+}
+
+fn word_from_json(json: &serde_json::Value) -> Option<U256> {
+    let hex = json.as_str()?.trim_start_matches("0x");
+    U256::from_str_radix(if hex.is_empty() { "0" } else { hex }, 16).ok()
+}
+
+fn expression_to_json(expr: &Expression) -> serde_json::Value {
+    match expr {
+        Expression::BoolLiteral(b) => json!(b),
+        Expression::NumberLiteral(n) => word_to_json(n),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn expression_from_json(json: &serde_json::Value) -> Option<Expression> {
+    match json {
+        serde_json::Value::Bool(b) => Some(Expression::BoolLiteral(*b)),
+        _ => word_from_json(json).map(Expression::NumberLiteral),
+    }
+}
+
+fn halt_name(halt: &Option<Halt>) -> serde_json::Value {
+    match halt {
+        Some(Halt::OutOfGas) => json!("OutOfGas"),
+        Some(Halt::NonPayableFunctionReceivedValue) => json!("NonPayableFunctionReceivedValue"),
+        Some(Halt::InsufficientFunds) => json!("InsufficientFunds"),
+        Some(Halt::FeeCapTooLow) => json!("FeeCapTooLow"),
+        Some(Halt::MemoryLimitExceeded) => json!("MemoryLimitExceeded"),
+        Some(Halt::InvalidJump) => json!("InvalidJump"),
+        Some(Halt::ReturnDataLimitExceeded) => json!("ReturnDataLimitExceeded"),
+        Some(Halt::StackUnderflow) => json!("StackUnderflow"),
+        Some(Halt::StackOverflow) => json!("StackOverflow"),
+        Some(Halt::StateChangeInStaticCall) => json!("StateChangeInStaticCall"),
+        Some(Halt::ReturnDataOutOfBounds) => json!("ReturnDataOutOfBounds"),
+        Some(Halt::InvalidInstruction) => json!("InvalidInstruction"),
+        Some(Halt::InvalidOpcode(opcode)) => json!(format!("InvalidOpcode({})", to_hex(&[*opcode]))),
+        Some(Halt::FunctionNotFound(selector)) => json!(format!("FunctionNotFound({})", to_hex(selector))),
+        Some(Halt::Revert(data)) => json!(format!("Revert({})", to_hex(data))),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn halt_from_json(json: &serde_json::Value) -> Option<Option<Halt>> {
+    match json.as_str() {
+        Some("OutOfGas") => Some(Some(Halt::OutOfGas)),
+        Some("NonPayableFunctionReceivedValue") => Some(Some(Halt::NonPayableFunctionReceivedValue)),
+        Some("InsufficientFunds") => Some(Some(Halt::InsufficientFunds)),
+        Some("FeeCapTooLow") => Some(Some(Halt::FeeCapTooLow)),
+        Some("MemoryLimitExceeded") => Some(Some(Halt::MemoryLimitExceeded)),
+        Some("InvalidJump") => Some(Some(Halt::InvalidJump)),
+        Some("ReturnDataLimitExceeded") => Some(Some(Halt::ReturnDataLimitExceeded)),
+        Some("StackUnderflow") => Some(Some(Halt::StackUnderflow)),
+        Some("StackOverflow") => Some(Some(Halt::StackOverflow)),
+        Some("StateChangeInStaticCall") => Some(Some(Halt::StateChangeInStaticCall)),
+        Some("ReturnDataOutOfBounds") => Some(Some(Halt::ReturnDataOutOfBounds)),
+        Some("InvalidInstruction") => Some(Some(Halt::InvalidInstruction)),
+        Some(name) if name.starts_with("FunctionNotFound(") => {
+            let selector = from_hex(name.strip_prefix("FunctionNotFound(")?.strip_suffix(')')?)?;
+            Some(Some(Halt::FunctionNotFound(selector.try_into().ok()?)))
+        },
+        Some(name) if name.starts_with("InvalidOpcode(") => {
+            let opcode = from_hex(name.strip_prefix("InvalidOpcode(")?.strip_suffix(')')?)?;
+            Some(Some(Halt::InvalidOpcode(*opcode.first()?)))
+        },
+        Some(name) if name.starts_with("Revert(") => {
+            Some(Some(Halt::Revert(from_hex(name.strip_prefix("Revert(")?.strip_suffix(')')?)?)))
+        },
+        Some(_) => None,
+        None => Some(None),
+    }
+}
+
+impl Outcome {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "output": self.output.iter().map(expression_to_json).collect::<Vec<_>>(),
+            "storage": self.storage.iter().map(word_to_json).collect::<Vec<_>>(),
+            "gasUsed": self.gas_used,
+            "halt": halt_name(&self.halt),
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Outcome> {
+        Some(Outcome {
+            output: json["output"].as_array()?.iter().map(expression_from_json).collect::<Option<_>>()?,
+            storage: json["storage"].as_array()?.iter().map(word_from_json).collect::<Option<_>>()?,
+            gas_used: json["gasUsed"].as_u64()?,
+            halt: halt_from_json(&json["halt"])?,
+        })
+    }
+}
+
+impl Fixture {
+    /// Runs `signature` on `contract` and captures its inputs together with the outcome.
+    pub fn record(contract: &Contract, signature: &str, env: Env) -> Option<Fixture> {
+        let calldata = calldata(signature, &[]);
+        let function = contract.functions.get(&selector(signature))?;
+        let execution = contract.execute(&calldata, env.clone(), &mut NoopInspector);
+        Some(Fixture {
+            contract: contract.name.clone(),
+            function: signature.to_string(),
+            code: assemble(function.program()),
+            returns: function.returns.iter().map(|p| param_type_name(p).unwrap_or_default().to_string()).collect(),
+            mutability: mutability_name(&function.mutability).to_string(),
+            storage: contract.storage.slots().to_vec(),
+            env,
+            expected: Outcome {
+                output: execution.output,
+                storage: execution.contract.storage.slots().to_vec(),
+                gas_used: execution.gas_used,
+                halt: execution.halt,
+            },
+            calldata,
+        })
+    }
+
+    /// Re-executes the call from the recorded inputs.
+    pub fn replay(&self) -> Option<Outcome> {
+        let mut function = Function::from_program(self.function.clone(), disassemble(&self.code)?);
+        function.returns = self.returns.iter().map(|ty| parameter_from_type_name(ty)).collect::<Option<_>>()?;
+        function.mutability = mutability_from_name(&self.mutability);
+
+        let mut contract = Contract::new(self.contract.clone());
+        contract.storage = ContractStorage::new(self.storage.clone());
+        contract.functions.insert(selector(&self.function), function);
+
+        let execution = contract.execute(&self.calldata, self.env.clone(), &mut NoopInspector);
+        Some(Outcome {
+            output: execution.output,
+            storage: execution.contract.storage.slots().to_vec(),
+            gas_used: execution.gas_used,
+            halt: execution.halt,
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "contract": self.contract,
+            "function": self.function,
+            "calldata": self.calldata,
+            "code": to_hex(&self.code),
+            "returns": self.returns,
+            "mutability": self.mutability,
+            "storage": self.storage.iter().map(word_to_json).collect::<Vec<_>>(),
+            "env": {
+                "caller": self.env.caller.to_string(),
+                "value": word_to_json(&self.env.value),
+                "gasLimit": self.env.gas_limit,
+                "blockNumber": self.env.block_number,
+                "timestamp": self.env.timestamp,
+                "chainId": self.env.chain_id,
+            },
+            "expected": self.expected.to_json(),
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Option<Fixture> {
+        let env = &json["env"];
+        Some(Fixture {
+            contract: json["contract"].as_str()?.to_string(),
+            function: json["function"].as_str()?.to_string(),
+            calldata: json["calldata"].as_str()?.to_string(),
+            code: from_hex(json["code"].as_str()?)?,
+            returns: json["returns"].as_array()?.iter().map(|r| r.as_str().map(String::from)).collect::<Option<_>>()?,
+            mutability: json["mutability"].as_str()?.to_string(),
+            storage: json["storage"].as_array()?.iter().map(word_from_json).collect::<Option<_>>()?,
+            env: Env {
+                caller: Address(from_hex(env["caller"].as_str()?)?.try_into().ok()?),
+                value: word_from_json(&env["value"])?,
+                gas_limit: env["gasLimit"].as_u64()?,
+                block_number: env["blockNumber"].as_u64()?,
+                timestamp: env["timestamp"].as_u64()?,
+                chain_id: env["chainId"].as_u64()?,
+                ..Env::default()
+            },
+            expected: Outcome::from_json(&json["expected"])?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::flipper;
+
+    #[test]
+    fn test_record_and_replay() {
+        let env = Env::builder().caller(Address([9; 20])).block_number(7).build();
+        let fixture = Fixture::record(&flipper(), "flip()", env).unwrap();
+        assert_eq!(fixture.expected.storage, vec![U256::ONE]);
+
+        let json = fixture.to_json().to_string();
+        let loaded = Fixture::from_json(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(loaded, fixture);
+        assert_eq!(loaded.replay(), Some(fixture.expected));
+    }
+
+    #[test]
+    fn test_replay_detects_changed_inputs() {
+        let mut fixture = Fixture::record(&flipper(), "get()", Env::default()).unwrap();
+        assert_eq!(fixture.expected.output, vec![Expression::BoolLiteral(false)]);
+        fixture.storage = vec![U256::ONE];
+        assert_ne!(fixture.replay(), Some(fixture.expected.clone()));
+    }
+}
@@ -0,0 +1,160 @@
+use ethnum::U256;
+use crate::abi::*;
+
+/// Selector of `Error(string)`, emitted by `require(cond, "reason")` and `revert("reason")`.
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, emitted by failing asserts and checked arithmetic.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicCode {
+    Generic,
+    Assert,
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidEnumValue,
+    InvalidStorageByteArray,
+    EmptyArrayPop,
+    ArrayOutOfBounds,
+    OutOfMemory,
+    InvalidInternalFunction,
+    Unknown(U256),
+}
+
+impl PanicCode {
+    pub fn from_code(code: U256) -> Self {
+        if code > U256::from(u8::MAX) {
+            return PanicCode::Unknown(code);
+        }
+        match code.as_u8() {
+            0x00 => PanicCode::Generic,
+            0x01 => PanicCode::Assert,
+            0x11 => PanicCode::ArithmeticOverflow,
+            0x12 => PanicCode::DivisionByZero,
+            0x21 => PanicCode::InvalidEnumValue,
+            0x22 => PanicCode::InvalidStorageByteArray,
+            0x31 => PanicCode::EmptyArrayPop,
+            0x32 => PanicCode::ArrayOutOfBounds,
+            0x41 => PanicCode::OutOfMemory,
+            0x51 => PanicCode::InvalidInternalFunction,
+            _ => PanicCode::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> U256 {
+        match self {
+            PanicCode::Generic => U256::from(0x00u8),
+            PanicCode::Assert => U256::from(0x01u8),
+            PanicCode::ArithmeticOverflow => U256::from(0x11u8),
+            PanicCode::DivisionByZero => U256::from(0x12u8),
+            PanicCode::InvalidEnumValue => U256::from(0x21u8),
+            PanicCode::InvalidStorageByteArray => U256::from(0x22u8),
+            PanicCode::EmptyArrayPop => U256::from(0x31u8),
+            PanicCode::ArrayOutOfBounds => U256::from(0x32u8),
+            PanicCode::OutOfMemory => U256::from(0x41u8),
+            PanicCode::InvalidInternalFunction => U256::from(0x51u8),
+            PanicCode::Unknown(code) => *code,
+        }
+    }
+
+    /// What went wrong, worded as geth reports it.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PanicCode::Generic => "generic panic",
+            PanicCode::Assert => "assert(false)",
+            PanicCode::ArithmeticOverflow => "arithmetic underflow or overflow",
+            PanicCode::DivisionByZero => "division or modulo by zero",
+            PanicCode::InvalidEnumValue => "enum overflow",
+            PanicCode::InvalidStorageByteArray => "invalid encoded storage byte array accessed",
+            PanicCode::EmptyArrayPop => "out-of-bounds array access; popping on an empty array",
+            PanicCode::ArrayOutOfBounds => "out-of-bounds access of an array or bytesN",
+            PanicCode::OutOfMemory => "out of memory",
+            PanicCode::InvalidInternalFunction => "uninitialized function",
+            PanicCode::Unknown(_) => "unknown panic code",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    /// Revert without any data, e.g. a bare `revert()` or `require(cond)`.
+    Empty,
+    Error(String),
+    Panic(PanicCode),
+    Custom { name: String, args: Vec<Value> },
+    /// Data that could not be matched against any known error.
+    Raw(Vec<u8>),
+}
+
+impl RevertReason {
+    /// Decodes the built-in `Error(string)` and `Panic(uint256)` payloads.
+    pub fn decode(data: &[u8]) -> Self {
+        Self::decode_with_errors(data, &[])
+    }
+
+    /// Like `decode`, additionally resolving custom errors from the given ABI.
+    pub fn decode_with_errors(data: &[u8], errors: &[AbiError]) -> Self {
+        if data.is_empty() {
+            return RevertReason::Empty;
+        }
+        if data.len() < 4 {
+            return RevertReason::Raw(data.to_vec());
+        }
+
+        let (sel, args) = data.split_at(4);
+        if sel == ERROR_SELECTOR {
+            if let Some(Value::String(reason)) = decode(&[AbiType::String], args).and_then(|v| v.into_iter().next()) {
+                return RevertReason::Error(reason);
+            }
+        } else if sel == PANIC_SELECTOR {
+            if let Some(Value::Uint(code)) = decode(&[AbiType::Uint(256)], args).and_then(|v| v.into_iter().next()) {
+                return RevertReason::Panic(PanicCode::from_code(code));
+            }
+        } else if let Some(error) = errors.iter().find(|error| error.selector() == sel) {
+            let types = error.inputs.iter().map(|input| AbiType::parse(&input.ty)).collect::<Option<Vec<_>>>();
+            if let Some(args) = types.and_then(|types| decode(&types, args)) {
+                return RevertReason::Custom { name: error.name.clone(), args };
+            }
+        }
+        RevertReason::Raw(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selector::selector;
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(selector("Error(string)"), ERROR_SELECTOR);
+        assert_eq!(selector("Panic(uint256)"), PANIC_SELECTOR);
+    }
+
+    #[test]
+    fn test_decode_error_string() {
+        let data = [ERROR_SELECTOR.to_vec(), encode(&[Value::String("not owner".to_string())])].concat();
+        assert_eq!(RevertReason::decode(&data), RevertReason::Error("not owner".to_string()));
+    }
+
+    #[test]
+    fn test_decode_panic() {
+        let data = [PANIC_SELECTOR.to_vec(), encode(&[Value::Uint(U256::from(0x11u8))])].concat();
+        assert_eq!(RevertReason::decode(&data), RevertReason::Panic(PanicCode::ArithmeticOverflow));
+        assert_eq!(RevertReason::decode(&[]), RevertReason::Empty);
+    }
+
+    #[test]
+    fn test_decode_custom_error() {
+        let error = AbiError {
+            name: "Locked".to_string(),
+            inputs: vec![AbiParam { name: "flag".to_string(), ty: "bool".to_string() }],
+        };
+        let data = [error.selector().to_vec(), encode(&[Value::Bool(true)])].concat();
+        assert_eq!(RevertReason::decode(&data), RevertReason::Raw(data.clone()));
+        assert_eq!(
+            RevertReason::decode_with_errors(&data, &[error]),
+            RevertReason::Custom { name: "Locked".to_string(), args: vec![Value::Bool(true)] }
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use ethnum::U256;
+use std::fmt;
+use crate::env::Address;
+
+/// An RLP item: a byte string or a list of items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rlp {
+    Bytes(Vec<u8>),
+    List(Vec<Rlp>),
+}
+
+impl Rlp {
+    /// Integers are encoded big-endian without leading zeros, zero being the empty string.
+    pub fn uint(value: U256) -> Rlp {
+        let bytes = value.to_be_bytes();
+        let first = bytes.iter().position(|b| *b != 0).unwrap_or(32);
+        Rlp::Bytes(bytes[first..].to_vec())
+    }
+
+    pub fn address(address: &Address) -> Rlp {
+        Rlp::Bytes(address.0.to_vec())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => bytes.clone(),
+            Rlp::Bytes(bytes) => [length_prefix(bytes.len(), 0x80), bytes.clone()].concat(),
+            Rlp::List(items) => {
+                let payload = items.iter().flat_map(Rlp::encode).collect::<Vec<_>>();
+                [length_prefix(payload.len(), 0xc0), payload].concat()
+            }
+        }
+    }
+
+    /// Decodes exactly one item from `data`, rejecting non-canonical encodings.
+    pub fn decode(data: &[u8]) -> Result<Rlp, RlpError> {
+        let (item, rest) = decode_item(data)?;
+        if !rest.is_empty() {
+            return Err(RlpError::TrailingBytes);
+        }
+        Ok(item)
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8], RlpError> {
+        match self {
+            Rlp::Bytes(bytes) => Ok(bytes),
+            Rlp::List(_) => Err(RlpError::ExpectedBytes),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&[Rlp], RlpError> {
+        match self {
+            Rlp::List(items) => Ok(items),
+            Rlp::Bytes(_) => Err(RlpError::ExpectedList),
+        }
+    }
+
+    /// Reads an integer written by `uint`: at most 32 bytes, without leading zeros.
+    pub fn as_uint(&self) -> Result<U256, RlpError> {
+        let bytes = self.as_bytes()?;
+        if bytes.len() > 32 || bytes.first() == Some(&0) {
+            return Err(RlpError::InvalidUint);
+        }
+        let mut word = [0u8; 32];
+        word[32 - bytes.len()..].copy_from_slice(bytes);
+        Ok(U256::from_be_bytes(word))
+    }
+
+    pub fn as_u64(&self) -> Result<u64, RlpError> {
+        u64::try_from(self.as_uint()?).map_err(|_| RlpError::InvalidUint)
+    }
+
+    pub fn as_address(&self) -> Result<Address, RlpError> {
+        Ok(Address(self.as_bytes()?.try_into().map_err(|_| RlpError::InvalidAddress)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RlpError {
+    UnexpectedEnd,
+    /// A length or single byte that has a shorter encoding.
+    NonCanonical,
+    TrailingBytes,
+    ExpectedBytes,
+    ExpectedList,
+    InvalidUint,
+    InvalidAddress,
+}
+
+impl fmt::Display for RlpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RlpError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            RlpError::NonCanonical => write!(f, "non-canonical encoding"),
+            RlpError::TrailingBytes => write!(f, "trailing bytes after item"),
+            RlpError::ExpectedBytes => write!(f, "expected a byte string, found a list"),
+            RlpError::ExpectedList => write!(f, "expected a list, found a byte string"),
+            RlpError::InvalidUint => write!(f, "invalid integer"),
+            RlpError::InvalidAddress => write!(f, "invalid address"),
+        }
+    }
+}
+
+/// The first item in `data` and the bytes after it.
+fn decode_item(data: &[u8]) -> Result<(Rlp, &[u8]), RlpError> {
+    let (&prefix, rest) = data.split_first().ok_or(RlpError::UnexpectedEnd)?;
+    let (list, len, rest) = match prefix {
+        0x00..=0x7f => return Ok((Rlp::Bytes(vec![prefix]), rest)),
+        0x80..=0xb7 => (false, (prefix - 0x80) as usize, rest),
+        0xb8..=0xbf => {
+            let (len, rest) = long_length(rest, (prefix - 0xb7) as usize)?;
+            (false, len, rest)
+        },
+        0xc0..=0xf7 => (true, (prefix - 0xc0) as usize, rest),
+        0xf8..=0xff => {
+            let (len, rest) = long_length(rest, (prefix - 0xf7) as usize)?;
+            (true, len, rest)
+        },
+    };
+    if rest.len() < len {
+        return Err(RlpError::UnexpectedEnd);
+    }
+    let (payload, rest) = rest.split_at(len);
+    if !list {
+        if len == 1 && payload[0] < 0x80 {
+            return Err(RlpError::NonCanonical);
+        }
+        return Ok((Rlp::Bytes(payload.to_vec()), rest));
+    }
+    let mut items = vec![];
+    let mut payload = payload;
+    while !payload.is_empty() {
+        let (item, tail) = decode_item(payload)?;
+        items.push(item);
+        payload = tail;
+    }
+    Ok((Rlp::List(items), rest))
+}
+
+/// Reads a big-endian length of `size` bytes, which must need the long form.
+fn long_length(data: &[u8], size: usize) -> Result<(usize, &[u8]), RlpError> {
+    if data.len() < size {
+        return Err(RlpError::UnexpectedEnd);
+    }
+    let (bytes, rest) = data.split_at(size);
+    if bytes[0] == 0 || size > 8 {
+        return Err(RlpError::NonCanonical);
+    }
+    let len = bytes.iter().fold(0u64, |len, b| len << 8 | *b as u64);
+    if len < 56 {
+        return Err(RlpError::NonCanonical);
+    }
+    Ok((usize::try_from(len).map_err(|_| RlpError::UnexpectedEnd)?, rest))
+}
+
+fn length_prefix(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![offset + len as u8]
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let first = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+        [vec![offset + 55 + (8 - first) as u8], bytes[first..].to_vec()].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(Rlp::Bytes(b"dog".to_vec()).encode(), vec![0x83, b'd', b'o', b'g']);
+        assert_eq!(Rlp::uint(U256::ZERO).encode(), vec![0x80]);
+        assert_eq!(Rlp::uint(U256::from(0x0fu8)).encode(), vec![0x0f]);
+        assert_eq!(Rlp::uint(U256::from(1024u16)).encode(), vec![0x82, 0x04, 0x00]);
+        assert_eq!(Rlp::List(vec![]).encode(), vec![0xc0]);
+        assert_eq!(
+            Rlp::List(vec![Rlp::Bytes(b"cat".to_vec()), Rlp::Bytes(b"dog".to_vec())]).encode(),
+            vec![0xc8, 0x83, b'c', b'a', b't', 0x83, b'd', b'o', b'g']
+        );
+        let long = Rlp::Bytes(vec![b'a'; 56]).encode();
+        assert_eq!(&long[..2], &[0xb8, 56]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let item = Rlp::List(vec![Rlp::uint(U256::from(1024u16)), Rlp::Bytes(vec![b'a'; 60]), Rlp::List(vec![Rlp::List(vec![])])]);
+        assert_eq!(Rlp::decode(&item.encode()), Ok(item.clone()));
+        assert_eq!(item.as_list().unwrap()[0].as_uint(), Ok(U256::from(1024u16)));
+        assert_eq!(item.as_uint(), Err(RlpError::ExpectedBytes));
+
+        assert_eq!(Rlp::decode(&[0x81, 0x05]), Err(RlpError::NonCanonical));
+        assert_eq!(Rlp::decode(&[0xb8, 0x05, 1, 2, 3, 4, 5]), Err(RlpError::NonCanonical));
+        assert_eq!(Rlp::decode(&[0x83, b'd', b'o']), Err(RlpError::UnexpectedEnd));
+        assert_eq!(Rlp::decode(&[0x80, 0x80]), Err(RlpError::TrailingBytes));
+        assert_eq!(Rlp::Bytes(vec![0, 1]).as_uint(), Err(RlpError::InvalidUint));
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::json;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    InvalidUrl(String),
+    Io(String),
+    Http(String),
+    Rpc { code: i64, message: String },
+    InvalidResponse(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::InvalidUrl(url) => write!(f, "invalid RPC url {}", url),
+            RpcError::Io(e) => write!(f, "connection error: {}", e),
+            RpcError::Http(status) => write!(f, "HTTP error: {}", status),
+            RpcError::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+            RpcError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+/// A minimal blocking JSON-RPC client over plain HTTP, enough to talk to anvil or a local node.
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Result<Self, RpcError> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| RpcError::InvalidUrl(url.to_string()))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| RpcError::InvalidUrl(url.to_string()))?),
+            None => (authority, 80),
+        };
+        Ok(Self { host: host.to_string(), port, path: path.to_string() })
+    }
+
+    pub fn request(&self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, RpcError> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path, self.host, self.port, body.len(), body
+        );
+
+        let io = |e: std::io::Error| RpcError::Io(e.to_string());
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(io)?;
+        stream.write_all(request.as_bytes()).map_err(io)?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response).map_err(io)?;
+
+        let split = find(&response, b"\r\n\r\n").ok_or_else(|| RpcError::InvalidResponse("missing HTTP body".to_string()))?;
+        let head = String::from_utf8_lossy(&response[..split]);
+        let body = &response[split + 4..];
+        let status = head.lines().next().unwrap_or_default();
+        if !status.contains(" 200") {
+            return Err(RpcError::Http(status.to_string()));
+        }
+        let body = if head.to_ascii_lowercase().contains("transfer-encoding: chunked") {
+            dechunk(body)
+        } else {
+            body.to_vec()
+        };
+
+        let json: serde_json::Value = serde_json::from_slice(&body).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+        if let Some(error) = json.get("error") {
+            return Err(RpcError::Rpc {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+        Ok(json["result"].clone())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Joins the chunks of a chunked body. Chunk sizes count bytes, which may split a UTF-8
+/// character, so this works on the body before it is decoded.
+fn dechunk(body: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut rest = body;
+    while let Some(line) = find(rest, b"\r\n") {
+        let size = std::str::from_utf8(&rest[..line]).ok()
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .unwrap_or_default();
+        let tail = &rest[line + 2..];
+        if size == 0 || tail.len() < size {
+            break;
+        }
+        out.extend_from_slice(&tail[..size]);
+        rest = tail[size..].strip_prefix(b"\r\n").unwrap_or(&tail[size..]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let client = RpcClient::new("http://localhost:8545").unwrap();
+        assert_eq!((client.host.as_str(), client.port, client.path.as_str()), ("localhost", 8545, "/"));
+        let client = RpcClient::new("http://node/rpc").unwrap();
+        assert_eq!((client.host.as_str(), client.port, client.path.as_str()), ("node", 80, "/rpc"));
+        assert!(RpcClient::new("https://node").is_err());
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(dechunk(b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"), b"Wikipedia");
+        // A chunk may end halfway through a character.
+        assert_eq!(dechunk(b"4\r\ncaf\xc3\r\n2\r\n\xa9!\r\n0\r\n\r\n"), "caf\u{e9}!".as_bytes());
+    }
+}
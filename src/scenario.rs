@@ -0,0 +1,254 @@
+use ethnum::U256;
+use std::collections::HashMap;
+use std::fmt;
+use crate::abi::calldata;
+use crate::env::*;
+use crate::solidity::grammar::Expression;
+use crate::tinyvm::*;
+use crate::world::*;
+
+#[derive(Debug, Clone)]
+enum Action {
+    Deploy { label: String, deployer: Address, contract: Box<Contract> },
+    Call { from: Address, target: String, signature: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Success,
+    Failure,
+    Return(Vec<Expression>),
+    /// `contract` is the label given at deployment.
+    Storage { contract: String, variable: String, value: U256 },
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    action: Action,
+    value: Option<U256>,
+    gas_limit: Option<u64>,
+    block_number: Option<u64>,
+    timestamp: Option<u64>,
+    expectations: Vec<Expectation>,
+}
+
+/// A reproducible sequence of deployments and calls with assertions, run against a `World`.
+///
+/// Modifiers like `value` or `expect_return` apply to the most recently added step.
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, action: Action) -> Self {
+        self.steps.push(Step {
+            action,
+            value: None,
+            gas_limit: None,
+            block_number: None,
+            timestamp: None,
+            expectations: vec![],
+        });
+        self
+    }
+
+    fn last(mut self, f: impl FnOnce(&mut Step)) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            f(step);
+        }
+        self
+    }
+
+    pub fn deploy(self, label: &str, deployer: Address, contract: Contract) -> Self {
+        self.push(Action::Deploy { label: label.to_string(), deployer, contract: Box::new(contract) })
+    }
+
+    /// Calls `signature` (e.g. `"flip()"`) on the contract deployed as `target`.
+    pub fn call(self, from: Address, target: &str, signature: &str) -> Self {
+        self.push(Action::Call { from, target: target.to_string(), signature: signature.to_string() })
+    }
+
+    pub fn value(self, value: U256) -> Self {
+        self.last(|step| step.value = Some(value))
+    }
+
+    pub fn gas_limit(self, gas_limit: u64) -> Self {
+        self.last(|step| step.gas_limit = Some(gas_limit))
+    }
+
+    pub fn block_number(self, block_number: u64) -> Self {
+        self.last(|step| step.block_number = Some(block_number))
+    }
+
+    pub fn timestamp(self, timestamp: u64) -> Self {
+        self.last(|step| step.timestamp = Some(timestamp))
+    }
+
+    pub fn expect(self, expectation: Expectation) -> Self {
+        self.last(|step| step.expectations.push(expectation))
+    }
+
+    pub fn expect_success(self) -> Self {
+        self.expect(Expectation::Success)
+    }
+
+    pub fn expect_failure(self) -> Self {
+        self.expect(Expectation::Failure)
+    }
+
+    pub fn expect_return(self, output: Vec<Expression>) -> Self {
+        self.expect(Expectation::Return(output))
+    }
+
+    pub fn expect_storage(self, contract: &str, variable: &str, value: U256) -> Self {
+        self.expect(Expectation::Storage { contract: contract.to_string(), variable: variable.to_string(), value })
+    }
+
+    pub fn run(&self, world: &mut World) -> ScenarioReport {
+        let mut addresses: HashMap<String, Address> = HashMap::new();
+        let base_env = world.env.clone();
+
+        let steps = self.steps.iter().map(|step| {
+            world.env = Env {
+                block_number: step.block_number.unwrap_or(base_env.block_number),
+                timestamp: step.timestamp.unwrap_or(base_env.timestamp),
+                ..base_env.clone()
+            };
+
+            let (description, result) = match &step.action {
+                Action::Deploy { label, deployer, contract } => {
+                    let address = world.deploy(*deployer, (**contract).clone());
+                    addresses.insert(label.clone(), address);
+                    (format!("deploy {} at {}", label, address), CallResult::success(vec![]))
+                },
+                Action::Call { from, target, signature } => {
+                    let description = format!("{}.{}", target, signature);
+                    match addresses.get(target) {
+                        Some(to) => {
+                            let tx = Transaction {
+                                value: step.value.unwrap_or_default(),
+                                gas_limit: step.gas_limit.unwrap_or(base_env.gas_limit),
+                                ..Transaction::new(*from, *to, &calldata(signature, &[]))
+                            };
+                            (description, world.apply_transaction(&tx))
+                        },
+                        None => {
+                            let failure = format!("unknown contract label {}", target);
+                            return StepReport { description, success: false, gas_used: 0, output: vec![], failures: vec![failure] };
+                        }
+                    }
+                },
+            };
+
+            let failures = step.expectations.iter()
+                .filter_map(|expectation| check(expectation, &result, world, &addresses))
+                .collect();
+            StepReport {
+                description,
+                success: result.success,
+                gas_used: result.gas_used,
+                output: result.output,
+                failures,
+            }
+        }).collect();
+
+        world.env = base_env;
+        ScenarioReport { steps }
+    }
+}
+
+fn check(expectation: &Expectation, result: &CallResult, world: &World, addresses: &HashMap<String, Address>) -> Option<String> {
+    match expectation {
+        Expectation::Success => (!result.success).then(|| "expected success, call failed".to_string()),
+        Expectation::Failure => result.success.then(|| "expected failure, call succeeded".to_string()),
+        Expectation::Return(expected) => (result.output != *expected)
+            .then(|| format!("expected return {:?}, got {:?}", expected, result.output)),
+        Expectation::Storage { contract, variable, value } => {
+            let account = addresses.get(contract).and_then(|address| world.account(address));
+            let contract_state = account.as_deref().and_then(|account| account.contract.as_ref());
+            let actual = contract_state.and_then(|c| {
+                c.variable_map.get(variable).map(|slot| c.storage.slots().get(*slot).copied().unwrap_or_default())
+            });
+            match actual {
+                Some(actual) if actual == *value => None,
+                Some(actual) => Some(format!("expected {}.{} == {}, got {}", contract, variable, value, actual)),
+                None => Some(format!("no storage variable {}.{}", contract, variable)),
+            }
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepReport {
+    pub description: String,
+    pub success: bool,
+    pub gas_used: u64,
+    pub output: Vec<Expression>,
+    /// Expectations that did not hold.
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.failures.is_empty())
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            let status = if step.failures.is_empty() { "ok" } else { "FAILED" };
+            writeln!(f, "{:>3}. {} ... {} (gas {})", i + 1, step.description, status, step.gas_used)?;
+            for failure in &step.failures {
+                writeln!(f, "       {}", failure)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_scenario_passes() {
+        let report = Scenario::new()
+            .deploy("flipper", alice(), flipper())
+            .call(bob(), "flipper", "flip()").block_number(10).expect_success()
+            .expect_storage("flipper", "value", U256::ONE)
+            .call(bob(), "flipper", "get()").expect_return(vec![Expression::BoolLiteral(true)])
+            .run(&mut World::new_dev());
+
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.steps.len(), 3);
+        assert!(report.steps[1].gas_used > 21000);
+    }
+
+    #[test]
+    fn test_scenario_reports_failures() {
+        let report = Scenario::new()
+            .deploy("flipper", alice(), flipper())
+            .call(bob(), "flipper", "flip()").gas_limit(21010).expect_success()
+            .call(bob(), "flipper", "get()").expect_return(vec![Expression::BoolLiteral(true)])
+            .call(bob(), "missing", "get()")
+            .run(&mut World::new_dev());
+
+        assert!(!report.passed());
+        assert_eq!(report.steps[1].failures, vec!["expected success, call failed".to_string()]);
+        assert_eq!(report.steps[2].failures.len(), 1);
+        assert_eq!(report.steps[3].failures, vec!["unknown contract label missing".to_string()]);
+        assert!(report.to_string().contains("FAILED"));
+    }
+}
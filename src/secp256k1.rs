@@ -0,0 +1,80 @@
+use ethnum::U256;
+use k256::ecdsa::{RecoveryId, Signature as EcdsaSignature, SigningKey, VerifyingKey};
+use keccak_hash::keccak;
+use crate::env::Address;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub r: U256,
+    pub s: U256,
+    /// Parity of the nonce point's y coordinate, 0 or 1.
+    pub recovery_id: u8,
+}
+
+/// `None` for zero and keys not below the group order.
+fn signing_key(private_key: U256) -> Option<SigningKey> {
+    SigningKey::from_slice(&private_key.to_be_bytes()).ok()
+}
+
+fn address_of(key: &VerifyingKey) -> Address {
+    // The uncompressed point without its 0x04 tag: x then y.
+    let point = key.to_encoded_point(false);
+    let hash = keccak(&point.as_bytes()[1..]);
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&hash[12..]);
+    Address(bytes)
+}
+
+/// The Ethereum address controlled by `private_key`.
+pub fn address_from_private_key(private_key: U256) -> Option<Address> {
+    signing_key(private_key).map(|key| address_of(key.verifying_key()))
+}
+
+/// Signs a 32-byte message hash with an RFC 6979 deterministic nonce and low-s normalization.
+pub fn sign(hash: &[u8; 32], private_key: U256) -> Option<Signature> {
+    let (signature, recovery_id) = signing_key(private_key)?.sign_prehash_recoverable(hash).ok()?;
+    let (r, s) = signature.split_bytes();
+    Some(Signature {
+        r: U256::from_be_bytes(r.into()),
+        s: U256::from_be_bytes(s.into()),
+        recovery_id: recovery_id.to_byte(),
+    })
+}
+
+/// Recovers the signer's address, like the ECRECOVER precompile.
+pub fn recover(hash: &[u8; 32], signature: &Signature) -> Option<Address> {
+    let ecdsa = EcdsaSignature::from_scalars(signature.r.to_be_bytes(), signature.s.to_be_bytes()).ok()?;
+    let recovery_id = RecoveryId::from_byte(signature.recovery_id)?;
+    VerifyingKey::recover_from_prehash(hash, &ecdsa, recovery_id).ok().map(|key| address_of(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Group order.
+    const N: U256 = U256::from_words(
+        0xFFFFFFFF_FFFFFFFF_FFFFFFFF_FFFFFFFE,
+        0xBAAEDCE6_AF48A03B_BFD25E8C_D0364141,
+    );
+
+    #[test]
+    fn test_address_from_private_key() {
+        // First default anvil/hardhat account.
+        let key = U256::from_str_radix("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80", 16).unwrap();
+        assert_eq!(format!("{}", address_from_private_key(key).unwrap()), "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        assert_eq!(address_from_private_key(U256::ZERO), None);
+        assert_eq!(address_from_private_key(N), None);
+    }
+
+    #[test]
+    fn test_sign_and_recover() {
+        let key = U256::from(0x4646u16);
+        let hash: [u8; 32] = keccak(b"tinysol").into();
+        let signature = sign(&hash, key).unwrap();
+        assert!(signature.s <= N >> 1);
+        assert_eq!(sign(&hash, key), Some(signature));
+        assert_eq!(recover(&hash, &signature), address_from_private_key(key));
+        assert_ne!(recover(&hash, &Signature { recovery_id: signature.recovery_id ^ 1, ..signature }), address_from_private_key(key));
+    }
+}
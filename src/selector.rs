@@ -0,0 +1,33 @@
+//! Function selectors and event topics from canonical signatures, such as
+//! `transfer(address,uint256)`. The `selector!` and `event_topic!` macros of
+//! `tinysol-macros` compute the same values at compile time.
+
+use ethnum::U256;
+use keccak_hash::keccak;
+
+/// The first four bytes of the keccak256 hash of `signature`, which calldata for the
+/// function starts with.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// The keccak256 hash of `signature`, the first topic of the event's non-anonymous logs.
+pub fn event_topic(signature: &str) -> U256 {
+    U256::from_be_bytes(keccak(signature.as_bytes()).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(selector("transfer(address,uint256)"), [0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(
+            event_topic("Transfer(address,address,uint256)"),
+            U256::from_str_radix("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef", 16).unwrap(),
+        );
+        assert_eq!(event_topic("Transfer(address,address,uint256)").to_be_bytes()[..4], selector("Transfer(address,address,uint256)"));
+    }
+}
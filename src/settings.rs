@@ -0,0 +1,98 @@
+use std::str::FromStr;
+
+/// How much source information compiled functions carry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugInfo {
+    /// No source map, so traces and the debugger can't point at source.
+    None,
+    /// Every instruction maps to the statement it was compiled from.
+    #[default]
+    Statements,
+}
+
+impl FromStr for DebugInfo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DebugInfo::None),
+            "statements" => Ok(DebugInfo::Statements),
+            other => Err(format!("unknown debug info level `{}`, expected `none` or `statements`", other)),
+        }
+    }
+}
+
+/// Individual optimizer passes, each applied only while the optimizer is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Passes {
+    /// Replaces calls to small internal functions with their bodies.
+    pub inline: bool,
+    pub fold_constants: bool,
+    pub eliminate_redundant_loads: bool,
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self { inline: true, fold_constants: true, eliminate_redundant_loads: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompilerSettings {
+    /// When off, the IR is emitted exactly as lowered from the source.
+    pub optimizer: bool,
+    /// How often each function is expected to run over the contract's lifetime. Higher
+    /// values favour cheaper execution over smaller code, as with solc's `--optimize-runs`.
+    pub runs: u32,
+    pub passes: Passes,
+    /// Largest callee, in live IR instructions, that is inlined at the default 200 runs.
+    pub inline_size: usize,
+    pub debug_info: DebugInfo,
+    /// Appends a metadata trailer with the compiler version and source hash to runtime code.
+    pub metadata: bool,
+}
+
+impl Default for CompilerSettings {
+    fn default() -> Self {
+        Self {
+            optimizer: true,
+            runs: 200,
+            passes: Passes::default(),
+            inline_size: 32,
+            debug_info: DebugInfo::default(),
+            metadata: false,
+        }
+    }
+}
+
+impl CompilerSettings {
+    /// Settings that skip every optimizer pass.
+    pub fn unoptimized() -> Self {
+        Self { optimizer: false, ..Self::default() }
+    }
+
+    /// Whether `pass` runs under these settings.
+    pub fn enabled(&self, pass: impl Fn(&Passes) -> bool) -> bool {
+        self.optimizer && pass(&self.passes)
+    }
+
+    /// `inline_size` scaled by `runs`, so tuning for deployment size inlines less.
+    pub fn inline_threshold(&self) -> usize {
+        (self.inline_size as u64 * self.runs as u64 / 200).max(1) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_threshold_scales_with_runs() {
+        let settings = CompilerSettings::default();
+        assert_eq!(settings.inline_threshold(), 32);
+        assert_eq!(CompilerSettings { runs: 1, ..settings }.inline_threshold(), 1);
+        assert_eq!(CompilerSettings { runs: 1000, ..settings }.inline_threshold(), 160);
+        assert!(!CompilerSettings::unoptimized().enabled(|p| p.inline));
+        assert_eq!("none".parse(), Ok(DebugInfo::None));
+    }
+}
@@ -1,35 +1,69 @@
+use rust_sitter::tree_sitter;
+use serde::{Deserialize, Serialize};
+
+/// A parsed node together with its byte span in the source. Stands in for
+/// `rust_sitter::Spanned`, which can't be compared, so the AST can keep deriving `PartialEq`.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: (usize, usize),
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: rust_sitter::Extract<U>, U> rust_sitter::Extract<Spanned<U>> for Spanned<T> {
+    type LeafFn = T::LeafFn;
+
+    fn extract(node: Option<tree_sitter::Node>, source: &[u8], last_idx: usize, leaf_fn: Option<&Self::LeafFn>) -> Spanned<U> {
+        Spanned {
+            value: T::extract(node, source, last_idx, leaf_fn),
+            span: node.map(|n| (n.start_byte(), n.end_byte())).unwrap_or((last_idx, last_idx)),
+        }
+    }
+}
+
+/// The AST serializes with serde, e.g. to JSON for outside tools. Fixed tokens such as
+/// keywords and punctuation are left out and come back as `()`.
 #[rust_sitter::grammar("solidity")]
 pub mod grammar {
+    pub use super::Spanned;
+    use serde::{Deserialize, Serialize};
 
     #[rust_sitter::language]
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub struct SourceUnit{
         pub parts: Vec<SourceUnitPart>
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum SourceUnitPart {
         ContractDefinition(
-            #[rust_sitter::leaf(text = "contract")] (),
-            #[rust_sitter::leaf(pattern = r"[a-zA-Z_][a-zA-Z0-9_]*", transform = |s| s.to_string())] String,
-            #[rust_sitter::leaf(text = "{")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "contract")] (),
+            Identifier,
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
             Vec<ContractPart>,
-            #[rust_sitter::leaf(text = "}")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
         )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum ContractPart {
         VariableDefinition(
             Type,
             Option<Visibility>,
-            #[rust_sitter::leaf(pattern = r"[a-zA-Z_][a-zA-Z0-9_]*", transform = |s| s.to_string())] String,
+            Identifier,
             //TODO: Add initializer expression
-            #[rust_sitter::leaf(text = ";")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
         ),
         FunctionDefinition(
-            #[rust_sitter::leaf(text = "function")] (),
-            #[rust_sitter::leaf(pattern = r"[a-zA-Z_][a-zA-Z0-9_]*", transform = |s| s.to_string())] String,
+            #[serde(skip)] #[rust_sitter::leaf(text = "function")] (),
+            Identifier,
 
             ParameterList,
 
@@ -41,12 +75,12 @@ pub mod grammar {
             
             Option<FunctionReturnParams>,
             
-            #[rust_sitter::leaf(text = "{")] (),
-            Option<Statement>,
-            #[rust_sitter::leaf(text = "}")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            Vec<Spanned<Statement>>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
         ),
         ConstructorDefinition(
-            #[rust_sitter::leaf(text = "constructor")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "constructor")] (),
 
             ParameterList,
 
@@ -56,35 +90,200 @@ pub mod grammar {
             )]
             Vec<Option<FunctionAttribute>>,
             
-            #[rust_sitter::leaf(text = "{")] (),
-            Option<Statement>,
-            #[rust_sitter::leaf(text = "}")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            Vec<Spanned<Statement>>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
+        ),
+        ReceiveDefinition(
+            #[serde(skip)] #[rust_sitter::leaf(text = "receive")] (),
+
+            ParameterList,
+
+            #[rust_sitter::repeat(non_empty = true)]
+            #[rust_sitter::delimited(
+                #[rust_sitter::leaf(text = " ")] ()
+            )]
+            Vec<Option<FunctionAttribute>>,
+
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            Vec<Spanned<Statement>>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
+        ),
+        FallbackDefinition(
+            #[serde(skip)] #[rust_sitter::leaf(text = "fallback")] (),
+
+            ParameterList,
+
+            #[rust_sitter::repeat(non_empty = true)]
+            #[rust_sitter::delimited(
+                #[rust_sitter::leaf(text = " ")] ()
+            )]
+            Vec<Option<FunctionAttribute>>,
+
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            Vec<Spanned<Statement>>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
+        ),
+        ErrorDefinition(
+            #[serde(skip)] #[rust_sitter::leaf(text = "error")] (),
+            Identifier,
+            ParameterList,
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
+        ),
+        EventDefinition(
+            #[serde(skip)] #[rust_sitter::leaf(text = "event")] (),
+            Identifier,
+            EventParameterList,
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
         )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub enum EventParameterList {
+        Params(
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Option<EventParams>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] ()
+        )
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct EventParams {
+        #[rust_sitter::repeat(non_empty = true)]
+        #[rust_sitter::delimited(
+            #[rust_sitter::leaf(text = ",")] ()
+        )]
+        pub params: Vec<EventParameter>
+    }
+
+    /// A field of an event. Indexed fields are logged as topics, the rest as data.
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct EventParameter {
+        pub ty: Type,
+        pub indexed: Option<Indexed>,
+        pub name: Option<Identifier>
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct Indexed {
+        #[rust_sitter::leaf(text = "indexed")]
+        _indexed: (),
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum Statement {
         Expression(
             Expression,
-            #[rust_sitter::leaf(text = ";")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
         ),
         Return(
-            #[rust_sitter::leaf(text = "return")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "return")] (),
             Option<Expression>,
-            #[rust_sitter::leaf(text = ";")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
+        ),
+        /// An `else` belongs to the nearest `if`.
+        #[rust_sitter::prec_right(1)]
+        If(
+            #[serde(skip)] #[rust_sitter::leaf(text = "if")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Expression,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] (),
+            Box<Spanned<Statement>>,
+            Option<Else>,
+        ),
+        Block(
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            Vec<Spanned<Statement>>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] (),
+        ),
+        Emit(
+            #[serde(skip)] #[rust_sitter::leaf(text = "emit")] (),
+            Identifier,
+            Arguments,
+            #[serde(skip)] #[rust_sitter::leaf(text = ";")] (),
+        )
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub enum Else {
+        Else(
+            #[serde(skip)] #[rust_sitter::leaf(text = "else")] (),
+            Box<Spanned<Statement>>,
+        )
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub enum Arguments {
+        Args(
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Option<ArgumentList>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] ()
+        )
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct ArgumentList {
+        #[rust_sitter::repeat(non_empty = true)]
+        #[rust_sitter::delimited(
+            #[rust_sitter::leaf(text = ",")] ()
+        )]
+        pub args: Vec<Expression>
+    }
+
+    /// The `{value: amount, gas: limit}` of an external call.
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub enum CallOptions {
+        Options(
+            #[serde(skip)] #[rust_sitter::leaf(text = "{")] (),
+            CallOptionList,
+            #[serde(skip)] #[rust_sitter::leaf(text = "}")] ()
         )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct CallOptionList {
+        #[rust_sitter::repeat(non_empty = true)]
+        #[rust_sitter::delimited(
+            #[rust_sitter::leaf(text = ",")] ()
+        )]
+        pub options: Vec<CallOption>
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub enum CallOption {
+        Named(
+            Identifier,
+            #[serde(skip)] #[rust_sitter::leaf(text = ":")] (),
+            Expression,
+        )
+    }
+
+    impl CallOptions {
+        pub fn options(&self) -> &[CallOption] {
+            let CallOptions::Options(_, list, _) = self;
+            &list.options
+        }
+    }
+
+    impl Arguments {
+        pub fn args(&self) -> &[Expression] {
+            match self {
+                Arguments::Args(_, Some(list), _) => &list.args,
+                Arguments::Args(_, None, _) => &[],
+            }
+        }
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum ParameterList {
         Params(
-            #[rust_sitter::leaf(text = "(")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
             Option<Params>,
-            #[rust_sitter::leaf(text = ")")] ()
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] ()
         )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub struct Params {
         #[rust_sitter::repeat(non_empty = true)]
         #[rust_sitter::delimited(
@@ -93,54 +292,242 @@ pub mod grammar {
         pub params: Vec<Parameter>
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum FunctionReturnParams {
         ParameterList(
-            #[rust_sitter::leaf(text = "returns")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "returns")] (),
             ParameterList,
         )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub struct Parameter {
         pub ty: Expression,
         //TODO: add storage
-        pub name: Option<Identifier>
+        pub name: Option<Spanned<Identifier>>
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
-    pub struct Identifier {
-        #[rust_sitter::leaf(pattern = r"[a-zA-Z_][a-zA-Z0-9_]*", transform = |s| s.to_string())]
-        pub name: String
+    /// The `target.member` that `Expression::Member` and `Expression::MemberCall` both
+    /// start with, so the parser needn't pick one before it sees what follows.
+    #[rust_sitter::prec_left(13)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct MemberAccess {
+        pub target: Box<Expression>,
+        #[serde(skip)]
+        #[rust_sitter::leaf(text = ".")]
+        pub dot: (),
+        pub member: Identifier
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum Expression {
         BoolLiteral(
             #[rust_sitter::leaf(pattern = r"true|false", transform = |v| v.parse::<bool>().unwrap())]
             bool,
         ),
+        NumberLiteral(
+            #[rust_sitter::leaf(pattern = r"\d+", transform = |v| v.parse::<ethnum::U256>().unwrap())]
+            ethnum::U256,
+        ),
+        StringLiteral(
+            #[rust_sitter::leaf(pattern = r#""([^"\\]|\\.)*""#, transform = |s| s[1..s.len() - 1].to_string())]
+            String,
+        ),
         Variable(Identifier),
+        /// A member of a built-in global, e.g. `msg.sender` or `block.prevrandao`, or
+        /// `block.difficulty`, its name before the merge.
+        Global(
+            #[rust_sitter::leaf(pattern = r"block\.(prevrandao|difficulty)|msg\.(sender|value)|tx\.origin", transform = |s| s.to_string())]
+            String,
+        ),
+        #[rust_sitter::prec(13)]
+        Paren(
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] (),
+        ),
+        #[rust_sitter::prec(13)]
+        Call(
+            Identifier,
+            Arguments,
+        ),
+        /// A member of a value, e.g. `owner.balance`.
+        Member(MemberAccess),
+        /// A call through a member, e.g. `token.transfer(to, amount)`, or a low-level call
+        /// such as `to.call{value: amount}("")`.
+        MemberCall(
+            MemberAccess,
+            Option<CallOptions>,
+            Arguments,
+        ),
+        /// A type conversion, e.g. `address(0)`.
+        #[rust_sitter::prec(13)]
+        Cast(
+            Type,
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] (),
+        ),
+        #[rust_sitter::prec_left(13)]
+        Index(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "[")] (),
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "]")] (),
+        ),
         #[rust_sitter::prec_right(1)]
         Assign(
             Box<Expression>,
-            #[rust_sitter::leaf(text = "=")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "=")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(2)]
+        Or(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "||")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(3)]
+        And(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "&&")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Lt(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "<")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Gt(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ">")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Le(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "<=")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Ge(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ">=")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Eq(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "==")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(4)]
+        Ne(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "!=")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(5)]
+        BitOr(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "|")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(6)]
+        BitXor(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "^")] (),
             Box<Expression>,
         ),
+        #[rust_sitter::prec_left(7)]
+        BitAnd(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "&")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(8)]
+        Shl(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "<<")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(8)]
+        Shr(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ">>")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(9)]
+        Add(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "+")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(9)]
+        Sub(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "-")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(10)]
+        Mul(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "*")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(10)]
+        Div(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "/")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_left(10)]
+        Mod(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "%")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec_right(12)]
+        Exp(
+            Box<Expression>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "**")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec(11)]
         Not(
-            #[rust_sitter::leaf(text = "!")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "!")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec(11)]
+        Neg(
+            #[serde(skip)] #[rust_sitter::leaf(text = "-")] (),
+            Box<Expression>,
+        ),
+        #[rust_sitter::prec(11)]
+        BitNot(
+            #[serde(skip)] #[rust_sitter::leaf(text = "~")] (),
             Box<Expression>,
         ),
         Type(Type)
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    /// Declared after `Expression`: of two patterns matching the same text, tree-sitter
+    /// prefers the one that comes first, so `true` and `false` lex as `BoolLiteral`.
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+    pub struct Identifier {
+        #[rust_sitter::leaf(pattern = r"[a-zA-Z_][a-zA-Z0-9_]*", transform = |s| s.to_string())]
+        pub name: String
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum FunctionAttribute {
         Visibility(Visibility),
         Mutability(Mutability)
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum Mutability {
         Pure(#[rust_sitter::leaf(text = "pure")] ()),
         View(#[rust_sitter::leaf(text = "view")] ()),
@@ -148,12 +535,23 @@ pub mod grammar {
         Payable(#[rust_sitter::leaf(text = "payable")] ())
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum Type {
-        Bool(#[rust_sitter::leaf(text = "bool")] ())
+        Bool(#[rust_sitter::leaf(text = "bool")] ()),
+        Uint256(#[rust_sitter::leaf(text = "uint256")] ()),
+        Int256(#[rust_sitter::leaf(text = "int256")] ()),
+        Address(#[rust_sitter::leaf(text = "address")] ()),
+        Mapping(
+            #[serde(skip)] #[rust_sitter::leaf(text = "mapping")] (),
+            #[serde(skip)] #[rust_sitter::leaf(text = "(")] (),
+            Box<Type>,
+            #[serde(skip)] #[rust_sitter::leaf(text = "=>")] (),
+            Box<Type>,
+            #[serde(skip)] #[rust_sitter::leaf(text = ")")] (),
+        )
     }
 
-    #[derive(PartialEq, Eq, Debug, Clone)]
+    #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
     pub enum Visibility {
         Internal(#[rust_sitter::leaf(text = "internal")] ()),
         External(#[rust_sitter::leaf(text = "external")] ()), //External keyword only applies to functions
@@ -164,20 +562,56 @@ pub mod grammar {
     // ------------- Extras -------------
 
     #[rust_sitter::extra]
+    #[allow(dead_code)]
     struct Whitespace {
         #[rust_sitter::leaf(pattern = r"\s")]
         _whitespace: (),
     }
 
     #[rust_sitter::extra]
+    #[allow(dead_code)]
     struct Newline {
         #[rust_sitter::leaf(pattern = r"\n")]
         _new_line: (),
     }
 
     #[rust_sitter::extra]
+    #[allow(dead_code)]
     struct SingleLineComment {
         #[rust_sitter::leaf(pattern = r"//.*")]
         _comment: (),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::grammar::*;
+
+    #[test]
+    fn test_ast_json_round_trip() {
+        let source = "contract Counter {
+    uint256 private count;
+    function bump(uint256 by) public returns (bool) { return count + by > 10; }
+}";
+        let unit = parse(source).unwrap();
+        let json = serde_json::to_value(&unit).unwrap();
+        assert_eq!(json["parts"][0]["ContractDefinition"][0]["name"], "Counter");
+        assert_eq!(serde_json::from_value::<SourceUnit>(json).unwrap(), unit);
+    }
+
+    #[test]
+    fn test_member_access_and_calls() {
+        let source = r#"contract Payout {
+    function pay(address to) public { to.call{value: to.balance}(""); }
+}"#;
+        let SourceUnitPart::ContractDefinition(_, _, _, parts, _) = parse(source).unwrap().parts.remove(0);
+        let ContractPart::FunctionDefinition(_, _, _, _, _, _, body, _) = &parts[0] else { panic!("no function") };
+        let Statement::Expression(Expression::MemberCall(MemberAccess { target, member, .. }, Some(options), args), _) = &body[0].value else { panic!("no call") };
+        let variable = |name: &str| Expression::Variable(Identifier { name: name.to_string() });
+        assert_eq!((&**target, member.name.as_str()), (&variable("to"), "call"));
+        let CallOption::Named(option, _, value) = &options.options()[0];
+        assert_eq!(option.name, "value");
+        assert_eq!(value, &Expression::Member(MemberAccess { target: Box::new(variable("to")), dot: (), member: Identifier { name: "balance".to_string() } }));
+        assert_eq!(args.args(), [Expression::StringLiteral(String::new())]);
+    }
+}
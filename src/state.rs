@@ -0,0 +1,406 @@
+use ethnum::U256;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use serde_json::json;
+use crate::bytecode::from_hex;
+use crate::env::*;
+use crate::executor::{Executor, Storage};
+use crate::rpc::{RpcClient, RpcError};
+use crate::tinyvm::*;
+use crate::world::World;
+
+/// Account state a contract runs against, independent of where it's kept.
+pub trait StateDB: fmt::Debug + Send + Sync {
+    fn slot(&self, address: &Address, key: U256) -> U256;
+    fn set_slot(&mut self, address: Address, key: U256, value: U256);
+    /// Runtime bytecode, empty for accounts without code.
+    fn code(&self, address: &Address) -> Vec<u8>;
+    fn set_code(&mut self, address: Address, code: Vec<u8>);
+    fn balance(&self, address: &Address) -> U256;
+    fn set_balance(&mut self, address: Address, balance: U256);
+    fn nonce(&self, address: &Address) -> u64;
+    fn set_nonce(&mut self, address: Address, nonce: u64);
+}
+
+/// The slots of one account in a `StateDB`, as `Storage`.
+pub struct AccountStorage<'a> {
+    pub db: &'a mut dyn StateDB,
+    pub address: Address,
+}
+
+impl Storage for AccountStorage<'_> {
+    fn load(&self, key: U256) -> U256 {
+        self.db.slot(&self.address, key)
+    }
+
+    fn store(&mut self, key: U256, value: U256) {
+        self.db.set_slot(self.address, key, value);
+    }
+}
+
+impl Contract {
+    /// Like `execute_with`, running on the storage of the account at `address` in `db`
+    /// rather than `self.storage`. Writes reach `db` unless the call halts.
+    pub fn execute_on(&self, db: &mut dyn StateDB, address: Address, executor: &dyn Executor, calldata: &str, env: Env, tx_state: &mut TxState) -> Execution {
+        self.execute_on_storage(executor, calldata, env, &mut AccountStorage { db, address }, &mut NoopInspector, tx_state)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbAccount {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Vec<u8>,
+    pub slots: HashMap<U256, U256>,
+}
+
+/// A `StateDB` held in memory. Unset slots read as zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryDB {
+    pub accounts: HashMap<Address, DbAccount>,
+}
+
+impl StateDB for MemoryDB {
+    fn slot(&self, address: &Address, key: U256) -> U256 {
+        self.accounts.get(address).and_then(|account| account.slots.get(&key)).copied().unwrap_or_default()
+    }
+
+    fn set_slot(&mut self, address: Address, key: U256, value: U256) {
+        self.accounts.entry(address).or_default().slots.insert(key, value);
+    }
+
+    fn code(&self, address: &Address) -> Vec<u8> {
+        self.accounts.get(address).map(|account| account.code.clone()).unwrap_or_default()
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.accounts.entry(address).or_default().code = code;
+    }
+
+    fn balance(&self, address: &Address) -> U256 {
+        self.accounts.get(address).map(|account| account.balance).unwrap_or_default()
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.accounts.entry(address).or_default().balance = balance;
+    }
+
+    fn nonce(&self, address: &Address) -> u64 {
+        self.accounts.get(address).map(|account| account.nonce).unwrap_or_default()
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) {
+        self.accounts.entry(address).or_default().nonce = nonce;
+    }
+}
+
+/// A snapshot of the accounts `world` holds, as `World::save` writes them.
+impl From<&World> for MemoryDB {
+    fn from(world: &World) -> Self {
+        let mut db = Self::default();
+        world.save(&mut db);
+        db
+    }
+}
+
+fn word(hex: &str) -> Option<U256> {
+    U256::from_str_radix(hex.trim_start_matches("0x"), 16).ok()
+}
+
+/// State kept on disk in a sled database, so it outlives the process. Each value is
+/// stored under the account's address followed by a tag byte: `b` for the balance, `n`
+/// for the nonce, `c` for the runtime code, and `s` plus the 32-byte key for a storage slot. Reads that fail
+/// come back as zero or empty code, like unset ones.
+#[cfg(feature = "persistent")]
+#[derive(Debug)]
+pub struct FileDB {
+    db: sled::Db,
+}
+
+#[cfg(feature = "persistent")]
+impl FileDB {
+    /// Opens the database at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        // No background flusher, so the file lock is released as soon as this is dropped.
+        Ok(Self { db: sled::Config::new().path(path).flush_every_ms(None).open()? })
+    }
+
+    /// Writes everything set so far to disk, which also happens when dropped.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn key(address: &Address, tag: u8, slot: Option<U256>) -> Vec<u8> {
+        let mut key = address.0.to_vec();
+        key.push(tag);
+        if let Some(slot) = slot {
+            key.extend_from_slice(&slot.to_be_bytes());
+        }
+        key
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<sled::IVec> {
+        self.db.get(key).ok().flatten()
+    }
+
+    fn word(&self, key: Vec<u8>) -> U256 {
+        self.get(key).and_then(|value| Some(U256::from_be_bytes(value.as_ref().try_into().ok()?))).unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl StateDB for FileDB {
+    fn slot(&self, address: &Address, key: U256) -> U256 {
+        self.word(Self::key(address, b's', Some(key)))
+    }
+
+    fn set_slot(&mut self, address: Address, key: U256, value: U256) {
+        let key = Self::key(&address, b's', Some(key));
+        let _ = match value {
+            U256::ZERO => self.db.remove(key),
+            value => self.db.insert(key, &value.to_be_bytes()[..]),
+        };
+    }
+
+    fn code(&self, address: &Address) -> Vec<u8> {
+        self.get(Self::key(address, b'c', None)).map(|code| code.to_vec()).unwrap_or_default()
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        let _ = self.db.insert(Self::key(&address, b'c', None), code);
+    }
+
+    fn balance(&self, address: &Address) -> U256 {
+        self.word(Self::key(address, b'b', None))
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        let _ = self.db.insert(Self::key(&address, b'b', None), &balance.to_be_bytes()[..]);
+    }
+
+    fn nonce(&self, address: &Address) -> u64 {
+        self.get(Self::key(address, b'n', None)).and_then(|nonce| Some(u64::from_be_bytes(nonce.as_ref().try_into().ok()?))).unwrap_or_default()
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) {
+        let _ = self.db.insert(Self::key(&address, b'n', None), &nonce.to_be_bytes()[..]);
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl Drop for FileDB {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// State forked from a node at a fixed block: reads go to the node over JSON-RPC the first
+/// time and are cached, writes stay local. A failed request reads as zero or empty code
+/// and is kept for `error`, since `StateDB` reads can't fail.
+#[derive(Debug)]
+pub struct ForkDB {
+    client: RpcClient,
+    /// Block tag or hex number the fork is taken at, e.g. `"latest"`.
+    block: String,
+    local: MemoryDB,
+    cache: Mutex<MemoryDB>,
+    error: Mutex<Option<RpcError>>,
+}
+
+impl ForkDB {
+    pub fn new(client: RpcClient, block: &str) -> Self {
+        Self {
+            client,
+            block: block.to_string(),
+            local: MemoryDB::default(),
+            cache: Mutex::new(MemoryDB::default()),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// The first request that failed, if any.
+    pub fn error(&self) -> Option<RpcError> {
+        self.error.lock().unwrap().clone()
+    }
+
+    fn fetch(&self, method: &str, params: serde_json::Value) -> Option<String> {
+        match self.client.request(method, params) {
+            Ok(result) => result.as_str().map(str::to_string),
+            Err(e) => {
+                self.error.lock().unwrap().get_or_insert(e);
+                None
+            }
+        }
+    }
+
+    /// Queries the node for `address` once, filling in the cached account.
+    fn account(&self, address: &Address) -> DbAccount {
+        if let Some(account) = self.cache.lock().unwrap().accounts.get(address) {
+            return account.clone();
+        }
+        let balance = self.fetch("eth_getBalance", json!([address.to_string(), self.block])).and_then(|hex| word(&hex));
+        let nonce = self.fetch("eth_getTransactionCount", json!([address.to_string(), self.block])).and_then(|hex| word(&hex));
+        let code = self.fetch("eth_getCode", json!([address.to_string(), self.block])).and_then(|hex| from_hex(&hex));
+        let account = DbAccount {
+            balance: balance.unwrap_or_default(),
+            nonce: nonce.map_or(0, |nonce| nonce.as_u64()),
+            code: code.unwrap_or_default(),
+            slots: HashMap::new(),
+        };
+        self.cache.lock().unwrap().accounts.insert(*address, account.clone());
+        account
+    }
+
+    /// The cached account at `address`, which local writes to its balance, nonce or code
+    /// change in place.
+    fn account_mut(&mut self, address: Address) -> &mut DbAccount {
+        self.account(&address);
+        self.cache.get_mut().unwrap().accounts.get_mut(&address).unwrap()
+    }
+}
+
+impl StateDB for ForkDB {
+    fn slot(&self, address: &Address, key: U256) -> U256 {
+        if let Some(value) = self.local.accounts.get(address).and_then(|account| account.slots.get(&key)) {
+            return *value;
+        }
+        if let Some(value) = self.cache.lock().unwrap().accounts.get(address).and_then(|account| account.slots.get(&key)) {
+            return *value;
+        }
+        let value = self.fetch("eth_getStorageAt", json!([address.to_string(), format!("{:#x}", key), self.block]))
+            .and_then(|hex| word(&hex))
+            .unwrap_or_default();
+        self.account(address);
+        self.cache.lock().unwrap().set_slot(*address, key, value);
+        value
+    }
+
+    fn set_slot(&mut self, address: Address, key: U256, value: U256) {
+        self.local.set_slot(address, key, value);
+    }
+
+    fn code(&self, address: &Address) -> Vec<u8> {
+        self.account(address).code
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.account_mut(address).code = code;
+    }
+
+    fn balance(&self, address: &Address) -> U256 {
+        self.account(address).balance
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.account_mut(address).balance = balance;
+    }
+
+    fn nonce(&self, address: &Address) -> u64 {
+        self.account(address).nonce
+    }
+
+    fn set_nonce(&mut self, address: Address, nonce: u64) {
+        self.account_mut(address).nonce = nonce;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::executor::Interpreter;
+    use crate::solidity::grammar::Expression;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_execute_on_memory_db() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract.clone());
+        let mut db = MemoryDB::from(&world);
+        assert_eq!(db.code(&address), contract.deployed_bytecode());
+
+        let flip = calldata("flip()", &[]);
+        let get = calldata("get()", &[]);
+        contract.execute_on(&mut db, address, &Interpreter, &flip, Env::default(), &mut TxState::default());
+        assert_eq!(db.slot(&address, U256::ZERO), U256::ONE);
+        let execution = contract.execute_on(&mut db, address, &Interpreter, &get, Env::default(), &mut TxState::default());
+        assert_eq!(execution.output, vec![Expression::BoolLiteral(true)]);
+    }
+
+    #[test]
+    fn test_fork_db_local_writes() {
+        // The node answers every request with a server error, so reads fail and come
+        // back as zero.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                // Read the whole request first, so closing doesn't reset the connection.
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+                while !request.ends_with(b"}") {
+                    match std::io::Read::read(&mut stream, &mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = std::io::Write::write_all(&mut stream, b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let mut db = ForkDB::new(RpcClient::new(&url).unwrap(), "latest");
+        db.set_slot(alice(), U256::ONE, U256::from(5u8));
+        assert_eq!(db.slot(&alice(), U256::ONE), U256::from(5u8));
+        assert!(db.error().is_none());
+        assert_eq!(db.slot(&alice(), U256::ZERO), U256::ZERO);
+        assert!(matches!(db.error(), Some(RpcError::Http(_))));
+    }
+
+    #[cfg(feature = "persistent")]
+    #[test]
+    fn test_file_db_round_trip() {
+        let path = std::env::temp_dir().join(format!("tinysol-state-{}", std::process::id()));
+        let mut db = FileDB::open(&path).unwrap();
+        db.set_slot(alice(), U256::from(3u8), U256::from(7u8));
+        db.set_slot(alice(), U256::MAX, U256::ONE);
+        db.set_code(alice(), vec![0x60, 0x00]);
+        db.set_balance(bob(), U256::from(9u8));
+        drop(db);
+
+        let db = FileDB::open(&path).unwrap();
+        assert_eq!(db.slot(&alice(), U256::from(3u8)), U256::from(7u8));
+        assert_eq!(db.slot(&alice(), U256::MAX), U256::ONE);
+        assert_eq!(db.slot(&bob(), U256::from(3u8)), U256::ZERO);
+        assert_eq!(db.code(&alice()), vec![0x60, 0x00]);
+        assert_eq!(db.balance(&bob()), U256::from(9u8));
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[cfg(feature = "persistent")]
+    #[test]
+    fn test_world_on_file_db() {
+        use crate::abi::{encode, Value};
+        let path = std::env::temp_dir().join(format!("tinysol-world-{}", std::process::id()));
+        let contract = flipper();
+        let (flip, get) = (calldata("flip()", &[]), calldata("get()", &[]));
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        assert!(world.call(alice(), address, &flip).success);
+        let mut db = FileDB::open(&path).unwrap();
+        world.save(&mut db);
+        drop(db);
+
+        let mut world = World::with_db(FileDB::open(&path).unwrap());
+        assert_eq!(world.account(&alice()).unwrap().nonce, 1);
+        assert_eq!(world.call(bob(), address, &get).return_data, encode(&[Value::Bool(true)]));
+        assert!(world.call(bob(), address, &flip).success);
+        assert_eq!(world.call(bob(), address, &get).return_data, encode(&[Value::Bool(false)]));
+        // The second flip stays in the world until it's saved.
+        assert_eq!(world.db.slot(&address, U256::ZERO), U256::ONE);
+        drop(world);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
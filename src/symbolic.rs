@@ -0,0 +1,413 @@
+use ethnum::U256;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::bytecode::code_offsets;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::tinyvm::*;
+
+/// Instructions the explorer executes across all paths before giving up.
+const STEP_BUDGET: usize = 10_000;
+
+/// An inclusive range of unsigned 256-bit values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: U256,
+    pub hi: U256,
+}
+
+impl Interval {
+    pub const FULL: Interval = Interval { lo: U256::ZERO, hi: U256::MAX };
+    const BOOL: Interval = Interval { lo: U256::ZERO, hi: U256::ONE };
+    const TRUE: Interval = Interval { lo: U256::ONE, hi: U256::MAX };
+
+    pub fn point(value: U256) -> Interval {
+        Interval { lo: value, hi: value }
+    }
+
+    fn as_point(&self) -> Option<U256> {
+        (self.lo == self.hi).then_some(self.lo)
+    }
+
+    fn boolean(value: bool) -> Interval {
+        Interval::point(U256::from(value as u8))
+    }
+
+    /// The values in both, `None` if there are none.
+    fn meet(&self, other: &Interval) -> Option<Interval> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        (lo <= hi).then_some(Interval { lo, hi })
+    }
+}
+
+/// A value as a function of the unknowns it was computed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Const(U256),
+    /// An unknown: a calldata word, a storage slot or anything else the code can't know.
+    Var(usize),
+    /// A pure instruction applied to its operands, top of the stack first.
+    Op(OP, Vec<Rc<Term>>),
+}
+
+fn is_pure(op: &OP) -> bool {
+    matches!(op, OP::ISZERO | OP::ADD | OP::SUB | OP::MUL | OP::DIV | OP::MOD | OP::ADDMOD | OP::MULMOD | OP::EXP
+        | OP::SDIV | OP::SMOD | OP::SIGNEXTEND | OP::LT | OP::GT | OP::SLT | OP::SGT | OP::EQ | OP::AND | OP::OR
+        | OP::XOR | OP::NOT | OP::BYTE | OP::SHL | OP::SHR | OP::SAR)
+}
+
+/// Runs a pure instruction on constant operands.
+fn fold(op: &OP, args: &[U256]) -> U256 {
+    let mut program = args.iter().rev().map(|arg| OP::PUSH32(*arg)).collect::<Vec<_>>();
+    program.push(op.clone());
+    let mut vm = VM::builder(program).build();
+    vm.run(ContractStorage::default());
+    vm.stack.peek(0).unwrap_or_default()
+}
+
+/// Every value up to the highest set bit of `value`.
+fn smear(value: U256) -> U256 {
+    match value.leading_zeros() {
+        0 => U256::MAX,
+        zeros => (U256::ONE << (256 - zeros)) - 1,
+    }
+}
+
+/// One path through the program, with what it has learned about its unknowns.
+#[derive(Debug, Clone)]
+struct State {
+    pc: usize,
+    stack: Vec<Rc<Term>>,
+    /// Range of each unknown on this path.
+    vars: Vec<Interval>,
+    /// Words at constant offsets, so a value read back is the one written.
+    memory: BTreeMap<usize, Rc<Term>>,
+    storage: BTreeMap<U256, Rc<Term>>,
+    calldata: BTreeMap<U256, Rc<Term>>,
+}
+
+impl State {
+    fn fresh(&mut self) -> Rc<Term> {
+        self.vars.push(Interval::FULL);
+        Rc::new(Term::Var(self.vars.len() - 1))
+    }
+
+    fn eval(&self, term: &Term) -> Interval {
+        match term {
+            Term::Const(value) => Interval::point(*value),
+            Term::Var(var) => self.vars[*var],
+            Term::Op(op, args) => self.eval_op(op, &args.iter().map(|arg| self.eval(arg)).collect::<Vec<_>>()),
+        }
+    }
+
+    fn eval_op(&self, op: &OP, args: &[Interval]) -> Interval {
+        if let Some(points) = args.iter().map(Interval::as_point).collect::<Option<Vec<_>>>() {
+            return Interval::point(fold(op, &points));
+        }
+        let (a, b) = (args[0], args.get(1).copied().unwrap_or(Interval::FULL));
+        let decide = |always: bool, never: bool| if always { Interval::boolean(true) } else if never { Interval::boolean(false) } else { Interval::BOOL };
+        match op {
+            OP::ADD => match a.hi.checked_add(b.hi) {
+                Some(hi) => Interval { lo: a.lo + b.lo, hi },
+                None => Interval::FULL,
+            },
+            OP::SUB if a.lo >= b.hi => Interval { lo: a.lo - b.hi, hi: a.hi - b.lo },
+            OP::MUL => match a.hi.checked_mul(b.hi) {
+                Some(hi) => Interval { lo: a.lo * b.lo, hi },
+                None => Interval::FULL,
+            },
+            OP::DIV if b.lo > U256::ZERO => Interval { lo: a.lo / b.hi, hi: a.hi / b.lo },
+            OP::DIV => Interval { lo: U256::ZERO, hi: a.hi },
+            OP::MOD if b.lo > U256::ZERO => Interval { lo: U256::ZERO, hi: a.hi.min(b.hi - 1) },
+            OP::MOD => Interval { lo: U256::ZERO, hi: a.hi },
+            OP::AND => Interval { lo: U256::ZERO, hi: a.hi.min(b.hi) },
+            OP::OR => Interval { lo: a.lo.max(b.lo), hi: smear(a.hi | b.hi) },
+            OP::SHR => match a.as_point() {
+                Some(shift) if shift < 256 => Interval { lo: b.lo >> shift.as_u32(), hi: b.hi >> shift.as_u32() },
+                _ => Interval { lo: U256::ZERO, hi: b.hi },
+            },
+            OP::LT => decide(a.hi < b.lo, a.lo >= b.hi),
+            OP::GT => decide(a.lo > b.hi, a.hi <= b.lo),
+            OP::EQ => decide(false, a.meet(&b).is_none()),
+            OP::ISZERO => decide(a.hi == U256::ZERO, a.lo > U256::ZERO),
+            OP::SLT | OP::SGT => Interval::BOOL,
+            _ => Interval::FULL,
+        }
+    }
+
+    /// Narrows the unknown behind `term` to `range`, or checks that `term` can lie in it.
+    fn constrain(&mut self, term: &Term, range: Interval) -> Option<()> {
+        match term {
+            Term::Var(var) => self.vars[*var] = self.vars[*var].meet(&range)?,
+            _ => { self.eval(term).meet(&range)?; },
+        }
+        Some(())
+    }
+
+    /// Narrows the unknowns so that `term` is nonzero, or zero if `truth` is false.
+    /// `None` if that's impossible on this path.
+    fn assume(&mut self, term: &Term, truth: bool) -> Option<()> {
+        match (term, truth) {
+            (Term::Op(OP::ISZERO, args), _) => self.assume(&args[0], !truth)?,
+            (Term::Op(OP::AND, args), true) | (Term::Op(OP::OR, args), false) => {
+                self.assume(&args[0], truth)?;
+                self.assume(&args[1], truth)?;
+            },
+            (Term::Op(op @ (OP::LT | OP::GT), args), _) => {
+                // Normalise to `lesser < greater`, or `greater <= lesser` when false.
+                let (lesser, greater) = if *op == OP::LT { (&args[0], &args[1]) } else { (&args[1], &args[0]) };
+                let (l, g) = (self.eval(lesser), self.eval(greater));
+                if truth {
+                    self.constrain(lesser, Interval { lo: U256::ZERO, hi: g.hi.checked_sub(U256::ONE)? })?;
+                    self.constrain(greater, Interval { lo: l.lo.checked_add(U256::ONE)?, hi: U256::MAX })?;
+                } else {
+                    self.constrain(lesser, Interval { lo: g.lo, hi: U256::MAX })?;
+                    self.constrain(greater, Interval { lo: U256::ZERO, hi: l.hi })?;
+                }
+            },
+            (Term::Op(OP::EQ, args), true) => {
+                let (a, b) = (self.eval(&args[0]), self.eval(&args[1]));
+                self.constrain(&args[0], b)?;
+                self.constrain(&args[1], a)?;
+            },
+            _ => self.constrain(term, if truth { Interval::TRUE } else { Interval::point(U256::ZERO) })?,
+        }
+        let range = self.eval(term);
+        range.meet(&if truth { Interval::TRUE } else { Interval::point(U256::ZERO) }).map(|_| ())
+    }
+}
+
+/// What the explorer found out about one conditional jump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub pc: usize,
+    pub span: Option<(usize, usize)>,
+    pub taken: bool,
+    pub not_taken: bool,
+}
+
+/// Whether a `REVERT` can be reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revert {
+    pub pc: usize,
+    pub span: Option<(usize, usize)>,
+    pub reachable: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolicReport {
+    pub branches: Vec<Branch>,
+    pub reverts: Vec<Revert>,
+    /// Whether every path was explored. Otherwise paths past the step budget, or through
+    /// computed jumps, were left out, and "unreachable" results can't be trusted.
+    pub complete: bool,
+}
+
+impl SymbolicReport {
+    /// Warnings for branches that are never taken and notes for reverts that can never
+    /// happen, such as a `require` that always holds. Empty unless exploration completed.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        if !self.complete {
+            return vec![];
+        }
+        let located = |diagnostic: Diagnostic, span: Option<(usize, usize)>| match span {
+            Some(span) => diagnostic.with_span(span),
+            None => diagnostic,
+        };
+        let branches = self.branches.iter().filter_map(|branch| {
+            let message = match (branch.taken, branch.not_taken) {
+                (false, false) => "condition is never evaluated",
+                (true, false) => "condition is always true",
+                (false, true) => "condition is always false",
+                (true, true) => return None,
+            };
+            Some(located(Diagnostic::warning("unreachable-branch", message), branch.span))
+        });
+        let reverts = self.reverts.iter().filter(|revert| !revert.reachable).map(|revert| {
+            located(Diagnostic::new(Severity::Note, "never-reverts", "this revert can never be reached"), revert.span)
+        });
+        branches.chain(reverts).collect()
+    }
+}
+
+/// Walks every path through `program` with symbolic values, tracking a range for each
+/// unknown input and narrowing it at every branch, to find which branches and reverts
+/// can be reached. Ranges over-approximate, so what's reported unreachable is, when the
+/// exploration completes, but "reachable" may not be.
+///
+/// Experimental: only ranges are tracked, not relations between unknowns.
+pub fn explore(program: &[OP]) -> SymbolicReport {
+    let offsets = code_offsets(program);
+    let jump_target = |dest: &Interval| {
+        let index = offsets.binary_search(&usize::try_from(dest.as_point()?).ok()?).ok()?;
+        (program[index] == OP::JUMPDEST).then_some(index)
+    };
+    let mut report = SymbolicReport {
+        branches: program.iter().enumerate().filter(|(_, op)| **op == OP::JUMPI)
+            .map(|(pc, _)| Branch { pc, span: None, taken: false, not_taken: false })
+            .collect(),
+        reverts: program.iter().enumerate().filter(|(_, op)| **op == OP::REVERT)
+            .map(|(pc, _)| Revert { pc, span: None, reachable: false })
+            .collect(),
+        complete: true,
+    };
+
+    let mut paths = vec![State { pc: 0, stack: vec![], vars: vec![], memory: BTreeMap::new(), storage: BTreeMap::new(), calldata: BTreeMap::new() }];
+    let mut steps = 0;
+    while let Some(mut state) = paths.pop() {
+        while let Some(op) = program.get(state.pc) {
+            steps += 1;
+            if steps > STEP_BUDGET {
+                report.complete = false;
+                return report;
+            }
+            let (pops, _) = op.stack_io();
+            if state.stack.len() < pops {
+                break;
+            }
+            let args = state.stack.split_off(state.stack.len() - pops).into_iter().rev().collect::<Vec<_>>();
+            let constant = |state: &State, i: usize| state.eval(&args[i]).as_point();
+            match op {
+                OP::PUSH0 | OP::PUSH1(_) | OP::PUSHN(..) | OP::PUSH32(_) => state.stack.extend(op.push_value().map(|value| Rc::new(Term::Const(value)))),
+                OP::PC => state.stack.push(Rc::new(Term::Const(U256::from(offsets[state.pc] as u64)))),
+                OP::POP | OP::JUMPDEST => {},
+                OP::DUP1 => state.stack.extend([args[0].clone(), args[0].clone()]),
+                OP::SWAP1 => state.stack.extend([args[0].clone(), args[1].clone()]),
+                OP::DUP(n) => {
+                    state.stack.extend(args.iter().rev().cloned());
+                    state.stack.push(args[*n as usize - 1].clone());
+                },
+                OP::SWAP(n) => {
+                    let mut items = args.clone();
+                    items.swap(0, *n as usize);
+                    state.stack.extend(items.into_iter().rev());
+                },
+                op if is_pure(op) => state.stack.push(Rc::new(Term::Op(op.clone(), args))),
+                OP::CALLDATALOAD | OP::SLOAD => {
+                    let value = match constant(&state, 0) {
+                        Some(key) if *op == OP::SLOAD => state.storage.get(&key).cloned(),
+                        Some(key) => state.calldata.get(&key).cloned(),
+                        None => None,
+                    };
+                    let value = value.unwrap_or_else(|| state.fresh());
+                    match constant(&state, 0) {
+                        Some(key) if *op == OP::SLOAD => { state.storage.insert(key, value.clone()); },
+                        Some(key) => { state.calldata.insert(key, value.clone()); },
+                        None => {},
+                    }
+                    state.stack.push(value);
+                },
+                OP::SSTORE => match constant(&state, 0) {
+                    Some(key) => { state.storage.insert(key, args[1].clone()); },
+                    None => state.storage.clear(),
+                },
+                OP::MLOAD => {
+                    let word = constant(&state, 0).and_then(|offset| state.memory.get(&usize::try_from(offset).ok()?).cloned());
+                    let word = word.unwrap_or_else(|| state.fresh());
+                    state.stack.push(word);
+                },
+                OP::MSTORE => match constant(&state, 0).and_then(|offset| usize::try_from(offset).ok()) {
+                    Some(offset) => {
+                        state.memory.retain(|at, _| at.abs_diff(offset) >= 32);
+                        state.memory.insert(offset, args[1].clone());
+                    },
+                    None => state.memory.clear(),
+                },
+                OP::MSTORE8 | OP::MCOPY | OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY | OP::EXTCODECOPY => state.memory.clear(),
+                OP::JUMP => match jump_target(&state.eval(&args[0])) {
+                    Some(target) => {
+                        state.pc = target;
+                        continue;
+                    },
+                    None => {
+                        report.complete &= state.eval(&args[0]).as_point().is_some();
+                        break;
+                    },
+                },
+                OP::JUMPI => {
+                    let Some(target) = jump_target(&state.eval(&args[0])) else {
+                        report.complete &= state.eval(&args[0]).as_point().is_some();
+                        break;
+                    };
+                    let branch = report.branches.iter_mut().find(|branch| branch.pc == state.pc).unwrap();
+                    let mut taken = state.clone();
+                    if taken.assume(&args[1], true).is_some() {
+                        branch.taken = true;
+                        taken.pc = target;
+                        paths.push(taken);
+                    }
+                    if state.assume(&args[1], false).is_none() {
+                        break;
+                    }
+                    branch.not_taken = true;
+                },
+                OP::REVERT => {
+                    report.reverts.iter_mut().find(|revert| revert.pc == state.pc).unwrap().reachable = true;
+                    break;
+                },
+                OP::RETURN | OP::SELFDESTRUCT | OP::STOP | OP::INVALID => break,
+                // Values the code can't know: the environment, other accounts' code, hashes and
+                // memory size. Logs push nothing.
+                _ => for _ in 0..op.stack_io().1 {
+                    let value = state.fresh();
+                    state.stack.push(value);
+                },
+            }
+            state.pc += 1;
+        }
+    }
+    report
+}
+
+/// Like `explore`, with each branch and revert located in the function's source.
+pub fn explore_function(function: &Function) -> SymbolicReport {
+    let mut report = explore(function.program());
+    report.branches.iter_mut().for_each(|branch| branch.span = function.span(branch.pc));
+    report.reverts.iter_mut().for_each(|revert| revert.span = function.span(revert.pc));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `require(x < 10)` on the first calldata word, followed by `require(x < 20)`.
+    fn requires() -> Vec<OP> {
+        let check = |bound: u8, ok: u8| vec![
+            OP::PUSH1(bound), OP::PUSH1(0), OP::CALLDATALOAD, OP::LT, OP::PUSH1(ok), OP::JUMPI,
+            OP::PUSH1(0), OP::PUSH1(0), OP::REVERT, OP::JUMPDEST,
+        ];
+        check(10, 14).into_iter().chain(check(20, 29)).collect()
+    }
+
+    #[test]
+    fn test_finds_requires_that_always_hold() {
+        let report = explore(&requires());
+        assert!(report.complete);
+        assert_eq!(report.reverts.iter().map(|revert| revert.reachable).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(report.branches[1], Branch { pc: 15, span: None, taken: true, not_taken: false });
+
+        let mut function = Function::from_program("check".to_string(), requires());
+        function.source_map = (0..requires().len()).map(|pc| (pc, pc + 1)).collect();
+        let diagnostics = explore_function(&function).diagnostics();
+        assert_eq!(diagnostics.iter().map(|d| (d.code, d.span)).collect::<Vec<_>>(), vec![
+            ("unreachable-branch", Some((15, 16))),
+            ("never-reverts", Some((18, 19))),
+        ]);
+        assert_eq!(diagnostics[0].message, "condition is always true");
+    }
+
+    #[test]
+    fn test_tracks_storage_and_arithmetic() {
+        // Stores 5, then reverts if the slot plus 1 isn't 6.
+        let program = vec![
+            OP::PUSH1(5), OP::PUSH1(0), OP::SSTORE,
+            OP::PUSH1(6), OP::PUSH1(1), OP::PUSH1(0), OP::SLOAD, OP::ADD, OP::EQ, OP::PUSH1(22), OP::JUMPI,
+            OP::PUSH1(0), OP::PUSH1(0), OP::REVERT, OP::JUMPDEST,
+        ];
+        let report = explore(&program);
+        assert!(report.complete && !report.reverts[0].reachable);
+
+        let unknown = explore(&program[3..].iter().cloned().map(|op| if op == OP::PUSH1(22) { OP::PUSH1(17) } else { op }).collect::<Vec<_>>());
+        assert!(unknown.complete && unknown.reverts[0].reachable);
+    }
+}
@@ -0,0 +1,280 @@
+use ethnum::U256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use crate::executor::Storage;
+use crate::tinyvm::*;
+
+/// Calldata byte offsets a value was derived from.
+pub type Taint = BTreeSet<usize>;
+
+/// A storage write whose key or value depends on calldata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintedWrite {
+    pub pc: usize,
+    pub slot: U256,
+    pub sources: Taint,
+}
+
+/// Tracks which calldata bytes flow into which storage slots, by shadowing every stack
+/// word with the set of calldata offsets it was computed from.
+///
+/// Taint follows data only: a value chosen by a branch on calldata is not tainted.
+#[derive(Debug, Default)]
+pub struct TaintTracker {
+    shadow: Vec<Taint>,
+    slots: BTreeMap<U256, Taint>,
+    /// Taint of each transient slot written so far.
+    transient: BTreeMap<U256, Taint>,
+    /// Taint of each memory byte written so far.
+    memory: BTreeMap<usize, Taint>,
+    /// Taint of what the last call or creation returned.
+    return_data: Taint,
+    pub writes: Vec<TaintedWrite>,
+}
+
+impl TaintTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pop(&mut self) -> Taint {
+        self.shadow.pop().unwrap_or_default()
+    }
+
+    /// Calldata bytes that can influence each storage slot written so far.
+    pub fn report(&self) -> TaintReport {
+        let mut influences = BTreeMap::<U256, Taint>::new();
+        self.writes.iter().for_each(|write| influences.entry(write.slot).or_default().extend(&write.sources));
+        TaintReport { influences }
+    }
+}
+
+impl Inspector for TaintTracker {
+    fn step(&mut self, vm: &VM, _storage: &dyn Storage) {
+        let peek = |n| vm.stack.peek(n).unwrap_or_default();
+        match &vm.program()[vm.pc()] {
+            OP::PUSH0 | OP::PUSH1(_) | OP::PUSHN(..) | OP::PUSH32(_) => self.shadow.push(Taint::new()),
+            OP::POP | OP::SELFDESTRUCT => {
+                self.pop();
+            },
+            OP::DUP1 => {
+                let top = self.shadow.last().cloned().unwrap_or_default();
+                self.shadow.push(top);
+            },
+            OP::SWAP1 => {
+                let len = self.shadow.len();
+                if len >= 2 {
+                    self.shadow.swap(len - 1, len - 2);
+                }
+            },
+            OP::DUP(n) => {
+                let item = self.shadow.len().checked_sub(*n as usize).map(|i| self.shadow[i].clone()).unwrap_or_default();
+                self.shadow.push(item);
+            },
+            OP::SWAP(n) => {
+                let len = self.shadow.len();
+                if len > *n as usize {
+                    self.shadow.swap(len - 1, len - 1 - *n as usize);
+                }
+            },
+            OP::ISZERO | OP::NOT | OP::BLOCKHASH | OP::HOST(_) | OP::BALANCE | OP::EXTCODESIZE | OP::EXTCODEHASH => {
+                let taint = self.pop();
+                self.shadow.push(taint);
+            },
+            OP::CALLDATALOAD => {
+                let mut taint = self.pop();
+                let start = usize::try_from(peek(0)).unwrap_or(usize::MAX).min(vm.calldata.len());
+                taint.extend(start..start.saturating_add(32).min(vm.calldata.len()));
+                self.shadow.push(taint);
+            },
+            OP::SLOAD => {
+                let mut taint = self.pop();
+                taint.extend(self.slots.get(&peek(0)).into_iter().flatten());
+                self.shadow.push(taint);
+            },
+            OP::SSTORE => {
+                let slot = peek(0);
+                let mut sources = self.pop();
+                sources.extend(self.pop());
+                if !sources.is_empty() {
+                    self.writes.push(TaintedWrite { pc: vm.pc(), slot, sources: sources.clone() });
+                }
+                self.slots.insert(slot, sources);
+            },
+            OP::TLOAD => {
+                let mut taint = self.pop();
+                taint.extend(self.transient.get(&peek(0)).into_iter().flatten());
+                self.shadow.push(taint);
+            },
+            OP::TSTORE => {
+                let slot = peek(0);
+                let mut sources = self.pop();
+                sources.extend(self.pop());
+                self.transient.insert(slot, sources);
+            },
+            OP::MLOAD => {
+                let mut taint = self.pop();
+                let offset = usize::try_from(peek(0)).unwrap_or(usize::MAX);
+                taint.extend(self.memory.range(offset..offset.saturating_add(32)).flat_map(|(_, taint)| taint));
+                self.shadow.push(taint);
+            },
+            OP::KECCAK256 => {
+                let offset = usize::try_from(peek(0)).unwrap_or(usize::MAX);
+                let len = usize::try_from(peek(1)).unwrap_or(usize::MAX);
+                let mut taint = self.pop();
+                taint.extend(self.pop());
+                taint.extend(self.memory.range(offset..offset.saturating_add(len)).flat_map(|(_, taint)| taint));
+                self.shadow.push(taint);
+            },
+            OP::MSTORE | OP::MSTORE8 => {
+                let offset = usize::try_from(peek(0)).unwrap_or(usize::MAX);
+                let len = if vm.program()[vm.pc()] == OP::MSTORE { 32 } else { 1 };
+                let mut taint = self.pop();
+                taint.extend(self.pop());
+                (offset..offset.saturating_add(len)).for_each(|byte| {
+                    self.memory.insert(byte, taint.clone());
+                });
+            },
+            OP::MCOPY => {
+                let (dest, offset, len) = (peek(0), peek(1), peek(2));
+                (0..3).for_each(|_| { self.pop(); });
+                let dest = usize::try_from(dest).unwrap_or(usize::MAX);
+                let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                // Read every byte before writing any, as the ranges may overlap.
+                let copied = (0..usize::try_from(len).unwrap_or(usize::MAX).min(vm.env.limits.memory))
+                    .map(|i| self.memory.get(&offset.saturating_add(i)).cloned().unwrap_or_default())
+                    .collect::<Vec<_>>();
+                copied.into_iter().enumerate().for_each(|(i, taint)| {
+                    self.memory.insert(dest.saturating_add(i), taint);
+                });
+            },
+            OP::CALLDATACOPY => {
+                let (dest, offset, len) = (peek(0), peek(1), peek(2));
+                (0..3).for_each(|_| { self.pop(); });
+                let dest = usize::try_from(dest).unwrap_or(usize::MAX);
+                let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                (0..usize::try_from(len).unwrap_or(usize::MAX).min(vm.env.limits.memory)).for_each(|i| {
+                    let source = offset.saturating_add(i);
+                    let taint = if source < vm.calldata.len() { Taint::from([source]) } else { Taint::new() };
+                    self.memory.insert(dest.saturating_add(i), taint);
+                });
+            },
+            // The code running is fixed, so what it copies carries no taint.
+            OP::CODECOPY => {
+                let (dest, len) = (peek(0), peek(2));
+                (0..3).for_each(|_| { self.pop(); });
+                let dest = usize::try_from(dest).unwrap_or(usize::MAX);
+                (0..usize::try_from(len).unwrap_or(usize::MAX).min(vm.env.limits.memory)).for_each(|i| {
+                    self.memory.insert(dest.saturating_add(i), Taint::new());
+                });
+            },
+            OP::RETURNDATASIZE => self.shadow.push(self.return_data.clone()),
+            OP::RETURNDATACOPY => {
+                let (dest, len) = (peek(0), peek(2));
+                (0..3).for_each(|_| { self.pop(); });
+                let dest = usize::try_from(dest).unwrap_or(usize::MAX);
+                (0..usize::try_from(len).unwrap_or(usize::MAX).min(vm.env.limits.memory)).for_each(|i| {
+                    self.memory.insert(dest.saturating_add(i), self.return_data.clone());
+                });
+            },
+            // The code copied depends only on whose it is.
+            OP::EXTCODECOPY => {
+                let (dest, len) = (peek(1), peek(3));
+                let taint = self.pop();
+                (0..3).for_each(|_| { self.pop(); });
+                let dest = usize::try_from(dest).unwrap_or(usize::MAX);
+                (0..usize::try_from(len).unwrap_or(usize::MAX).min(vm.env.limits.memory)).for_each(|i| {
+                    self.memory.insert(dest.saturating_add(i), taint.clone());
+                });
+            },
+            OP::MSIZE | OP::PC | OP::GAS | OP::CALLDATASIZE | OP::CODESIZE | OP::ADDRESS | OP::ORIGIN | OP::CALLER | OP::CALLVALUE | OP::GASPRICE
+            | OP::COINBASE | OP::TIMESTAMP | OP::NUMBER | OP::PREVRANDAO | OP::GASLIMIT | OP::CHAINID | OP::BASEFEE | OP::SELFBALANCE => self.shadow.push(Taint::new()),
+            OP::JUMP => {
+                self.pop();
+            },
+            OP::JUMPI => {
+                self.pop();
+                self.pop();
+            },
+            OP::JUMPDEST | OP::STOP | OP::INVALID => {},
+            OP::ADD | OP::SUB | OP::MUL | OP::DIV | OP::MOD | OP::EXP | OP::SDIV | OP::SMOD | OP::SIGNEXTEND
+            | OP::LT | OP::GT | OP::SLT | OP::SGT | OP::EQ | OP::AND | OP::OR | OP::XOR
+            | OP::BYTE | OP::SHL | OP::SHR | OP::SAR => {
+                let mut taint = self.pop();
+                taint.extend(self.pop());
+                self.shadow.push(taint);
+            },
+            OP::ADDMOD | OP::MULMOD => {
+                let mut taint = self.pop();
+                taint.extend(self.pop());
+                taint.extend(self.pop());
+                self.shadow.push(taint);
+            },
+            op @ (OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4) => {
+                (0..op.stack_io().0).for_each(|_| { self.pop(); });
+            },
+            OP::RETURN | OP::REVERT => {
+                self.pop();
+                self.pop();
+            },
+            // Whether the call or creation succeeds, and what it returns, can depend on any of
+            // its arguments.
+            op @ (OP::CALL | OP::CALLCODE | OP::DELEGATECALL | OP::STATICCALL | OP::CREATE | OP::CREATE2) => {
+                let mut taint = Taint::new();
+                (0..op.stack_io().0).for_each(|_| taint.extend(self.pop()));
+                self.return_data = taint.clone();
+                self.shadow.push(taint);
+            },
+        }
+    }
+}
+
+/// Storage slots mapped to the calldata bytes that can influence them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintReport {
+    pub influences: BTreeMap<U256, Taint>,
+}
+
+/// Collapses sorted offsets into `start..end` ranges.
+fn ranges(taint: &Taint) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    taint.iter().for_each(|offset| match ranges.last_mut() {
+        Some((_, end)) if *end == *offset => *end += 1,
+        _ => ranges.push((*offset, offset + 1)),
+    });
+    ranges
+}
+
+impl fmt::Display for TaintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.influences.iter().try_for_each(|(slot, taint)| {
+            let sources = ranges(taint).iter().map(|(start, end)| format!("calldata[{}..{}]", start, end)).collect::<Vec<_>>();
+            writeln!(f, "slot {:#x} <- {}", slot, sources.join(", "))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calldata_flows_into_storage() {
+        // slot 0 = !calldata[4..36], slot 1 = slot 0, slot 2 = constant
+        let program = vec![
+            OP::PUSH1(4), OP::CALLDATALOAD, OP::ISZERO, OP::PUSH1(0), OP::SSTORE,
+            OP::PUSH1(0), OP::SLOAD, OP::PUSH1(1), OP::SSTORE,
+            OP::PUSH1(1), OP::PUSH1(2), OP::SSTORE,
+        ];
+        let calldata = [0u8; 36];
+        let mut vm = VM::new(program, &calldata);
+        let mut tracker = TaintTracker::new();
+        vm.run_with_inspector(ContractStorage::new(vec![U256::ZERO; 3]), &mut tracker);
+
+        let report = tracker.report();
+        assert_eq!(report.influences.keys().copied().collect::<Vec<_>>(), vec![U256::ZERO, U256::ONE]);
+        assert_eq!(report.influences[&U256::ONE], (4..36).collect::<Taint>());
+        assert_eq!(tracker.writes.iter().map(|w| w.pc).collect::<Vec<_>>(), vec![4, 8]);
+        assert_eq!(report.to_string(), "slot 0x0 <- calldata[4..36]\nslot 0x1 <- calldata[4..36]\n");
+    }
+}
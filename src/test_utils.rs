@@ -0,0 +1,77 @@
+use ethnum::U256;
+use keccak_hash::keccak;
+use crate::env::Address;
+use crate::solidity::grammar::parse;
+use crate::tinyvm::{create_contracts, Contract};
+use crate::world::World;
+
+/// Named accounts funded by `World::new_dev()`.
+pub const DEV_ACCOUNTS: [&str; 4] = ["alice", "bob", "carol", "dave"];
+
+/// 10_000 ether in wei.
+pub const DEV_BALANCE: U256 = U256::new(10_000 * 10u128.pow(18));
+
+/// Derives a stable address from a human readable name, so "alice" is the same account everywhere.
+pub fn address(name: &str) -> Address {
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&keccak(name.as_bytes())[12..]);
+    Address(bytes)
+}
+
+pub fn alice() -> Address {
+    address("alice")
+}
+
+pub fn bob() -> Address {
+    address("bob")
+}
+
+pub fn carol() -> Address {
+    address("carol")
+}
+
+pub fn dave() -> Address {
+    address("dave")
+}
+
+/// Source of the bundled `contracts/flipper.sol`, built into the crate.
+pub fn flipper_source() -> &'static str {
+    include_str!("../contracts/flipper.sol")
+}
+
+/// `contracts/flipper.sol`, compiled with the default settings.
+pub fn flipper() -> Contract {
+    create_contracts(parse(flipper_source()).unwrap()).remove(0)
+}
+
+impl World {
+    pub fn new_dev() -> Self {
+        let mut world = World::new();
+        DEV_ACCOUNTS.iter().for_each(|name| {
+            world.set_balance(address(name), DEV_BALANCE);
+            world.registry.label(address(name), name);
+        });
+        world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_addresses_are_stable() {
+        assert_eq!(alice(), address("alice"));
+        assert_ne!(alice(), bob());
+        assert_eq!(format!("{}", alice()), "0x5dad7600c5d89fe3824ffa99ec1c3eb8bf3b0501");
+    }
+
+    #[test]
+    fn test_new_dev_funds_accounts() {
+        let world = World::new_dev();
+        DEV_ACCOUNTS.iter().for_each(|name| {
+            assert_eq!(world.balance(&address(name)), DEV_BALANCE);
+        });
+        assert_eq!(world.balance(&address("mallory")), U256::ZERO);
+    }
+}
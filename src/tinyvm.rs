@@ -1,30 +1,57 @@
 use ethnum::U256;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use crate::solidity::grammar::*;
+use crate::env::*;
+use crate::abi::{AbiError, AbiEvent, AbiEventParam, Value, abi_params, encode};
+use crate::bytecode::{assemble, code_offsets, from_hex, to_hex};
+use crate::diagnostics::Diagnostic;
+use crate::unsupported::parse_source;
+use crate::metadata::Metadata;
+use crate::ir::{IrFunction, lower_body, Scope};
+use crate::settings::{CompilerSettings, DebugInfo};
+use crate::executor::{Executor, Interpreter, Outcome, Overlay, Storage};
+use crate::log::LogEntry;
+use crate::host::{HostCall, HostFunctions};
+use crate::revert::{PanicCode, RevertReason};
+use crate::selector::selector;
+use crate::call::{CallHandler, CallKind, CallOutcome, ContractCreation, CreateOutcome, MessageCall};
 use keccak_hash::{keccak};
 
+/// Most items the stack holds, as on the EVM.
+pub const STACK_LIMIT: usize = 1024;
+
 pub struct Stack {
-    stackarr: [U256; 1024],
+    stackarr: [U256; STACK_LIMIT],
     top: usize,
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
     pub fn new() -> Self {
         Self {
-            stackarr: [U256::ZERO; 1024],
+            stackarr: [U256::ZERO; STACK_LIMIT],
             top: 0,
         }
     }
 
-    pub fn push32(&mut self, value: U256) {
-        if self.top < 1024 {
-            self.stackarr[self.top] = value;
-            self.top += 1;
-        }
+    /// `None`, leaving the stack unchanged, if it already holds `STACK_LIMIT` items. The VM
+    /// checks the height before each instruction, so its own pushes always fit.
+    pub fn push32(&mut self, value: U256) -> Option<()> {
+        *self.stackarr.get_mut(self.top)? = value;
+        self.top += 1;
+        Some(())
     }
 
-    pub fn push1(&mut self, value: u8) {
-        self.push32(U256::from(value));
+    pub fn push1(&mut self, value: u8) -> Option<()> {
+        self.push32(U256::from(value))
     }
 
     pub fn pop(&mut self) -> Option<U256> {
@@ -37,110 +64,1512 @@ impl Stack {
             //and it will return either None or Some() depending on the condition
     }
 
-    pub fn swap(&mut self) {
-        self.stackarr.swap(self.top - 1, self.top - 2);
+    /// Swaps the top two items, as `SWAP1` does. `None`, leaving the stack unchanged, if
+    /// it holds fewer than two.
+    pub fn swap(&mut self) -> Option<()> {
+        self.swap_n(1)
+    }
+
+    /// Pushes a copy of the `n`th item from the top, counting from 1 as `DUPn` does.
+    /// `None`, leaving the stack unchanged, if it holds fewer than `n` items.
+    pub fn dup(&mut self, n: usize) -> Option<()> {
+        let value = self.peek(n.checked_sub(1)?)?;
+        self.push32(value);
+        Some(())
+    }
+
+    /// Swaps the top item with the one `n` below it, as `SWAPn` does. `None`, leaving the
+    /// stack unchanged, if it holds `n` items or fewer.
+    pub fn swap_n(&mut self, n: usize) -> Option<()> {
+        self.peek(n)?;
+        self.stackarr.swap(self.top - 1, self.top - 1 - n);
+        Some(())
+    }
+
+    /// The `n`th item from the top, without popping it.
+    pub fn peek(&self, n: usize) -> Option<U256> {
+        if n < self.top {
+            Some(self.stackarr[self.top - 1 - n])
+        } else {
+            None
+        }
+    }
+
+    /// The live part of the stack, bottom first.
+    pub fn as_slice(&self) -> &[U256] {
+        &self.stackarr[..self.top]
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OP {
     PUSH32(U256),
     PUSH1(u8),
+    /// Pushes zero, without an immediate.
+    PUSH0,
+    /// `PUSH2` through `PUSH31`: pushes a value given in `n` big-endian bytes. The value
+    /// must fit in them.
+    PUSHN(u8, U256),
     POP,
     DUP1,
     SWAP1,
+    /// `DUP2` through `DUP16`: pushes a copy of the `n`th item from the top.
+    DUP(u8),
+    /// `SWAP2` through `SWAP16`: swaps the top item with the one `n` below it.
+    SWAP(u8),
+    CALLDATALOAD,
+    /// Length of the calldata in bytes.
+    CALLDATASIZE,
+    /// Pops a word and pushes what the host function registered for `n` returns for it. See
+    /// `HostFunctions`.
+    HOST(u8),
+    /// Address of the account whose code runs.
+    ADDRESS,
+    /// Address of the account that signed the transaction.
+    ORIGIN,
+    /// Address of the account that made this call.
+    CALLER,
+    /// Wei sent with this call.
+    CALLVALUE,
+    /// Price per gas the transaction pays.
+    GASPRICE,
+    /// Copies `third` bytes of calldata from offset `second` to memory at `top`, padding
+    /// with zeros past the end of the calldata.
+    CALLDATACOPY,
+    /// Size in bytes of the code running, as assembled.
+    CODESIZE,
+    /// Like `CALLDATACOPY`, copying from the code running, as assembled. This is how init
+    /// code reads the runtime code appended to it.
+    CODECOPY,
+    /// Size in bytes of what the last call or creation returned.
+    RETURNDATASIZE,
+    /// Like `CALLDATACOPY`, copying from what the last call or creation returned, but
+    /// halting instead of padding if the range runs past its end.
+    RETURNDATACOPY,
+    /// Balance in wei of the account at `top`.
+    BALANCE,
+    /// Balance in wei of the account whose code runs, cheaper than `ADDRESS BALANCE`.
+    SELFBALANCE,
+    /// Size in bytes of the runtime code of the account at `top`, 0 for an account
+    /// without code.
+    EXTCODESIZE,
+    /// Like `CALLDATACOPY`, copying from the runtime code of the account at `top` to memory
+    /// at `second`.
+    EXTCODECOPY,
+    /// keccak256 of the runtime code of the account at `top`, or 0 if the account is
+    /// empty.
+    EXTCODEHASH,
     SLOAD,
     SSTORE,
+    /// Like `SLOAD` and `SSTORE`, on the transient storage of EIP-1153, which starts out
+    /// zero in every transaction and is discarded at its end.
+    TLOAD,
+    TSTORE,
+    /// Loads the word at byte offset `top` of memory, and stores `second` there. Memory
+    /// grows in 32-byte words as it's touched, and reads as zero beyond what was written.
+    MLOAD,
+    MSTORE,
+    /// Stores the low byte of `second` at offset `top`.
+    MSTORE8,
+    /// Size of memory in bytes, always a multiple of 32.
+    MSIZE,
+    /// Copies `third` bytes of memory from offset `second` to offset `top`. The ranges
+    /// may overlap; the copy reads them as they were before it (EIP-5656).
+    MCOPY,
     ISZERO,
+    /// Arithmetic on the top two items, `top op second`, wrapping modulo 2^256.
+    ADD,
+    SUB,
+    MUL,
+    /// Division by zero gives zero, as in the EVM.
+    DIV,
+    /// `top % second`, zero for a zero modulus.
+    MOD,
+    /// `(top + second) % third` and `(top * second) % third` without wrapping the
+    /// intermediate result, zero for a zero modulus.
+    ADDMOD,
+    MULMOD,
+    /// `top ** second`, wrapping.
+    EXP,
+    /// Signed `DIV` and `MOD` on two's-complement words. `MIN / -1` wraps to `MIN`, and the
+    /// remainder takes the sign of the dividend.
+    SDIV,
+    SMOD,
+    /// Sign-extends `second` from its lowest `top + 1` bytes.
+    SIGNEXTEND,
+    /// Comparisons of `top` against `second`, pushing 1 if they hold and 0 otherwise.
+    /// `SLT` and `SGT` compare as two's-complement.
+    LT,
+    GT,
+    SLT,
+    SGT,
+    EQ,
+    AND,
+    OR,
+    XOR,
+    /// Flips every bit of the top item.
+    NOT,
+    /// Byte `top` of `second`, counting from the most significant, or zero past the end.
+    BYTE,
+    /// Shift `second` by `top` bits. Shifts of 256 or more leave 0, or for `SAR` all
+    /// copies of the sign bit.
+    SHL,
+    SHR,
+    SAR,
+    /// Keccak-256 hash of `second` bytes of memory from offset `top`.
+    KECCAK256,
+    /// Hash of the block numbered by the top item, or zero unless it is one of the 256
+    /// before the current block.
+    BLOCKHASH,
+    /// Address the block's priority fees go to.
+    COINBASE,
+    /// Seconds since the Unix epoch at which the block was sealed.
+    TIMESTAMP,
+    /// Number of the block.
+    NUMBER,
+    /// Randomness of the block, as the beacon chain provides it.
+    PREVRANDAO,
+    /// Gas limit of the block.
+    GASLIMIT,
+    CHAINID,
+    /// Base fee per gas of the block.
+    BASEFEE,
+    /// Jumps to the byte offset on top of the stack, which must hold a `JUMPDEST`. `JUMPI`
+    /// only jumps if `second` is nonzero. Offsets count bytes of the assembled program, as
+    /// in the EVM, not instructions.
+    JUMP,
+    JUMPI,
+    JUMPDEST,
+    /// Byte offset of this instruction.
+    PC,
+    /// Gas left after paying for this instruction.
+    GAS,
+    /// Appends a log with `second` bytes of memory from offset `top` as its data, and the
+    /// next 0 to 4 items as its topics.
+    LOG0,
+    LOG1,
+    LOG2,
+    LOG3,
+    LOG4,
+    /// Calls the account at `second` with `third` wei, forwarding at most `top` gas. The
+    /// input is `fifth` bytes of memory from offset `fourth`, and up to `seventh` bytes of
+    /// what the callee returns are copied to offset `sixth`. Pushes 1 if the call
+    /// succeeded and 0 otherwise. See `CallHandler`.
+    CALL,
+    /// Like `CALL`, but runs the callee's code on this contract's storage.
+    CALLCODE,
+    /// Like `CALL` without the value, running the callee's code as this contract, with its
+    /// storage, sender and value.
+    DELEGATECALL,
+    /// Like `CALL` without the value, failing if the callee or any call it makes tries to
+    /// change state.
+    STATICCALL,
+    /// Creates a contract with `top` wei, running the `third` bytes of memory from offset
+    /// `second` as its init code. Pushes the new contract's address, or 0 if the creation
+    /// failed. The address is derived from this contract's address and nonce.
+    CREATE,
+    /// Like `CREATE`, deriving the address from this contract's address, the salt `fourth`
+    /// and the init code instead, so it is known before the creation.
+    CREATE2,
+    /// Ends the call, returning `second` bytes of memory from offset `top`.
     RETURN,
+    /// Like `RETURN`, but fails the call: storage changes are discarded and the bytes are
+    /// passed back as revert data.
+    REVERT,
+    /// Ends the call, sending this contract's balance to the account at `top` and marking
+    /// the contract for deletion when the transaction ends. See `World::cancun`.
+    SELFDESTRUCT,
+    /// Ends the call successfully without returning any data.
+    STOP,
+    /// The designated invalid instruction, which fails the call and uses up all its gas.
+    /// Solidity before 0.8 compiled a failing `assert` to it.
+    INVALID,
 }
 
-#[derive(Debug, Clone, Default)]
+// Gas schedule (Shanghai, EIP-2929 cold/warm storage access, no refunds).
+const G_ZERO: u64 = 0;
+const G_BASE: u64 = 2;
+const G_VERYLOW: u64 = 3;
+const G_LOW: u64 = 5;
+const G_MID: u64 = 8;
+const G_EXP: u64 = 10;
+const G_EXPBYTE: u64 = 50;
+const G_BLOCKHASH: u64 = 20;
+const G_KECCAK256: u64 = 30;
+const G_KECCAK256_WORD: u64 = 6;
+const G_LOG: u64 = 375;
+const G_LOGTOPIC: u64 = 375;
+const G_LOGDATA: u64 = 8;
+const G_MEMORY: u64 = 3;
+const G_HIGH: u64 = 10;
+const G_JUMPDEST: u64 = 1;
+const G_COPY: u64 = 3;
+const G_WARM_ACCESS: u64 = 100;
+const G_COLD_SLOAD: u64 = 2100;
+const G_SSTORE_SET: u64 = 20000;
+const G_SSTORE_RESET: u64 = 2900;
+/// Every call is priced as the first to its account in the transaction.
+const G_COLD_ACCOUNT_ACCESS: u64 = 2600;
+const G_CALLVALUE: u64 = 9000;
+const G_CREATE: u64 = 32000;
+const G_INITCODE_WORD: u64 = 2;
+const G_SELFDESTRUCT: u64 = 5000;
+
+impl OP {
+    /// Static part of the cost, storage ops are priced by the VM from the slot state.
+    pub fn base_gas(&self) -> u64 {
+        match self {
+            OP::PUSH32(_) | OP::PUSH1(_) | OP::PUSHN(..) | OP::DUP1 | OP::SWAP1 | OP::DUP(_) | OP::SWAP(_) | OP::ISZERO | OP::CALLDATALOAD | OP::ADD | OP::SUB
+            | OP::LT | OP::GT | OP::SLT | OP::SGT | OP::EQ | OP::AND | OP::OR | OP::XOR | OP::NOT
+            | OP::BYTE | OP::SHL | OP::SHR | OP::SAR => G_VERYLOW,
+            OP::MUL | OP::DIV | OP::MOD | OP::SDIV | OP::SMOD | OP::SIGNEXTEND => G_LOW,
+            OP::ADDMOD | OP::MULMOD | OP::JUMP => G_MID,
+            OP::JUMPI => G_HIGH,
+            OP::JUMPDEST => G_JUMPDEST,
+            OP::EXP => G_EXP,
+            OP::BLOCKHASH => G_BLOCKHASH,
+            OP::KECCAK256 => G_KECCAK256,
+            OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4 => G_LOG + G_LOGTOPIC * self.log_topics().unwrap_or_default() as u64,
+            OP::CALL | OP::CALLCODE | OP::DELEGATECALL | OP::STATICCALL => G_COLD_ACCOUNT_ACCESS,
+            OP::CREATE | OP::CREATE2 => G_CREATE,
+            OP::SELFDESTRUCT => G_SELFDESTRUCT + G_COLD_ACCOUNT_ACCESS,
+            OP::BALANCE | OP::EXTCODESIZE | OP::EXTCODECOPY | OP::EXTCODEHASH => G_COLD_ACCOUNT_ACCESS,
+            OP::SELFBALANCE => G_LOW,
+            OP::TLOAD | OP::TSTORE => G_WARM_ACCESS,
+            OP::MLOAD | OP::MSTORE | OP::MSTORE8 | OP::MCOPY | OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY => G_VERYLOW,
+            OP::PUSH0 | OP::POP | OP::MSIZE | OP::PC | OP::GAS | OP::CALLDATASIZE | OP::CODESIZE | OP::RETURNDATASIZE
+            | OP::ADDRESS | OP::ORIGIN | OP::CALLER | OP::CALLVALUE | OP::GASPRICE
+            | OP::COINBASE | OP::TIMESTAMP | OP::NUMBER | OP::PREVRANDAO | OP::GASLIMIT | OP::CHAINID | OP::BASEFEE => G_BASE,
+            OP::SLOAD | OP::SSTORE | OP::RETURN | OP::REVERT | OP::STOP | OP::INVALID | OP::HOST(_) => G_ZERO,
+        }
+    }
+
+    /// Number of stack items the instruction pops and pushes.
+    pub fn stack_io(&self) -> (usize, usize) {
+        match self {
+            OP::PUSH32(_) | OP::PUSH1(_) | OP::PUSH0 | OP::PUSHN(..) | OP::MSIZE | OP::PC | OP::GAS | OP::CALLDATASIZE | OP::CODESIZE | OP::RETURNDATASIZE
+            | OP::ADDRESS | OP::ORIGIN | OP::CALLER | OP::CALLVALUE | OP::GASPRICE
+            | OP::COINBASE | OP::TIMESTAMP | OP::NUMBER | OP::PREVRANDAO | OP::GASLIMIT | OP::CHAINID | OP::BASEFEE | OP::SELFBALANCE => (0, 1),
+            OP::POP | OP::JUMP | OP::SELFDESTRUCT => (1, 0),
+            OP::DUP1 => (1, 2),
+            OP::SWAP1 => (2, 2),
+            OP::DUP(n) => (*n as usize, *n as usize + 1),
+            OP::SWAP(n) => (*n as usize + 1, *n as usize + 1),
+            OP::ISZERO | OP::NOT | OP::BLOCKHASH | OP::CALLDATALOAD | OP::SLOAD | OP::MLOAD | OP::HOST(_)
+            | OP::BALANCE | OP::EXTCODESIZE | OP::EXTCODEHASH | OP::TLOAD => (1, 1),
+            OP::SSTORE | OP::TSTORE | OP::MSTORE | OP::MSTORE8 | OP::JUMPI | OP::RETURN | OP::REVERT => (2, 0),
+            OP::ADD | OP::SUB | OP::MUL | OP::DIV | OP::MOD | OP::EXP | OP::SDIV | OP::SMOD | OP::SIGNEXTEND
+            | OP::LT | OP::GT | OP::SLT | OP::SGT | OP::EQ | OP::AND | OP::OR | OP::XOR
+            | OP::BYTE | OP::SHL | OP::SHR | OP::SAR | OP::KECCAK256 => (2, 1),
+            OP::ADDMOD | OP::MULMOD => (3, 1),
+            OP::MCOPY | OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY => (3, 0),
+            OP::EXTCODECOPY => (4, 0),
+            OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4 => (2 + self.log_topics().unwrap_or_default(), 0),
+            OP::CALL | OP::CALLCODE => (7, 1),
+            OP::DELEGATECALL | OP::STATICCALL => (6, 1),
+            OP::CREATE => (3, 1),
+            OP::CREATE2 => (4, 1),
+            OP::JUMPDEST | OP::STOP | OP::INVALID => (0, 0),
+        }
+    }
+
+    /// The shortest push of `value`.
+    pub fn push(value: U256) -> OP {
+        match 32 - value.leading_zeros() / 8 {
+            0 => OP::PUSH0,
+            1 => OP::PUSH1(value.as_u8()),
+            32 => OP::PUSH32(value),
+            n => OP::PUSHN(n as u8, value),
+        }
+    }
+
+    /// The value a push instruction pushes, `None` for other instructions.
+    pub fn push_value(&self) -> Option<U256> {
+        match self {
+            OP::PUSH0 => Some(U256::ZERO),
+            OP::PUSH1(value) => Some(U256::from(*value)),
+            OP::PUSHN(_, value) | OP::PUSH32(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Number of topics a `LOG` instruction takes, `None` for other instructions.
+    pub fn log_topics(&self) -> Option<usize> {
+        match self {
+            OP::LOG0 => Some(0),
+            OP::LOG1 => Some(1),
+            OP::LOG2 => Some(2),
+            OP::LOG3 => Some(3),
+            OP::LOG4 => Some(4),
+            _ => None,
+        }
+    }
+}
+
+/// Why execution stopped before reaching the end of the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Halt {
+    OutOfGas,
+    /// No function matches the selector and the contract has no fallback, so the call
+    /// reverts before running any code.
+    FunctionNotFound([u8; 4]),
+    /// The call carried value but the function isn't `payable`.
+    NonPayableFunctionReceivedValue,
+    /// The fee payer can't cover the gas limit at the gas price, so the transaction is
+    /// rejected before it runs.
+    InsufficientFunds,
+    /// The transaction's gas price or `max_fee_per_gas` is below the base fee, so it is
+    /// rejected before it runs.
+    FeeCapTooLow,
+    /// The stack and memory would grow past `ResourceLimits::memory`.
+    MemoryLimitExceeded,
+    /// A jump to an offset that isn't a `JUMPDEST`.
+    InvalidJump,
+    /// The returned bytes exceed `ResourceLimits::return_data`.
+    ReturnDataLimitExceeded,
+    /// The code executed `REVERT`, with the revert data.
+    Revert(Vec<u8>),
+    /// An opcode the VM doesn't implement, such as a host opcode with no function
+    /// registered.
+    InvalidOpcode(u8),
+    /// An instruction needs more items than the stack holds.
+    StackUnderflow,
+    /// An instruction would leave more than `STACK_LIMIT` items on the stack.
+    StackOverflow,
+    /// An `SSTORE`, a `TSTORE`, a log or a call with value inside a `STATICCALL`.
+    StateChangeInStaticCall,
+    /// A `RETURNDATACOPY` past the end of what the last call returned.
+    ReturnDataOutOfBounds,
+    /// The code executed `INVALID`.
+    InvalidInstruction,
+}
+
+impl Halt {
+    /// The code of a revert with a Solidity `Panic(uint256)` payload, which 0.8 emits for
+    /// failing asserts, checked arithmetic and out-of-bounds indexing.
+    pub fn panic_code(&self) -> Option<PanicCode> {
+        match self {
+            Halt::Revert(data) => match RevertReason::decode(data) {
+                RevertReason::Panic(code) => Some(code),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A contract's storage held in memory: the slots of its state variables, densely from
+/// zero, and any other slot set at runtime, such as a mapping entry.
+#[derive(Debug, Clone, Default, Eq)]
 pub struct ContractStorage {
-    slots: Vec<U256>
+    slots: Vec<U256>,
+    /// Slots past the state variables' that were written. Zero ones are kept, so that they
+    /// shadow the slot in a `StateDB` the storage sits over.
+    others: BTreeMap<U256, U256>,
+}
+
+/// Equal when every slot reads the same.
+impl PartialEq for ContractStorage {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
 }
 
+impl ContractStorage {
+    pub fn new(slots: Vec<U256>) -> Self {
+        Self { slots, others: BTreeMap::new() }
+    }
+
+    /// The state variables' slots.
+    pub fn slots(&self) -> &[U256] {
+        &self.slots
+    }
+
+    /// Every slot held with its value: the state variables' in order, then the nonzero
+    /// others by key.
+    pub fn iter(&self) -> impl Iterator<Item = (U256, U256)> + '_ {
+        self.slots.iter().enumerate().map(|(key, value)| (U256::from(key as u64), *value))
+            .chain(self.others.iter().filter(|(_, value)| **value != U256::ZERO).map(|(key, value)| (*key, *value)))
+    }
+
+    /// Whether `key` is a state variable's slot or was written.
+    pub fn contains(&self, key: U256) -> bool {
+        usize::try_from(key).is_ok_and(|key| key < self.slots.len()) || self.others.contains_key(&key)
+    }
+}
+
+impl Storage for ContractStorage {
+    /// Unset slots read as zero, as in the EVM.
+    fn load(&self, key: U256) -> U256 {
+        match usize::try_from(key).ok().and_then(|key| self.slots.get(key)) {
+            Some(value) => *value,
+            None => self.others.get(&key).copied().unwrap_or_default(),
+        }
+    }
+
+    fn store(&mut self, key: U256, value: U256) {
+        match usize::try_from(key).ok().and_then(|key| self.slots.get_mut(key)) {
+            Some(slot) => *slot = value,
+            None => { self.others.insert(key, value); },
+        }
+    }
+
+    /// Written slots are included even if they were set back to zero.
+    fn keys(&self) -> Vec<U256> {
+        (0..self.slots.len() as u64).map(U256::from).chain(self.others.keys().copied()).collect()
+    }
+}
+
+/// The storage a VM runs on, keeping the old value of every slot written so that a halt
+/// can undo the writes, those a call back into the contract made included.
+struct Journaled<'s> {
+    storage: &'s mut dyn Storage,
+    journal: &'s mut Vec<(U256, U256)>,
+}
+
+impl Storage for Journaled<'_> {
+    fn load(&self, key: U256) -> U256 {
+        self.storage.load(key)
+    }
+
+    fn store(&mut self, key: U256, value: U256) {
+        self.journal.push((key, self.storage.load(key)));
+        self.storage.store(key, value);
+    }
+
+    fn keys(&self) -> Vec<U256> {
+        let mut keys = self.storage.keys();
+        keys.extend(self.journal.iter().map(|(key, _)| *key));
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// Hooks into execution, called around every instruction the VM executes.
+pub trait Inspector {
+    fn step(&mut self, _vm: &VM, _storage: &dyn Storage) {}
+    fn step_end(&mut self, _vm: &VM, _storage: &dyn Storage) {}
+}
+
+pub struct NoopInspector;
+
+/// Per-contract state that lasts for a whole transaction rather than one call: the slots
+/// accessed so far, which are warm, storage as it was when the transaction started,
+/// which `SSTORE` pricing compares against, and transient storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxState {
+    pub warm_slots: HashSet<U256>,
+    /// The value each slot written so far had when the transaction started.
+    pub original: BTreeMap<U256, U256>,
+    /// Transient slots written so far, without those set back to zero.
+    pub transient: BTreeMap<U256, U256>,
+}
+
+impl Inspector for NoopInspector {}
+
 pub struct VM<'a> {
     pub stack: Stack,
     program: Vec<OP>,
+    /// Byte offset of each instruction, which jumps and `PC` work in.
+    offsets: Vec<usize>,
+    /// The program assembled, which `CODESIZE` and `CODECOPY` read.
+    code: Vec<u8>,
     pc: usize,
-    calldata: &'a [u8],
+    pub calldata: &'a [u8],
+    pub env: Env,
+    pub context: CallContext,
+    pub block: BlockEnv,
+    hosts: HostFunctions,
+    calls: Option<&'a mut dyn CallHandler>,
+    pub gas_used: u64,
+    pub halt: Option<Halt>,
+    memory: Vec<u8>,
+    return_data: Vec<u8>,
+    /// What the last call or creation returned, for `RETURNDATASIZE` and `RETURNDATACOPY`.
+    call_return_data: Vec<u8>,
+    logs: Vec<LogEntry>,
+    preimages: BTreeMap<U256, Vec<u8>>,
+    warm_slots: HashSet<U256>,
+    original: BTreeMap<U256, U256>,
+    transient: BTreeMap<U256, U256>,
+    /// `(slot, old value)` for every storage write so far, undone if execution halts.
+    journal: Vec<(U256, U256)>,
+    stopped: bool,
 }
 
 impl<'a> VM<'a> {
     pub fn new(program: Vec<OP>, calldata: &'a [u8]) -> Self {
-        Self {
-            stack: Stack::new(),
-            program,
-            pc: 0,
-            calldata: calldata,
-        }
+        VmBuilder::new(program).calldata(calldata).build()
+    }
+
+    pub fn builder(program: Vec<OP>) -> VmBuilder<'a> {
+        VmBuilder::new(program)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn program(&self) -> &[OP] {
+        &self.program
+    }
+
+    /// Byte offset of the current instruction in the assembled program.
+    pub fn code_offset(&self) -> usize {
+        self.offsets[self.pc]
+    }
+
+    /// The transaction state after this execution, to hand to the next call in the same
+    /// transaction.
+    pub fn tx_state(&self) -> TxState {
+        TxState { warm_slots: self.warm_slots.clone(), original: self.original.clone(), transient: self.transient.clone() }
     }
 
+    /// Runs the program against `storage` and returns the updated storage. If execution
+    /// halts (see `halt`), all storage changes are discarded.
     pub fn run(&mut self, storage: ContractStorage) -> ContractStorage {
-        let mut storage = storage;
-        while self.pc < self.program.len() {
-            match self.program[self.pc] {
-                OP::PUSH32(word) => {
-                    self.stack.push32(word);
-                    self.pc += 1;
-                },
-                OP::PUSH1(value) => {
-                    self.stack.push1(value);
-                    self.pc += 1;
-                },
-                OP::POP => {
-                    self.stack.pop();
-                    self.pc += 1;
-                },
-                OP::SWAP1 => {
-                    self.stack.swap();
-                    self.pc += 1;
-                },
-                OP::DUP1 => {
-                    let top = self.stack.pop().unwrap();
-                    self.stack.push32(top);
-                    self.stack.push32(top);
-                    self.pc += 1;
-                },
-                OP::SLOAD => {
-                    let key = self.stack.pop().unwrap();
-                    let val = storage.slots[key.as_usize()];
-                    self.stack.push32(val);
-                    self.pc += 1;
-                },
-                OP::SSTORE => {
-                    let key = self.stack.pop().unwrap();
-                    let val = self.stack.pop().unwrap();
-                    storage.slots[key.as_usize()] = val;
-                    self.pc += 1;
-                },
-                OP::RETURN => {
-                    self.pc += 1;
-                    break;
-                },
-                OP::ISZERO => {
-                    let top = self.stack.pop().unwrap();
+        self.run_with_inspector(storage, &mut NoopInspector)
+    }
+
+    /// Whether execution has ended, by reaching the end of the program, a `RETURN` or a halt.
+    pub fn is_finished(&self) -> bool {
+        self.stopped || self.halt.is_some() || self.pc >= self.program.len()
+    }
+
+    /// Gas the call can still spend, out of `env.gas_limit`.
+    pub fn gas_left(&self) -> u64 {
+        self.env.gas_limit - self.gas_used
+    }
+
+    /// Memory after the run, or so far while stepping.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Bytes passed to `RETURN`, empty if execution ended any other way.
+    pub fn return_data(&self) -> &[u8] {
+        &self.return_data
+    }
+
+    /// Bytes the last call or creation returned, or its revert data.
+    pub fn call_return_data(&self) -> &[u8] {
+        &self.call_return_data
+    }
+
+    /// Logs emitted so far, in order. They are kept on a halt, but a caller should discard
+    /// them along with the storage changes.
+    pub fn logs(&self) -> &[LogEntry] {
+        &self.logs
+    }
+
+    /// The input of every `KECCAK256` so far, keyed by its hash.
+    pub fn preimages(&self) -> &BTreeMap<U256, Vec<u8>> {
+        &self.preimages
+    }
+
+    /// End of the memory range the current instruction touches, `None` if it touches none.
+    fn memory_needed(&self) -> Option<U256> {
+        let len = match self.program[self.pc] {
+            OP::MLOAD | OP::MSTORE => U256::from(32u8),
+            OP::MSTORE8 => U256::ONE,
+            OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY => self.stack.peek(2).filter(|len| *len != U256::ZERO)?,
+            OP::EXTCODECOPY => return self.memory_end(1, self.stack.peek(3).filter(|len| *len != U256::ZERO)?),
+            // Both the source and the destination.
+            OP::MCOPY => return [0, 1].into_iter().filter_map(|i| self.memory_end(i, self.stack.peek(2).filter(|len| *len != U256::ZERO)?)).max(),
+            OP::KECCAK256 | OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4 | OP::RETURN | OP::REVERT => self.stack.peek(1).filter(|len| *len != U256::ZERO)?,
+            // The input and the space for the output.
+            OP::CALL | OP::CALLCODE => return [3, 5].into_iter().filter_map(|i| self.memory_end(i, self.stack.peek(i + 1).filter(|len| *len != U256::ZERO)?)).max(),
+            OP::CREATE | OP::CREATE2 => return self.memory_end(1, self.stack.peek(2).filter(|len| *len != U256::ZERO)?),
+            OP::DELEGATECALL | OP::STATICCALL => return [2, 4].into_iter().filter_map(|i| self.memory_end(i, self.stack.peek(i + 1).filter(|len| *len != U256::ZERO)?)).max(),
+            _ => return None,
+        };
+        self.memory_end(0, len)
+    }
 
-                    if top == U256::ZERO {
-                        self.stack.push32(U256::ONE);
+    /// End of `len` bytes of memory from the offset `n` items down the stack, in whole words.
+    fn memory_end(&self, n: usize, len: U256) -> Option<U256> {
+        Some(self.stack.peek(n)?.saturating_add(len).saturating_add(U256::from(31u8)) / 32 * 32)
+    }
+
+    /// Balance of the account at `address`: from the call handler if there is one, since
+    /// it tracks transfers, otherwise from the environment.
+    fn balance(&self, address: Address) -> U256 {
+        match &self.calls {
+            Some(calls) => calls.balance(address),
+            None => self.env.balances.get(&address).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Whether the current instruction changes state, which a static call may not do.
+    fn changes_state(&self) -> bool {
+        match self.program[self.pc] {
+            OP::SSTORE | OP::TSTORE | OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4 | OP::CREATE | OP::CREATE2 | OP::SELFDESTRUCT => true,
+            OP::CALL => self.stack.peek(2).is_some_and(|value| value != U256::ZERO),
+            _ => false,
+        }
+    }
+
+    /// Index of the `JUMPDEST` at byte offset `dest`.
+    fn jump_target(&self, dest: U256) -> Option<usize> {
+        let index = self.offsets.binary_search(&usize::try_from(dest).ok()?).ok()?;
+        (self.program[index] == OP::JUMPDEST).then_some(index)
+    }
+
+    /// Grows memory to cover `len` bytes from `offset`, in whole words.
+    fn expand_memory(&mut self, offset: usize, len: usize) {
+        let end = (offset + len).div_ceil(32) * 32;
+        if end > self.memory.len() {
+            self.memory.resize(end, 0);
+        }
+    }
+
+    /// Gas the current instruction costs up front. For calls and creations this leaves out
+    /// the gas handed to the callee, which is charged as it is used.
+    pub fn gas_cost(&self, storage: &dyn Storage) -> u64 {
+        let op = &self.program[self.pc];
+        let access = |key: U256| if self.warm_slots.contains(&key) { 0 } else { G_COLD_SLOAD };
+        match op {
+            OP::SLOAD => match self.stack.peek(0) {
+                Some(key) if self.warm_slots.contains(&key) => G_WARM_ACCESS,
+                _ => G_COLD_SLOAD,
+            },
+            OP::MLOAD | OP::MSTORE | OP::MSTORE8 | OP::MCOPY | OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY | OP::EXTCODECOPY | OP::KECCAK256 | OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4
+            | OP::RETURN | OP::REVERT | OP::CALL | OP::CALLCODE | OP::DELEGATECALL | OP::STATICCALL | OP::CREATE | OP::CREATE2 => {
+                // `step` has checked the size against the memory limit, so it fits.
+                let end = self.memory_needed().unwrap_or_default().as_u64();
+                let expansion = memory_cost(end.div_ceil(32)).saturating_sub(memory_cost(self.memory.len() as u64 / 32));
+                let words = match op {
+                    OP::MCOPY | OP::CALLDATACOPY | OP::CODECOPY | OP::RETURNDATACOPY => G_COPY * self.stack.peek(2).unwrap_or_default().as_u64().div_ceil(32),
+                    OP::EXTCODECOPY => G_COPY * self.stack.peek(3).unwrap_or_default().as_u64().div_ceil(32),
+                    OP::KECCAK256 => G_KECCAK256_WORD * self.stack.peek(1).unwrap_or_default().as_u64().div_ceil(32),
+                    _ if op.log_topics().is_some() => G_LOGDATA * self.stack.peek(1).unwrap_or_default().as_u64(),
+                    // Hashing for the address, then the init code's own charge.
+                    OP::CREATE2 => (G_KECCAK256_WORD + G_INITCODE_WORD) * self.stack.peek(2).unwrap_or_default().as_u64().div_ceil(32),
+                    OP::CREATE => G_INITCODE_WORD * self.stack.peek(2).unwrap_or_default().as_u64().div_ceil(32),
+                    OP::CALL | OP::CALLCODE if self.stack.peek(2).unwrap_or_default() != U256::ZERO => G_CALLVALUE,
+                    _ => 0,
+                };
+                op.base_gas() + words + expansion
+            },
+            OP::SSTORE => match (self.stack.peek(0), self.stack.peek(1)) {
+                (Some(key), Some(new)) => {
+                    let current = storage.load(key);
+                    // A slot not written yet in the transaction still holds its original value.
+                    let orig = self.original.get(&key).copied().unwrap_or(current);
+                    access(key) + if current == new || orig != current {
+                        G_WARM_ACCESS
+                    } else if orig == U256::ZERO {
+                        G_SSTORE_SET
                     } else {
-                        self.stack.push32(U256::ZERO);
+                        G_SSTORE_RESET
                     }
-                    self.pc += 1;
                 },
+                _ => G_ZERO,
+            },
+            OP::HOST(id) => self.hosts.gas(*id).unwrap_or_default(),
+            OP::EXP => {
+                let exponent = self.stack.peek(1).unwrap_or_default();
+                G_EXP + G_EXPBYTE * (256 - exponent.leading_zeros() as u64).div_ceil(8)
+            },
+            _ => op.base_gas(),
+        }
+    }
+
+    /// Like `run`, with `inspector` called around every instruction.
+    pub fn run_with_inspector(&mut self, mut storage: ContractStorage, inspector: &mut dyn Inspector) -> ContractStorage {
+        self.run_on(&mut storage, inspector);
+        storage
+    }
+
+    /// Runs the program against `storage`, updated in place. If execution halts, every
+    /// write is undone.
+    pub fn run_on(&mut self, storage: &mut dyn Storage, inspector: &mut dyn Inspector) {
+        while self.step(storage, inspector) {}
+    }
+
+    /// Like `run`, but yields to the async executor every `steps_per_yield` instructions.
+    pub async fn run_async(&mut self, mut storage: ContractStorage, steps_per_yield: usize) -> ContractStorage {
+        let mut steps = 0;
+        while self.step(&mut storage, &mut NoopInspector) {
+            steps += 1;
+            if steps % steps_per_yield.max(1) == 0 {
+                YieldNow(false).await;
             }
-        };
+        }
         storage
     }
+
+    /// Executes a single instruction against `storage`, which is updated in place.
+    /// Returns `false` once execution has finished. On a halt, every write the execution
+    /// made to `storage` is undone.
+    pub fn step(&mut self, storage: &mut dyn Storage, inspector: &mut dyn Inspector) -> bool {
+        let mut journal = std::mem::take(&mut self.journal);
+        let running = self.execute_step(&mut Journaled { storage, journal: &mut journal }, inspector);
+        if self.halt.is_some() {
+            journal.drain(..).rev().for_each(|(key, old)| storage.store(key, old));
+        }
+        self.journal = journal;
+        running
+    }
+
+    fn execute_step(&mut self, storage: &mut dyn Storage, inspector: &mut dyn Inspector) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        inspector.step(self, storage);
+
+        let (pops, pushes) = self.program[self.pc].stack_io();
+        let height = self.stack.as_slice().len();
+        if height < pops {
+            self.halt = Some(Halt::StackUnderflow);
+            return false;
+        }
+        if height - pops + pushes > STACK_LIMIT {
+            self.halt = Some(Halt::StackOverflow);
+            return false;
+        }
+        let stack = (height - pops + pushes) * 32;
+        let memory = self.memory_needed().unwrap_or_default().max(U256::from(self.memory.len() as u64));
+        if memory.saturating_add(U256::from(stack as u64)) > U256::from(self.env.limits.memory as u64) {
+            self.halt = Some(Halt::MemoryLimitExceeded);
+            return false;
+        }
+
+        if self.env.is_static && self.changes_state() {
+            self.halt = Some(Halt::StateChangeInStaticCall);
+            return false;
+        }
+
+        let cost = self.gas_cost(storage);
+        if self.gas_used + cost > self.env.gas_limit {
+            self.gas_used = self.env.gas_limit;
+            self.halt = Some(Halt::OutOfGas);
+            return false;
+        }
+        self.gas_used += cost;
+
+        match self.program[self.pc] {
+            OP::PUSH32(word) => {
+                self.stack.push32(word);
+                self.pc += 1;
+            },
+            OP::PUSH1(value) => {
+                self.stack.push1(value);
+                self.pc += 1;
+            },
+            OP::PUSH0 => {
+                self.stack.push32(U256::ZERO);
+                self.pc += 1;
+            },
+            OP::PUSHN(_, value) => {
+                self.stack.push32(value);
+                self.pc += 1;
+            },
+            OP::POP => {
+                self.stack.pop();
+                self.pc += 1;
+            },
+            OP::SWAP1 => {
+                self.stack.swap();
+                self.pc += 1;
+            },
+            OP::DUP1 => {
+                let top = self.stack.pop().unwrap();
+                self.stack.push32(top);
+                self.stack.push32(top);
+                self.pc += 1;
+            },
+            OP::DUP(n) => {
+                if self.stack.dup(n as usize).is_none() {
+                    self.halt = Some(Halt::StackUnderflow);
+                    return false;
+                }
+                self.pc += 1;
+            },
+            OP::SWAP(n) => {
+                if self.stack.swap_n(n as usize).is_none() {
+                    self.halt = Some(Halt::StackUnderflow);
+                    return false;
+                }
+                self.pc += 1;
+            },
+            OP::CALLDATALOAD => {
+                let offset = usize::try_from(self.stack.pop().unwrap()).unwrap_or(usize::MAX);
+                let mut word = [0u8; 32];
+                word.iter_mut().zip(self.calldata.iter().skip(offset)).for_each(|(w, b)| *w = *b);
+                self.stack.push32(U256::from_be_bytes(word));
+                self.pc += 1;
+            },
+            OP::CALLDATASIZE => {
+                self.stack.push32(U256::from(self.calldata.len() as u64));
+                self.pc += 1;
+            },
+            OP::HOST(id) => {
+                let input = self.stack.pop().unwrap();
+                match self.hosts.call(id, &mut HostCall::new(input, &self.context, &mut self.memory)) {
+                    Ok(output) => {
+                        self.stack.push32(output);
+                    },
+                    Err(halt) => {
+                        self.halt = Some(halt);
+                        return false;
+                    },
+                }
+                self.pc += 1;
+            },
+            OP::ADDRESS => {
+                self.stack.push32(self.context.address.to_word());
+                self.pc += 1;
+            },
+            OP::ORIGIN => {
+                self.stack.push32(self.context.origin.to_word());
+                self.pc += 1;
+            },
+            OP::CALLER => {
+                self.stack.push32(self.context.caller.to_word());
+                self.pc += 1;
+            },
+            OP::CALLVALUE => {
+                self.stack.push32(self.context.value);
+                self.pc += 1;
+            },
+            OP::GASPRICE => {
+                self.stack.push32(self.context.gas_price);
+                self.pc += 1;
+            },
+            OP::CALLDATACOPY => {
+                let dest = self.stack.pop().unwrap().as_usize();
+                let offset = usize::try_from(self.stack.pop().unwrap()).unwrap_or(usize::MAX);
+                let len = self.stack.pop().unwrap().as_usize();
+                if len > 0 {
+                    self.expand_memory(dest, len);
+                    let source = self.calldata.iter().skip(offset).copied().chain(std::iter::repeat(0));
+                    self.memory[dest..dest + len].iter_mut().zip(source).for_each(|(m, b)| *m = b);
+                }
+                self.pc += 1;
+            },
+            OP::CODESIZE => {
+                self.stack.push32(U256::from(self.code.len() as u64));
+                self.pc += 1;
+            },
+            OP::CODECOPY => {
+                let dest = self.stack.pop().unwrap().as_usize();
+                let offset = usize::try_from(self.stack.pop().unwrap()).unwrap_or(usize::MAX);
+                let len = self.stack.pop().unwrap().as_usize();
+                if len > 0 {
+                    self.expand_memory(dest, len);
+                    let source = self.code.iter().skip(offset).copied().chain(std::iter::repeat(0));
+                    self.memory[dest..dest + len].iter_mut().zip(source).for_each(|(m, b)| *m = b);
+                }
+                self.pc += 1;
+            },
+            OP::RETURNDATASIZE => {
+                self.stack.push32(U256::from(self.call_return_data.len() as u64));
+                self.pc += 1;
+            },
+            OP::RETURNDATACOPY => {
+                let dest = self.stack.pop().unwrap().as_usize();
+                let offset = self.stack.pop().unwrap();
+                let len = self.stack.pop().unwrap().as_usize();
+                let end = usize::try_from(offset).ok().and_then(|offset| offset.checked_add(len));
+                let Some(end) = end.filter(|end| *end <= self.call_return_data.len()) else {
+                    self.halt = Some(Halt::ReturnDataOutOfBounds);
+                    return false;
+                };
+                if len > 0 {
+                    self.expand_memory(dest, len);
+                    self.memory[dest..dest + len].copy_from_slice(&self.call_return_data[end - len..end]);
+                }
+                self.pc += 1;
+            },
+            OP::BALANCE => {
+                let address = Address::from_word(self.stack.pop().unwrap());
+                self.stack.push32(self.balance(address));
+                self.pc += 1;
+            },
+            OP::SELFBALANCE => {
+                self.stack.push32(self.balance(self.context.address));
+                self.pc += 1;
+            },
+            OP::EXTCODESIZE | OP::EXTCODEHASH => {
+                let address = Address::from_word(self.stack.pop().unwrap());
+                let word = match (self.calls.as_ref(), &self.program[self.pc]) {
+                    (Some(calls), OP::EXTCODESIZE) => U256::from(calls.code(address).len() as u64),
+                    (Some(calls), _) => calls.code_hash(address),
+                    (None, _) => U256::ZERO,
+                };
+                self.stack.push32(word);
+                self.pc += 1;
+            },
+            OP::EXTCODECOPY => {
+                let address = Address::from_word(self.stack.pop().unwrap());
+                let dest = self.stack.pop().unwrap().as_usize();
+                let offset = usize::try_from(self.stack.pop().unwrap()).unwrap_or(usize::MAX);
+                let len = self.stack.pop().unwrap().as_usize();
+                if len > 0 {
+                    let code = self.calls.as_ref().map(|calls| calls.code(address)).unwrap_or_default();
+                    self.expand_memory(dest, len);
+                    let source = code.into_iter().skip(offset).chain(std::iter::repeat(0));
+                    self.memory[dest..dest + len].iter_mut().zip(source).for_each(|(m, b)| *m = b);
+                }
+                self.pc += 1;
+            },
+            OP::SLOAD => {
+                let key = self.stack.pop().unwrap();
+                self.warm_slots.insert(key);
+                let val = storage.load(key);
+                self.stack.push32(val);
+                self.pc += 1;
+            },
+            OP::SSTORE => {
+                let key = self.stack.pop().unwrap();
+                self.warm_slots.insert(key);
+                let val = self.stack.pop().unwrap();
+                self.original.entry(key).or_insert_with(|| storage.load(key));
+                storage.store(key, val);
+                self.pc += 1;
+            },
+            OP::TLOAD => {
+                let key = self.stack.pop().unwrap();
+                self.stack.push32(self.transient.get(&key).copied().unwrap_or_default());
+                self.pc += 1;
+            },
+            OP::TSTORE => {
+                let key = self.stack.pop().unwrap();
+                let val = self.stack.pop().unwrap();
+                if val == U256::ZERO {
+                    self.transient.remove(&key);
+                } else {
+                    self.transient.insert(key, val);
+                }
+                self.pc += 1;
+            },
+            OP::LOG0 | OP::LOG1 | OP::LOG2 | OP::LOG3 | OP::LOG4 => {
+                let offset = self.stack.pop().unwrap().as_usize();
+                let len = self.stack.pop().unwrap().as_usize();
+                let topics = (0..self.program[self.pc].log_topics().unwrap()).map(|_| self.stack.pop().unwrap()).collect();
+                let mut data = vec![];
+                if len > 0 {
+                    self.expand_memory(offset, len);
+                    data = self.memory[offset..offset + len].to_vec();
+                }
+                self.logs.push(LogEntry { topics, data });
+                self.pc += 1;
+            },
+            OP::CALL | OP::CALLCODE | OP::DELEGATECALL | OP::STATICCALL => {
+                let op = self.program[self.pc].clone();
+                let gas = self.stack.pop().unwrap();
+                let to = Address::from_word(self.stack.pop().unwrap());
+                let (kind, sender, value) = match op {
+                    OP::CALL => (CallKind::Call, self.context.address, self.stack.pop().unwrap()),
+                    OP::CALLCODE => (CallKind::CallCode, self.context.address, self.stack.pop().unwrap()),
+                    OP::DELEGATECALL => (CallKind::DelegateCall, self.context.caller, self.context.value),
+                    _ => (CallKind::StaticCall, self.context.address, U256::ZERO),
+                };
+                let [in_offset, in_len, out_offset, out_len] = [(); 4].map(|_| self.stack.pop().unwrap().as_usize());
+                let mut input = vec![];
+                if in_len > 0 {
+                    self.expand_memory(in_offset, in_len);
+                    input = self.memory[in_offset..in_offset + in_len].to_vec();
+                }
+                if out_len > 0 {
+                    self.expand_memory(out_offset, out_len);
+                }
+                // All but one 64th of the gas left, as EIP-150 caps what a call can forward.
+                let gas = u64::try_from(gas).unwrap_or(u64::MAX).min(self.gas_left() - self.gas_left() / 64);
+                let is_static = self.env.is_static || kind == CallKind::StaticCall;
+                let call = MessageCall { kind, caller: self.context.address, sender, to, value, input, gas, is_static };
+                let outcome = match self.calls.as_mut() {
+                    Some(calls) => calls.call(call, storage),
+                    None => CallOutcome::failed(),
+                };
+                self.gas_used += outcome.gas_used.min(gas);
+                let copied = outcome.return_data.len().min(out_len);
+                self.memory[out_offset..out_offset + copied].copy_from_slice(&outcome.return_data[..copied]);
+                self.call_return_data = outcome.return_data;
+                self.stack.push32(U256::from(outcome.success));
+                self.pc += 1;
+            },
+            OP::CREATE | OP::CREATE2 => {
+                let value = self.stack.pop().unwrap();
+                let offset = self.stack.pop().unwrap().as_usize();
+                let len = self.stack.pop().unwrap().as_usize();
+                let salt = (self.program[self.pc] == OP::CREATE2).then(|| self.stack.pop().unwrap());
+                let mut init_code = vec![];
+                if len > 0 {
+                    self.expand_memory(offset, len);
+                    init_code = self.memory[offset..offset + len].to_vec();
+                }
+                let gas = self.gas_left() - self.gas_left() / 64;
+                let creation = ContractCreation { creator: self.context.address, value, init_code, salt, gas };
+                let outcome = match self.calls.as_mut() {
+                    Some(calls) => calls.create(creation, storage),
+                    None => CreateOutcome::failed(),
+                };
+                self.gas_used += outcome.gas_used.min(gas);
+                self.call_return_data = outcome.return_data;
+                self.stack.push32(outcome.address.map_or(U256::ZERO, |address| address.to_word()));
+                self.pc += 1;
+            },
+            OP::SELFDESTRUCT => {
+                let beneficiary = Address::from_word(self.stack.pop().unwrap());
+                if let Some(calls) = self.calls.as_mut() {
+                    calls.selfdestruct(self.context.address, beneficiary);
+                }
+                self.pc += 1;
+                self.stopped = true;
+            },
+            OP::STOP => {
+                self.pc += 1;
+                self.stopped = true;
+            },
+            OP::INVALID => {
+                self.gas_used = self.env.gas_limit;
+                self.halt = Some(Halt::InvalidInstruction);
+                return false;
+            },
+            OP::RETURN | OP::REVERT => {
+                let offset = self.stack.pop().unwrap().as_usize();
+                let len = self.stack.pop().unwrap().as_usize();
+                if len > self.env.limits.return_data {
+                    self.halt = Some(Halt::ReturnDataLimitExceeded);
+                    return false;
+                }
+                let mut data = vec![];
+                if len > 0 {
+                    self.expand_memory(offset, len);
+                    data = self.memory[offset..offset + len].to_vec();
+                }
+                self.pc += 1;
+                if self.program[self.pc - 1] == OP::REVERT {
+                    self.halt = Some(Halt::Revert(data));
+                    return false;
+                }
+                self.return_data = data;
+                self.stopped = true;
+            },
+            OP::JUMP | OP::JUMPI => {
+                let dest = self.stack.pop().unwrap();
+                let taken = self.program[self.pc] == OP::JUMP || self.stack.pop().unwrap() != U256::ZERO;
+                if !taken {
+                    self.pc += 1;
+                } else if let Some(target) = self.jump_target(dest) {
+                    self.pc = target;
+                } else {
+                    self.halt = Some(Halt::InvalidJump);
+                    return false;
+                }
+            },
+            OP::JUMPDEST => self.pc += 1,
+            OP::PC => {
+                self.stack.push32(U256::from(self.offsets[self.pc] as u64));
+                self.pc += 1;
+            },
+            OP::GAS => {
+                self.stack.push32(U256::from(self.gas_left()));
+                self.pc += 1;
+            },
+            OP::ISZERO => {
+                let top = self.stack.pop().unwrap();
+
+                if top == U256::ZERO {
+                    self.stack.push32(U256::ONE);
+                } else {
+                    self.stack.push32(U256::ZERO);
+                }
+                self.pc += 1;
+            },
+            OP::ADD | OP::SUB | OP::MUL | OP::DIV => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(match self.program[self.pc] {
+                    OP::ADD => a.wrapping_add(b),
+                    OP::SUB => a.wrapping_sub(b),
+                    OP::MUL => a.wrapping_mul(b),
+                    _ => a.checked_div(b).unwrap_or_default(),
+                });
+                self.pc += 1;
+            },
+            OP::MOD => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(a.checked_rem(b).unwrap_or_default());
+                self.pc += 1;
+            },
+            OP::ADDMOD | OP::MULMOD => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                let n = self.stack.pop().unwrap();
+                self.stack.push32(if self.program[self.pc] == OP::ADDMOD { addmod(a, b, n) } else { mulmod(a, b, n) });
+                self.pc += 1;
+            },
+            OP::EXP => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(exp(a, b));
+                self.pc += 1;
+            },
+            OP::SDIV | OP::SMOD => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(if self.program[self.pc] == OP::SDIV { sdiv(a, b) } else { smod(a, b) });
+                self.pc += 1;
+            },
+            OP::LT | OP::GT | OP::SLT | OP::SGT | OP::EQ => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                let holds = match self.program[self.pc] {
+                    OP::LT => a < b,
+                    OP::GT => a > b,
+                    OP::SLT => a.as_i256() < b.as_i256(),
+                    OP::SGT => a.as_i256() > b.as_i256(),
+                    _ => a == b,
+                };
+                self.stack.push32(U256::from(holds));
+                self.pc += 1;
+            },
+            OP::AND | OP::OR | OP::XOR => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(match self.program[self.pc] {
+                    OP::AND => a & b,
+                    OP::OR => a | b,
+                    _ => a ^ b,
+                });
+                self.pc += 1;
+            },
+            OP::NOT => {
+                let a = self.stack.pop().unwrap();
+                self.stack.push32(!a);
+                self.pc += 1;
+            },
+            OP::BYTE | OP::SHL | OP::SHR | OP::SAR => {
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                self.stack.push32(match self.program[self.pc] {
+                    OP::BYTE => byte(a, b),
+                    OP::SHL => shl(a, b),
+                    OP::SHR => shr(a, b),
+                    _ => sar(a, b),
+                });
+                self.pc += 1;
+            },
+            OP::KECCAK256 => {
+                let offset = self.stack.pop().unwrap().as_usize();
+                let len = self.stack.pop().unwrap().as_usize();
+                let data = if len > 0 {
+                    self.expand_memory(offset, len);
+                    self.memory[offset..offset + len].to_vec()
+                } else {
+                    vec![]
+                };
+                let hash = U256::from_be_bytes(keccak(&data).0);
+                self.preimages.insert(hash, data);
+                self.stack.push32(hash);
+                self.pc += 1;
+            },
+            OP::MLOAD => {
+                let offset = self.stack.pop().unwrap().as_usize();
+                self.expand_memory(offset, 32);
+                let word = U256::from_be_bytes(self.memory[offset..offset + 32].try_into().unwrap());
+                self.stack.push32(word);
+                self.pc += 1;
+            },
+            OP::MSTORE | OP::MSTORE8 => {
+                let offset = self.stack.pop().unwrap().as_usize();
+                let value = self.stack.pop().unwrap();
+                if self.program[self.pc] == OP::MSTORE {
+                    self.expand_memory(offset, 32);
+                    self.memory[offset..offset + 32].copy_from_slice(&value.to_be_bytes());
+                } else {
+                    self.expand_memory(offset, 1);
+                    self.memory[offset] = value.to_be_bytes()[31];
+                }
+                self.pc += 1;
+            },
+            OP::MSIZE => {
+                self.stack.push32(U256::from(self.memory.len() as u64));
+                self.pc += 1;
+            },
+            OP::MCOPY => {
+                let dest = self.stack.pop().unwrap().as_usize();
+                let offset = self.stack.pop().unwrap().as_usize();
+                let len = self.stack.pop().unwrap().as_usize();
+                if len > 0 {
+                    self.expand_memory(dest.max(offset), len);
+                    self.memory.copy_within(offset..offset + len, dest);
+                }
+                self.pc += 1;
+            },
+            OP::BLOCKHASH => {
+                let number = self.stack.pop().unwrap();
+                let current = U256::from(self.block.number);
+                let hash = match u64::try_from(number) {
+                    Ok(n) if number < current && current - number <= 256 => self.block.hashes.get(&n).copied(),
+                    _ => None,
+                };
+                self.stack.push32(hash.map(U256::from_be_bytes).unwrap_or_default());
+                self.pc += 1;
+            },
+            OP::COINBASE => {
+                self.stack.push32(self.block.coinbase.to_word());
+                self.pc += 1;
+            },
+            OP::TIMESTAMP => {
+                self.stack.push32(U256::from(self.block.timestamp));
+                self.pc += 1;
+            },
+            OP::NUMBER => {
+                self.stack.push32(U256::from(self.block.number));
+                self.pc += 1;
+            },
+            OP::PREVRANDAO => {
+                self.stack.push32(self.block.prevrandao);
+                self.pc += 1;
+            },
+            OP::GASLIMIT => {
+                self.stack.push32(U256::from(self.block.gas_limit));
+                self.pc += 1;
+            },
+            OP::CHAINID => {
+                self.stack.push32(U256::from(self.block.chain_id));
+                self.pc += 1;
+            },
+            OP::BASEFEE => {
+                self.stack.push32(self.block.base_fee);
+                self.pc += 1;
+            },
+            OP::SIGNEXTEND => {
+                let b = self.stack.pop().unwrap();
+                let x = self.stack.pop().unwrap();
+                self.stack.push32(signextend(b, x));
+                self.pc += 1;
+            },
+        }
+        inspector.step_end(self, storage);
+        !self.is_finished()
+    }
+}
+
+/// `(a + b) % n` computed without overflow, zero if `n` is zero.
+pub fn addmod(a: U256, b: U256, n: U256) -> U256 {
+    if n == U256::ZERO {
+        return U256::ZERO;
+    }
+    let (a, b) = (a % n, b % n);
+    // a + b < 2n, so one subtraction is enough, even when the sum wraps.
+    let (sum, carry) = a.overflowing_add(b);
+    if carry || sum >= n { sum.wrapping_sub(n) } else { sum }
+}
+
+/// `(a * b) % n` computed without overflow, zero if `n` is zero.
+pub fn mulmod(a: U256, b: U256, n: U256) -> U256 {
+    if n == U256::ZERO {
+        return U256::ZERO;
+    }
+    let a = a % n;
+    (0..256 - b.leading_zeros()).rev().fold(U256::ZERO, |acc, bit| {
+        let acc = addmod(acc, acc, n);
+        if (b >> bit) & U256::ONE == U256::ONE { addmod(acc, a, n) } else { acc }
+    })
+}
+
+/// `base ** exponent`, wrapping modulo 2^256.
+pub fn exp(base: U256, exponent: U256) -> U256 {
+    (0..256 - exponent.leading_zeros()).rev().fold(U256::ONE, |acc, bit| {
+        let acc = acc.wrapping_mul(acc);
+        if (exponent >> bit) & U256::ONE == U256::ONE { acc.wrapping_mul(base) } else { acc }
+    })
+}
+
+/// Signed `a / b`, zero if `b` is zero.
+pub fn sdiv(a: U256, b: U256) -> U256 {
+    if b == U256::ZERO {
+        return U256::ZERO;
+    }
+    a.as_i256().wrapping_div(b.as_i256()).as_u256()
+}
+
+/// Signed `a % b`, zero if `b` is zero.
+pub fn smod(a: U256, b: U256) -> U256 {
+    if b == U256::ZERO {
+        return U256::ZERO;
+    }
+    a.as_i256().wrapping_rem(b.as_i256()).as_u256()
+}
+
+/// `x` with bit `8 * b + 7` copied into every bit above it. Unchanged for `b >= 31`.
+pub fn signextend(b: U256, x: U256) -> U256 {
+    if b >= 31 {
+        return x;
+    }
+    let bit = b.as_u32() * 8 + 7;
+    let mask = (U256::ONE << (bit + 1)) - 1;
+    if (x >> bit) & U256::ONE == U256::ONE { x | !mask } else { x & mask }
+}
+
+/// Gas for `words` of memory: linear, plus quadratic once memory gets large.
+fn memory_cost(words: u64) -> u64 {
+    G_MEMORY * words + words * words / 512
+}
+
+/// Byte `i` of `x`, counting from the most significant, zero if `i >= 32`.
+pub fn byte(i: U256, x: U256) -> U256 {
+    if i >= 32 {
+        return U256::ZERO;
+    }
+    U256::from(x.to_be_bytes()[i.as_usize()])
+}
+
+/// `value << shift`, zero once everything is shifted out.
+pub fn shl(shift: U256, value: U256) -> U256 {
+    if shift >= 256 { U256::ZERO } else { value << shift.as_u32() }
+}
+
+/// `value >> shift`, filling with zeros.
+pub fn shr(shift: U256, value: U256) -> U256 {
+    if shift >= 256 { U256::ZERO } else { value >> shift.as_u32() }
+}
+
+/// `value >> shift`, filling with copies of the sign bit.
+pub fn sar(shift: U256, value: U256) -> U256 {
+    let value = value.as_i256();
+    (if shift >= 256 { value >> 255 } else { value >> shift.as_u32() }).as_u256()
+}
+
+/// Pending once, waking itself first, so the executor gets to run other tasks.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+pub struct VmBuilder<'a> {
+    program: Vec<OP>,
+    calldata: &'a [u8],
+    env: Env,
+    context: Option<CallContext>,
+    block: Option<BlockEnv>,
+    hosts: HostFunctions,
+    calls: Option<&'a mut dyn CallHandler>,
+    tx_state: TxState,
+}
+
+impl<'a> VmBuilder<'a> {
+    pub fn new(program: Vec<OP>) -> Self {
+        Self {
+            program,
+            calldata: &[],
+            env: Env::default(),
+            context: None,
+            block: None,
+            hosts: HostFunctions::new(),
+            calls: None,
+            tx_state: TxState::default(),
+        }
+    }
+
+    pub fn calldata(mut self, calldata: &'a [u8]) -> Self {
+        self.calldata = calldata;
+        self
+    }
+
+    pub fn env(mut self, env: Env) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Overrides the call context, which is otherwise taken from the `env`.
+    pub fn context(mut self, context: CallContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Overrides the block, which is otherwise taken from the `env`.
+    pub fn block(mut self, block: BlockEnv) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    pub fn hosts(mut self, hosts: HostFunctions) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    /// Runs the `CALL`s the code makes, which otherwise all fail.
+    pub fn calls(mut self, calls: &'a mut dyn CallHandler) -> Self {
+        self.calls = Some(calls);
+        self
+    }
+
+    /// Continues a transaction that earlier calls already ran in.
+    pub fn tx_state(mut self, tx_state: TxState) -> Self {
+        self.tx_state = tx_state;
+        self
+    }
+
+    pub fn build(self) -> VM<'a> {
+        VM {
+            stack: Stack::new(),
+            offsets: code_offsets(&self.program),
+            code: assemble(&self.program),
+            program: self.program,
+            pc: 0,
+            calldata: self.calldata,
+            context: self.context.unwrap_or_else(|| self.env.context()),
+            block: self.block.unwrap_or_else(|| self.env.block()),
+            hosts: self.hosts,
+            calls: self.calls,
+            env: self.env,
+            gas_used: 0,
+            halt: None,
+            memory: vec![],
+            return_data: vec![],
+            call_return_data: vec![],
+            logs: vec![],
+            preimages: BTreeMap::new(),
+            warm_slots: self.tx_state.warm_slots,
+            original: self.tx_state.original,
+            transient: self.tx_state.transient,
+            journal: vec![],
+            stopped: false,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct Contract {
     pub name: String,
-    pub functions: HashMap<String, Function>,
-    pub variable_map: HashMap<String, usize>,
+    /// Keyed by selector. Ordered maps keep compilation output deterministic.
+    pub functions: BTreeMap<[u8; 4], Function>,
+    pub variable_map: BTreeMap<String, usize>,
+    pub variable_types: BTreeMap<String, Type>,
     pub storage: ContractStorage,
+    pub errors: Vec<AbiError>,
+    pub events: Vec<AbiEvent>,
+    /// Problems found while compiling, e.g. function bodies code generation can't express yet.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Appended to the runtime code when set.
+    pub metadata: Option<Metadata>,
+    /// Runs for calls with empty calldata.
+    pub receive: Option<Function>,
+    /// Runs when no function matches, or for empty calldata without `receive`.
+    pub fallback: Option<Function>,
+    /// Runs once, when the contract is deployed. See `construct`.
+    pub constructor: Option<Function>,
+    /// Inputs of the hashes computed by calls so far, keyed by hash, which tell mapping
+    /// and array slots apart. See `explain_slot`.
+    pub preimages: BTreeMap<U256, Vec<u8>>,
 }
 
 impl Contract {
@@ -151,45 +1580,303 @@ impl Contract {
         }
     }
 
-    pub fn call(&self, calldata: &str) -> (Contract, Vec<Expression>) {
-        match self.functions.get(&calldata.to_string()) {
-            Some(function) => {
-                let mut vm = VM::new(function.program.clone(), calldata.as_bytes());
-                let new_storage = vm.run(self.storage.clone());
-        
-                //Read return values from stack
-                let mut ret: Vec<Expression> = vec![];
-                function.returns.iter().for_each(|param| {
-                    if let Some(r) = vm.stack.pop() {
-                        match param {
-                            Parameter { ty: Expression::Type(Type::Bool(_)), .. } => {
-                                    ret.push(Expression::BoolLiteral(r == U256::ONE));
-                            },
-                            _ => {},
-                        }
-                    }
-                });
-        
-                (Contract {
-                    storage: if let FuncMutability::View | FuncMutability::Pure = function.mutability { self.storage.clone() } else { new_storage },
-                    ..self.clone()
-                }, ret)
+    /// Adds `function` under `selector`, refusing to replace a function already there:
+    /// either the same signature defined twice or a different one whose selector collides.
+    pub fn insert_function(&mut self, selector: [u8; 4], function: Function) -> Result<(), Diagnostic> {
+        let Some(existing) = self.functions.get(&selector) else {
+            self.functions.insert(selector, function);
+            return Ok(());
+        };
+        let (first, second) = (existing.signature(), function.signature());
+        Err(if first == second {
+            Diagnostic::error("duplicate-function", format!("function `{}` is defined more than once", second))
+        } else {
+            Diagnostic::error("selector-collision", format!("functions `{}` and `{}` have the same selector {}", first, second, to_hex(&selector)))
+        })
+    }
+
+    fn entry(&self, entry: &Entry) -> &Function {
+        match entry {
+            Entry::Function(selector) => &self.functions[selector],
+            Entry::Receive => self.receive.as_ref().unwrap(),
+            Entry::Fallback => self.fallback.as_ref().unwrap(),
+            Entry::Constructor => self.constructor.as_ref().unwrap(),
+        }
+    }
+
+    fn entry_mut(&mut self, entry: &Entry) -> &mut Function {
+        match entry {
+            Entry::Function(selector) => self.functions.get_mut(selector).unwrap(),
+            Entry::Receive => self.receive.as_mut().unwrap(),
+            Entry::Fallback => self.fallback.as_mut().unwrap(),
+            Entry::Constructor => self.constructor.as_mut().unwrap(),
+        }
+    }
+
+    pub fn call(&self, calldata: &str) -> Result<(Contract, Vec<Expression>, Vec<LogEntry>), Halt> {
+        self.call_with_env(calldata, Env::default())
+    }
+
+    pub fn call_with_env(&self, calldata: &str, env: Env) -> Result<(Contract, Vec<Expression>, Vec<LogEntry>), Halt> {
+        self.call_with_inspector(calldata, env, &mut NoopInspector)
+    }
+
+    /// Runs the function selected by `calldata`, returning the updated contract, the
+    /// return values and the logs emitted, or why the call halted.
+    pub fn call_with_inspector(&self, calldata: &str, env: Env, inspector: &mut dyn Inspector) -> Result<(Contract, Vec<Expression>, Vec<LogEntry>), Halt> {
+        let execution = self.execute(calldata, env, inspector);
+        match execution.halt {
+            Some(halt) => Err(halt),
+            None => Ok((execution.contract, execution.output, execution.logs)),
+        }
+    }
+
+    /// The function selected by the first four bytes of `calldata`, a hex string that may
+    /// carry ABI-encoded arguments after the selector. Like Solidity, empty calldata goes
+    /// to `receive` and anything unmatched to `fallback`.
+    pub fn dispatch(&self, calldata: &str) -> Option<&Function> {
+        if let (true, Some(receive)) = (calldata.is_empty(), &self.receive) {
+            return Some(receive);
+        }
+        let selector = calldata.get(..8).and_then(from_hex).and_then(|bytes| <[u8; 4]>::try_from(bytes).ok());
+        selector.and_then(|selector| self.functions.get(&selector)).or(self.fallback.as_ref())
+    }
+
+    pub fn execute(&self, calldata: &str, env: Env, inspector: &mut dyn Inspector) -> Execution {
+        self.execute_in_tx(calldata, env, inspector, &mut TxState::default())
+    }
+
+    /// Like `execute`, as one of several calls in a transaction. `tx_state` is updated
+    /// unless the call halts, since a failed call's accesses are rolled back.
+    pub fn execute_in_tx(&self, calldata: &str, env: Env, inspector: &mut dyn Inspector, tx_state: &mut TxState) -> Execution {
+        self.execute_with(&Interpreter, calldata, env, inspector, tx_state)
+    }
+
+    /// Like `execute_in_tx`, running the code on `executor` instead of the interpreter.
+    pub fn execute_with(&self, executor: &dyn Executor, calldata: &str, env: Env, inspector: &mut dyn Inspector, tx_state: &mut TxState) -> Execution {
+        self.execute_on_executor(calldata, env, tx_state, |program, data, env, storage, tx_state| {
+            executor.execute(program, data, env, storage, tx_state, inspector)
+        })
+    }
+
+    /// Like `execute_with`, with `calls` running the `CALL`s the code makes.
+    pub fn execute_with_calls(&self, executor: &dyn Executor, calldata: &str, env: Env, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Execution {
+        self.execute_on_executor(calldata, env, tx_state, |program, data, env, storage, tx_state| {
+            executor.execute_with_calls(program, data, env, storage, tx_state, calls)
+        })
+    }
+
+    /// Like `execute_with`, running on `storage` instead of `self.storage`, which the
+    /// returned contract keeps. `storage` is only written if the call succeeds, and never
+    /// by a `view` or `pure` function.
+    pub fn execute_on_storage(&self, executor: &dyn Executor, calldata: &str, env: Env, storage: &mut dyn Storage, inspector: &mut dyn Inspector, tx_state: &mut TxState) -> Execution {
+        self.run_on_storage(calldata, env, storage, tx_state, |program, data, env, storage, tx_state| {
+            executor.execute(program, data, env, storage, tx_state, inspector)
+        })
+    }
+
+    /// Like `execute_on_storage`, with `calls` running the `CALL`s the code makes.
+    pub fn execute_on_storage_with_calls(&self, executor: &dyn Executor, calldata: &str, env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Execution {
+        self.run_on_storage(calldata, env, storage, tx_state, |program, data, env, storage, tx_state| {
+            executor.execute_with_calls(program, data, env, storage, tx_state, calls)
+        })
+    }
+
+    fn execute_on_executor(&self, calldata: &str, env: Env, tx_state: &mut TxState, execute: impl FnOnce(&[OP], &[u8], Env, &mut dyn Storage, &mut TxState) -> Outcome) -> Execution {
+        let mut storage = self.storage.clone();
+        let execution = self.run_on_storage(calldata, env, &mut storage, tx_state, execute);
+        Execution { contract: Contract { storage, ..execution.contract }, ..execution }
+    }
+
+    /// Runs the constructor with `args`, which it reads the way other functions read
+    /// their arguments: ABI-encoded after four zero bytes standing in for a selector. A
+    /// contract without a constructor keeps its initial storage.
+    pub fn construct(&self, executor: &dyn Executor, args: &[Value], env: Env) -> Execution {
+        let default = Function::default();
+        let constructor = self.constructor.as_ref().unwrap_or(&default);
+        if env.value != U256::ZERO && !matches!(constructor.mutability, FuncMutability::Payable) {
+            return self.halted(Halt::NonPayableFunctionReceivedValue);
+        }
+        let data = [&[0u8; 4][..], &encode(args)].concat();
+        let mut storage = self.storage.clone();
+        let execution = self.run_function(constructor, &data, env, &mut storage, &mut TxState::default(), |program, data, env, storage, tx_state| {
+            executor.execute(program, data, env, storage, tx_state, &mut NoopInspector)
+        });
+        Execution { contract: Contract { storage, ..execution.contract }, ..execution }
+    }
+
+    fn run_on_storage(&self, calldata: &str, env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, execute: impl FnOnce(&[OP], &[u8], Env, &mut dyn Storage, &mut TxState) -> Outcome) -> Execution {
+        let function = match self.select(calldata, &env) {
+            Ok(function) => function,
+            Err(halt) => return self.halted(halt),
+        };
+        self.run_function(function, &from_hex(calldata).unwrap_or_default(), env, storage, tx_state, execute)
+    }
+
+    fn run_function(&self, function: &Function, data: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, execute: impl FnOnce(&[OP], &[u8], Env, &mut dyn Storage, &mut TxState) -> Outcome) -> Execution {
+        let limits = env.limits;
+        let mut overlay = Overlay::new(storage);
+        let mut new_tx_state = tx_state.clone();
+        let outcome = execute(&function.program, data, env, &mut overlay, &mut new_tx_state);
+        let execution = self.finish(function, outcome, &limits);
+        if execution.halt.is_none() {
+            *tx_state = new_tx_state;
+            if !matches!(function.mutability, FuncMutability::View | FuncMutability::Pure) {
+                overlay.commit();
             }
+        }
+        execution
+    }
+
+    /// Like `call_with_env`, but yields to the async executor every `steps_per_yield`
+    /// instructions, so long executions don't block a worker thread.
+    pub async fn call_async(&self, calldata: &str, env: Env, steps_per_yield: usize) -> Result<(Contract, Vec<Expression>, Vec<LogEntry>), Halt> {
+        let function = self.select(calldata, &env)?;
+        let data = from_hex(calldata).unwrap_or_default();
+        let mut vm = VM::builder(function.program.clone())
+            .calldata(&data)
+            .env(env)
+            .build();
+        let mut storage = vm.run_async(self.storage.clone(), steps_per_yield).await;
+        let logs = if vm.halt.is_none() { vm.logs().to_vec() } else { vec![] };
+        let outcome = Outcome {
+            stack: vm.stack.as_slice().to_vec(),
+            return_data: vm.return_data().to_vec(),
+            logs,
+            preimages: vm.preimages().clone(),
+            gas_used: vm.gas_used,
+            halt: vm.halt,
+        };
+        let execution = self.finish(function, outcome, &vm.env.limits);
+        if let FuncMutability::View | FuncMutability::Pure = function.mutability {
+            storage = self.storage.clone();
+        }
+        match execution.halt {
+            Some(halt) => Err(halt),
+            None => Ok((Contract { storage, ..execution.contract }, execution.output, execution.logs)),
+        }
+    }
+
+    /// The function `calldata` runs, or why the call halts before running any code.
+    fn select(&self, calldata: &str, env: &Env) -> Result<&Function, Halt> {
+        match self.dispatch(calldata) {
+            Some(function) if env.value != U256::ZERO && !matches!(function.mutability, FuncMutability::Payable) => {
+                Err(Halt::NonPayableFunctionReceivedValue)
+            },
+            Some(function) => Ok(function),
             None => {
-                return (self.clone(), vec![]);
+                let mut selector = [0u8; 4];
+                let data = from_hex(calldata.get(..8).unwrap_or(calldata)).unwrap_or_default();
+                selector[..data.len().min(4)].copy_from_slice(&data[..data.len().min(4)]);
+                Err(Halt::FunctionNotFound(selector))
             }
         }
     }
+
+    fn halted(&self, halt: Halt) -> Execution {
+        Execution {
+            contract: self.clone(),
+            output: vec![],
+            return_data: vec![],
+            logs: vec![],
+            preimages: BTreeMap::new(),
+            gas_used: 0,
+            halt: Some(halt),
+        }
+    }
+
+    /// Collects the `outcome` of running `function`, leaving the contract's storage as it
+    /// is for the caller to update.
+    fn finish(&self, function: &Function, outcome: Outcome, limits: &ResourceLimits) -> Execution {
+        if outcome.halt.is_none() && outcome.return_data.len() > limits.return_data {
+            return Execution { preimages: outcome.preimages, gas_used: outcome.gas_used, ..self.halted(Halt::ReturnDataLimitExceeded) };
+        }
+
+        // Decode the return values from the returned words.
+        let mut ret: Vec<Expression> = vec![];
+        if outcome.halt.is_none() {
+            function.returns.iter().zip(outcome.return_data.chunks_exact(32)).for_each(|(param, word)| {
+                let r = U256::from_be_bytes(word.try_into().unwrap());
+                match param.ty {
+                    Expression::Type(Type::Bool(_)) => ret.push(Expression::BoolLiteral(r == U256::ONE)),
+                    Expression::Type(Type::Uint256(_) | Type::Int256(_) | Type::Address(_)) => ret.push(Expression::NumberLiteral(r)),
+                    _ => {},
+                }
+            });
+        }
+
+        let mut preimages = self.preimages.clone();
+        preimages.extend(outcome.preimages.clone());
+        Execution {
+            contract: Contract { preimages, ..self.clone() },
+            output: ret,
+            return_data: outcome.return_data,
+            logs: outcome.logs,
+            preimages: outcome.preimages,
+            gas_used: outcome.gas_used,
+            halt: outcome.halt,
+        }
+    }
+}
+
+/// Outcome of running one function of a contract.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub contract: Contract,
+    /// The return values, decoded from `return_data`.
+    pub output: Vec<Expression>,
+    /// ABI-encoded bytes the function returned.
+    pub return_data: Vec<u8>,
+    pub logs: Vec<LogEntry>,
+    /// Inputs of the `KECCAK256` instructions this execution ran, by hash, kept even if it
+    /// halts.
+    pub preimages: BTreeMap<U256, Vec<u8>>,
+    pub gas_used: u64,
+    pub halt: Option<Halt>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Function {
     program: Vec<OP>,
+    /// Byte span in the source of the statement each instruction was compiled from.
+    pub source_map: Vec<(usize, usize)>,
+    pub name: String,
+    pub params: Vec<Parameter>,
     pub visibility: FuncVisibility,
     pub mutability: FuncMutability,
     pub returns: Vec<Parameter>,
 }
 
+impl Function {
+    pub fn from_program(name: String, program: Vec<OP>) -> Self {
+        Self {
+            program,
+            name,
+            ..Function::default()
+        }
+    }
+
+    pub fn program(&self) -> &[OP] {
+        &self.program
+    }
+
+    /// Replaces the compiled code. `source_map` must be kept in step by the caller.
+    pub(crate) fn set_program(&mut self, program: Vec<OP>) {
+        self.program = program;
+    }
+
+    /// Canonical signature, e.g. `set(bool)`.
+    pub fn signature(&self) -> String {
+        let types = self.params.iter().map(|p| param_type_name(p).unwrap_or_default()).collect::<Vec<_>>();
+        format!("{}({})", self.name, types.join(","))
+    }
+
+    /// Source span of the instruction at `pc`, if the function was compiled from source.
+    pub fn span(&self, pc: usize) -> Option<(usize, usize)> {
+        self.source_map.get(pc).copied()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub enum FuncVisibility {
     Public,
@@ -209,60 +1896,199 @@ pub enum FuncMutability {
 }
 
 pub fn create_contracts(source_unit: SourceUnit) -> Vec<Contract> {
-    handle_source_unit(source_unit)
+    compile(source_unit, &CompilerSettings::default())
 }
 
-fn handle_source_unit(source_unit: SourceUnit) -> Vec<Contract> {
-    source_unit.parts.iter().flat_map(|part| handle_source_unit_part(part.clone())).collect::<Vec<Contract>>()
+pub fn compile(source_unit: SourceUnit, settings: &CompilerSettings) -> Vec<Contract> {
+    handle_source_unit(source_unit, settings)
 }
 
-fn handle_source_unit_part(part: SourceUnitPart) -> Option<Contract> {
+/// Parses and compiles `source`, attaching metadata when `settings` asks for it. Source
+/// that doesn't parse gives error diagnostics, naming any unsupported constructs in it.
+pub fn compile_source(source: &str, settings: &CompilerSettings) -> Result<Vec<Contract>, Vec<Diagnostic>> {
+    let mut contracts = compile(parse_source(source)?, settings);
+    if settings.metadata {
+        contracts.iter_mut().for_each(|contract| contract.metadata = Some(Metadata::for_source(source)));
+    }
+    Ok(contracts)
+}
+
+fn handle_source_unit(source_unit: SourceUnit, settings: &CompilerSettings) -> Vec<Contract> {
+    source_unit.parts.iter().flat_map(|part| handle_source_unit_part(part.clone(), settings)).collect::<Vec<Contract>>()
+}
+
+/// Which function of a contract a body belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// A dispatchable function, by selector.
+    Function([u8; 4]),
+    Receive,
+    Fallback,
+    Constructor,
+}
+
+/// A function body waiting for code generation.
+type Body = (Entry, Vec<Spanned<Statement>>);
+
+/// The source span from the first statement of `body` to the end of the last.
+fn body_span(body: &[Spanned<Statement>]) -> Option<(usize, usize)> {
+    Some((body.first()?.span.0, body.last()?.span.1))
+}
+
+fn handle_source_unit_part(part: SourceUnitPart, settings: &CompilerSettings) -> Option<Contract> {
     match part {
-        SourceUnitPart::ContractDefinition(_, name, _, parts, _) => {
+        SourceUnitPart::ContractDefinition(_, Identifier { name }, _, parts, _) => {
             let mut contract = Contract::new(name);
-            let _ = parts.iter().map(|part| handle_contract_part(part.clone(), &mut contract)).collect::<Vec<_>>();
+            let mut bodies = vec![];
+            parts.iter().for_each(|part| handle_contract_part(part.clone(), &mut contract, &mut bodies));
+            compile_bodies(&mut contract, bodies, settings);
             Some(contract)
         },
-        _ => None,
     }
 }
 
-fn handle_contract_part(part: ContractPart, contract: &mut Contract) {
-    match part {
-        ContractPart::FunctionDefinition(_, name, params, attr_list, ret_params, _, statement, _) => {
-            if let Some(statement) = statement {
-                //TODO: handle function arguments
-                let program = handle_statement(statement, contract);
-                
-                let (visibility, mutability) = handle_attrs(attr_list.clone());
-                
-                let mut returns = vec![];
-                if let Some(FunctionReturnParams::ParameterList(_, ParameterList::Params(_, Some(ret_param), _))) = ret_params.clone() {
-                    returns = ret_param.params;
+/// Generates code once every function and state variable of the contract is known, so
+/// bodies can refer to both regardless of declaration order.
+fn compile_bodies(contract: &mut Contract, bodies: Vec<Body>, settings: &CompilerSettings) {
+    let lowered = bodies.iter().map(|(entry, body)| {
+        let scope = Scope {
+            slots: &contract.variable_map,
+            types: &contract.variable_types,
+            params: &contract.entry(entry).params,
+            events: &contract.events,
+        };
+        lower_body(body, &scope)
+    }).collect::<Vec<_>>();
+    let callees = bodies.iter().zip(&lowered)
+        .filter_map(|((entry, _), ir)| Some((contract.entry(entry).name.clone(), ir.clone().ok()?)))
+        .collect::<HashMap<String, IrFunction>>();
+    let optimized = lowered.into_iter().map(|ir| ir.map(|mut ir| {
+        if settings.enabled(|p| p.inline) {
+            ir.inline_calls(&callees, settings.inline_threshold());
+        }
+        if settings.enabled(|p| p.fold_constants) {
+            ir.fold_constants();
+        }
+        if settings.enabled(|p| p.eliminate_redundant_loads) {
+            ir.eliminate_redundant_loads();
+        }
+        ir
+    })).collect::<Vec<_>>();
+    // Calls left after inlining jump to a copy of the optimized callee.
+    let internal = bodies.iter().zip(&optimized)
+        .filter_map(|((entry, _), ir)| Some((contract.entry(entry).name.clone(), ir.as_ref().ok()?.clone())))
+        .collect::<HashMap<String, IrFunction>>();
+    for ((entry, body), ir) in bodies.into_iter().zip(optimized) {
+        let program = ir.and_then(|ir| ir.emit_with(&internal));
+        let span = body_span(&body);
+        match program {
+            Ok(program) => {
+                let function = contract.entry_mut(&entry);
+                if settings.debug_info == DebugInfo::Statements {
+                    function.source_map = span.map_or(vec![], |span| vec![span; program.len()]);
                 }
+                function.program = program;
+            },
+            Err(e) => {
+                let diagnostic = Diagnostic::error("codegen", format!("cannot compile function `{}`: {}", contract.entry(&entry).name, e));
+                contract.diagnostics.push(match span {
+                    Some(span) => diagnostic.with_span(span),
+                    None => diagnostic,
+                });
+            },
+        }
+    }
+}
 
-                contract.functions.insert(
-                    find_function_signature(name.clone(), params.clone()),
-                    Function {
-                        program: program,
-                        visibility: visibility,
-                        mutability: mutability,
-                        returns: returns,
-                        ..Function::default()
-                    }
-                );
+fn handle_contract_part(part: ContractPart, contract: &mut Contract, bodies: &mut Vec<Body>) {
+    match part {
+        ContractPart::FunctionDefinition(_, Identifier { name }, params, attr_list, ret_params, _, statements, _) => {
+            let (visibility, mutability) = handle_attrs(attr_list.clone());
+            
+            let mut returns = vec![];
+            if let Some(FunctionReturnParams::ParameterList(_, ParameterList::Params(_, Some(ret_param), _))) = ret_params.clone() {
+                returns = ret_param.params;
+            }
+
+            let mut param_list = vec![];
+            if let ParameterList::Params(_, Some(p), _) = params.clone() {
+                param_list = p.params;
+            }
+
+            let selector = find_function_signature(name.clone(), params.clone());
+            let function = Function {
+                program: vec![],
+                source_map: vec![],
+                name,
+                params: param_list,
+                visibility,
+                mutability,
+                returns,
+            };
+            match contract.insert_function(selector, function) {
+                Ok(()) => bodies.push((Entry::Function(selector), statements)),
+                Err(diagnostic) => contract.diagnostics.push(match body_span(&statements) {
+                    Some(span) => diagnostic.with_span(span),
+                    None => diagnostic,
+                }),
             }
         },
-        ContractPart::VariableDefinition(ty, visibility, name, _) => {
+        ContractPart::VariableDefinition(ty, _visibility, Identifier { name }, _) => {
+            contract.variable_types.insert(name.clone(), ty);
             contract.variable_map.insert(name, contract.variable_map.len());
             contract.storage.slots.push(U256::ZERO);
         },
-        ContractPart::ConstructorDefinition(_, params, attr_list, _, statement, _) => {
-            //TODO
+        ContractPart::ConstructorDefinition(_, params, attr_list, _, statements, _) => {
+            let params = match params {
+                ParameterList::Params(_, Some(p), _) => p.params,
+                _ => vec![],
+            };
+            handle_special_function(Entry::Constructor, params, attr_list, statements, contract, bodies);
+        },
+        ContractPart::ReceiveDefinition(_, _params, attr_list, _, statements, _) => {
+            handle_special_function(Entry::Receive, vec![], attr_list, statements, contract, bodies);
+        },
+        ContractPart::FallbackDefinition(_, _params, attr_list, _, statements, _) => {
+            handle_special_function(Entry::Fallback, vec![], attr_list, statements, contract, bodies);
+        },
+        ContractPart::EventDefinition(_, Identifier { name }, EventParameterList::Params(_, params, _), _) => {
+            let inputs = params.map(|p| p.params).unwrap_or_default().into_iter().map(|param| AbiEventParam {
+                name: param.name.map(|n| n.name).unwrap_or_default(),
+                ty: type_name(&param.ty),
+                indexed: param.indexed.is_some(),
+            }).collect();
+            contract.events.push(AbiEvent { name, inputs });
+        },
+        ContractPart::ErrorDefinition(_, Identifier { name }, params, _) => {
+            let inputs = match params {
+                ParameterList::Params(_, Some(p), _) => abi_params(&p.params),
+                _ => vec![],
+            };
+            contract.errors.push(AbiError { name, inputs });
         }
     }
 }
 
+/// Registers `receive`, `fallback` or the constructor, none of which has a selector.
+fn handle_special_function(entry: Entry, params: Vec<Parameter>, attr_list: Vec<Option<FunctionAttribute>>, statements: Vec<Spanned<Statement>>, contract: &mut Contract, bodies: &mut Vec<Body>) {
+    let (visibility, mutability) = handle_attrs(attr_list);
+    let (name, slot) = match entry {
+        Entry::Receive => ("receive", &mut contract.receive),
+        Entry::Constructor => ("constructor", &mut contract.constructor),
+        _ => ("fallback", &mut contract.fallback),
+    };
+    if slot.is_some() {
+        let diagnostic = Diagnostic::error("duplicate-function", format!("function `{}()` is defined more than once", name));
+        contract.diagnostics.push(match body_span(&statements) {
+            Some(span) => diagnostic.with_span(span),
+            None => diagnostic,
+        });
+        return;
+    }
+    *slot = Some(Function { name: name.to_string(), params, visibility, mutability, ..Function::default() });
+    bodies.push((entry, statements));
+}
+
 fn handle_attrs(attr_list: Vec<Option<FunctionAttribute>>) -> (FuncVisibility, FuncMutability) {
     let mut visibility = FuncVisibility::default();
     let mut mutability = FuncMutability::default();
@@ -292,76 +2118,31 @@ fn handle_attrs(attr_list: Vec<Option<FunctionAttribute>>) -> (FuncVisibility, F
     (visibility, mutability)
 }
 
-fn handle_statement(statement: Statement, contract: &mut Contract) -> Vec<OP> {
-    match statement {
-        Statement::Expression(expr, _) => {
-            handle_expression(expr, contract)
-        },
-        Statement::Return(_, expr, _) => {
-            match expr {
-                Some(expr) => [handle_expression(expr, contract), vec![OP::RETURN]].concat(),
-                None => vec![OP::RETURN],
-            }
-        },
+pub fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Bool(_) => "bool".to_string(),
+        Type::Uint256(_) => "uint256".to_string(),
+        Type::Int256(_) => "int256".to_string(),
+        Type::Address(_) => "address".to_string(),
+        Type::Mapping(_, _, key, _, value, _) => format!("mapping({} => {})", type_name(key), type_name(value)),
     }
 }
 
-fn handle_expression(expr: Expression, contract: &mut Contract) -> Vec<OP> {
-    match expr {
-        Expression::BoolLiteral(val) => {
-            vec![]
-        },
-        Expression::Variable(identifier) => {
-            let mut slot = 0;
-            if let Some(found) = contract.variable_map.get(&identifier.name.clone()) {
-                slot = *found;
-            }
-
-            vec![
-                OP::PUSH1(slot as u8),
-                OP::SLOAD
-            ]
-        },
-        Expression::Assign(left, _, right) => {
-            if let Expression::Variable(identifier) = *left {
-                let mut slot = 0;
-                if let Some(found) = contract.variable_map.get(&identifier.name.clone()) {
-                    slot = *found;
-                }
-                [handle_expression(*right, contract),
-                vec![OP::PUSH1(slot as u8), OP::SSTORE]].concat()
-            } else {
-                vec![]
-            }
-        },
-        Expression::Not(_, expr) => {
-            [handle_expression(*expr, contract), vec![OP::ISZERO]].concat()
-        },
-        Expression::Type(ty) => {
-            match ty {
-                Type::Bool(_) => vec![], //TODO
-                _ => vec![],
-            }
-        },
+/// The canonical ABI type name of a parameter, as used in signatures.
+pub fn param_type_name(param: &Parameter) -> Option<String> {
+    match &param.ty {
+        Expression::Type(ty) => Some(type_name(ty)),
+        _ => None,
     }
 }
 
-fn find_function_signature(name: String, params: ParameterList) -> String {
+fn find_function_signature(name: String, params: ParameterList) -> [u8; 4] {
     if let ParameterList::Params((), Some(p), ()) = params {
-        let params_string = p.params.iter().map(|param| {
-            match param.ty {
-                Expression::Type(Type::Bool(_)) => Some("bool"),
-                _ => None,
-            }
-        })
-        .collect::<Option<Vec<&str>>>().map(|v| v.join(",")).unwrap_or_default();
+        let params_string = p.params.iter().map(param_type_name)
+        .collect::<Option<Vec<String>>>().map(|v| v.join(",")).unwrap_or_default();
 
-        get_func_sig(format!("{}({})", name, params_string))
+        selector(&format!("{}({})", name, params_string))
     } else {
-        get_func_sig(format!("{}()", name))
+        selector(&format!("{}()", name))
     }
-}
-
-pub fn get_func_sig(in_str: String) -> String {
-    keccak(in_str.as_bytes())[..4].to_vec().iter().map(|b| format!("{:02x}", b)).collect::<String>()
 }
\ No newline at end of file
@@ -0,0 +1,404 @@
+use ethnum::U256;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::call::CallHandler;
+use crate::env::{Address, Env};
+use crate::executor::{interpret, Executor, Outcome, Storage};
+use crate::tinyvm::*;
+use crate::world::{CallResult, Transaction, World};
+
+/// One executed instruction, in the shape of an EIP-3155 trace line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    pub pc: usize,
+    pub op: u8,
+    pub op_name: String,
+    /// Gas left before the instruction.
+    pub gas: u64,
+    /// What the instruction costs. For calls this includes the gas handed to the callee,
+    /// as geth reports it.
+    pub gas_cost: u64,
+    pub stack: Vec<U256>,
+    /// 1 in the transaction's own frame, one more in each nested call.
+    pub depth: usize,
+    pub storage: Option<BTreeMap<U256, U256>>,
+}
+
+fn hex_word(word: &U256) -> String {
+    format!("{:#x}", word)
+}
+
+fn parse_word(hex: &str) -> Option<U256> {
+    let digits = hex.trim_start_matches("0x");
+    if digits.is_empty() {
+        Some(U256::ZERO)
+    } else {
+        U256::from_str_radix(digits, 16).ok()
+    }
+}
+
+/// Gas amounts are hex strings in geth's and revm's traces, but plain numbers in some
+/// older tools.
+fn parse_gas(json: &serde_json::Value) -> Option<u64> {
+    match json.as_str() {
+        Some(hex) => u64::try_from(parse_word(hex)?).ok(),
+        None => json.as_u64(),
+    }
+}
+
+impl TraceStep {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = json!({
+            "pc": self.pc,
+            "op": self.op,
+            "opName": self.op_name,
+            "gas": format!("{:#x}", self.gas),
+            "gasCost": format!("{:#x}", self.gas_cost),
+            "stack": self.stack.iter().map(hex_word).collect::<Vec<_>>(),
+            "depth": self.depth,
+        });
+        if let Some(storage) = &self.storage {
+            json["storage"] = storage.iter().map(|(k, v)| (hex_word(k), json!(hex_word(v)))).collect();
+        }
+        json
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Option<TraceStep> {
+        let storage = match json["storage"].as_object() {
+            Some(map) => Some(map.iter()
+                .map(|(k, v)| Some((parse_word(k)?, parse_word(v.as_str()?)?)))
+                .collect::<Option<BTreeMap<_, _>>>()?),
+            None => None,
+        };
+        Some(TraceStep {
+            pc: json["pc"].as_u64()? as usize,
+            op: json["op"].as_u64()? as u8,
+            op_name: json["opName"].as_str().unwrap_or_default().to_string(),
+            gas: parse_gas(&json["gas"])?,
+            gas_cost: parse_gas(&json["gasCost"])?,
+            stack: json["stack"].as_array()?.iter()
+                .map(|item| parse_word(item.as_str()?))
+                .collect::<Option<Vec<_>>>()?,
+            depth: json["depth"].as_u64().unwrap_or(1) as usize,
+            storage,
+        })
+    }
+}
+
+/// Renders steps as JSON lines.
+pub fn to_eip3155(steps: &[TraceStep]) -> String {
+    steps.iter().map(|step| format!("{}\n", step.to_json())).collect()
+}
+
+/// Parses a JSON-lines trace as written by geth's `evm --json` or revm.
+/// Summary lines without a `pc` are skipped.
+pub fn parse_eip3155(trace: &str) -> Option<Vec<TraceStep>> {
+    trace.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let json: serde_json::Value = match serde_json::from_str(line) {
+                Ok(json) => json,
+                Err(_) => return Some(None),
+            };
+            json.get("pc").map(|_| TraceStep::from_json(&json))
+        })
+        .collect()
+}
+
+/// Records every step of an execution. Like geth's struct logger, storage is only
+/// reported on SLOAD and SSTORE steps, as every slot of the running account the
+/// execution has touched so far.
+///
+/// As an `Inspector` it sees a single frame, so steps are at depth 1 and pcs are offsets
+/// into whatever program runs. To compare against a node's trace, record with
+/// `trace_call` or `trace_transaction`, which run the contract's runtime code.
+#[derive(Debug, Default)]
+pub struct TraceRecorder {
+    pub steps: Vec<TraceStep>,
+    touched: HashMap<Address, BTreeMap<U256, U256>>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the instruction `vm` is about to execute, in a frame `depth` calls deep.
+    fn record(&mut self, vm: &VM, storage: &dyn Storage, depth: usize) {
+        let op = &vm.program()[vm.pc()];
+        let touched = self.touched.entry(vm.context.address).or_default();
+        let reports_storage = match (op, vm.stack.peek(0)) {
+            (OP::SLOAD, Some(key)) => {
+                touched.insert(key, storage.load(key));
+                true
+            },
+            (OP::SSTORE, Some(key)) => {
+                touched.insert(key, vm.stack.peek(1).unwrap_or_default());
+                true
+            },
+            _ => false,
+        };
+        let gas = vm.gas_left();
+        let mut gas_cost = vm.gas_cost(storage);
+        if let OP::CALL | OP::CALLCODE | OP::DELEGATECALL | OP::STATICCALL = op {
+            // What the call forwards, worked out the way `VM` does once the cost is paid.
+            let left = gas.saturating_sub(gas_cost);
+            let requested = u64::try_from(vm.stack.peek(0).unwrap_or_default()).unwrap_or(u64::MAX);
+            gas_cost += requested.min(left - left / 64);
+        }
+        self.steps.push(TraceStep {
+            pc: vm.code_offset(),
+            op: op.opcode(),
+            op_name: op.name().to_string(),
+            gas,
+            gas_cost,
+            stack: vm.stack.as_slice().to_vec(),
+            depth,
+            storage: reports_storage.then(|| touched.clone()),
+        });
+    }
+}
+
+impl Inspector for TraceRecorder {
+    fn step(&mut self, vm: &VM, storage: &dyn Storage) {
+        self.record(vm, storage, 1);
+    }
+}
+
+/// Calls `contract` with `calldata`, running its runtime code the way a node would, and
+/// records the trace. Calls it makes fail, as `Contract::execute` has no other accounts
+/// to call; see `trace_transaction` for those.
+pub fn trace_call(contract: &Contract, calldata: &str, env: Env) -> (Execution, Vec<TraceStep>) {
+    let mut recorder = TraceRecorder::new();
+    let execution = contract.as_deployed().execute(calldata, env, &mut recorder);
+    (execution, recorder.steps)
+}
+
+/// Runs every frame of a transaction on the interpreter, recording all their steps in
+/// order, each with its frame's depth.
+#[derive(Debug, Default)]
+struct Tracer {
+    recorder: Mutex<TraceRecorder>,
+    depth: AtomicUsize,
+}
+
+/// The inspector for one frame of a `Tracer`.
+struct Frame<'a> {
+    recorder: &'a Mutex<TraceRecorder>,
+    depth: usize,
+}
+
+impl Inspector for Frame<'_> {
+    fn step(&mut self, vm: &VM, storage: &dyn Storage) {
+        self.recorder.lock().unwrap().record(vm, storage, self.depth);
+    }
+}
+
+impl Tracer {
+    fn run(&self, builder: VmBuilder, storage: &mut dyn Storage, tx_state: &mut TxState) -> Outcome {
+        let depth = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        let outcome = interpret(builder, storage, tx_state, &mut Frame { recorder: &self.recorder, depth });
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+        outcome
+    }
+}
+
+impl Executor for Tracer {
+    fn execute(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, _inspector: &mut dyn Inspector) -> Outcome {
+        self.run(VM::builder(program.to_vec()).calldata(calldata).env(env), storage, tx_state)
+    }
+
+    fn execute_with_calls(&self, program: &[OP], calldata: &[u8], env: Env, storage: &mut dyn Storage, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Outcome {
+        self.run(VM::builder(program.to_vec()).calldata(calldata).env(env).calls(calls), storage, tx_state)
+    }
+}
+
+/// Simulates `tx` on a copy of `world` whose contracts run their runtime code, as a node
+/// would, and records the trace of every frame, nested calls included. Nothing is
+/// committed.
+pub fn trace_transaction(world: &World, tx: &Transaction) -> (CallResult, Vec<TraceStep>) {
+    let mut world = world.clone();
+    world.accounts.values_mut()
+        .filter_map(|account| account.contract.as_mut())
+        .for_each(|contract| *contract = contract.as_deployed());
+    let tracer = Arc::new(Tracer::default());
+    world.executor = tracer.clone();
+    let result = world.simulate(tx);
+    let steps = std::mem::take(&mut tracer.recorder.lock().unwrap().steps);
+    (result, steps)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    Pc { ours: usize, expected: usize },
+    Op { ours: String, expected: String },
+    Stack { ours: Vec<U256>, expected: Vec<U256> },
+    GasCost { ours: u64, expected: u64 },
+    Storage { slot: U256, ours: U256, expected: U256 },
+    Length { ours: usize, expected: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceDivergence {
+    pub step: usize,
+    pub pc: usize,
+    pub divergence: Divergence,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} (pc {}): ", self.step, self.pc)?;
+        match &self.divergence {
+            Divergence::Pc { ours, expected } => write!(f, "pc {} (expected {})", ours, expected),
+            Divergence::Op { ours, expected } => write!(f, "op {} (expected {})", ours, expected),
+            Divergence::Stack { ours, expected } => write!(f, "stack {:?} (expected {:?})",
+                ours.iter().map(hex_word).collect::<Vec<_>>(), expected.iter().map(hex_word).collect::<Vec<_>>()),
+            Divergence::GasCost { ours, expected } => write!(f, "gas cost {} (expected {})", ours, expected),
+            Divergence::Storage { slot, ours, expected } => write!(f, "storage[{}] = {} (expected {})",
+                hex_word(slot), hex_word(ours), hex_word(expected)),
+            Divergence::Length { ours, expected } => write!(f, "trace has {} steps (expected {})", ours, expected),
+        }
+    }
+}
+
+/// Walks both traces in lockstep and reports the first step where they disagree.
+/// Storage is only compared for slots the reference trace reports. Gas left isn't
+/// compared, only what each step costs: the intrinsic cost tinysol charges leaves out
+/// calldata, so the two start from different amounts.
+pub fn compare_traces(ours: &[TraceStep], reference: &[TraceStep]) -> Option<TraceDivergence> {
+    let first = ours.iter().zip(reference).enumerate().find_map(|(i, (step, exp))| {
+        let divergence = if step.pc != exp.pc {
+            Some(Divergence::Pc { ours: step.pc, expected: exp.pc })
+        } else if step.op != exp.op {
+            Some(Divergence::Op { ours: step.op_name.clone(), expected: exp.op_name.clone() })
+        } else if step.stack != exp.stack {
+            Some(Divergence::Stack { ours: step.stack.clone(), expected: exp.stack.clone() })
+        } else if step.gas_cost != exp.gas_cost {
+            Some(Divergence::GasCost { ours: step.gas_cost, expected: exp.gas_cost })
+        } else {
+            exp.storage.iter().flatten().find_map(|(slot, expected)| {
+                let actual = step.storage.as_ref().and_then(|s| s.get(slot)).copied().unwrap_or_default();
+                (actual != *expected).then_some(Divergence::Storage { slot: *slot, ours: actual, expected: *expected })
+            })
+        };
+        divergence.map(|divergence| TraceDivergence { step: i, pc: exp.pc, divergence })
+    });
+
+    first.or_else(|| (ours.len() != reference.len()).then(|| {
+        let step = ours.len().min(reference.len());
+        TraceDivergence {
+            step,
+            pc: reference.get(step).or(ours.get(step)).map(|s| s.pc).unwrap_or_default(),
+            divergence: Divergence::Length { ours: ours.len(), expected: reference.len() },
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::selector::selector;
+    use crate::test_utils::*;
+
+    fn trace_flip() -> Vec<TraceStep> {
+        let (execution, steps) = trace_call(&flipper(), &calldata("flip()", &[]), Env::default());
+        assert_eq!(execution.halt, None);
+        steps
+    }
+
+    #[test]
+    fn test_record_trace() {
+        let steps = trace_flip();
+        // The selector dispatch runs first, then the non-payable check, then the body.
+        assert_eq!((steps[0].pc, steps[0].op_name.as_str()), (0, "PUSH1"));
+        let names = steps.iter().map(|s| s.op_name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names[names.len() - 6..], ["PUSH0", "SLOAD", "ISZERO", "PUSH0", "SSTORE", "STOP"]);
+        assert!(names.contains(&"CALLVALUE"));
+
+        // Every pc is an instruction of the runtime code, with the opcode recorded for it.
+        let code = flipper().deployed_bytecode();
+        assert!(steps.iter().all(|s| code[s.pc] == s.op));
+
+        // Each step costs what the next one has less.
+        assert!(steps.windows(2).all(|w| w[1].gas == w[0].gas - w[0].gas_cost));
+        let sload = &steps[steps.len() - 5];
+        assert_eq!((sload.gas_cost, sload.storage.clone()), (2100, Some(BTreeMap::from([(U256::ZERO, U256::ZERO)]))));
+        assert!(steps.iter().all(|s| s.depth == 1));
+    }
+
+    #[test]
+    fn test_eip3155_roundtrip() {
+        let steps = trace_flip();
+        let jsonl = to_eip3155(&steps) + "{\"output\":\"\",\"gasUsed\":\"0x5\"}\n";
+        let parsed = parse_eip3155(&jsonl).unwrap();
+        assert_eq!(parsed, steps);
+        assert_eq!(compare_traces(&steps, &parsed), None);
+
+        // Gas is a hex string, as geth writes it.
+        let json = steps[0].to_json();
+        assert_eq!((json["gas"].as_str(), json["gasCost"].as_str()), (Some("0x1c9c380"), Some("0x3")));
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let steps = trace_flip();
+        let i = steps.len() - 3;
+        let mut reference = steps.clone();
+        reference[i].stack = vec![U256::ZERO];
+        let divergence = compare_traces(&steps, &reference).unwrap();
+        assert_eq!(divergence.step, i);
+        assert_eq!(divergence.pc, steps[i].pc);
+        assert_eq!(divergence.divergence, Divergence::Stack { ours: vec![U256::ONE], expected: vec![U256::ZERO] });
+
+        let mut reference = steps.clone();
+        reference[i - 2].gas_cost = 100;
+        let divergence = compare_traces(&steps, &reference).unwrap();
+        assert_eq!(divergence.divergence, Divergence::GasCost { ours: 2100, expected: 100 });
+
+        let divergence = compare_traces(&steps, &steps[..i]).unwrap();
+        assert_eq!(divergence.divergence, Divergence::Length { ours: steps.len(), expected: i });
+    }
+
+    #[test]
+    fn test_trace_nested_calls() {
+        let function = |name: &str, program: Vec<OP>| Function::from_program(name.to_string(), program);
+        let mut callee = Contract::new("Callee".to_string());
+        callee.insert_function(selector("answer()"), function("answer", vec![
+            OP::PUSH1(42), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ])).unwrap();
+        let mut world = World::new_dev();
+        let callee_address = world.deploy(alice(), callee);
+
+        // `ask()` calls `answer()` with all its gas and returns what it returned.
+        let answer = U256::from(u32::from_be_bytes(selector("answer()")));
+        let mut caller = Contract::new("Caller".to_string());
+        caller.insert_function(selector("ask()"), function("ask", vec![
+            OP::PUSHN(4, answer), OP::PUSH1(224), OP::SHL, OP::PUSH0, OP::MSTORE,
+            OP::PUSH1(32), OP::PUSH0, OP::PUSH1(4), OP::PUSH0, OP::PUSH0, OP::PUSH32(callee_address.to_word()), OP::GAS, OP::CALL,
+            OP::POP, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ])).unwrap();
+        let caller_address = world.deploy(alice(), caller);
+
+        let (result, steps) = trace_transaction(&world, &Transaction::new(bob(), caller_address, &calldata("ask()", &[])));
+        assert!(result.success);
+        assert_eq!(result.return_data, U256::from(42u8).to_be_bytes());
+
+        // The callee's steps, dispatcher first, sit between the call and the step after it.
+        let call = steps.iter().position(|s| s.op_name == "CALL").unwrap();
+        let nested = steps.iter().filter(|s| s.depth == 2).count();
+        assert!(nested > 0);
+        assert!(steps[call + 1..=call + nested].iter().all(|s| s.depth == 2));
+        assert_eq!((steps[call + 1].pc, steps[call + 1].op_name.as_str()), (0, "PUSH1"));
+        assert_eq!((steps[call + nested + 1].depth, steps[call + nested + 1].op_name.as_str()), (1, "POP"));
+
+        // The call's cost includes the gas handed to the callee, which starts with it, on
+        // top of the cold account access.
+        assert!(steps[call].gas_cost > steps[call + 1].gas);
+        assert!(steps[call].gas_cost - steps[call + 1].gas <= 2600 + 100);
+        assert_eq!(steps.len(), steps.iter().filter(|s| s.depth == 1).count() + nested);
+    }
+}
@@ -0,0 +1,70 @@
+use keccak_hash::keccak;
+use crate::rlp::Rlp;
+
+/// keccak256 of an empty trie's root node.
+pub const EMPTY_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// Root hash of a Merkle-Patricia trie holding `entries`, computed the way Ethereum does
+/// for state, storage and transaction tries. Keys must be unique.
+pub fn trie_root(entries: &[(Vec<u8>, Vec<u8>)]) -> [u8; 32] {
+    let mut entries = entries.iter()
+        .map(|(key, value)| (key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect::<Vec<_>>(), value.as_slice()))
+        .collect::<Vec<_>>();
+    entries.sort();
+    keccak(node(&entries, 0).encode()).into()
+}
+
+/// The node for `entries`, sorted by key, whose first `depth` nibbles are shared.
+fn node(entries: &[(Vec<u8>, &[u8])], depth: usize) -> Rlp {
+    match entries {
+        [] => Rlp::Bytes(vec![]),
+        [(key, value)] => Rlp::List(vec![Rlp::Bytes(hex_prefix(&key[depth..], true)), Rlp::Bytes(value.to_vec())]),
+        [(first, _), .., (last, _)] => {
+            // Sorted keys share whatever prefix the first and last share.
+            let shared = first[depth..].iter().zip(&last[depth..]).take_while(|(a, b)| a == b).count();
+            if shared > 0 {
+                return Rlp::List(vec![
+                    Rlp::Bytes(hex_prefix(&first[depth..depth + shared], false)),
+                    reference(node(entries, depth + shared)),
+                ]);
+            }
+            let mut branch = (0..16u8).map(|nibble| {
+                let children = entries.iter().filter(|(key, _)| key.get(depth) == Some(&nibble)).cloned().collect::<Vec<_>>();
+                if children.is_empty() { Rlp::Bytes(vec![]) } else { reference(node(&children, depth + 1)) }
+            }).collect::<Vec<_>>();
+            let value = entries.iter().find(|(key, _)| key.len() == depth).map(|(_, value)| value.to_vec());
+            branch.push(Rlp::Bytes(value.unwrap_or_default()));
+            Rlp::List(branch)
+        }
+    }
+}
+
+/// How a parent refers to `node`: inline if its encoding is shorter than a hash.
+fn reference(node: Rlp) -> Rlp {
+    let encoded = node.encode();
+    if encoded.len() < 32 { node } else { Rlp::Bytes(keccak(encoded).as_bytes().to_vec()) }
+}
+
+/// Packs `nibbles` into bytes, flagging whether they end in a leaf and their parity.
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 } + (nibbles.len() % 2) as u8;
+    let (first, rest) = if nibbles.len() % 2 == 1 { (flag << 4 | nibbles[0], &nibbles[1..]) } else { (flag << 4, nibbles) };
+    std::iter::once(first).chain(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::to_hex;
+
+    #[test]
+    fn test_trie_root() {
+        assert_eq!(trie_root(&[]), EMPTY_ROOT);
+        let entries = [("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()));
+        assert_eq!(to_hex(&trie_root(&entries)), "0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3");
+    }
+}
@@ -0,0 +1,146 @@
+use crate::diagnostics::Diagnostic;
+use crate::solidity::grammar::{parse, SourceUnit};
+
+/// Solidity constructs recognized by their keyword that the grammar can't parse yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Pragma,
+    Import,
+    Interface,
+    Library,
+    Using,
+    Modifier,
+    Struct,
+    Enum,
+    Loop,
+}
+
+impl Feature {
+    /// The feature that `keyword` starts, if any.
+    pub fn from_keyword(keyword: &str) -> Option<Feature> {
+        Some(match keyword {
+            "pragma" => Feature::Pragma,
+            "import" => Feature::Import,
+            "interface" => Feature::Interface,
+            "library" => Feature::Library,
+            "using" => Feature::Using,
+            "modifier" => Feature::Modifier,
+            "struct" => Feature::Struct,
+            "enum" => Feature::Enum,
+            "for" | "while" | "do" => Feature::Loop,
+            _ => return None,
+        })
+    }
+
+    /// Machine-readable diagnostic code, e.g. `unsupported-modifier`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Feature::Pragma => "unsupported-pragma",
+            Feature::Import => "unsupported-import",
+            Feature::Interface => "unsupported-interface",
+            Feature::Library => "unsupported-library",
+            Feature::Using => "unsupported-using",
+            Feature::Modifier => "unsupported-modifier",
+            Feature::Struct => "unsupported-struct",
+            Feature::Enum => "unsupported-enum",
+            Feature::Loop => "unsupported-loop",
+        }
+    }
+
+    /// What the diagnostic calls it, e.g. `modifiers`.
+    pub fn description(self) -> &'static str {
+        match self {
+            Feature::Pragma => "pragma directives",
+            Feature::Import => "imports",
+            Feature::Interface => "interfaces",
+            Feature::Library => "libraries",
+            Feature::Using => "`using for` directives",
+            Feature::Modifier => "modifiers",
+            Feature::Struct => "structs",
+            Feature::Enum => "enums",
+            Feature::Loop => "loops",
+        }
+    }
+}
+
+fn line(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+/// Byte spans of the words in `source`, skipping comments and string literals.
+fn words(source: &str) -> Vec<(usize, usize)> {
+    let bytes = source.as_bytes();
+    let mut words = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &source[i..];
+        if rest.starts_with("//") {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            i += comment.find("*/").map_or(rest.len(), |end| end + 4);
+        } else if bytes[i] == b'"' || bytes[i] == b'\'' {
+            i += rest[1..].find(bytes[i] as char).map_or(rest.len(), |end| end + 2);
+        } else if bytes[i].is_ascii_alphabetic() || bytes[i] == b'_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            words.push((i, i + len));
+            i += len;
+        } else if bytes[i].is_ascii_digit() {
+            i += rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    words
+}
+
+/// A "not yet supported" error for every construct in `source` tinysol recognizes but
+/// can't compile.
+pub fn unsupported_features(source: &str) -> Vec<Diagnostic> {
+    words(source).into_iter().filter_map(|(start, end)| {
+        let feature = Feature::from_keyword(&source[start..end])?;
+        let message = format!("not yet supported: {} at line {}", feature.description(), line(source, start));
+        Some(Diagnostic::error(feature.code(), message).with_span((start, end)))
+    }).collect()
+}
+
+/// Parses `source`, explaining a failure with the unsupported constructs in it, or with
+/// where parsing stopped if there are none.
+pub fn parse_source(source: &str) -> Result<SourceUnit, Vec<Diagnostic>> {
+    parse(source).map_err(|errors| {
+        let unsupported = unsupported_features(source);
+        if !unsupported.is_empty() {
+            return unsupported;
+        }
+        errors.iter().map(|error| {
+            Diagnostic::error("syntax", format!("syntax error at line {}", line(source, error.start))).with_span((error.start, error.end))
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_modifier() {
+        let source = "contract Owned {
+    bool private locked;
+    // Only the owner's `mapping` entry may lock.
+    modifier onlyOwner() { _; }
+    function lock() public onlyOwner { locked = true; }
+}";
+        let diagnostics = parse_source(source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "unsupported-modifier");
+        assert_eq!(diagnostics[0].message, "not yet supported: modifiers at line 4");
+        assert_eq!(&source[diagnostics[0].span.unwrap().0..diagnostics[0].span.unwrap().1], "modifier");
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let diagnostics = parse_source("contract A {\n    bool private a\n}").unwrap_err();
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.code == "syntax"));
+        assert!(parse_source("contract A { bool private a; }").is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+use ethnum::U256;
+use std::fmt;
+use crate::bytecode::code_offsets;
+use crate::cfg::{static_jump_target, Cfg};
+use crate::tinyvm::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackErrorKind {
+    Underflow { height: usize, needed: usize },
+    Overflow { height: usize },
+    /// Two paths reach the block with different stack heights.
+    UnbalancedJoin { block: usize, heights: (usize, usize) },
+    /// The program exits with something other than its return values on the stack.
+    UnbalancedExit { height: usize, expected: usize },
+    /// A jump to a byte offset that doesn't hold a `JUMPDEST`.
+    InvalidJump { target: U256 },
+    /// A jump whose target is computed at run time, which can't be checked.
+    DynamicJump,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackError {
+    /// Index of the offending instruction.
+    pub pc: usize,
+    pub kind: StackErrorKind,
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction {}: ", self.pc)?;
+        match &self.kind {
+            StackErrorKind::Underflow { height, needed } => write!(f, "stack underflow, needs {} items but has {}", needed, height),
+            StackErrorKind::Overflow { height } => write!(f, "stack overflow, height {} exceeds {}", height, STACK_LIMIT),
+            StackErrorKind::UnbalancedJoin { block, heights: (a, b) } => write!(f, "block {} is reached with stack heights {} and {}", block, a, b),
+            StackErrorKind::UnbalancedExit { height, expected } => write!(f, "exits with {} stack items, expected {}", height, expected),
+            StackErrorKind::InvalidJump { target } => write!(f, "jump to offset {}, which is not a JUMPDEST", target),
+            StackErrorKind::DynamicJump => f.write_str("jump target is not a constant"),
+        }
+    }
+}
+
+/// Checks that every jump in `program` goes to a `JUMPDEST`, pushed as a constant right
+/// before the jump.
+pub fn verify_jumps(program: &[OP]) -> Result<(), StackError> {
+    let offsets = code_offsets(program);
+    let is_jumpdest = |target: U256| usize::try_from(target).ok()
+        .and_then(|target| offsets.binary_search(&target).ok())
+        .is_some_and(|index| program[index] == OP::JUMPDEST);
+    program.iter().enumerate()
+        .filter(|(_, op)| matches!(op, OP::JUMP | OP::JUMPI))
+        .try_for_each(|(pc, _)| match static_jump_target(program, pc) {
+            None => Err(StackError { pc, kind: StackErrorKind::DynamicJump }),
+            Some(target) if !is_jumpdest(target) => Err(StackError { pc, kind: StackErrorKind::InvalidJump { target } }),
+            Some(_) => Ok(()),
+        })
+}
+
+/// Checks that `program` never underflows or overflows the stack, that every block is
+/// entered with the same height on all paths, and that it exits with an empty stack, after
+/// `RETURN` has taken its operands or when running off the end. `REVERT` may leave items
+/// behind, since the call's effects are discarded anyway.
+///
+/// Jumps are checked first, with `verify_jumps`, so the control-flow graph is complete.
+///
+/// Returns the entry height of every reachable block.
+pub fn verify_stack(program: &[OP]) -> Result<Vec<Option<usize>>, StackError> {
+    verify_jumps(program)?;
+    let cfg = Cfg::build(program);
+    let mut entry = vec![None; cfg.blocks.len()];
+    let mut work = vec![];
+    if !cfg.blocks.is_empty() {
+        entry[0] = Some(0);
+        work.push(0);
+    }
+
+    while let Some(id) = work.pop() {
+        let block = &cfg.blocks[id];
+        let mut height = entry[id].unwrap_or_default();
+        for (pc, op) in program.iter().enumerate().take(block.end).skip(block.start) {
+            let (pops, pushes) = op.stack_io();
+            if height < pops {
+                return Err(StackError { pc, kind: StackErrorKind::Underflow { height, needed: pops } });
+            }
+            height = height - pops + pushes;
+            if height > STACK_LIMIT {
+                return Err(StackError { pc, kind: StackErrorKind::Overflow { height } });
+            }
+            if *op == OP::RETURN && height != 0 {
+                return Err(StackError { pc, kind: StackErrorKind::UnbalancedExit { height, expected: 0 } });
+            }
+        }
+
+        let last = block.end - 1;
+        let mut successors = cfg.successors(id).peekable();
+        if successors.peek().is_none() && !matches!(program[last], OP::RETURN | OP::REVERT | OP::SELFDESTRUCT) && height != 0 {
+            return Err(StackError { pc: last, kind: StackErrorKind::UnbalancedExit { height, expected: 0 } });
+        }
+        for next in successors {
+            match entry[next] {
+                Some(existing) if existing != height => {
+                    return Err(StackError { pc: last, kind: StackErrorKind::UnbalancedJoin { block: next, heights: (existing, height) } });
+                },
+                Some(_) => {},
+                None => {
+                    entry[next] = Some(height);
+                    work.push(next);
+                },
+            }
+        }
+    }
+    Ok(entry)
+}
+
+impl Function {
+    pub fn verify_stack(&self) -> Result<(), StackError> {
+        verify_stack(self.program()).map(|_| ())
+    }
+}
+
+impl Contract {
+    /// Verifies every function, reporting failures by function name.
+    pub fn verify_stack(&self) -> Vec<(String, StackError)> {
+        let mut errors = self.functions.values()
+            .filter_map(|function| function.verify_stack().err().map(|e| (function.name.clone(), e)))
+            .collect::<Vec<_>>();
+        errors.sort_by(|a, b| a.0.cmp(&b.0));
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solidity::grammar::parse;
+
+    #[test]
+    fn test_compiled_contracts_verify() {
+        for file in ["flipper.sol", "contract_with_var.sol"] {
+            let code = std::fs::read_to_string(format!("./contracts/{}", file)).unwrap();
+            create_contracts(parse(&code).unwrap()).iter().for_each(|contract| assert_eq!(contract.verify_stack(), vec![]));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_stacks() {
+        assert_eq!(verify_stack(&[OP::PUSH1(0), OP::SSTORE]).unwrap_err(), StackError { pc: 1, kind: StackErrorKind::Underflow { height: 1, needed: 2 } });
+        assert_eq!(verify_stack(&[OP::PUSH1(0), OP::PUSH1(1)]).unwrap_err().kind, StackErrorKind::UnbalancedExit { height: 2, expected: 0 });
+        assert_eq!(verify_stack(&[OP::PUSH1(0), OP::RETURN]).unwrap_err().kind, StackErrorKind::Underflow { height: 1, needed: 2 });
+        assert_eq!(verify_stack(&[OP::PUSH1(1), OP::PUSH1(0), OP::PUSH1(0), OP::RETURN]).unwrap_err().kind, StackErrorKind::UnbalancedExit { height: 1, expected: 0 });
+        assert_eq!(verify_stack(&[OP::PUSH1(0), OP::PUSH1(0), OP::RETURN]), Ok(vec![Some(0)]));
+        assert_eq!(verify_stack(&[OP::PUSH1(1), OP::PUSH1(0), OP::PUSH1(0), OP::REVERT]), Ok(vec![Some(0)]));
+    }
+
+    #[test]
+    fn test_rejects_bad_jumps() {
+        let program = [OP::PUSH1(4), OP::JUMP, OP::JUMPDEST, OP::JUMPDEST];
+        assert_eq!(verify_jumps(&program), Ok(()));
+        assert_eq!(verify_stack(&program), Ok(vec![Some(0), None, Some(0)]));
+        assert_eq!(verify_jumps(&[OP::PUSH1(2), OP::JUMP, OP::PUSH1(0)]).unwrap_err().kind, StackErrorKind::InvalidJump { target: U256::from(2u8) });
+        assert_eq!(verify_jumps(&[OP::PUSH1(0), OP::PUSH1(0), OP::JUMPI]).unwrap_err().kind, StackErrorKind::InvalidJump { target: U256::ZERO });
+        assert_eq!(verify_stack(&[OP::PUSH1(0), OP::DUP1, OP::POP, OP::JUMP]).unwrap_err(), StackError { pc: 3, kind: StackErrorKind::DynamicJump });
+    }
+}
@@ -0,0 +1,319 @@
+//! Traversal and rewriting of the grammar's AST, for tools that only care about some of
+//! its nodes. Every trait method defaults to visiting, or rebuilding, the node's children
+//! through the `walk_*` or `fold_*` function of the same name, so an override that still
+//! wants to descend calls that function.
+
+use crate::solidity::grammar::*;
+
+/// Read-only traversal, parents before children and in source order.
+pub trait Visitor {
+    fn visit_source_unit(&mut self, unit: &SourceUnit) {
+        walk_source_unit(self, unit);
+    }
+
+    fn visit_contract(&mut self, contract: &SourceUnitPart) {
+        walk_contract(self, contract);
+    }
+
+    fn visit_contract_part(&mut self, part: &ContractPart) {
+        walk_contract_part(self, part);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr);
+    }
+
+    /// Variables, called functions and emitted events, by name.
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        let _ = identifier;
+    }
+}
+
+pub fn walk_source_unit<V: Visitor + ?Sized>(visitor: &mut V, unit: &SourceUnit) {
+    unit.parts.iter().for_each(|contract| visitor.visit_contract(contract));
+}
+
+pub fn walk_contract<V: Visitor + ?Sized>(visitor: &mut V, contract: &SourceUnitPart) {
+    let SourceUnitPart::ContractDefinition(_, _, _, parts, _) = contract;
+    parts.iter().for_each(|part| visitor.visit_contract_part(part));
+}
+
+fn parameters(list: &ParameterList) -> &[Parameter] {
+    match list {
+        ParameterList::Params(_, Some(params), _) => &params.params,
+        ParameterList::Params(_, None, _) => &[],
+    }
+}
+
+pub fn walk_contract_part<V: Visitor + ?Sized>(visitor: &mut V, part: &ContractPart) {
+    let (params, returns, body) = match part {
+        ContractPart::VariableDefinition(..) | ContractPart::EventDefinition(..) => return,
+        ContractPart::FunctionDefinition(_, _, params, _, returns, _, body, _) => {
+            (params, returns.as_ref().map(|FunctionReturnParams::ParameterList(_, list)| list), &body[..])
+        },
+        ContractPart::ConstructorDefinition(_, params, _, _, body, _)
+        | ContractPart::ReceiveDefinition(_, params, _, _, body, _)
+        | ContractPart::FallbackDefinition(_, params, _, _, body, _) => (params, None, &body[..]),
+        ContractPart::ErrorDefinition(_, _, params, _) => (params, None, &[][..]),
+    };
+    parameters(params).iter().chain(returns.map_or(&[][..], parameters)).for_each(|parameter| visitor.visit_parameter(parameter));
+    body.iter().for_each(|statement| visitor.visit_statement(statement));
+}
+
+pub fn walk_parameter<V: Visitor + ?Sized>(visitor: &mut V, parameter: &Parameter) {
+    visitor.visit_expression(&parameter.ty);
+    if let Some(name) = &parameter.name {
+        visitor.visit_identifier(name);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Spanned<Statement>) {
+    match &statement.value {
+        Statement::Expression(expr, _) | Statement::Return(_, Some(expr), _) => visitor.visit_expression(expr),
+        Statement::Return(_, None, _) => {},
+        Statement::If(_, _, cond, _, then, otherwise) => {
+            visitor.visit_expression(cond);
+            visitor.visit_statement(then);
+            if let Some(Else::Else(_, otherwise)) = otherwise {
+                visitor.visit_statement(otherwise);
+            }
+        },
+        Statement::Block(_, statements, _) => statements.iter().for_each(|statement| visitor.visit_statement(statement)),
+        Statement::Emit(_, event, args, _) => {
+            visitor.visit_identifier(event);
+            args.args().iter().for_each(|arg| visitor.visit_expression(arg));
+        },
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match expr {
+        Expression::BoolLiteral(_) | Expression::NumberLiteral(_) | Expression::StringLiteral(_) | Expression::Global(_) | Expression::Type(_) => {},
+        Expression::Variable(identifier) => visitor.visit_identifier(identifier),
+        Expression::Call(identifier, args) => {
+            visitor.visit_identifier(identifier);
+            args.args().iter().for_each(|arg| visitor.visit_expression(arg));
+        },
+        // Member names aren't variables, so only the value they belong to is visited.
+        Expression::Member(MemberAccess { target, .. }) => visitor.visit_expression(target),
+        Expression::MemberCall(MemberAccess { target, .. }, options, args) => {
+            visitor.visit_expression(target);
+            options.iter().flat_map(|options| options.options()).for_each(|CallOption::Named(_, _, value)| visitor.visit_expression(value));
+            args.args().iter().for_each(|arg| visitor.visit_expression(arg));
+        },
+        Expression::Not(_, operand) | Expression::Neg(_, operand) | Expression::BitNot(_, operand)
+        | Expression::Paren(_, operand, _) | Expression::Cast(_, _, operand, _) => visitor.visit_expression(operand),
+        Expression::Assign(lhs, _, rhs) | Expression::Lt(lhs, _, rhs) | Expression::Gt(lhs, _, rhs)
+        | Expression::Le(lhs, _, rhs) | Expression::Ge(lhs, _, rhs) | Expression::Eq(lhs, _, rhs) | Expression::Ne(lhs, _, rhs)
+        | Expression::And(lhs, _, rhs) | Expression::Or(lhs, _, rhs) | Expression::Index(lhs, _, rhs, _)
+        | Expression::BitOr(lhs, _, rhs) | Expression::BitXor(lhs, _, rhs) | Expression::BitAnd(lhs, _, rhs)
+        | Expression::Shl(lhs, _, rhs) | Expression::Shr(lhs, _, rhs) | Expression::Add(lhs, _, rhs)
+        | Expression::Sub(lhs, _, rhs) | Expression::Mul(lhs, _, rhs) | Expression::Div(lhs, _, rhs)
+        | Expression::Mod(lhs, _, rhs) | Expression::Exp(lhs, _, rhs) => {
+            visitor.visit_expression(lhs);
+            visitor.visit_expression(rhs);
+        },
+    }
+}
+
+/// Rewriting traversal: each method takes a node and returns its replacement, children
+/// first rebuilt the same way.
+pub trait Folder {
+    fn fold_source_unit(&mut self, unit: SourceUnit) -> SourceUnit {
+        fold_source_unit(self, unit)
+    }
+
+    fn fold_contract(&mut self, contract: SourceUnitPart) -> SourceUnitPart {
+        fold_contract(self, contract)
+    }
+
+    fn fold_contract_part(&mut self, part: ContractPart) -> ContractPart {
+        fold_contract_part(self, part)
+    }
+
+    fn fold_parameter(&mut self, parameter: Parameter) -> Parameter {
+        fold_parameter(self, parameter)
+    }
+
+    fn fold_statement(&mut self, statement: Spanned<Statement>) -> Spanned<Statement> {
+        fold_statement(self, statement)
+    }
+
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold_expression(self, expr)
+    }
+
+    /// Variables, called functions and emitted events, and the names state variables are
+    /// declared with.
+    fn fold_identifier(&mut self, identifier: Identifier) -> Identifier {
+        identifier
+    }
+}
+
+pub fn fold_source_unit<F: Folder + ?Sized>(folder: &mut F, unit: SourceUnit) -> SourceUnit {
+    SourceUnit { parts: unit.parts.into_iter().map(|contract| folder.fold_contract(contract)).collect() }
+}
+
+pub fn fold_contract<F: Folder + ?Sized>(folder: &mut F, contract: SourceUnitPart) -> SourceUnitPart {
+    let SourceUnitPart::ContractDefinition(keyword, name, open, parts, close) = contract;
+    SourceUnitPart::ContractDefinition(keyword, name, open, parts.into_iter().map(|part| folder.fold_contract_part(part)).collect(), close)
+}
+
+fn fold_parameters<F: Folder + ?Sized>(folder: &mut F, list: &mut ParameterList) {
+    if let ParameterList::Params(_, Some(params), _) = list {
+        params.params = std::mem::take(&mut params.params).into_iter().map(|parameter| folder.fold_parameter(parameter)).collect();
+    }
+}
+
+pub fn fold_contract_part<F: Folder + ?Sized>(folder: &mut F, mut part: ContractPart) -> ContractPart {
+    let (params, returns, body) = match &mut part {
+        ContractPart::VariableDefinition(_, _, identifier, _) => {
+            let name = std::mem::take(&mut identifier.name);
+            *identifier = folder.fold_identifier(Identifier { name });
+            return part;
+        },
+        ContractPart::EventDefinition(..) => return part,
+        ContractPart::FunctionDefinition(_, _, params, _, returns, _, body, _) => {
+            (params, returns.as_mut().map(|FunctionReturnParams::ParameterList(_, list)| list), Some(body))
+        },
+        ContractPart::ConstructorDefinition(_, params, _, _, body, _)
+        | ContractPart::ReceiveDefinition(_, params, _, _, body, _)
+        | ContractPart::FallbackDefinition(_, params, _, _, body, _) => (params, None, Some(body)),
+        ContractPart::ErrorDefinition(_, _, params, _) => (params, None, None),
+    };
+    fold_parameters(folder, params);
+    if let Some(returns) = returns {
+        fold_parameters(folder, returns);
+    }
+    if let Some(body) = body {
+        *body = fold_statements(folder, std::mem::take(body));
+    }
+    part
+}
+
+fn fold_statements<F: Folder + ?Sized>(folder: &mut F, statements: Vec<Spanned<Statement>>) -> Vec<Spanned<Statement>> {
+    statements.into_iter().map(|statement| folder.fold_statement(statement)).collect()
+}
+
+pub fn fold_parameter<F: Folder + ?Sized>(folder: &mut F, parameter: Parameter) -> Parameter {
+    Parameter {
+        ty: folder.fold_expression(parameter.ty),
+        name: parameter.name.map(|name| Spanned { value: folder.fold_identifier(name.value), span: name.span }),
+    }
+}
+
+pub fn fold_statement<F: Folder + ?Sized>(folder: &mut F, statement: Spanned<Statement>) -> Spanned<Statement> {
+    let value = match statement.value {
+        Statement::Expression(expr, semicolon) => Statement::Expression(folder.fold_expression(expr), semicolon),
+        Statement::Return(keyword, expr, semicolon) => Statement::Return(keyword, expr.map(|expr| folder.fold_expression(expr)), semicolon),
+        Statement::If(keyword, open, cond, close, then, otherwise) => Statement::If(
+            keyword, open, folder.fold_expression(cond), close,
+            Box::new(folder.fold_statement(*then)),
+            otherwise.map(|Else::Else(keyword, otherwise)| Else::Else(keyword, Box::new(folder.fold_statement(*otherwise)))),
+        ),
+        Statement::Block(open, statements, close) => Statement::Block(open, fold_statements(folder, statements), close),
+        Statement::Emit(keyword, event, args, semicolon) => Statement::Emit(keyword, folder.fold_identifier(event), fold_arguments(folder, args), semicolon),
+    };
+    Spanned { value, span: statement.span }
+}
+
+fn fold_arguments<F: Folder + ?Sized>(folder: &mut F, args: Arguments) -> Arguments {
+    let Arguments::Args(open, list, close) = args;
+    Arguments::Args(open, list.map(|list| ArgumentList { args: list.args.into_iter().map(|arg| folder.fold_expression(arg)).collect() }), close)
+}
+
+/// Replaces the expression in `operand` with its folded form.
+fn fold_operand<F: Folder + ?Sized>(folder: &mut F, operand: &mut Box<Expression>) {
+    let expr = std::mem::replace(operand.as_mut(), Expression::BoolLiteral(false));
+    **operand = folder.fold_expression(expr);
+}
+
+pub fn fold_expression<F: Folder + ?Sized>(folder: &mut F, mut expr: Expression) -> Expression {
+    match &mut expr {
+        Expression::BoolLiteral(_) | Expression::NumberLiteral(_) | Expression::StringLiteral(_) | Expression::Global(_) | Expression::Type(_) => {},
+        Expression::Variable(identifier) => {
+            let name = std::mem::take(&mut identifier.name);
+            *identifier = folder.fold_identifier(Identifier { name });
+        },
+        Expression::Call(identifier, args) => {
+            let name = std::mem::take(&mut identifier.name);
+            *identifier = folder.fold_identifier(Identifier { name });
+            *args = fold_arguments(folder, std::mem::replace(args, Arguments::Args((), None, ())));
+        },
+        Expression::Member(MemberAccess { target, .. }) => fold_operand(folder, target),
+        Expression::MemberCall(MemberAccess { target, .. }, options, args) => {
+            fold_operand(folder, target);
+            if let Some(CallOptions::Options(_, list, _)) = options {
+                list.options.iter_mut().for_each(|CallOption::Named(_, _, value)| {
+                    *value = folder.fold_expression(std::mem::replace(value, Expression::BoolLiteral(false)));
+                });
+            }
+            *args = fold_arguments(folder, std::mem::replace(args, Arguments::Args((), None, ())));
+        },
+        Expression::Not(_, operand) | Expression::Neg(_, operand) | Expression::BitNot(_, operand)
+        | Expression::Paren(_, operand, _) | Expression::Cast(_, _, operand, _) => fold_operand(folder, operand),
+        Expression::Assign(lhs, _, rhs) | Expression::Lt(lhs, _, rhs) | Expression::Gt(lhs, _, rhs)
+        | Expression::Le(lhs, _, rhs) | Expression::Ge(lhs, _, rhs) | Expression::Eq(lhs, _, rhs) | Expression::Ne(lhs, _, rhs)
+        | Expression::And(lhs, _, rhs) | Expression::Or(lhs, _, rhs) | Expression::Index(lhs, _, rhs, _)
+        | Expression::BitOr(lhs, _, rhs) | Expression::BitXor(lhs, _, rhs) | Expression::BitAnd(lhs, _, rhs)
+        | Expression::Shl(lhs, _, rhs) | Expression::Shr(lhs, _, rhs) | Expression::Add(lhs, _, rhs)
+        | Expression::Sub(lhs, _, rhs) | Expression::Mul(lhs, _, rhs) | Expression::Div(lhs, _, rhs)
+        | Expression::Mod(lhs, _, rhs) | Expression::Exp(lhs, _, rhs) => {
+            fold_operand(folder, lhs);
+            fold_operand(folder, rhs);
+        },
+    }
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "contract Counter {
+    uint256 private count;
+    uint256 private limit;
+    function bump(uint256 by) public { count = count + by; }
+    function full() public view returns (bool) { return count >= limit; }
+}";
+
+    #[test]
+    fn test_visitor_collects_variables() {
+        #[derive(Default)]
+        struct Reads(Vec<String>);
+        impl Visitor for Reads {
+            fn visit_identifier(&mut self, identifier: &Identifier) {
+                self.0.push(identifier.name.clone());
+            }
+        }
+
+        let mut reads = Reads::default();
+        reads.visit_source_unit(&parse(SOURCE).unwrap());
+        assert_eq!(reads.0, ["by", "count", "count", "by", "count", "limit"]);
+    }
+
+    #[test]
+    fn test_folder_renames_variables() {
+        // Same-length names keep every span where it was.
+        struct Rename;
+        impl Folder for Rename {
+            fn fold_identifier(&mut self, identifier: Identifier) -> Identifier {
+                match identifier.name.as_str() {
+                    "count" => Identifier { name: "total".to_string() },
+                    _ => identifier,
+                }
+            }
+        }
+
+        let renamed = Rename.fold_source_unit(parse(SOURCE).unwrap());
+        assert_eq!(renamed, parse(&SOURCE.replace("count", "total")).unwrap());
+    }
+}
@@ -0,0 +1,2050 @@
+use ethnum::U256;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use keccak_hash::keccak;
+use crate::env::*;
+use crate::solidity::grammar::Expression;
+use crate::tinyvm::*;
+use crate::executor::{Executor, Interpreter, Storage};
+use crate::call::{CallHandler, CallKind, CallOutcome, ContractCreation, CreateOutcome, MessageCall, G_CODE_DEPOSIT, MAX_CALL_DEPTH};
+use crate::abi::{AbiError, Value};
+use crate::revert::RevertReason;
+use crate::rlp::Rlp;
+use crate::block::Block;
+use crate::log::{Bloom, Log, LogFilter, LogRecord};
+use crate::registry::{ContractInfo, ContractRegistry};
+use crate::fuzz::Rng;
+use crate::bytecode::{disassemble, from_hex, to_hex};
+use crate::metadata::strip_metadata;
+use crate::trie::{trie_root, EMPTY_ROOT};
+use crate::overrides::StateOverride;
+use crate::state::{MemoryDB, StateDB};
+
+#[derive(Debug, Clone, Default)]
+pub struct Account {
+    pub balance: U256,
+    pub nonce: u64,
+    pub contract: Option<Contract>,
+}
+
+impl Account {
+    /// Whether the account has no nonce, balance or code, which EIP-161 treats as not
+    /// existing.
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance == U256::ZERO && self.contract.is_none()
+    }
+
+    /// The account at `address` in `db`, unless it is empty. Its code becomes a contract
+    /// whose fallback runs it, like one `CREATE` deploys, with the slots left in `db`. Code
+    /// the VM can't read back is left out.
+    fn load(db: &dyn StateDB, address: &Address) -> Option<Self> {
+        let code = db.code(address);
+        let (code, metadata) = strip_metadata(&code);
+        let contract = disassemble(code).filter(|_| !code.is_empty()).map(|program| {
+            let mut fallback = Function::from_program("fallback".to_string(), program);
+            fallback.mutability = FuncMutability::Payable;
+            Contract { fallback: Some(fallback), metadata, ..Contract::new("Stored".to_string()) }
+        });
+        let account = Self { balance: db.balance(address), nonce: db.nonce(address), contract };
+        (!account.is_empty()).then_some(account)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallResult {
+    pub success: bool,
+    pub output: Vec<Expression>,
+    /// ABI-encoded bytes a successful call returned.
+    pub return_data: Vec<u8>,
+    pub revert_data: Vec<u8>,
+    pub revert_reason: Option<RevertReason>,
+    pub gas_used: u64,
+    pub halt: Option<Halt>,
+    /// Set once the transaction is included in the world's pending block.
+    pub tx_hash: Option<[u8; 32]>,
+    /// Logs emitted by a successful call.
+    pub logs: Vec<Log>,
+    /// Inputs of every `KECCAK256` the call executed, by hash, whether or not it succeeded.
+    pub preimages: BTreeMap<U256, Vec<u8>>,
+}
+
+impl CallResult {
+    pub fn success(output: Vec<Expression>) -> Self {
+        Self {
+            success: true,
+            output,
+            return_data: vec![],
+            revert_data: vec![],
+            revert_reason: None,
+            gas_used: 0,
+            halt: None,
+            tx_hash: None,
+            logs: vec![],
+            preimages: BTreeMap::new(),
+        }
+    }
+
+    pub fn halted(halt: Halt) -> Self {
+        Self {
+            success: false,
+            output: vec![],
+            return_data: vec![],
+            revert_data: vec![],
+            revert_reason: None,
+            gas_used: 0,
+            halt: Some(halt),
+            tx_hash: None,
+            logs: vec![],
+            preimages: BTreeMap::new(),
+        }
+    }
+
+    /// The receipt's logs bloom, which a failed call's lack of logs leaves empty.
+    pub fn logs_bloom(&self) -> Bloom {
+        Bloom::from_logs(&self.logs)
+    }
+
+    /// A failed call, with `data` decoded against the callee's custom errors.
+    pub fn reverted(data: Vec<u8>, errors: &[AbiError]) -> Self {
+        Self {
+            success: false,
+            output: vec![],
+            return_data: vec![],
+            revert_reason: Some(RevertReason::decode_with_errors(&data, errors)),
+            revert_data: data,
+            gas_used: 0,
+            halt: None,
+            tx_hash: None,
+            logs: vec![],
+            preimages: BTreeMap::new(),
+        }
+    }
+}
+
+/// One call of a `World::multicall` batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub to: Address,
+    pub calldata: String,
+    /// Whether the batch goes on if this call fails, like Multicall3's `allowFailure`.
+    pub allow_failure: bool,
+}
+
+impl Call {
+    pub fn new(to: Address, calldata: &str) -> Self {
+        Self { to, calldata: calldata.to_string(), allow_failure: false }
+    }
+
+    pub fn allow_failure(mut self) -> Self {
+        self.allow_failure = true;
+        self
+    }
+}
+
+/// The combined receipt of a batch. `results` has one entry per call that ran, each with
+/// its own gas; `gas_used` is the total including the intrinsic cost, paid once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticallReceipt {
+    pub success: bool,
+    pub results: Vec<CallResult>,
+    pub gas_used: u64,
+}
+
+/// Identifies a `World::subscribe` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(pub usize);
+
+type LogCallback = Arc<Mutex<dyn FnMut(&LogRecord) + Send>>;
+
+#[derive(Clone)]
+struct Subscription {
+    id: SubscriptionId,
+    filter: LogFilter,
+    callback: LogCallback,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").field("id", &self.id).field("filter", &self.filter).finish_non_exhaustive()
+    }
+}
+
+/// When the world seals blocks, as with anvil's mining modes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mining {
+    /// Only when `mine` or `mine_n` is called.
+    #[default]
+    Manual,
+    /// After every transaction, each in a block of its own.
+    Auto,
+    /// Every `block_time` seconds, as `advance_time` moves the clock.
+    Interval,
+}
+
+/// Flat cost of every transaction, charged before any code runs.
+pub const INTRINSIC_GAS: u64 = 21000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Address,
+    pub calldata: String,
+    pub value: U256,
+    pub gas_limit: u64,
+    /// Sponsor paying for gas instead of `from`.
+    pub payer: Option<Address>,
+    /// EIP-1559 fee caps. Without them the transaction pays the world's `gas_price`.
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+}
+
+impl Transaction {
+    pub fn new(from: Address, to: Address, calldata: &str) -> Self {
+        Self {
+            from,
+            to,
+            calldata: calldata.to_string(),
+            value: U256::ZERO,
+            gas_limit: Env::default().gas_limit,
+            payer: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+        }
+    }
+
+    /// The most the transaction may pay per gas, `max_fee_per_gas` or else `gas_price`.
+    pub fn fee_cap(&self, gas_price: U256) -> U256 {
+        self.max_fee_per_gas.unwrap_or(gas_price)
+    }
+
+    /// What the transaction pays per gas at `base_fee`, capped by `fee_cap`.
+    pub fn effective_gas_price(&self, gas_price: U256, base_fee: U256) -> U256 {
+        match self.max_priority_fee_per_gas {
+            Some(priority) => self.fee_cap(gas_price).min(base_fee.saturating_add(priority)),
+            None => self.fee_cap(gas_price),
+        }
+    }
+
+    /// Identifies the transaction as the `index`th in block `block_number`. Simulated
+    /// transactions aren't signed, so this hashes their fields and position instead of a
+    /// raw signed transaction, which keeps identical transactions apart.
+    pub fn hash(&self, block_number: u64, index: usize) -> [u8; 32] {
+        let fields = Rlp::List(vec![
+            Rlp::address(&self.from),
+            Rlp::address(&self.to),
+            Rlp::Bytes(from_hex(&self.calldata).unwrap_or_default()),
+            Rlp::uint(self.value),
+            Rlp::uint(U256::from(self.gas_limit)),
+            self.payer.as_ref().map_or(Rlp::Bytes(vec![]), Rlp::address),
+            Rlp::uint(U256::from(block_number)),
+            Rlp::uint(U256::from(index as u64)),
+        ]);
+        keccak(fields.encode()).into()
+    }
+}
+
+/// What a transaction would change, before it is committed.
+struct Run {
+    result: CallResult,
+    /// The callee's new state, if the call succeeded.
+    contract: Option<Contract>,
+    /// Accounts the callee's calls touched, and both ends of `tx.value`, if the call
+    /// succeeded. Applied before `contract`.
+    accounts: HashMap<Address, Account>,
+    /// Who pays for gas, or `None` if the transaction was rejected before running.
+    payer: Option<Address>,
+    gas_price: U256,
+    /// Accounts the callee's code read, whether or not the call succeeded.
+    read: HashSet<Address>,
+}
+
+/// Each nested call takes tens of kilobytes of native stack, so every this many levels
+/// the calls go on in a fresh thread with a stack of `FRAME_THREAD_STACK` bytes.
+const FRAMES_PER_THREAD: usize = 16;
+const FRAME_THREAD_STACK: usize = 4 << 20;
+
+/// Runs the `CALL`s a transaction's code makes against the world, keeping the accounts
+/// they touch apart until the transaction commits.
+struct CallFrames<'w> {
+    world: &'w World,
+    /// The transaction's environment.
+    env: Env,
+    /// Accounts touched so far, shadowing `world.accounts`.
+    changed: HashMap<Address, Account>,
+    tx_states: HashMap<Address, TxState>,
+    /// Logs of the calls that succeeded, after the ones the transaction's callee emits.
+    logs: Vec<Log>,
+    /// Contracts created in the transaction, which `SELFDESTRUCT` deletes even under Cancun.
+    created: HashSet<Address>,
+    /// Accounts to delete when the transaction ends.
+    destroyed: HashSet<Address>,
+    depth: usize,
+    /// Every account looked up so far, for `World::apply_parallel`'s conflict check.
+    read: RefCell<HashSet<Address>>,
+}
+
+/// What a failed call or creation rolls back.
+struct Snapshot {
+    changed: HashMap<Address, Account>,
+    tx_states: HashMap<Address, TxState>,
+    logs: usize,
+    created: HashSet<Address>,
+    destroyed: HashSet<Address>,
+}
+
+impl<'w> CallFrames<'w> {
+    fn new(world: &'w World, env: Env) -> Self {
+        Self { world, env, changed: HashMap::new(), tx_states: HashMap::new(), logs: vec![], created: HashSet::new(), destroyed: HashSet::new(), depth: 0, read: RefCell::default() }
+    }
+
+    /// The accounts the transaction changed, with destroyed ones emptied.
+    fn finish(mut self) -> (HashMap<Address, Account>, Vec<Log>) {
+        for address in std::mem::take(&mut self.destroyed) {
+            *self.account_mut(address) = Account::default();
+        }
+        (self.changed, self.logs)
+    }
+
+    fn account(&self, address: &Address) -> Option<Cow<'_, Account>> {
+        self.read.borrow_mut().insert(*address);
+        match self.changed.get(address) {
+            Some(account) => Some(Cow::Borrowed(account)),
+            None => self.world.account(address),
+        }
+    }
+
+    fn account_mut(&mut self, address: Address) -> &mut Account {
+        self.changed.entry(address).or_insert_with(|| self.world.account(&address).map(Cow::into_owned).unwrap_or_default())
+    }
+
+    fn transfer(&mut self, from: Address, to: Address, value: U256) {
+        self.account_mut(from).balance -= value;
+        self.account_mut(to).balance += value;
+    }
+
+    /// Runs `contract` one level deeper, with these frames handling its calls.
+    fn execute(&mut self, contract: &Contract, calldata: &str, env: Env, tx_state: &mut TxState) -> Execution {
+        let world = self.world;
+        let execute = |frames: &mut Self| world.execute_at(contract, calldata, env, tx_state, frames);
+        self.depth += 1;
+        let execution = if self.depth.is_multiple_of(FRAMES_PER_THREAD) {
+            std::thread::scope(|scope| {
+                let thread = std::thread::Builder::new().stack_size(FRAME_THREAD_STACK).spawn_scoped(scope, || execute(self)).unwrap();
+                thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        } else {
+            execute(self)
+        };
+        self.depth -= 1;
+        execution
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            changed: self.changed.clone(),
+            tx_states: self.tx_states.clone(),
+            logs: self.logs.len(),
+            created: self.created.clone(),
+            destroyed: self.destroyed.clone(),
+        }
+    }
+
+    /// Copies the slots the running code at `address` sees in `storage` into its account,
+    /// so that a call back into it sees the writes made so far.
+    fn store_running(&mut self, address: Address, storage: &dyn Storage) {
+        if let Some(contract) = &mut self.account_mut(address).contract {
+            storage.keys().into_iter().for_each(|key| contract.storage.store(key, storage.load(key)));
+        }
+    }
+
+    /// Copies what calls back into `address` wrote to its account into the running code's
+    /// `storage`.
+    fn load_running(&self, address: Address, storage: &mut dyn Storage) {
+        let account = self.account(&address);
+        let Some(contract) = account.as_deref().and_then(|account| account.contract.as_ref()) else { return };
+        let mut keys = storage.keys();
+        keys.extend(contract.storage.keys());
+        for key in keys {
+            let value = self.world.slot(&address, &contract.storage, key);
+            if storage.load(key) != value {
+                storage.store(key, value);
+            }
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.changed = snapshot.changed;
+        self.tx_states = snapshot.tx_states;
+        self.logs.truncate(snapshot.logs);
+        self.created = snapshot.created;
+        self.destroyed = snapshot.destroyed;
+    }
+}
+
+/// The storage of the contract at `address` as its code sees it, over the account's slots
+/// in the world's `db`.
+struct StoredStorage<'w> {
+    world: &'w World,
+    address: Address,
+    storage: ContractStorage,
+}
+
+impl Storage for StoredStorage<'_> {
+    fn load(&self, key: U256) -> U256 {
+        self.world.slot(&self.address, &self.storage, key)
+    }
+
+    fn store(&mut self, key: U256, value: U256) {
+        self.storage.store(key, value);
+    }
+
+    fn keys(&self) -> Vec<U256> {
+        self.storage.keys()
+    }
+}
+
+impl CallHandler for CallFrames<'_> {
+    fn call(&mut self, call: MessageCall, storage: &mut dyn Storage) -> CallOutcome {
+        let address = call.address();
+        let transfers = call.kind != CallKind::DelegateCall;
+        if self.depth == MAX_CALL_DEPTH || transfers && self.account(&call.caller).map_or(U256::ZERO, |account| account.balance) < call.value {
+            return CallOutcome::failed();
+        }
+        self.store_running(call.caller, storage);
+        let snapshot = self.snapshot();
+        if transfers {
+            self.transfer(call.caller, address, call.value);
+        }
+
+        let outcome = match self.account(&call.to).and_then(|account| account.contract.clone()) {
+            Some(mut contract) => {
+                if address != call.to {
+                    // The callee's code runs on the caller's storage.
+                    contract.storage = self.account(&address).and_then(|account| account.contract.as_ref().map(|own| own.storage.clone())).unwrap_or_default();
+                }
+                let env = Env {
+                    caller: call.sender,
+                    value: call.value,
+                    address,
+                    origin: Some(self.env.context().origin),
+                    gas_limit: call.gas,
+                    is_static: call.is_static,
+                    ..self.env.clone()
+                };
+                let mut tx_state = self.tx_states.get(&address).cloned().unwrap_or_default();
+                let execution = self.execute(&contract, to_hex(&call.input).trim_start_matches("0x"), env, &mut tx_state);
+                match execution.halt {
+                    None => {
+                        let account = self.account_mut(address);
+                        match &mut account.contract {
+                            Some(own) if address != call.to => own.storage = execution.contract.storage,
+                            _ => account.contract = Some(execution.contract),
+                        }
+                        self.tx_states.insert(address, tx_state);
+                        self.logs.extend(execution.logs.into_iter().map(|log| log.emitted_by(address)));
+                        CallOutcome { success: true, return_data: execution.return_data, gas_used: execution.gas_used }
+                    },
+                    Some(Halt::Revert(data)) => CallOutcome { success: false, return_data: data, gas_used: execution.gas_used },
+                    Some(_) => CallOutcome { success: false, return_data: vec![], gas_used: execution.gas_used },
+                }
+            },
+            None => CallOutcome { success: true, return_data: vec![], gas_used: 0 },
+        };
+
+        if !outcome.success {
+            self.restore(snapshot);
+        }
+        self.load_running(call.caller, storage);
+        outcome
+    }
+
+    fn create(&mut self, creation: ContractCreation, storage: &mut dyn Storage) -> CreateOutcome {
+        if self.depth == MAX_CALL_DEPTH || self.account(&creation.creator).map_or(U256::ZERO, |account| account.balance) < creation.value {
+            return CreateOutcome::failed();
+        }
+        self.store_running(creation.creator, storage);
+        let creator = self.account_mut(creation.creator);
+        // The nonce goes up whether or not the creation succeeds.
+        let nonce = creator.nonce;
+        creator.nonce += 1;
+        let address = match creation.salt {
+            Some(salt) => create2_address(&creation.creator, salt, &creation.init_code),
+            None => create_address(&creation.creator, nonce),
+        };
+        // A collision with an existing contract fails and uses up the gas.
+        let collides = self.account(&address).is_some_and(|account| account.nonce != 0 || account.contract.is_some());
+        let Some(program) = disassemble(&creation.init_code).filter(|_| !collides) else {
+            return CreateOutcome { gas_used: creation.gas, ..CreateOutcome::failed() };
+        };
+        let snapshot = self.snapshot();
+        self.transfer(creation.creator, address, creation.value);
+        self.account_mut(address).nonce = 1;
+        self.created.insert(address);
+
+        // Raw code checks the value itself, if at all.
+        let payable = |name: &str, program| {
+            let mut function = Function::from_program(name.to_string(), program);
+            function.mutability = FuncMutability::Payable;
+            function
+        };
+        let mut init = Contract::new("Created".to_string());
+        init.fallback = Some(payable("constructor", program));
+        let env = Env {
+            caller: creation.creator,
+            value: creation.value,
+            address,
+            origin: Some(self.env.context().origin),
+            gas_limit: creation.gas,
+            is_static: false,
+            ..self.env.clone()
+        };
+        let mut tx_state = TxState::default();
+        let execution = self.execute(&init, "", env, &mut tx_state);
+        let deposit = G_CODE_DEPOSIT * execution.return_data.len() as u64;
+        let runtime = disassemble(&execution.return_data).filter(|_| execution.gas_used + deposit <= creation.gas);
+        let outcome = match (execution.halt, runtime) {
+            (None, Some(program)) => {
+                let contract = Contract {
+                    storage: execution.contract.storage,
+                    fallback: Some(payable("fallback", program)),
+                    ..init
+                };
+                self.account_mut(address).contract = Some(contract);
+                self.tx_states.insert(address, tx_state);
+                self.logs.extend(execution.logs.into_iter().map(|log| log.emitted_by(address)));
+                CreateOutcome { address: Some(address), return_data: vec![], gas_used: execution.gas_used + deposit }
+            },
+            (Some(Halt::Revert(data)), _) => CreateOutcome { address: None, return_data: data, gas_used: execution.gas_used },
+            (None, None) => CreateOutcome { gas_used: creation.gas, ..CreateOutcome::failed() },
+            (Some(_), _) => CreateOutcome { gas_used: execution.gas_used, ..CreateOutcome::failed() },
+        };
+
+        if outcome.address.is_none() {
+            self.restore(snapshot);
+        }
+        self.load_running(creation.creator, storage);
+        outcome
+    }
+
+    fn selfdestruct(&mut self, contract: Address, beneficiary: Address) {
+        self.transfer(contract, beneficiary, self.balance(contract));
+        // The contract stays callable until the transaction ends. A contract that names
+        // itself as beneficiary burns its balance, unless it survives under Cancun.
+        if !self.world.cancun || self.created.contains(&contract) {
+            self.destroyed.insert(contract);
+        }
+    }
+
+    fn balance(&self, address: Address) -> U256 {
+        self.account(&address).map_or(U256::ZERO, |account| account.balance)
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.account(&address).and_then(|account| account.contract.as_ref().map(Contract::deployed_bytecode)).unwrap_or_default()
+    }
+
+    fn code_hash(&self, address: Address) -> U256 {
+        match self.account(&address) {
+            Some(account) if !account.is_empty() => {
+                let hash = account.contract.as_ref().map_or_else(|| keccak([]).into(), Contract::code_hash);
+                U256::from_be_bytes(hash)
+            },
+            _ => U256::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct World {
+    /// Accounts the world has written, shadowing `db`.
+    pub accounts: HashMap<Address, Account>,
+    /// State of the accounts not in `accounts`, and of the slots their contracts haven't
+    /// written, read as it's needed. The world never writes to it; see `save`. Clones share
+    /// it, and state roots only cover `accounts`.
+    pub db: Arc<dyn StateDB>,
+    /// Block-level environment every transaction starts from.
+    pub env: Env,
+    /// Backend contract code runs on.
+    pub executor: Arc<dyn Executor>,
+    /// Blocks sealed by `mine`, oldest first.
+    pub blocks: Vec<Block>,
+    pub mining: Mining,
+    /// Seconds between consecutive blocks.
+    pub block_time: u64,
+    /// Hashes of transactions applied since the last block was sealed.
+    pending: Vec<[u8; 32]>,
+    pending_gas_used: u64,
+    /// Seconds `advance_time` has moved past the current block in interval mode.
+    elapsed: u64,
+    /// Logs of mined blocks, oldest first, and of the pending block.
+    logs: Vec<LogRecord>,
+    pending_logs: Vec<LogRecord>,
+    subscriptions: Vec<Subscription>,
+    next_subscription: usize,
+    /// Names and ABIs of deployed contracts and named accounts, for traces.
+    pub registry: ContractRegistry,
+    /// Draws each new block's `prevrandao`. See `seed`.
+    randomness: Rng,
+    /// Gas each `view_call` may use.
+    pub view_gas_limit: u64,
+    /// Follow Cancun's EIP-6780, under which `SELFDESTRUCT` only sends the balance away
+    /// unless the contract was created in the same transaction.
+    pub cancun: bool,
+    /// Bumped by every change `World`'s methods make, invalidating cached `view_call`
+    /// results.
+    state_version: u64,
+    views: ViewCache,
+}
+
+/// `view_call` results for one state version, keyed by address and calldata.
+#[derive(Debug, Default)]
+struct Views {
+    version: u64,
+    results: HashMap<(Address, String), CallResult>,
+}
+
+#[derive(Debug, Default)]
+struct ViewCache(Mutex<Views>);
+
+/// A copy of the world can change independently of the original at the same version, so
+/// it starts with an empty cache.
+impl Clone for ViewCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            accounts: HashMap::new(),
+            db: Arc::new(MemoryDB::default()),
+            env: Env::default(),
+            executor: Arc::new(Interpreter),
+            blocks: vec![],
+            mining: Mining::Manual,
+            block_time: 12,
+            pending: vec![],
+            pending_gas_used: 0,
+            elapsed: 0,
+            logs: vec![],
+            pending_logs: vec![],
+            subscriptions: vec![],
+            next_subscription: 0,
+            registry: ContractRegistry::new(),
+            randomness: Rng::new(0),
+            view_gas_limit: 10_000_000,
+            cancun: false,
+            state_version: 0,
+            views: ViewCache::default(),
+        }
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restarts the generator behind `PREVRANDAO` from `seed` and draws the current block's
+    /// value from it, so runs with the same seed see the same randomness and block hashes.
+    pub fn seed(&mut self, seed: u64) {
+        self.invalidate_views();
+        self.randomness = Rng::new(seed);
+        self.env.prevrandao = self.randomness.uniform_word();
+    }
+
+    /// A world over the state in `db`, which its accounts start out as.
+    pub fn with_db(db: impl StateDB + 'static) -> Self {
+        Self { db: Arc::new(db), ..Self::default() }
+    }
+
+    /// The account at `address`, from `accounts` or else `db`.
+    pub fn account(&self, address: &Address) -> Option<Cow<'_, Account>> {
+        match self.accounts.get(address) {
+            Some(account) => Some(Cow::Borrowed(account)),
+            None => Account::load(&*self.db, address).map(Cow::Owned),
+        }
+    }
+
+    /// The account at `address` in `accounts`, copied there from `db` first if need be.
+    fn account_mut(&mut self, address: Address) -> &mut Account {
+        let db = &*self.db;
+        self.accounts.entry(address).or_insert_with(|| Account::load(db, &address).unwrap_or_default())
+    }
+
+    /// Slot `key` of the contract at `address` whose storage is `storage`: the value
+    /// `storage` holds if it was written, and otherwise `db`'s.
+    fn slot(&self, address: &Address, storage: &ContractStorage, key: U256) -> U256 {
+        match storage.contains(key) {
+            true => storage.load(key),
+            false => self.db.slot(address, key),
+        }
+    }
+
+    pub fn balance(&self, address: &Address) -> U256 {
+        self.account(address).map(|account| account.balance).unwrap_or_default()
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        self.invalidate_views();
+        self.account_mut(address).balance = balance;
+    }
+
+    /// Writes the accounts in `accounts` to `db`: balances, nonces, runtime code and the
+    /// slots their contracts wrote. A destroyed account is written empty, though slots of
+    /// its that only `db` holds stay there.
+    pub fn save(&self, db: &mut dyn StateDB) {
+        for (address, account) in &self.accounts {
+            db.set_balance(*address, account.balance);
+            db.set_nonce(*address, account.nonce);
+            db.set_code(*address, account.contract.as_ref().map(Contract::deployed_bytecode).unwrap_or_default());
+            if let Some(contract) = &account.contract {
+                contract.storage.keys().into_iter().for_each(|key| db.set_slot(*address, key, contract.storage.load(key)));
+            }
+        }
+    }
+
+    /// Root of the account's storage trie, mapping `keccak(slot)` to the RLP of each
+    /// nonzero value. Accounts without code have the empty root.
+    pub fn storage_root(&self, address: &Address) -> [u8; 32] {
+        let Some(contract) = self.accounts.get(address).and_then(|account| account.contract.as_ref()) else {
+            return EMPTY_ROOT;
+        };
+        let entries = contract.storage.iter()
+            .filter(|(_, value)| *value != U256::ZERO)
+            .map(|(slot, value)| (keccak(slot.to_be_bytes()).as_bytes().to_vec(), Rlp::uint(value).encode()))
+            .collect::<Vec<_>>();
+        trie_root(&entries)
+    }
+
+    /// Root of the state trie, mapping `keccak(address)` to the RLP of each account's
+    /// nonce, balance, storage root and code hash, as in a block header. Empty accounts
+    /// are left out, as after EIP-161. Computed on demand, so only pay for it when comparing
+    /// post-states.
+    pub fn state_root(&self) -> [u8; 32] {
+        let entries = self.accounts.iter()
+            .filter(|(_, account)| !account.is_empty())
+            .map(|(address, account)| {
+                let code_hash = account.contract.as_ref().map_or_else(|| keccak([]).into(), Contract::code_hash);
+                let rlp = Rlp::List(vec![
+                    Rlp::uint(U256::from(account.nonce)),
+                    Rlp::uint(account.balance),
+                    Rlp::Bytes(self.storage_root(address).to_vec()),
+                    Rlp::Bytes(code_hash.to_vec()),
+                ]);
+                (keccak(address.0).as_bytes().to_vec(), rlp.encode())
+            })
+            .collect::<Vec<_>>();
+        trie_root(&entries)
+    }
+
+    /// Places `contract` at an address derived from the deployer and its nonce.
+    pub fn deploy(&mut self, deployer: Address, contract: Contract) -> Address {
+        self.invalidate_views();
+        let sender = self.account_mut(deployer);
+        let nonce = sender.nonce;
+        sender.nonce += 1;
+
+        let address = create_address(&deployer, nonce);
+        self.registry.register(address, ContractInfo { name: contract.name.clone(), abi: contract.abi(), source: None });
+        self.account_mut(address).contract = Some(contract);
+        address
+    }
+
+    /// Like `deploy`, running the contract's constructor with `args` first, with the
+    /// deployer as `msg.sender`. The contract is only placed if the constructor succeeds,
+    /// though the deployer's nonce goes up either way. Logs the constructor emits are in
+    /// the result.
+    pub fn deploy_with_args(&mut self, deployer: Address, contract: Contract, args: &[Value]) -> (Address, CallResult) {
+        self.invalidate_views();
+        let sender = self.account_mut(deployer);
+        let nonce = sender.nonce;
+        sender.nonce += 1;
+
+        let address = create_address(&deployer, nonce);
+        let env = Env { caller: deployer, address, origin: Some(deployer), ..self.env.clone() };
+        let execution = contract.construct(&*self.executor, args, env);
+        let preimages = execution.preimages;
+        let result = match execution.halt {
+            Some(Halt::Revert(data)) => CallResult { gas_used: execution.gas_used, preimages, ..CallResult::reverted(data, &contract.errors) },
+            Some(halt) => CallResult { gas_used: execution.gas_used, preimages, ..CallResult::halted(halt) },
+            None => {
+                self.registry.register(address, ContractInfo { name: contract.name.clone(), abi: contract.abi(), source: None });
+                self.account_mut(address).contract = Some(execution.contract);
+                CallResult {
+                    gas_used: execution.gas_used,
+                    preimages,
+                    logs: execution.logs.into_iter().map(|log| log.emitted_by(address)).collect(),
+                    ..CallResult::success(vec![])
+                }
+            },
+        };
+        (address, result)
+    }
+
+    pub fn call(&mut self, from: Address, to: Address, calldata: &str) -> CallResult {
+        self.apply_transaction(&Transaction::new(from, to, calldata))
+    }
+
+    /// Executes `tx`, committing its storage changes and moving `tx.value` from the sender
+    /// to the callee on success. `gas_used` includes the intrinsic cost. The fee payer,
+    /// `tx.payer` or else the sender, must afford the whole gas limit up front and is
+    /// charged for the gas used whether or not the call succeeds.
+    ///
+    /// Calls to accounts without code are plain transfers; a contract receiving empty
+    /// calldata runs its `receive` or `fallback` function, and a failure there keeps the
+    /// value with the sender.
+    pub fn apply_transaction(&mut self, tx: &Transaction) -> CallResult {
+        let run = self.run(tx);
+        self.commit(tx, run)
+    }
+
+    /// Applies `txs` in order with the same results as `apply_transaction` one by one, but
+    /// executes them in parallel first, optimistically against the state before the batch.
+    /// A transaction that read or wrote an account (sender, fee payer, callee or any its
+    /// code looked at) an earlier one in the batch wrote, the coinbase included, may have
+    /// seen stale state, so it is executed again before committing.
+    ///
+    /// Under `Mining::Auto` every transaction mines a block, so each runs in a different
+    /// environment from the one before it; they are applied one by one instead.
+    pub fn apply_parallel(&mut self, txs: &[Transaction]) -> Vec<CallResult> {
+        if self.mining == Mining::Auto {
+            return txs.iter().map(|tx| self.apply_transaction(tx)).collect();
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = txs.len().div_ceil(threads).max(1);
+        let world = &*self;
+        let runs = std::thread::scope(|scope| {
+            let handles = txs.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(|tx| world.run(tx)).collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+
+        let mut written = HashSet::new();
+        txs.iter().zip(runs).map(|(tx, run)| {
+            let ends = [tx.from, tx.payer.unwrap_or(tx.from), tx.to];
+            let stale = ends.iter().chain(run.accounts.keys()).chain(&run.read).any(|account| written.contains(account));
+            let run = if stale { self.run(tx) } else { run };
+            written.extend(ends);
+            written.extend(run.accounts.keys().copied());
+            written.insert(self.env.coinbase);
+            self.commit(tx, run)
+        }).collect()
+    }
+
+    fn commit(&mut self, tx: &Transaction, run: Run) -> CallResult {
+        self.invalidate_views();
+        let Run { mut result, contract, accounts, payer, gas_price, .. } = run;
+        let Some(payer) = payer else {
+            return result;
+        };
+        let hash = tx.hash(self.env.block_number, self.pending.len());
+        self.pending.push(hash);
+        self.pending_gas_used += result.gas_used;
+        result.tx_hash = Some(hash);
+        if result.success {
+            let block_number = self.env.block_number;
+            let records = result.logs.iter().enumerate().map(|(i, log)| LogRecord {
+                log: log.clone(),
+                block_number,
+                tx_hash: hash,
+                log_index: self.pending_logs.len() + i,
+            }).collect::<Vec<_>>();
+            self.pending_logs.extend(records);
+        }
+        if result.success && !accounts.is_empty() {
+            self.accounts.extend(accounts);
+        } else if result.success && tx.value > U256::ZERO {
+            self.account_mut(tx.from).balance -= tx.value;
+            self.account_mut(tx.to).balance += tx.value;
+        }
+        if let Some(contract) = contract {
+            self.account_mut(tx.to).contract = Some(contract);
+        }
+        // The base fee is burned; the rest goes to the coinbase.
+        let fee = U256::from(result.gas_used) * gas_price;
+        let tip = U256::from(result.gas_used) * (gas_price - self.env.base_fee);
+        if fee > U256::ZERO {
+            self.account_mut(payer).balance -= fee;
+        }
+        if tip > U256::ZERO {
+            self.account_mut(self.env.coinbase).balance += tip;
+        }
+        if self.mining == Mining::Auto {
+            self.mine();
+        }
+        result
+    }
+
+    /// Seals the transactions applied so far into a block at the current block number and
+    /// returns its hash. The environment moves on to the next block, `block_time` later,
+    /// with the new hash visible to `BLOCKHASH` and the base fee adjusted for the gas the
+    /// block used.
+    pub fn mine(&mut self) -> [u8; 32] {
+        self.invalidate_views();
+        let block = Block {
+            number: self.env.block_number,
+            timestamp: self.env.timestamp,
+            parent_hash: self.blocks.last().map(Block::hash).unwrap_or_default(),
+            state_root: self.state_root(),
+            transactions: std::mem::take(&mut self.pending),
+            gas_used: std::mem::take(&mut self.pending_gas_used),
+            gas_limit: self.env.gas_limit,
+            base_fee: self.env.base_fee,
+            prevrandao: self.env.prevrandao,
+            logs_bloom: Bloom::from_logs(self.pending_logs.iter().map(|record| &record.log)),
+        };
+        let hash = block.hash();
+        self.env.block_hashes.insert(block.number, hash);
+        self.env.block_hashes.retain(|number, _| block.number - number < 256);
+        self.env.block_number += 1;
+        self.env.timestamp += self.block_time;
+        self.env.base_fee = block.next_base_fee();
+        self.env.prevrandao = self.randomness.uniform_word();
+        self.elapsed = 0;
+        self.blocks.push(block);
+        let logs = std::mem::take(&mut self.pending_logs);
+        self.subscriptions.iter().for_each(|subscription| {
+            let mut callback = subscription.callback.lock().unwrap();
+            logs.iter().filter(|record| subscription.filter.matches(record)).for_each(&mut *callback);
+        });
+        self.logs.extend(logs);
+        hash
+    }
+
+    /// Logs of mined blocks matching `filter`, oldest first, like `eth_getLogs`.
+    pub fn logs(&self, filter: &LogFilter) -> Vec<LogRecord> {
+        self.logs.iter().filter(|record| filter.matches(record)).cloned().collect()
+    }
+
+    /// Calls `callback` with every log matching `filter` as blocks are mined, like an
+    /// `eth_subscribe("logs")` subscription. Clones of the world share the callback.
+    pub fn subscribe(&mut self, filter: LogFilter, callback: impl FnMut(&LogRecord) + Send + 'static) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription);
+        self.next_subscription += 1;
+        self.subscriptions.push(Subscription { id, filter, callback: Arc::new(Mutex::new(callback)) });
+        id
+    }
+
+    /// Stops a subscription, returning whether it existed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|subscription| subscription.id != id);
+        self.subscriptions.len() != before
+    }
+
+    /// Mines `n` blocks, the first with any pending transactions and the rest empty, and
+    /// returns their hashes.
+    pub fn mine_n(&mut self, n: u64) -> Vec<[u8; 32]> {
+        (0..n).map(|_| self.mine()).collect()
+    }
+
+    /// Moves the simulated clock forward. In interval mode this mines a block for every
+    /// `block_time` that passes; otherwise the pending block's timestamp moves instead.
+    pub fn advance_time(&mut self, seconds: u64) {
+        if self.mining != Mining::Interval {
+            self.invalidate_views();
+            self.env.timestamp += seconds;
+            return;
+        }
+        let elapsed = self.elapsed + seconds;
+        self.mine_n(elapsed / self.block_time.max(1));
+        self.elapsed = elapsed % self.block_time.max(1);
+    }
+
+    /// Runs `tx` without committing anything, like `eth_call`. It only needs `&self`, so
+    /// any number of simulations can run in parallel against one shared world, for example
+    /// one behind an `Arc<RwLock<World>>` while transactions are applied in between.
+    pub fn simulate(&self, tx: &Transaction) -> CallResult {
+        self.run(tx).result
+    }
+
+    /// Like `simulate`, with `overrides` applied to a copy of the world first, as
+    /// `eth_call`'s `stateOverride` does.
+    pub fn simulate_with_overrides(&self, tx: &Transaction, overrides: &StateOverride) -> CallResult {
+        let mut world = self.clone();
+        overrides.accounts.keys().for_each(|address| { world.account_mut(*address); });
+        overrides.apply(&mut world.accounts);
+        world.simulate(tx)
+    }
+
+    /// Calls a getter at `address` from the zero address, for free, like an `eth_call`
+    /// without a transaction. The call runs as a static call, so anything that would change
+    /// state halts it, and may use at most `view_gas_limit` gas, not counting the intrinsic
+    /// cost. Results are cached until the world next changes, for dashboards that poll the
+    /// same getters; changes made through `accounts` or `env` directly need
+    /// `invalidate_views` to be seen.
+    pub fn view_call(&self, address: Address, calldata: &str) -> CallResult {
+        let key = (address, calldata.to_string());
+        {
+            let mut views = self.views.0.lock().unwrap();
+            if views.version != self.state_version {
+                *views = Views { version: self.state_version, results: HashMap::new() };
+            }
+            if let Some(result) = views.results.get(&key) {
+                return result.clone();
+            }
+        }
+
+        let account = self.account(&address);
+        let result = match account.as_deref().and_then(|account| account.contract.as_ref()) {
+            Some(contract) => {
+                let env = Env {
+                    caller: Address::ZERO,
+                    value: U256::ZERO,
+                    address,
+                    gas_limit: self.view_gas_limit,
+                    block_gas_limit: Some(self.env.gas_limit),
+                    payer: None,
+                    gas_price: U256::ZERO,
+                    is_static: true,
+                    ..self.env.clone()
+                };
+                let mut frames = CallFrames::new(self, env.clone());
+                let execution = self.execute_at(contract, calldata, env, &mut TxState::default(), &mut frames);
+                let (gas_used, preimages) = (execution.gas_used, execution.preimages);
+                match execution.halt {
+                    Some(Halt::Revert(data)) => CallResult { gas_used, preimages, ..CallResult::reverted(data, &contract.errors) },
+                    Some(halt) => CallResult { gas_used, preimages, ..CallResult::halted(halt) },
+                    None => CallResult { gas_used, preimages, return_data: execution.return_data, ..CallResult::success(execution.output) },
+                }
+            },
+            None => CallResult::success(vec![]),
+        };
+        let mut views = self.views.0.lock().unwrap();
+        if views.version == self.state_version {
+            views.results.insert(key, result.clone());
+        }
+        result
+    }
+
+    /// Drops cached `view_call` results, after changing `accounts` or `env` directly.
+    pub fn invalidate_views(&mut self) {
+        self.state_version += 1;
+    }
+
+    fn run(&self, tx: &Transaction) -> Run {
+        let rejected = |result| Run { result, contract: None, accounts: HashMap::new(), payer: None, gas_price: U256::ZERO, read: HashSet::new() };
+        if tx.gas_limit < INTRINSIC_GAS {
+            return rejected(CallResult { gas_used: tx.gas_limit, ..CallResult::halted(Halt::OutOfGas) });
+        }
+        if tx.fee_cap(self.env.gas_price) < self.env.base_fee {
+            return rejected(CallResult::halted(Halt::FeeCapTooLow));
+        }
+        let gas_price = tx.effective_gas_price(self.env.gas_price, self.env.base_fee);
+        let env = Env {
+            caller: tx.from,
+            value: tx.value,
+            address: tx.to,
+            gas_limit: tx.gas_limit - INTRINSIC_GAS,
+            block_gas_limit: Some(self.env.gas_limit),
+            payer: tx.payer,
+            gas_price,
+            ..self.env.clone()
+        };
+        let payer = env.fee_payer();
+        // Like a node, require the fee cap up front, not just the effective price.
+        let upfront = U256::from(tx.gas_limit) * tx.fee_cap(self.env.gas_price);
+        let affordable = if payer == tx.from {
+            self.balance(&payer) >= upfront + tx.value
+        } else {
+            self.balance(&payer) >= upfront && self.balance(&tx.from) >= tx.value
+        };
+        if !affordable {
+            return rejected(CallResult::halted(Halt::InsufficientFunds));
+        }
+
+        let callee = self.account(&tx.to);
+        match callee.as_deref() {
+            Some(Account { contract: Some(contract), .. }) => {
+                let mut frames = CallFrames::new(self, env.clone());
+                frames.transfer(tx.from, tx.to, tx.value);
+                let execution = self.execute_at(contract, &tx.calldata, env, &mut TxState::default(), &mut frames);
+                let gas_used = INTRINSIC_GAS + execution.gas_used;
+                let preimages = execution.preimages;
+                let read = frames.read.take();
+                let failed = |result| Run { result, contract: None, accounts: HashMap::new(), payer: Some(payer), gas_price, read: read.clone() };
+                match execution.halt {
+                    Some(Halt::Revert(data)) => failed(CallResult { gas_used, preimages, ..CallResult::reverted(data, &contract.errors) }),
+                    Some(halt) => failed(CallResult { gas_used, preimages, ..CallResult::halted(halt) }),
+                    None => {
+                        let destroyed = frames.destroyed.contains(&tx.to);
+                        let (accounts, logs) = frames.finish();
+                        Run {
+                            result: CallResult {
+                                gas_used,
+                                preimages,
+                                return_data: execution.return_data,
+                                logs: execution.logs.into_iter().map(|log| log.emitted_by(tx.to)).chain(logs).collect(),
+                                ..CallResult::success(execution.output)
+                            },
+                            contract: (!destroyed).then_some(execution.contract),
+                            accounts,
+                            payer: Some(payer),
+                            gas_price,
+                            read,
+                        }
+                    },
+                }
+            },
+            _ => Run {
+                result: CallResult { gas_used: INTRINSIC_GAS, ..CallResult::success(vec![]) },
+                contract: None,
+                accounts: HashMap::new(),
+                payer: Some(payer),
+                gas_price,
+                read: HashSet::new(),
+            },
+        }
+    }
+
+    /// Runs `contract` as the code at `env.address`, reading the slots it hasn't written
+    /// from `db`.
+    fn execute_at(&self, contract: &Contract, calldata: &str, env: Env, tx_state: &mut TxState, calls: &mut dyn CallHandler) -> Execution {
+        let mut storage = StoredStorage { world: self, address: env.address, storage: contract.storage.clone() };
+        let mut execution = contract.execute_on_storage_with_calls(&*self.executor, calldata, env, &mut storage, tx_state, calls);
+        execution.contract.storage = storage.storage;
+        execution
+    }
+
+    /// Runs `calls` from `from` as a single transaction, the way Multicall3's `aggregate3`
+    /// batches them: calls share one gas limit and see each other's warm storage slots and
+    /// state changes. A failing call that doesn't allow failure reverts the whole batch.
+    ///
+    /// Unlike going through a deployed Multicall3, each call sees `from` as its caller.
+    pub fn multicall(&mut self, from: Address, calls: &[Call]) -> MulticallReceipt {
+        let mut frames = CallFrames::new(self, Env { caller: from, block_gas_limit: Some(self.env.gas_limit), ..self.env.clone() });
+        let mut gas_used = INTRINSIC_GAS;
+        let mut results = vec![];
+
+        for call in calls {
+            let env = Env {
+                caller: from,
+                address: call.to,
+                gas_limit: self.env.gas_limit.saturating_sub(gas_used),
+                block_gas_limit: Some(self.env.gas_limit),
+                ..self.env.clone()
+            };
+            let result = match frames.account(&call.to).and_then(|account| account.contract.clone()) {
+                Some(contract) => {
+                    let snapshot = frames.snapshot();
+                    let mut tx_state = frames.tx_states.get(&call.to).cloned().unwrap_or_default();
+                    let execution = self.execute_at(&contract, &call.calldata, env, &mut tx_state, &mut frames);
+                    gas_used += execution.gas_used;
+                    let preimages = execution.preimages;
+                    match execution.halt {
+                        Some(halt) => {
+                            frames.restore(snapshot);
+                            match halt {
+                                Halt::Revert(data) => CallResult { gas_used: execution.gas_used, preimages, ..CallResult::reverted(data, &contract.errors) },
+                                halt => CallResult { gas_used: execution.gas_used, preimages, ..CallResult::halted(halt) },
+                            }
+                        },
+                        None => {
+                            frames.account_mut(call.to).contract = Some(execution.contract);
+                            frames.tx_states.insert(call.to, tx_state);
+                            // The callee's own logs come before those of the calls it made.
+                            let nested = frames.logs[snapshot.logs..].to_vec();
+                            CallResult {
+                                gas_used: execution.gas_used,
+                                preimages,
+                                return_data: execution.return_data,
+                                logs: execution.logs.into_iter().map(|log| log.emitted_by(call.to)).chain(nested).collect(),
+                                ..CallResult::success(execution.output)
+                            }
+                        }
+                    }
+                },
+                _ => CallResult::success(vec![]),
+            };
+            let abort = !result.success && !call.allow_failure;
+            results.push(result);
+            if abort {
+                return MulticallReceipt { success: false, results, gas_used };
+            }
+        }
+        let (accounts, _) = frames.finish();
+        self.invalidate_views();
+        self.accounts.extend(accounts);
+        MulticallReceipt { success: true, results, gas_used }
+    }
+
+    /// The smallest gas limit `tx` succeeds with, like `eth_estimateGas`. Binary searches
+    /// between the intrinsic cost and `tx.gas_limit` with simulations; `None` if the
+    /// transaction fails even with its full limit.
+    pub fn estimate_gas(&self, tx: &Transaction) -> Option<u64> {
+        let succeeds = |gas_limit: u64| self.simulate(&Transaction { gas_limit, ..tx.clone() }).success;
+        if !succeeds(tx.gas_limit) {
+            return None;
+        }
+        let (mut lo, mut hi) = (INTRINSIC_GAS, tx.gas_limit);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if succeeds(mid) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Some(lo)
+    }
+}
+
+/// Address of the contract `deployer` creates with `nonce`, as `CREATE` derives it:
+/// the last 20 bytes of `keccak(rlp([deployer, nonce]))`.
+pub fn create_address(deployer: &Address, nonce: u64) -> Address {
+    let preimage = Rlp::List(vec![Rlp::address(deployer), Rlp::uint(U256::from(nonce))]).encode();
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&keccak(preimage)[12..]);
+    Address(bytes)
+}
+
+/// Address of the contract `deployer` creates with `CREATE2`: the last 20 bytes of
+/// `keccak(0xff ++ deployer ++ salt ++ keccak(init_code))`, per EIP-1014.
+pub fn create2_address(deployer: &Address, salt: U256, init_code: &[u8]) -> Address {
+    let preimage = [&[0xff][..], &deployer.0, &salt.to_be_bytes(), keccak(init_code).as_bytes()].concat();
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(&keccak(preimage)[12..]);
+    Address(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::calldata;
+    use crate::selector::selector;
+    use crate::solidity::grammar::{parse, Parameter, Type};
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_deploy_and_call_flipper() {
+        let contract = flipper();
+
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        assert_eq!(world.account(&alice()).unwrap().nonce, 1);
+
+        world.call(bob(), address, &calldata("flip()", &[]));
+        let ret = world.call(bob(), address, &calldata("get()", &[]));
+        assert!(ret.success);
+        assert_eq!(ret.output, vec![Expression::BoolLiteral(true)]);
+
+        let missing = world.call(bob(), address, "deadbeef");
+        assert!(!missing.success);
+        assert_eq!(missing.halt, Some(Halt::FunctionNotFound([0xde, 0xad, 0xbe, 0xef])));
+    }
+
+    #[test]
+    fn test_value_sent_to_non_payable_function() {
+        let source = "contract Vault {
+    bool private open;
+    function deposit() public payable { open = true; }
+    function close() public { open = false; }
+}";
+        let contract = create_contracts(parse(source).unwrap()).remove(0);
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+
+        let deposit = Transaction { value: U256::ONE, ..Transaction::new(bob(), address, &calldata("deposit()", &[])) };
+        assert!(world.apply_transaction(&deposit).success);
+        let close = Transaction { value: U256::ONE, ..Transaction::new(bob(), address, &calldata("close()", &[])) };
+        let result = world.apply_transaction(&close);
+        assert!(!result.success);
+        assert_eq!(result.halt, Some(Halt::NonPayableFunctionReceivedValue));
+    }
+
+    #[test]
+    fn test_create_address() {
+        // anvil's first deployment from its first dev account.
+        let deployer = crate::secp256k1::address_from_private_key(U256::from_str_radix("ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80", 16).unwrap()).unwrap();
+        assert_eq!(create_address(&deployer, 0).to_string(), "0x5fbdb2315678afecb367f032d93f642f64180aa3");
+
+        // EIP-1014's first two examples.
+        assert_eq!(create2_address(&Address::ZERO, U256::ZERO, &[0]).to_string(), "0x4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38");
+        let deployer = Address(from_hex("deadbeef00000000000000000000000000000000").unwrap().try_into().unwrap());
+        assert_eq!(create2_address(&deployer, U256::ZERO, &[0]).to_string(), "0xb928f69bb1d91cd65274e3c79d8986362984fda3");
+    }
+
+    #[test]
+    fn test_create() {
+        // Init code that sets slot 0 to 7 and returns runtime code answering every call
+        // with 42.
+        let word = |bytes: &[u8]| {
+            let mut word = [0u8; 32];
+            word[32 - bytes.len()..].copy_from_slice(bytes);
+            U256::from_be_bytes(word)
+        };
+        let runtime = crate::bytecode::assemble(&[OP::PUSH1(42), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN]);
+        let init_code = crate::bytecode::assemble(&[
+            OP::PUSH1(7), OP::PUSH0, OP::SSTORE,
+            OP::PUSHN(runtime.len() as u8, word(&runtime)),
+            OP::PUSH0, OP::MSTORE, OP::PUSH1(runtime.len() as u8), OP::PUSH1(32 - runtime.len() as u8), OP::RETURN,
+        ]);
+
+        // `make()` creates a contract from the init code and returns its address;
+        // `make2()` does the same with `CREATE2` and salt 1.
+        let make = |create: Vec<OP>| {
+            let mut function = Function::from_program("make".to_string(), [
+                vec![OP::PUSHN(init_code.len() as u8, word(&init_code)), OP::PUSH0, OP::MSTORE],
+                create,
+                vec![OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN],
+            ].concat());
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        let (len, offset) = (OP::PUSH1(init_code.len() as u8), OP::PUSH1(32 - init_code.len() as u8));
+        let mut factory = Contract::new("Factory".to_string());
+        factory.insert_function(selector("make()"), make(vec![len.clone(), offset.clone(), OP::PUSH0, OP::CREATE])).unwrap();
+        factory.insert_function(selector("make2()"), make(vec![OP::PUSH1(1), len, offset, OP::PUSH0, OP::CREATE2])).unwrap();
+        let mut world = World::new_dev();
+        let factory_address = world.deploy(alice(), factory);
+
+        let created = create_address(&factory_address, 0);
+        let result = world.call(bob(), factory_address, &calldata("make()", &[]));
+        assert_eq!(result.output, vec![Expression::NumberLiteral(created.to_word())]);
+        assert_eq!(world.account(&factory_address).unwrap().nonce, 1);
+        assert_eq!(world.account(&created).unwrap().contract.as_ref().unwrap().storage.load(U256::ZERO), U256::from(7u8));
+        assert_eq!(world.call(bob(), created, "").return_data, U256::from(42u8).to_be_bytes());
+
+        let created2 = create2_address(&factory_address, U256::ONE, &init_code);
+        let result = world.call(bob(), factory_address, &calldata("make2()", &[]));
+        assert_eq!(result.output, vec![Expression::NumberLiteral(created2.to_word())]);
+        // The same salt and init code lead to the same address, which is taken.
+        let result = world.call(bob(), factory_address, &calldata("make2()", &[]));
+        assert_eq!(result.output, vec![Expression::NumberLiteral(U256::ZERO)]);
+    }
+
+    #[test]
+    fn test_selfdestruct() {
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), program);
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        let destroy = calldata("destroy()", &[]);
+        let mut doomed = Contract::new("Doomed".to_string());
+        doomed.insert_function(selector("destroy()"), function("destroy", vec![OP::PUSH32(carol().to_word()), OP::SELFDESTRUCT])).unwrap();
+        let deploy = |cancun: bool| {
+            let mut world = World::new_dev();
+            world.cancun = cancun;
+            let address = world.deploy(alice(), doomed.clone());
+            world.set_balance(address, U256::from(10u8));
+            (world, address)
+        };
+
+        // The balance goes to the beneficiary and the account is deleted.
+        let (mut world, address) = deploy(false);
+        let before = world.balance(&carol());
+        assert!(world.call(bob(), address, &destroy).success);
+        assert_eq!(world.balance(&carol()) - before, U256::from(10u8));
+        assert!(world.account(&address).unwrap().contract.is_none());
+        assert_eq!(world.call(bob(), address, &destroy).return_data, Vec::<u8>::new());
+
+        // Under Cancun only the balance moves.
+        let (mut world, address) = deploy(true);
+        assert!(world.call(bob(), address, &destroy).success);
+        assert_eq!(world.balance(&address), U256::ZERO);
+        assert!(world.account(&address).unwrap().contract.is_some());
+
+        // ...unless the contract was created in the same transaction, here by init code
+        // that destroys itself.
+        let mut factory = Contract::new("Factory".to_string());
+        let make = vec![
+            OP::PUSHN(2, U256::from(0x5fffu16)), OP::PUSH0, OP::MSTORE,
+            OP::PUSH1(2), OP::PUSH1(30), OP::PUSH0, OP::CREATE,
+            OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ];
+        factory.insert_function(selector("make()"), function("make", make)).unwrap();
+        let factory_address = world.deploy(alice(), factory);
+        let result = world.call(bob(), factory_address, &calldata("make()", &[]));
+        let created = create_address(&factory_address, 0);
+        assert_eq!(result.output, vec![Expression::NumberLiteral(created.to_word())]);
+        assert!(world.account(&created).unwrap().contract.is_none());
+    }
+
+    #[test]
+    fn test_balance() {
+        use crate::abi::{calldata, Value};
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), [program, vec![OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN]].concat());
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function.mutability = FuncMutability::Payable;
+            function
+        };
+        let mut wallet = Contract::new("Wallet".to_string());
+        wallet.insert_function(selector("own()"), function("own", vec![OP::SELFBALANCE])).unwrap();
+        wallet.insert_function(selector("of(address)"), function("of", vec![OP::PUSH1(4), OP::CALLDATALOAD, OP::BALANCE])).unwrap();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), wallet);
+        world.set_balance(address, U256::from(10u8));
+
+        // The value sent with the call has already arrived.
+        let own = Transaction { value: U256::from(5u8), ..Transaction::new(bob(), address, &calldata("own()", &[])) };
+        assert_eq!(world.apply_transaction(&own).output, vec![Expression::NumberLiteral(U256::from(15u8))]);
+        let of = |account: Address| calldata("of(address)", &[Value::Address(account)]);
+        assert_eq!(world.call(bob(), address, &of(carol())).output, vec![Expression::NumberLiteral(DEV_BALANCE)]);
+        assert_eq!(world.call(bob(), address, &of(Address([0x42; 20]))).output, vec![Expression::NumberLiteral(U256::ZERO)]);
+    }
+
+    #[test]
+    fn test_extcode() {
+        use crate::abi::{calldata, Value};
+        // Each function looks at the code of the address it's given.
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), [
+                vec![OP::PUSH1(4), OP::CALLDATALOAD],
+                program,
+                vec![OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN],
+            ].concat());
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        let mut probe = Contract::new("Probe".to_string());
+        probe.insert_function(selector("size(address)"), function("size", vec![OP::EXTCODESIZE])).unwrap();
+        probe.insert_function(selector("hash(address)"), function("hash", vec![OP::EXTCODEHASH])).unwrap();
+        // Copies the first word of the code to memory, where it is returned from.
+        let head = vec![OP::PUSH1(32), OP::PUSH0, OP::PUSH0, OP::DUP(4), OP::EXTCODECOPY, OP::POP, OP::PUSH0, OP::MLOAD];
+        probe.insert_function(selector("head(address)"), function("head", head)).unwrap();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), probe);
+        let code = world.account(&address).unwrap().contract.as_ref().unwrap().deployed_bytecode();
+        let mut ask = |function: &str, account: Address| {
+            let output = world.call(bob(), address, &calldata(function, &[Value::Address(account)])).output;
+            let [Expression::NumberLiteral(word)] = &output[..] else { panic!("no word returned") };
+            *word
+        };
+
+        assert_eq!(ask("size(address)", address), U256::from(code.len() as u64));
+        assert_eq!(ask("hash(address)", address), U256::from_be_bytes(keccak(&code).into()));
+        assert_eq!(ask("head(address)", address), U256::from_be_bytes(code[..32].try_into().unwrap()));
+        // An account without code has the empty code's hash, and one that doesn't exist
+        // has none.
+        assert_eq!(ask("size(address)", carol()), U256::ZERO);
+        assert_eq!(ask("hash(address)", carol()), U256::from_be_bytes(keccak([]).into()));
+        assert_eq!(ask("hash(address)", Address([0x42; 20])), U256::ZERO);
+        assert_eq!(ask("head(address)", Address([0x42; 20])), U256::ZERO);
+    }
+
+    #[test]
+    fn test_return_data_buffer() {
+        use crate::abi::{calldata, Value};
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), [
+                vec![OP::PUSH1(4), OP::CALLDATALOAD],
+                program,
+                vec![OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN],
+            ].concat());
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        let mut callee = Contract::new("Answer".to_string());
+        callee.insert_function(selector("answer()"), function("answer", vec![OP::POP, OP::PUSH1(42)])).unwrap();
+
+        // Each function calls `answer()` on the address it's given, copying none of the
+        // result, then looks at the return data buffer.
+        let answer_selector = U256::from(u32::from_be_bytes(selector("answer()")));
+        let call = vec![
+            OP::PUSHN(4, answer_selector), OP::PUSH1(224), OP::SHL, OP::PUSH0, OP::MSTORE,
+            OP::PUSH0, OP::PUSH0, OP::PUSH1(4), OP::PUSH0, OP::PUSH0, OP::DUP(6), OP::GAS, OP::CALL, OP::POP,
+        ];
+        let copy = |len: u8| vec![OP::PUSH1(len), OP::PUSH0, OP::PUSH0, OP::RETURNDATACOPY, OP::PUSH0, OP::MLOAD];
+        let mut caller = Contract::new("Relay".to_string());
+        caller.insert_function(selector("size(address)"), function("size", [call.clone(), vec![OP::RETURNDATASIZE]].concat())).unwrap();
+        caller.insert_function(selector("relay(address)"), function("relay", [call.clone(), copy(32)].concat())).unwrap();
+        caller.insert_function(selector("overrun(address)"), function("overrun", [call, copy(33)].concat())).unwrap();
+
+        let mut world = World::new_dev();
+        let answer = world.deploy(alice(), callee);
+        let relay = world.deploy(alice(), caller);
+        let mut ask = |function: &str| world.call(bob(), relay, &calldata(function, &[Value::Address(answer)]));
+
+        assert_eq!(ask("size(address)").output, vec![Expression::NumberLiteral(U256::from(32u8))]);
+        assert_eq!(ask("relay(address)").output, vec![Expression::NumberLiteral(U256::from(42u8))]);
+        assert_eq!(ask("overrun(address)").halt, Some(Halt::ReturnDataOutOfBounds));
+    }
+
+    #[test]
+    fn test_fee_market() {
+        let mut world = World::new();
+        world.env.base_fee = U256::from(100u8);
+        world.env.gas_limit = 84_000;
+        world.env.coinbase = carol();
+        world.set_balance(alice(), U256::from(10_000_000u32));
+
+        let transfer = Transaction {
+            gas_limit: INTRINSIC_GAS,
+            max_fee_per_gas: Some(U256::from(150u8)),
+            max_priority_fee_per_gas: Some(U256::from(20u8)),
+            ..Transaction::new(alice(), bob(), "")
+        };
+        assert!(world.apply_transaction(&transfer).success);
+        // 120 per gas: 100 burned, 20 to the coinbase.
+        assert_eq!(world.balance(&alice()), U256::from(10_000_000u32 - 21000 * 120));
+        assert_eq!(world.balance(&carol()), U256::from(21000u32 * 20));
+
+        // Legacy transactions pay the gas price, which must cover the base fee.
+        assert_eq!(world.apply_transaction(&Transaction::new(alice(), bob(), "")).halt, Some(Halt::FeeCapTooLow));
+
+        // Three of four possible transfers is above the half-full target, so the base fee
+        // rises by (63000 - 42000) / 42000 / 8 = 1/16.
+        world.apply_transaction(&transfer);
+        world.apply_transaction(&transfer);
+        world.mine();
+        assert_eq!(world.env.base_fee, U256::from(106u8));
+        world.mine();
+        assert_eq!(world.env.base_fee, U256::from(93u8));
+    }
+
+    #[test]
+    fn test_mine_blocks() {
+        let mut world = World::new();
+        let transfer = world.call(alice(), bob(), "");
+        let again = world.call(alice(), bob(), "");
+        assert!(transfer.tx_hash.is_some() && transfer.tx_hash != again.tx_hash);
+        assert_eq!(world.simulate(&Transaction::new(alice(), bob(), "")).tx_hash, None);
+
+        let first = world.mine();
+        let second = world.mine();
+        assert_eq!(world.blocks[0].transactions, vec![transfer.tx_hash.unwrap(), again.tx_hash.unwrap()]);
+        assert_eq!(world.blocks[1].parent_hash, first);
+        assert_eq!(world.env.block_number, 3);
+
+        let blockhash = |number: u8| {
+            let mut vm = VM::builder(vec![OP::PUSH1(number), OP::BLOCKHASH]).env(world.env.clone()).build();
+            vm.run(ContractStorage::new(vec![]));
+            vm.stack.peek(0).unwrap()
+        };
+        assert_eq!(blockhash(1), U256::from_be_bytes(first));
+        assert_eq!(blockhash(2), U256::from_be_bytes(second));
+        assert_eq!(blockhash(3), U256::ZERO);
+    }
+
+    #[test]
+    fn test_revert_data() {
+        let mut contract = Contract::new("Reverter".to_string());
+        let program = vec![OP::PUSH1(0xab), OP::PUSH1(0), OP::MSTORE8, OP::PUSH1(1), OP::PUSH1(0), OP::REVERT];
+        contract.insert_function(selector("fail()"), Function::from_program("fail".to_string(), program)).unwrap();
+        let mut world = World::new();
+        let address = world.deploy(alice(), contract);
+
+        let result = world.call(bob(), address, &calldata("fail()", &[]));
+        assert!(!result.success);
+        assert_eq!(result.halt, None);
+        assert_eq!(result.revert_data, vec![0xab]);
+        assert_eq!(result.revert_reason, Some(RevertReason::Raw(vec![0xab])));
+        assert!(result.gas_used > INTRINSIC_GAS);
+    }
+
+    #[test]
+    fn test_receipt_preimages() {
+        let mut contract = Contract::new("Hasher".to_string());
+        let program = vec![
+            OP::PUSH1(0xab), OP::PUSH0, OP::MSTORE8, OP::PUSH1(1), OP::PUSH0, OP::KECCAK256, OP::POP,
+            OP::PUSH1(1), OP::PUSH0, OP::REVERT,
+        ];
+        contract.insert_function(selector("hash()"), Function::from_program("hash".to_string(), program)).unwrap();
+        let mut world = World::new();
+        let address = world.deploy(alice(), contract);
+
+        // The preimages of a reverted call are still on its receipt.
+        let result = world.call(bob(), address, &calldata("hash()", &[]));
+        assert!(!result.success);
+        assert_eq!(result.preimages, BTreeMap::from([(U256::from_be_bytes(keccak([0xab]).0), vec![0xab])]));
+    }
+
+    #[test]
+    fn test_message_calls() {
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), program);
+            function.mutability = FuncMutability::Payable;
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        // `answer()` counts its calls in slot 0 and returns 42; `fail()` counts and reverts
+        // with 7.
+        let count = vec![OP::PUSH0, OP::SLOAD, OP::PUSH1(1), OP::ADD, OP::PUSH0, OP::SSTORE];
+        let mut callee = Contract::new("Callee".to_string());
+        callee.storage = ContractStorage::new(vec![U256::ZERO]);
+        let answer = [count.clone(), vec![OP::PUSH1(42), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN]].concat();
+        callee.insert_function(selector("answer()"), function("answer", answer)).unwrap();
+        callee.insert_function(selector("fail()"), function("fail", [count, vec![OP::PUSH1(7), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::REVERT]].concat())).unwrap();
+        let mut world = World::new_dev();
+        let callee_address = world.deploy(alice(), callee);
+
+        // `ask(...)` calls the callee's function of the same name with 5 wei, keeps whether
+        // that succeeded in slot 0 and returns the first word the callee returned or
+        // reverted with.
+        let ask = |name: &str| {
+            let callee_selector = U256::from(u32::from_be_bytes(selector(&format!("{}()", name))));
+            vec![
+                OP::PUSHN(4, callee_selector), OP::PUSH0, OP::MSTORE,
+                OP::PUSH1(32), OP::PUSH0, OP::PUSH1(4), OP::PUSH1(28), OP::PUSH1(5), OP::PUSH32(callee_address.to_word()), OP::GAS, OP::CALL,
+                OP::PUSH0, OP::SSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+            ]
+        };
+        let mut caller = Contract::new("Caller".to_string());
+        caller.storage = ContractStorage::new(vec![U256::ZERO]);
+        caller.insert_function(selector("answer()"), function("answer", ask("answer"))).unwrap();
+        caller.insert_function(selector("fail()"), function("fail", ask("fail"))).unwrap();
+        let caller_address = world.deploy(alice(), caller);
+        world.set_balance(caller_address, U256::from(10u8));
+        let slot = |world: &World, address| world.account(&address).unwrap().contract.as_ref().unwrap().storage.slots()[0];
+
+        let result = world.call(bob(), caller_address, &calldata("answer()", &[]));
+        assert_eq!(result.output, vec![Expression::NumberLiteral(U256::from(42u8))]);
+        assert_eq!((slot(&world, caller_address), slot(&world, callee_address)), (U256::ONE, U256::ONE));
+        assert_eq!((world.balance(&caller_address), world.balance(&callee_address)), (U256::from(5u8), U256::from(5u8)));
+
+        // A reverted call fails without touching the callee or moving the value.
+        let result = world.call(bob(), caller_address, &calldata("fail()", &[]));
+        assert!(result.success);
+        assert_eq!(result.output, vec![Expression::NumberLiteral(U256::from(7u8))]);
+        assert_eq!((slot(&world, caller_address), slot(&world, callee_address)), (U256::ZERO, U256::ONE));
+        assert_eq!(world.balance(&callee_address), U256::from(5u8));
+
+        // Without a call handler, as when running a contract on its own, every call fails.
+        let mut vm = VM::builder(ask("answer")).build();
+        let storage = vm.run(ContractStorage::new(vec![U256::ONE]));
+        assert_eq!(vm.halt, None);
+        assert_eq!(storage.slots(), [U256::ZERO]);
+    }
+
+    #[test]
+    fn test_call_kinds() {
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), program);
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        let returns_caller = vec![OP::CALLER, OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN];
+        // `bump()` counts its calls in slot 0 and returns its sender; `whoami()` only
+        // returns it.
+        let mut callee = Contract::new("Callee".to_string());
+        callee.storage = ContractStorage::new(vec![U256::ZERO]);
+        let bump = [vec![OP::PUSH0, OP::SLOAD, OP::PUSH1(1), OP::ADD, OP::PUSH0, OP::SSTORE], returns_caller.clone()].concat();
+        callee.insert_function(selector("bump()"), function("bump", bump)).unwrap();
+        callee.insert_function(selector("whoami()"), function("whoami", returns_caller)).unwrap();
+        let mut world = World::new_dev();
+        let callee_address = world.deploy(alice(), callee);
+
+        // Each function calls the callee's `name()` with `op`, keeps whether that succeeded
+        // in slot 1 and returns the first word the callee returned.
+        let forward = |op: OP, name: &str| {
+            let callee_selector = U256::from(u32::from_be_bytes(selector(&format!("{}()", name))));
+            let value = if op.stack_io().0 == 7 { vec![OP::PUSH0] } else { vec![] };
+            [
+                vec![OP::PUSHN(4, callee_selector), OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::PUSH1(4), OP::PUSH1(28)],
+                value,
+                vec![OP::PUSH32(callee_address.to_word()), OP::GAS, op, OP::PUSH1(1), OP::SSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN],
+            ].concat()
+        };
+        let mut caller = Contract::new("Caller".to_string());
+        caller.storage = ContractStorage::new(vec![U256::ZERO; 2]);
+        for (name, op, target) in [
+            ("delegate", OP::DELEGATECALL, "bump"),
+            ("callcode", OP::CALLCODE, "bump"),
+            ("staticBump", OP::STATICCALL, "bump"),
+            ("staticWhoami", OP::STATICCALL, "whoami"),
+        ] {
+            caller.insert_function(selector(&format!("{}()", name)), function(name, forward(op, target))).unwrap();
+        }
+        let caller_address = world.deploy(alice(), caller);
+        let slots = |world: &World, address| world.account(&address).unwrap().contract.as_ref().unwrap().storage.slots().to_vec();
+        let word = |address: Address| vec![Expression::NumberLiteral(address.to_word())];
+
+        // A delegate call runs the callee's code on the caller's storage, as the caller.
+        let result = world.call(bob(), caller_address, &calldata("delegate()", &[]));
+        assert_eq!(result.output, word(bob()));
+        assert_eq!(slots(&world, caller_address), [U256::ONE, U256::ONE]);
+        assert_eq!(slots(&world, callee_address), [U256::ZERO]);
+
+        // `CALLCODE` also uses the caller's storage, but with the caller as sender.
+        let result = world.call(bob(), caller_address, &calldata("callcode()", &[]));
+        assert_eq!(result.output, word(caller_address));
+        assert_eq!(slots(&world, caller_address), [U256::from(2u8), U256::ONE]);
+
+        // A static call can read but not write.
+        world.call(bob(), caller_address, &calldata("staticBump()", &[]));
+        assert_eq!(slots(&world, caller_address), [U256::from(2u8), U256::ZERO]);
+        assert_eq!(slots(&world, callee_address), [U256::ZERO]);
+        let result = world.call(bob(), caller_address, &calldata("staticWhoami()", &[]));
+        assert_eq!(result.output, word(caller_address));
+        assert_eq!(slots(&world, caller_address), [U256::from(2u8), U256::ONE]);
+
+        let mut vm = VM::builder(vec![OP::PUSH1(1), OP::PUSH0, OP::SSTORE]).env(Env::builder().is_static(true).build()).build();
+        vm.run(ContractStorage::new(vec![U256::ZERO]));
+        assert_eq!(vm.halt, Some(Halt::StateChangeInStaticCall));
+    }
+
+    #[test]
+    fn test_mining_modes() {
+        let mut world = World::new();
+        world.mining = Mining::Auto;
+        world.call(alice(), bob(), "");
+        world.call(alice(), bob(), "");
+        assert_eq!(world.blocks.iter().map(|block| block.transactions.len()).collect::<Vec<_>>(), vec![1, 1]);
+
+        world.mining = Mining::Interval;
+        world.block_time = 5;
+        world.call(alice(), bob(), "");
+        world.advance_time(4);
+        assert_eq!(world.blocks.len(), 2);
+        world.advance_time(7);
+        assert_eq!(world.blocks.len(), 4);
+        assert_eq!(world.blocks[2].transactions.len(), 1);
+        assert_eq!(world.env.timestamp - world.blocks[2].timestamp, 10);
+
+        world.mining = Mining::Manual;
+        world.advance_time(100);
+        assert_eq!(world.mine_n(3).len(), 3);
+        assert_eq!(world.blocks[4].timestamp, world.blocks[3].timestamp + 105);
+        assert_eq!(world.env.block_number, 8);
+    }
+
+    #[test]
+    fn test_seeded_randomness() {
+        let run = |seed: u64| {
+            let mut world = World::new();
+            world.seed(seed);
+            let first = world.env.prevrandao;
+            world.call(alice(), bob(), "");
+            let hashes = world.mine_n(2);
+            assert_eq!(world.blocks[0].prevrandao, first);
+            assert_ne!(world.env.prevrandao, first);
+            (hashes, world.env.prevrandao)
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_log_filters_and_subscriptions() {
+        let mut world = World::new();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let sink = seen.clone();
+        let id = world.subscribe(LogFilter::new().address(bob()), move |record| sink.lock().unwrap().push(record.block_number));
+
+        // Nothing compiles to LOG yet, so place logs in the pending block directly.
+        let record = |address, block_number, log_index| LogRecord {
+            log: Log { address, topics: vec![U256::from(log_index as u64)], data: vec![] },
+            block_number,
+            tx_hash: [0; 32],
+            log_index,
+        };
+        world.pending_logs.extend([record(alice(), 1, 0), record(bob(), 1, 1)]);
+        assert!(world.logs(&LogFilter::new()).is_empty());
+        world.mine();
+        world.pending_logs.push(record(bob(), 2, 0));
+        world.mine();
+
+        assert_eq!(world.logs(&LogFilter::new()).len(), 3);
+        assert_eq!(world.logs(&LogFilter::new().address(bob()).from_block(2)), vec![record(bob(), 2, 0)]);
+        assert_eq!(world.logs(&LogFilter::new().topic(0, vec![U256::ONE])), vec![record(bob(), 1, 1)]);
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+        assert!(LogFilter::new().address(alice()).may_match(&world.blocks[0].logs_bloom));
+        assert!(!LogFilter::new().address(alice()).may_match(&world.blocks[1].logs_bloom));
+
+        assert!(world.unsubscribe(id) && !world.unsubscribe(id));
+        world.pending_logs.push(record(bob(), 3, 0));
+        world.mine();
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_state_root() {
+        let contract = flipper();
+        let mut world = World::new();
+        assert_eq!(world.state_root(), EMPTY_ROOT);
+        let address = world.deploy(alice(), contract);
+        let before = world.state_root();
+        assert_eq!(world.storage_root(&address), EMPTY_ROOT);
+
+        let flip = calldata("flip()", &[]);
+        world.call(bob(), address, &flip);
+        assert_ne!(world.storage_root(&address), EMPTY_ROOT);
+        assert_ne!(world.state_root(), before);
+        world.call(bob(), address, &flip);
+        assert_eq!(world.state_root(), before);
+    }
+
+    #[test]
+    fn test_plain_value_transfers() {
+        let source = "contract Vault {
+    bool private received;
+    receive() external payable { received = !received; }
+    function get() public view returns (bool) { return received; }
+}";
+        let mut world = World::new_dev();
+        let vault = world.deploy(alice(), create_contracts(parse(source).unwrap()).remove(0));
+        let flipper = world.deploy(alice(), flipper());
+        let send = |to| Transaction { value: U256::from(7u8), ..Transaction::new(bob(), to, "") };
+
+        assert!(world.apply_transaction(&send(carol())).success);
+        assert_eq!(world.balance(&carol()), DEV_BALANCE + 7);
+
+        assert!(world.apply_transaction(&send(vault)).success);
+        assert_eq!(world.balance(&vault), U256::from(7u8));
+        assert_eq!(world.call(bob(), vault, &calldata("get()", &[])).output, vec![Expression::BoolLiteral(true)]);
+
+        // Without receive or fallback the transfer reverts and bob keeps the value.
+        assert!(!world.apply_transaction(&send(flipper)).success);
+        assert_eq!(world.balance(&flipper), U256::ZERO);
+        assert_eq!(world.balance(&bob()), DEV_BALANCE - 14);
+    }
+
+    #[test]
+    fn test_sponsored_call_charges_payer() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        world.env.gas_price = U256::from(2u8);
+        let address = world.deploy(alice(), contract);
+        let sender = crate::test_utils::address("sender");
+
+        let flip = Transaction { payer: Some(carol()), ..Transaction::new(sender, address, &calldata("flip()", &[])) };
+        let result = world.apply_transaction(&Transaction { gas_limit: 100_000, ..flip.clone() });
+        assert!(result.success);
+        assert_eq!(world.balance(&carol()), DEV_BALANCE - U256::from(result.gas_used) * 2);
+        assert_eq!(world.balance(&sender), U256::ZERO);
+
+        // Without a sponsor the unfunded sender can't pay for gas.
+        let unsponsored = world.apply_transaction(&Transaction { payer: None, ..flip });
+        assert_eq!(unsponsored.halt, Some(Halt::InsufficientFunds));
+    }
+
+    #[test]
+    fn test_parallel_simulations_share_world() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        let world = std::sync::Arc::new(std::sync::RwLock::new(world));
+        let flip = Transaction::new(bob(), address, &calldata("flip()", &[]));
+        let get = Transaction::new(bob(), address, &calldata("get()", &[]));
+
+        // Simulated flips are never committed, so every reader still sees `false`.
+        let outputs = std::thread::scope(|scope| {
+            let handles = (0..4).map(|_| scope.spawn(|| {
+                let world = world.read().unwrap();
+                assert!(world.simulate(&flip).success);
+                world.simulate(&get).output
+            })).collect::<Vec<_>>();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert!(outputs.iter().all(|output| *output == vec![Expression::BoolLiteral(false)]));
+
+        world.write().unwrap().apply_transaction(&flip);
+        assert_eq!(world.read().unwrap().simulate(&get).output, vec![Expression::BoolLiteral(true)]);
+    }
+
+    #[test]
+    fn test_view_calls() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        let (flip, get) = (calldata("flip()", &[]), calldata("get()", &[]));
+        assert_eq!(world.view_call(address, &get).output, vec![Expression::BoolLiteral(false)]);
+
+        // Writing storage halts instead.
+        assert_eq!(world.view_call(address, &flip).halt, Some(Halt::StateChangeInStaticCall));
+        assert_eq!(world.view_call(address, &get).output, vec![Expression::BoolLiteral(false)]);
+
+        // Results are cached until the world changes through its methods.
+        world.accounts.get_mut(&address).unwrap().contract.as_mut().unwrap().storage = ContractStorage::new(vec![U256::ONE]);
+        assert_eq!(world.view_call(address, &get).output, vec![Expression::BoolLiteral(false)]);
+        world.invalidate_views();
+        assert_eq!(world.view_call(address, &get).output, vec![Expression::BoolLiteral(true)]);
+        world.call(bob(), address, &flip);
+        assert_eq!(world.view_call(address, &get).output, vec![Expression::BoolLiteral(false)]);
+
+        let mut limited = world.clone();
+        limited.view_gas_limit = 100;
+        assert_eq!(limited.view_call(address, &get).halt, Some(Halt::OutOfGas));
+    }
+
+    #[test]
+    fn test_apply_parallel_matches_sequential() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        world.env.gas_price = U256::ONE;
+        let first = world.deploy(alice(), contract.clone());
+        let second = world.deploy(alice(), contract);
+        let flip = calldata("flip()", &[]);
+        let get = calldata("get()", &[]);
+
+        // The last flip conflicts with the first one and has to see its write.
+        let txs = [
+            Transaction::new(bob(), first, &flip),
+            Transaction::new(carol(), second, &flip),
+            Transaction::new(dave(), second, &get),
+            Transaction::new(dave(), first, &flip),
+        ];
+        let mut sequential = world.clone();
+        let expected = txs.iter().map(|tx| sequential.apply_transaction(tx)).collect::<Vec<_>>();
+        assert_eq!(world.apply_parallel(&txs), expected);
+        for address in [first, second] {
+            assert_eq!(world.call(bob(), address, &get).output, sequential.call(bob(), address, &get).output);
+        }
+        assert_eq!(world.balance(&dave()), sequential.balance(&dave()));
+    }
+
+    #[test]
+    fn test_apply_parallel_tracks_reads() {
+        let flipper = flipper();
+        let mut reader = Contract::new("Reader".to_string());
+        let mut function = Function::from_program("balance".to_string(), vec![
+            OP::PUSH32(carol().to_word()), OP::BALANCE, OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ]);
+        function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+        reader.insert_function(selector("balance()"), function).unwrap();
+        let mut world = World::new_dev();
+        world.env.gas_price = U256::ONE;
+        let flipper = world.deploy(alice(), flipper);
+        let reader = world.deploy(alice(), reader);
+
+        // Carol pays for her flip, so the second transaction, which only reads her balance,
+        // has to see the fee taken.
+        let txs = [
+            Transaction::new(carol(), flipper, &calldata("flip()", &[])),
+            Transaction::new(dave(), reader, &calldata("balance()", &[])),
+        ];
+        let mut sequential = world.clone();
+        let expected = txs.iter().map(|tx| sequential.apply_transaction(tx)).collect::<Vec<_>>();
+        assert_eq!(world.apply_parallel(&txs), expected);
+        assert_eq!(expected[1].output, vec![Expression::NumberLiteral(DEV_BALANCE - U256::from(expected[0].gas_used))]);
+    }
+
+    #[test]
+    fn test_apply_parallel_auto_mining() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        world.mining = Mining::Auto;
+        world.env.base_fee = U256::from(1_000_000_000u64);
+        let address = world.deploy(alice(), contract);
+
+        // Each transaction mines a block with a lower base fee, which the next one pays.
+        let txs = [bob(), carol(), dave()].map(|from| Transaction {
+            max_fee_per_gas: Some(U256::from(3_000_000_000u64)),
+            max_priority_fee_per_gas: Some(U256::from(1_000_000_000u64)),
+            ..Transaction::new(from, address, &calldata("flip()", &[]))
+        });
+        let mut sequential = world.clone();
+        let expected = txs.iter().map(|tx| sequential.apply_transaction(tx)).collect::<Vec<_>>();
+        assert_eq!(world.apply_parallel(&txs), expected);
+        assert_eq!(world.blocks.len(), 3);
+        for account in [bob(), carol(), dave(), world.env.coinbase] {
+            assert_eq!(world.balance(&account), sequential.balance(&account));
+        }
+    }
+
+    #[test]
+    fn test_multicall_shares_warm_slots() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+        let flip = Call::new(address, &calldata("flip()", &[]));
+        let get = Call::new(address, &calldata("get()", &[]));
+
+        let receipt = world.multicall(bob(), &[flip.clone(), get.clone(), flip.clone()]);
+        assert!(receipt.success);
+        assert_eq!(receipt.results[1].output, vec![Expression::BoolLiteral(true)]);
+        // Only the first flip pays for a cold slot and setting it from zero; the second
+        // restores the value the transaction started with.
+        assert_eq!(receipt.results[0].gas_used, 2 + 2100 + 3 + 2 + 20000);
+        assert_eq!(receipt.results[2].gas_used, 2 + 100 + 3 + 2 + 100);
+        assert_eq!(receipt.gas_used, INTRINSIC_GAS + receipt.results.iter().map(|r| r.gas_used).sum::<u64>());
+        assert_eq!(world.call(bob(), address, &get.calldata).output, vec![Expression::BoolLiteral(false)]);
+
+        // A failure reverts the batch unless the call allows it.
+        let missing = Call::new(address, "deadbeef");
+        assert!(!world.multicall(bob(), &[flip.clone(), missing.clone()]).success);
+        assert_eq!(world.call(bob(), address, &get.calldata).output, vec![Expression::BoolLiteral(false)]);
+        assert!(world.multicall(bob(), &[flip, missing.allow_failure()]).success);
+        assert_eq!(world.call(bob(), address, &get.calldata).output, vec![Expression::BoolLiteral(true)]);
+    }
+
+    #[test]
+    fn test_multicall_runs_message_calls() {
+        let function = |name: &str, program: Vec<OP>| {
+            let mut function = Function::from_program(name.to_string(), program);
+            function.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+            function
+        };
+        // `answer()` counts its calls in slot 0 and returns the count; `ask()` calls it and
+        // returns what it returned.
+        let mut callee = Contract::new("Callee".to_string());
+        callee.storage = ContractStorage::new(vec![U256::ZERO]);
+        callee.insert_function(selector("answer()"), function("answer", vec![
+            OP::PUSH0, OP::SLOAD, OP::PUSH1(1), OP::ADD, OP::DUP1, OP::PUSH0, OP::SSTORE,
+            OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ])).unwrap();
+        let mut world = World::new_dev();
+        let callee_address = world.deploy(alice(), callee);
+        let answer_selector = U256::from(u32::from_be_bytes(selector("answer()")));
+        let mut caller = Contract::new("Caller".to_string());
+        caller.insert_function(selector("ask()"), function("ask", vec![
+            OP::PUSHN(4, answer_selector), OP::PUSH0, OP::MSTORE,
+            OP::PUSH1(32), OP::PUSH0, OP::PUSH1(4), OP::PUSH1(28), OP::PUSH0, OP::PUSH32(callee_address.to_word()), OP::GAS, OP::CALL,
+            OP::POP, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ])).unwrap();
+        let caller_address = world.deploy(alice(), caller);
+        let ask = Call::new(caller_address, &calldata("ask()", &[]));
+
+        // The second call sees the callee's count from the first.
+        let receipt = world.multicall(bob(), &[ask.clone(), ask.clone()]);
+        assert!(receipt.success);
+        let outputs = receipt.results.iter().map(|result| result.output.clone()).collect::<Vec<_>>();
+        assert_eq!(outputs, [vec![Expression::NumberLiteral(U256::ONE)], vec![Expression::NumberLiteral(U256::from(2u8))]]);
+        assert_eq!(world.account(&callee_address).unwrap().contract.as_ref().unwrap().storage.slots()[0], U256::from(2u8));
+
+        // A batch that fails leaves the callee as it was.
+        assert!(!world.multicall(bob(), &[ask, Call::new(caller_address, "deadbeef")]).success);
+        assert_eq!(world.account(&callee_address).unwrap().contract.as_ref().unwrap().storage.slots()[0], U256::from(2u8));
+    }
+
+    #[test]
+    fn test_transient_storage() {
+        // Counts calls in transient slot 0 and returns the count.
+        let mut bump = Function::from_program("bump".to_string(), vec![
+            OP::PUSH0, OP::TLOAD, OP::PUSH1(1), OP::ADD, OP::DUP1, OP::PUSH0, OP::TSTORE,
+            OP::PUSH0, OP::MSTORE, OP::PUSH1(32), OP::PUSH0, OP::RETURN,
+        ]);
+        bump.returns = vec![Parameter { ty: Expression::Type(Type::Uint256(())), name: None }];
+        let mut counter = Contract::new("Counter".to_string());
+        counter.insert_function(selector("bump()"), bump).unwrap();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), counter);
+        let bump = Call::new(address, &calldata("bump()", &[]));
+        let count = |n: u8| vec![Expression::NumberLiteral(U256::from(n))];
+
+        // Calls in one transaction share the slot; the next transaction starts from zero.
+        let receipt = world.multicall(bob(), &[bump.clone(), bump.clone()]);
+        assert_eq!((&receipt.results[0].output, &receipt.results[1].output), (&count(1), &count(2)));
+        assert_eq!(receipt.results[0].gas_used, 2 + 100 + 3 + 3 + 3 + 2 + 100 + 2 + 3 + 3 + 3 + 2);
+        assert_eq!(world.call(bob(), address, &bump.calldata).output, count(1));
+    }
+
+    #[test]
+    fn test_estimate_gas() {
+        let contract = flipper();
+        let mut world = World::new_dev();
+        let address = world.deploy(alice(), contract);
+
+        // PUSH0 + cold SLOAD + ISZERO + PUSH0 + cold SSTORE setting a zero slot.
+        let flip = Transaction::new(alice(), address, &calldata("flip()", &[]));
+        let estimate = world.estimate_gas(&flip).unwrap();
+        assert_eq!(estimate, INTRINSIC_GAS + 2 + 2100 + 3 + 2 + 20000);
+
+        let result = world.apply_transaction(&Transaction { gas_limit: estimate - 1, ..flip.clone() });
+        assert_eq!(result.halt, Some(Halt::OutOfGas));
+        assert_eq!(world.call(alice(), address, &calldata("get()", &[])).output, vec![Expression::BoolLiteral(false)]);
+
+        let result = world.apply_transaction(&Transaction { gas_limit: estimate, ..flip.clone() });
+        assert!(result.success);
+        assert_eq!(result.gas_used, estimate);
+        assert_eq!(world.estimate_gas(&Transaction { gas_limit: 100, ..flip }), None);
+    }
+}